@@ -4,10 +4,17 @@ DELETE FROM content;
 DELETE FROM movie;
 DELETE FROM episode;
 DELETE FROM song;
+DELETE FROM extra;
 DELETE FROM franchise;
 DELETE FROM season;
 DELETE FROM series;
 DELETE FROM theme;
+DELETE FROM user_collection;
 DELETE FROM collection;
 DELETE FROM collection_contains;
+DELETE FROM metadata;
+DELETE FROM technical_details;
+DELETE FROM nfo;
+DELETE FROM playback_reports;
+DELETE FROM series_activity;
 COMMIT;
\ No newline at end of file
@@ -1,4 +1,6 @@
 BEGIN;
+DELETE FROM series_activity;
+DELETE FROM user_preferences;
 DELETE FROM user_groups;
 DELETE FROM user_permissions;
 DELETE FROM users;
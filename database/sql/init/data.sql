@@ -10,17 +10,42 @@ CREATE TABLE storage_locations (
 
 CREATE TABLE data_file (
     id INTEGER PRIMARY KEY,
-    path TEXT NOT NULL UNIQUE
+    path TEXT NOT NULL UNIQUE,
+    size INTEGER, -- In bytes, updated whenever the file is (re)indexed
+    -- Set when this is an identical copy of content that is played from another file, null otherwise
+    duplicate_of INTEGER REFERENCES content (id) ON DELETE SET NULL,
+    classification_error TEXT -- Why the file couldn't be classified, null when it could
 );
 
 CREATE TABLE content (
     id INTEGER PRIMARY KEY,
     last_changed INTEGER NOT NULL,
     hash BLOB NOT NULL,
-    data_id INTEGER, -- Reference to a data_file id, is null when the data_file was invalidated
+    data_id INTEGER REFERENCES data_file (id) ON DELETE SET NULL, -- Is null when the data_file was invalidated
     type INTEGER NOT NULL, -- ContentType
     reference INTEGER, -- The key to another table based on type
-    part INTEGER NOT NULL
+    part INTEGER NOT NULL,
+    resolution INTEGER, -- Vertical resolution from the file name, null when unknown
+    source TEXT, -- Like BluRay or WEB-DL, null when unknown
+    confidence INTEGER NOT NULL DEFAULT 0 -- Confidence, how sure indexing was about where this belongs
+);
+
+CREATE TABLE technical_details (
+    content_id INTEGER PRIMARY KEY REFERENCES content (id) ON DELETE CASCADE,
+    container TEXT NOT NULL,
+    video_codec TEXT,
+    width INTEGER,
+    height INTEGER,
+    audio TEXT, -- Comma seperated codec and language of each audio stream, null when there are none
+    bitrate INTEGER -- Bits per second
+);
+
+-- What a Kodi style .nfo file next to the content says about it
+CREATE TABLE nfo (
+    content_id INTEGER PRIMARY KEY REFERENCES content (id) ON DELETE CASCADE,
+    title TEXT,
+    year INTEGER,
+    plot TEXT
 );
 
 ------------
@@ -29,7 +54,8 @@ CREATE TABLE content (
 
 CREATE TABLE movie (
     id INTEGER PRIMARY KEY,
-    title TEXT NOT NULL
+    title TEXT NOT NULL,
+    year INTEGER -- From the file name, NULL when it doesn't have one
 );
 
 CREATE TABLE episode (
@@ -42,6 +68,13 @@ CREATE TABLE song (
     id INTEGER PRIMARY KEY,
     title TEXT NOT NULL
 );
+
+-- Featurettes, behind the scenes and similar, only shown next to what they belong to
+CREATE TABLE extra (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    directory TEXT -- Set for extras of a single movie, the directory it is in. Others are in collections
+);
 ------------
 
 -- # Collections
@@ -53,7 +86,7 @@ CREATE TABLE collection (
 );
 
 CREATE TABLE collection_contains (
-    collection_id INTEGER REFERENCES collection (id), -- TODO: This fails somewhere!
+    collection_id INTEGER REFERENCES collection (id) ON DELETE CASCADE, -- TODO: This fails somewhere!
     type INTEGER NOT NULL, -- TableId
     reference INTEGER, -- Either a collection or content
     UNIQUE (collection_id, type, reference) ON CONFLICT IGNORE
@@ -84,6 +117,84 @@ CREATE TABLE theme (
     theme_target INTEGER -- Either a collection or content
 );
 
+CREATE TABLE user_collection (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL
+);
+
+------------
+
+-- # Metadata from external providers
+
+CREATE TABLE metadata (
+    id INTEGER PRIMARY KEY,
+    type INTEGER NOT NULL, -- TableId
+    reference INTEGER NOT NULL, -- Either a collection or content
+    overview TEXT,
+    poster_url TEXT,
+    genres TEXT, -- Comma seperated, null when none are known
+    UNIQUE (type, reference) ON CONFLICT REPLACE
+);
+
+------------
+
+-- # Problems users ran into while watching
+
+CREATE TABLE playback_reports (
+    id INTEGER PRIMARY KEY,
+    content_id INTEGER NOT NULL REFERENCES content (id) ON DELETE CASCADE,
+    session_id INTEGER NOT NULL,
+    user_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    video_time REAL NOT NULL, -- Seconds into the video
+    description TEXT NOT NULL,
+    created INTEGER NOT NULL -- Seconds since the unix epoch
+);
+
+------------
+
+-- # What users watched recently
+
+CREATE TABLE series_activity (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    series_id INTEGER NOT NULL REFERENCES collection (id) ON DELETE CASCADE, -- CollectionType::Series
+    content_id INTEGER NOT NULL REFERENCES content (id) ON DELETE CASCADE, -- The episode that was played last
+    resume_id INTEGER REFERENCES content (id) ON DELETE SET NULL, -- Where to continue, NULL once the last episode was watched
+    last_active INTEGER NOT NULL, -- Seconds since the unix epoch
+    PRIMARY KEY (user_id, series_id)
+);
+
+------------
+
+-- # Cleanup of references that can point into multiple tables, these can't be foreign keys
+
+CREATE TRIGGER content_cleanup AFTER DELETE ON content
+BEGIN
+    DELETE FROM collection_contains WHERE type = 1 AND reference = OLD.id; -- TableId::Content
+    DELETE FROM theme WHERE type = 1 AND theme_target = OLD.id;
+    DELETE FROM metadata WHERE type = 1 AND reference = OLD.id;
+    -- The parts of a movie or episode share its row, it goes with the last one
+    DELETE FROM movie WHERE OLD.type = 1 AND id = OLD.reference -- ContentType::Movie
+        AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+    DELETE FROM episode WHERE OLD.type = 2 AND id = OLD.reference -- ContentType::Episode
+        AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+    DELETE FROM song WHERE OLD.type = 3 AND id = OLD.reference -- ContentType::Song
+        AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+    DELETE FROM extra WHERE OLD.type = 4 AND id = OLD.reference -- ContentType::Extra
+        AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+END;
+
+CREATE TRIGGER collection_cleanup AFTER DELETE ON collection
+BEGIN
+    DELETE FROM collection_contains WHERE type = 0 AND reference = OLD.id; -- TableId::Collection
+    DELETE FROM theme WHERE type = 0 AND theme_target = OLD.id;
+    DELETE FROM metadata WHERE type = 0 AND reference = OLD.id;
+    DELETE FROM user_collection WHERE OLD.type = 0 AND id = OLD.reference; -- CollectionType::UserCollection
+    DELETE FROM franchise WHERE OLD.type = 1 AND id = OLD.reference; -- CollectionType::Franchise
+    DELETE FROM season WHERE OLD.type = 2 AND id = OLD.reference; -- CollectionType::Season
+    DELETE FROM series WHERE OLD.type = 3 AND id = OLD.reference; -- CollectionType::Series
+    DELETE FROM theme WHERE OLD.type = 4 AND id = OLD.reference; -- CollectionType::Theme
+END;
+
 ------------
 
 COMMIT;
@@ -50,4 +50,13 @@ CREATE TABLE group_permissions (
 
 ------------
 
+-- # Preferences
+
+CREATE TABLE user_preferences (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    show_recommendations BOOLEAN NOT NULL DEFAULT 1 -- The popup near the end of a video
+);
+
+------------
+
 COMMIT;
\ No newline at end of file
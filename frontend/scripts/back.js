@@ -1,3 +1,3 @@
 setTimeout(() => {
-    window.location.href = "/";
+    window.location.href = document.body.dataset.basePath + "/";
 }, 1000);
\ No newline at end of file
@@ -1,6 +1,37 @@
 document.body.addEventListener("htmx:beforeSwap", (event) => {
-    if (event.detail.xhr.status === 422) {
+    // Validation errors and rate limits come with a message to show
+    if (event.detail.xhr.status === 422 || event.detail.xhr.status === 429) {
         event.detail.shouldSwap = true;
         event.detail.isError = false;
     }
-});
\ No newline at end of file
+});
+
+// How many pages of each row are currently loaded according to the url, as "route:pages" separated by commas
+function loadedPages() {
+    const url = new URL(window.location.href);
+    const route = new URL(url.searchParams.get("content") ?? "/library", window.location.origin);
+    return route.searchParams.get("pages") ?? "";
+}
+
+// Records how many pages of a row are loaded in the url, so navigating back loads them again
+function rememberPages(row, pages) {
+    const url = new URL(window.location.href);
+    const route = new URL(url.searchParams.get("content") ?? "/library", window.location.origin);
+
+    const loaded = new Map();
+    for (const entry of (route.searchParams.get("pages") ?? "").split(",")) {
+        const split = entry.lastIndexOf(":");
+        if (split !== -1) {
+            loaded.set(entry.slice(0, split), Number(entry.slice(split + 1)));
+        }
+    }
+
+    if (pages <= (loaded.get(row) ?? 0)) {
+        return;
+    }
+
+    loaded.set(row, pages);
+    route.searchParams.set("pages", Array.from(loaded, ([row, pages]) => `${row}:${pages}`).join(","));
+    url.searchParams.set("content", route.pathname + route.search);
+    history.replaceState(history.state, "", url);
+}
@@ -35,6 +35,15 @@ async function wait_for_interact() {
 }
 setTimeout(wait_for_interact, 100); // Give the websocket a chance to connect
 
+// After a dropped connection the others might have moved on, so the current state is requested again
+let connectedBefore = false;
+document.body.addEventListener("htmx:wsOpen", () => {
+    if (connectedBefore && active) {
+        ws.send(JSON.stringify({ "type": "Join" }));
+    }
+    connectedBefore = true;
+});
+
 function sendVideoUpdate(type) {
     let message = {
         "type": "Update",
@@ -83,6 +92,11 @@ function handleServerEvent(data) {
         }
     } else if (type == "Reload") {
         reload();
+    } else if (type == "Recommendations") {
+        showRecommendationState(data["enabled"]);
+    } else if (type == "Stats") {
+        // Contains "participants", "state" and "video_time", anything that wants to show it can listen for this
+        document.dispatchEvent(new CustomEvent("sessionstats", { detail: data }));
     } else {
         console.log("Unknown type: ", type);
     }
@@ -199,8 +213,49 @@ function changePlaybackSpeed() {
 // Duration
 video.addEventListener("loadedmetadata", () => {
     totaltime.innerText = formatDuration(video.duration);
+    // Browsers that can decode the audio but not the video just show a black picture
+    if (video.videoWidth === 0) {
+        explainPlaybackProblem(true);
+    }
 })
 
+// Browsers fail silently when they don't support a codec, so the user at least gets to know why
+video.addEventListener("error", () => explainPlaybackProblem(false));
+
+async function explainPlaybackProblem(only_if_video) {
+    let info = { video_codec: null, audio: [] };
+    try {
+        let response = await fetch(`${document.body.dataset.basePath}/video/session/${videocontainer.dataset.session}/playback_info`);
+        if (response.ok) {
+            info = await response.json();
+        }
+    } catch (e) {
+        // Explaining it without the codecs is still better than nothing
+    }
+
+    if (only_if_video && !info.video_codec) {
+        return;
+    }
+
+    let codecs = [];
+    if (info.video_codec) {
+        codecs.push(`video: ${info.video_codec}`);
+    }
+    if (info.audio.length > 0) {
+        codecs.push(`audio: ${info.audio.join(", ")}`);
+    }
+
+    let message = "Your browser can't play this file";
+    if (codecs.length > 0) {
+        message += ` (${codecs.join("; ")})`;
+    }
+    message += ". Another browser or a different version of the file might work.";
+
+    let error = videocontainer.querySelector(".playback-error");
+    error.textContent = message;
+    error.hidden = false;
+}
+
 video.addEventListener("timeupdate", () => {
     currenttime.textContent = formatDuration(video.currentTime);
     const percent = video.currentTime / video.duration;
@@ -287,6 +342,9 @@ function togglePlay() {
 }
 
 document.addEventListener("keydown", e => {
+    if (e.target.tagName === "TEXTAREA") {
+        return;
+    }
     switch (e.key.toLocaleLowerCase()) {
         case " ":
             togglePlay();
@@ -317,6 +375,58 @@ video.addEventListener('pause', () => {
     }
 });
 
+// Reporting problems
+const reportdialog = document.querySelector(".report-dialog");
+
+function openReportDialog() {
+    reportdialog.showModal();
+}
+
+// Other players can't log in, so they get a link that only works for what is playing right now
+async function copyStreamLink() {
+    let response = await fetch(`${document.body.dataset.basePath}/video/session/${videocontainer.dataset.session}/link`);
+    if (response.ok) {
+        await navigator.clipboard.writeText(await response.text());
+    }
+}
+
+// Asks the server who is here and where the session is, the answer only goes to this client
+function requestStats() {
+    ws.send(JSON.stringify({ "type": "Stats" }));
+}
+
+// Turns the popup near the end on or off for the whole session
+function toggleRecommendations() {
+    let enabled = videocontainer.dataset.recommendations !== "true";
+    ws.send(JSON.stringify({ "type": "Recommendations", "enabled": enabled }));
+    showRecommendationState(enabled);
+}
+
+function showRecommendationState(enabled) {
+    videocontainer.dataset.recommendations = enabled;
+    videocontainer.querySelector(".recommendations").textContent = enabled ? "Suggestions: On" : "Suggestions: Off";
+}
+
+// The answer only goes to this client and is shown like the usual popup
+function requestWhatsNext() {
+    ws.send(JSON.stringify({ "type": "WhatsNext" }));
+}
+
+// Set when the video ended and the next queued content was requested, so it starts playing after the reload
+let advancingQueue = false;
+video.addEventListener("ended", () => {
+    let queued = document.querySelector(".popup[data-queued]");
+    if (queued) {
+        advancingQueue = true;
+        confirmpopup(Number(queued.dataset.queued));
+    }
+});
+
+// Either "Next" or "Previous", the server picks the episode and everyone reloads
+function stepEpisode(direction) {
+    ws.send(JSON.stringify({ "type": direction }));
+}
+
 // function for popup redirect
 function confirmpopup(id) {
     let message = {
@@ -327,6 +437,7 @@ function confirmpopup(id) {
 }
 
 function reload() {
+    videocontainer.querySelector(".playback-error").hidden = true;
     let paused = video.paused;
     video.pause();
     video.currentTime = 0;
@@ -339,7 +450,8 @@ function reload() {
     let popup = document.querySelector(".popup");
     popup.parentNode.removeChild(popup);// TODO: Make this failing not matter
 
-    if (!paused) {
+    if (!paused || advancingQueue) {
+        advancingQueue = false;
         video.play();
     }
 }
@@ -0,0 +1,24 @@
+use std::{process::Command, time::SystemTime};
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=MRE_GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=MRE_BUILD_TIMESTAMP={timestamp}");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+    // Branch heads end up in here once git packs its refs
+    println!("cargo:rerun-if-changed=../.git/packed-refs");
+}
@@ -0,0 +1,457 @@
+use rusqlite::{Connection, Transaction};
+use tracing::info;
+
+use super::QueryRowGetConnExt;
+
+enum Step {
+    /// Has to be safe to run on a database that already contains the change, like CREATE TABLE IF NOT EXISTS
+    Sql(&'static str),
+    /// Skipped when the table already has a column with that name
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+    /// For changes sqlite can't make conditionally on its own, skipped when `applied` returns true
+    Unless {
+        applied: &'static str,
+        sql: &'static str,
+    },
+}
+
+/// Every schema change since the first release, `PRAGMA user_version` is how many of these a database already has.
+/// The schema changed for a while before it was versioned, so databases at version 0 can contain any of these already
+const MIGRATIONS: &[&[Step]] = &[
+    // User collections
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS user_collection (
+            id INTEGER PRIMARY KEY,
+            title TEXT NOT NULL
+        );",
+    )],
+    // Metadata from external providers
+    &[
+        Step::Sql(
+            "CREATE TABLE IF NOT EXISTS metadata (
+                id INTEGER PRIMARY KEY,
+                type INTEGER NOT NULL,
+                reference INTEGER NOT NULL,
+                overview TEXT,
+                poster_url TEXT,
+                genres TEXT,
+                UNIQUE (type, reference) ON CONFLICT REPLACE
+            );",
+        ),
+        Step::AddColumn {
+            table: "movie",
+            column: "year",
+            definition: "INTEGER",
+        },
+    ],
+    // Foreign keys with delete behavior, sqlite can only add these by recreating the table
+    &[
+        Step::Unless {
+            applied: "SELECT exists(SELECT 1 FROM pragma_foreign_key_list('content') WHERE \"from\" = 'data_id')",
+            sql: "CREATE TABLE content_rebuild (
+                id INTEGER PRIMARY KEY,
+                last_changed INTEGER NOT NULL,
+                hash BLOB NOT NULL,
+                data_id INTEGER REFERENCES data_file (id) ON DELETE SET NULL,
+                type INTEGER NOT NULL,
+                reference INTEGER,
+                part INTEGER NOT NULL
+            );
+            INSERT INTO content_rebuild (id, last_changed, hash, data_id, type, reference, part)
+                SELECT id, last_changed, hash, data_id, type, reference, part FROM content;
+            DROP TABLE content;
+            ALTER TABLE content_rebuild RENAME TO content;",
+        },
+        Step::Unless {
+            applied: "SELECT exists(SELECT 1 FROM pragma_foreign_key_list('collection_contains') WHERE on_delete = 'CASCADE')",
+            sql: "CREATE TABLE collection_contains_rebuild (
+                collection_id INTEGER REFERENCES collection (id) ON DELETE CASCADE,
+                type INTEGER NOT NULL,
+                reference INTEGER,
+                UNIQUE (collection_id, type, reference) ON CONFLICT IGNORE
+            );
+            INSERT INTO collection_contains_rebuild (collection_id, type, reference)
+                SELECT collection_id, type, reference FROM collection_contains;
+            DROP TABLE collection_contains;
+            ALTER TABLE collection_contains_rebuild RENAME TO collection_contains;",
+        },
+        Step::Sql(
+            "CREATE TRIGGER IF NOT EXISTS content_cleanup AFTER DELETE ON content
+            BEGIN
+                DELETE FROM collection_contains WHERE type = 1 AND reference = OLD.id;
+                DELETE FROM theme WHERE type = 1 AND theme_target = OLD.id;
+                DELETE FROM metadata WHERE type = 1 AND reference = OLD.id;
+                DELETE FROM movie WHERE OLD.type = 1 AND id = OLD.reference;
+                DELETE FROM episode WHERE OLD.type = 2 AND id = OLD.reference;
+                DELETE FROM song WHERE OLD.type = 3 AND id = OLD.reference;
+            END;
+            CREATE TRIGGER IF NOT EXISTS collection_cleanup AFTER DELETE ON collection
+            BEGIN
+                DELETE FROM collection_contains WHERE type = 0 AND reference = OLD.id;
+                DELETE FROM theme WHERE type = 0 AND theme_target = OLD.id;
+                DELETE FROM metadata WHERE type = 0 AND reference = OLD.id;
+                DELETE FROM user_collection WHERE OLD.type = 0 AND id = OLD.reference;
+                DELETE FROM franchise WHERE OLD.type = 1 AND id = OLD.reference;
+                DELETE FROM season WHERE OLD.type = 2 AND id = OLD.reference;
+                DELETE FROM series WHERE OLD.type = 3 AND id = OLD.reference;
+                DELETE FROM theme WHERE OLD.type = 4 AND id = OLD.reference;
+            END;",
+        ),
+    ],
+    // Resolution and source tags from file names
+    &[
+        Step::AddColumn {
+            table: "content",
+            column: "resolution",
+            definition: "INTEGER",
+        },
+        Step::AddColumn {
+            table: "content",
+            column: "source",
+            definition: "TEXT",
+        },
+    ],
+    // Technical details from probing
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS technical_details (
+            content_id INTEGER PRIMARY KEY REFERENCES content (id) ON DELETE CASCADE,
+            container TEXT NOT NULL,
+            video_codec TEXT,
+            width INTEGER,
+            height INTEGER,
+            audio TEXT,
+            bitrate INTEGER
+        );",
+    )],
+    // Playback reports
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS playback_reports (
+            id INTEGER PRIMARY KEY,
+            content_id INTEGER NOT NULL REFERENCES content (id) ON DELETE CASCADE,
+            session_id INTEGER NOT NULL,
+            user_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+            video_time REAL NOT NULL,
+            description TEXT NOT NULL,
+            created INTEGER NOT NULL
+        );",
+    )],
+    // Kodi style .nfo files
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS nfo (
+            content_id INTEGER PRIMARY KEY REFERENCES content (id) ON DELETE CASCADE,
+            title TEXT,
+            year INTEGER,
+            plot TEXT
+        );",
+    )],
+    // Extras, which the content cleanup has to know about as well
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS extra (
+            id INTEGER PRIMARY KEY,
+            title TEXT NOT NULL
+        );
+        DROP TRIGGER IF EXISTS content_cleanup;
+        CREATE TRIGGER content_cleanup AFTER DELETE ON content
+        BEGIN
+            DELETE FROM collection_contains WHERE type = 1 AND reference = OLD.id;
+            DELETE FROM theme WHERE type = 1 AND theme_target = OLD.id;
+            DELETE FROM metadata WHERE type = 1 AND reference = OLD.id;
+            DELETE FROM movie WHERE OLD.type = 1 AND id = OLD.reference;
+            DELETE FROM episode WHERE OLD.type = 2 AND id = OLD.reference;
+            DELETE FROM song WHERE OLD.type = 3 AND id = OLD.reference;
+            DELETE FROM extra WHERE OLD.type = 4 AND id = OLD.reference;
+        END;",
+    )],
+    // Extras that belong to a single movie
+    &[Step::AddColumn {
+        table: "extra",
+        column: "directory",
+        definition: "TEXT",
+    }],
+    // File sizes
+    &[Step::AddColumn {
+        table: "data_file",
+        column: "size",
+        definition: "INTEGER",
+    }],
+    // Identical copies of the same content
+    &[Step::AddColumn {
+        table: "data_file",
+        column: "duplicate_of",
+        definition: "INTEGER REFERENCES content (id) ON DELETE SET NULL",
+    }],
+    // Classification confidence
+    &[Step::AddColumn {
+        table: "content",
+        column: "confidence",
+        definition: "INTEGER NOT NULL DEFAULT 0",
+    }],
+    // Continue Series
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS series_activity (
+            user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+            series_id INTEGER NOT NULL REFERENCES collection (id) ON DELETE CASCADE,
+            content_id INTEGER NOT NULL REFERENCES content (id) ON DELETE CASCADE,
+            last_active INTEGER NOT NULL,
+            PRIMARY KEY (user_id, series_id)
+        );",
+    )],
+    // User preferences
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS user_preferences (
+            user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+            show_recommendations BOOLEAN NOT NULL DEFAULT 1
+        );",
+    )],
+    // Episode parts from names like "- s1e2p1" are counted from 0 like part directories, every database before this counted from 1
+    &[Step::Sql(
+        "UPDATE content SET part = part - 1 WHERE type = 2 AND part > 0;",
+    )],
+    // Continue Series resumes unfinished episodes instead of skipping past them
+    &[
+        Step::AddColumn {
+            table: "series_activity",
+            column: "resume_id",
+            definition: "INTEGER REFERENCES content (id) ON DELETE SET NULL",
+        },
+        Step::Sql("UPDATE series_activity SET resume_id = content_id WHERE resume_id IS NULL;"),
+    ],
+    // Files that failed to classify are listed for review
+    &[Step::AddColumn {
+        table: "data_file",
+        column: "classification_error",
+        definition: "TEXT",
+    }],
+    // Content cleanup keeps movies and episodes while other parts of them are left
+    &[Step::Sql(
+        "DROP TRIGGER IF EXISTS content_cleanup;
+        CREATE TRIGGER content_cleanup AFTER DELETE ON content
+        BEGIN
+            DELETE FROM collection_contains WHERE type = 1 AND reference = OLD.id;
+            DELETE FROM theme WHERE type = 1 AND theme_target = OLD.id;
+            DELETE FROM metadata WHERE type = 1 AND reference = OLD.id;
+            DELETE FROM movie WHERE OLD.type = 1 AND id = OLD.reference
+                AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+            DELETE FROM episode WHERE OLD.type = 2 AND id = OLD.reference
+                AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+            DELETE FROM song WHERE OLD.type = 3 AND id = OLD.reference
+                AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+            DELETE FROM extra WHERE OLD.type = 4 AND id = OLD.reference
+                AND NOT EXISTS (SELECT 1 FROM content WHERE type = OLD.type AND reference = OLD.reference);
+        END;",
+    )],
+];
+
+/// The version of a database that was just created with the newest schema
+pub fn current_version() -> usize {
+    MIGRATIONS.len()
+}
+
+/// Applies every migration the database doesn't have yet, each in its own transaction
+pub fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version = conn.query_row_get::<usize>("PRAGMA user_version", [])?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("Migrating the database to version {}", index + 1);
+
+        let tx = conn.unchecked_transaction()?;
+        for step in *migration {
+            apply(&tx, step)?;
+        }
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn apply(tx: &Transaction, step: &Step) -> rusqlite::Result<()> {
+    match step {
+        Step::Sql(sql) => tx.execute_batch(sql),
+        Step::AddColumn {
+            table,
+            column,
+            definition,
+        } => {
+            let exists = tx.query_row_get::<bool>(
+                "SELECT exists(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+                [table, column],
+            )?;
+
+            if exists {
+                return Ok(());
+            }
+            tx.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))
+        }
+        Step::Unless { applied, sql } => {
+            if tx.query_row_get::<bool>(applied, [])? {
+                return Ok(());
+            }
+            tx.execute_batch(sql)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// data.sql as it was before the schema was versioned
+    const FIRST_DATA_SCHEMA: &str = "
+        CREATE TABLE storage_locations (id INTEGER PRIMARY KEY, path TEXT NOT NULL, recurse BOOLEAN NOT NULL);
+        CREATE TABLE data_file (id INTEGER PRIMARY KEY, path TEXT NOT NULL UNIQUE);
+        CREATE TABLE content (
+            id INTEGER PRIMARY KEY,
+            last_changed INTEGER NOT NULL,
+            hash BLOB NOT NULL,
+            data_id INTEGER,
+            type INTEGER NOT NULL,
+            reference INTEGER,
+            part INTEGER NOT NULL
+        );
+        CREATE TABLE movie (id INTEGER PRIMARY KEY, title TEXT NOT NULL);
+        CREATE TABLE episode (id INTEGER PRIMARY KEY, title TEXT NOT NULL, episode INTEGER NOT NULL);
+        CREATE TABLE song (id INTEGER PRIMARY KEY, title TEXT NOT NULL);
+        CREATE TABLE collection (id INTEGER PRIMARY KEY, type INTEGER NOT NULL, reference INTEGER NOT NULL);
+        CREATE TABLE collection_contains (
+            collection_id INTEGER REFERENCES collection (id),
+            type INTEGER NOT NULL,
+            reference INTEGER,
+            UNIQUE (collection_id, type, reference) ON CONFLICT IGNORE
+        );
+        CREATE TABLE franchise (id INTEGER PRIMARY KEY, title TEXT NOT NULL);
+        CREATE TABLE season (id INTEGER PRIMARY KEY, title TEXT NOT NULL, season INTEGER NOT NULL);
+        CREATE TABLE series (id INTEGER PRIMARY KEY, title TEXT NULL);
+        CREATE TABLE theme (id INTEGER PRIMARY KEY, type INTEGER NOT NULL, theme_target INTEGER);
+    ";
+
+    fn fresh() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../../../database/sql/init/users.sql"))
+            .unwrap();
+        conn.execute_batch(include_str!("../../../database/sql/init/data.sql"))
+            .unwrap();
+        conn
+    }
+
+    /// Tables, triggers, columns and foreign keys, in a form that can be compared
+    fn schema(conn: &Connection) -> Vec<String> {
+        let mut schema = conn
+            .prepare("SELECT type || ' ' || name FROM sqlite_master WHERE type IN ('table', 'trigger') ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let tables = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        for table in tables {
+            let columns = conn
+                .prepare("SELECT name, type, \"notnull\", ifnull(dflt_value, ''), pk FROM pragma_table_info(?1)")
+                .unwrap()
+                .query_map([&table], |row| {
+                    Ok(format!(
+                        "{table}.{} {} {} {} {}",
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, u32>(4)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            schema.extend(columns);
+
+            let references = conn
+                .prepare("SELECT \"from\", \"table\", \"to\", on_delete FROM pragma_foreign_key_list(?1)")
+                .unwrap()
+                .query_map([&table], |row| {
+                    Ok(format!(
+                        "{table}.{} -> {}.{} on delete {}",
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            schema.extend(references);
+        }
+
+        // Added columns end up last, nothing depends on the order
+        schema.sort();
+        schema
+    }
+
+    #[test]
+    fn migrating_the_first_schema_matches_a_new_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../../../database/sql/init/users.sql"))
+            .unwrap();
+        conn.execute_batch("DROP TABLE user_preferences").unwrap();
+        conn.execute_batch(FIRST_DATA_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO data_file (id, path) VALUES (1, '/movies/a.mkv');
+            INSERT INTO content (id, last_changed, hash, data_id, type, reference, part) VALUES (1, 0, x'00', 1, 1, 1, 0);
+            INSERT INTO movie (id, title) VALUES (1, 'A');",
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+
+        assert_eq!(schema(&conn), schema(&fresh()));
+        assert_eq!(
+            conn.query_row_get::<usize>("PRAGMA user_version", [])
+                .unwrap(),
+            current_version()
+        );
+        assert_eq!(
+            conn.query_row_get::<u64>("SELECT data_id FROM content WHERE id = 1", [])
+                .unwrap(),
+            1
+        );
+
+        // The migrated cleanup leaves the movie to the part that is left
+        conn.execute_batch(
+            "INSERT INTO content (id, last_changed, hash, type, reference, part) VALUES (2, 0, x'00', 1, 1, 1);
+            DELETE FROM content WHERE id = 2;",
+        )
+        .unwrap();
+        assert_eq!(
+            conn.query_row_get::<String>("SELECT title FROM movie WHERE id = 1", [])
+                .unwrap(),
+            "A"
+        );
+    }
+
+    #[test]
+    fn migrations_skip_changes_the_database_already_has() {
+        // Created after some of the changes, but before the schema was versioned
+        let conn = fresh();
+        conn.execute_batch(
+            "ALTER TABLE content DROP COLUMN resolution; DROP TABLE series_activity;",
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+
+        assert_eq!(schema(&conn), schema(&fresh()));
+    }
+}
@@ -0,0 +1,528 @@
+use std::{
+    fmt::{self, Formatter},
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use rusqlite::OpenFlags;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    state::{AppError, AppResult, Shutdown},
+    utils::ConvertErr,
+};
+
+mod migrations;
+
+const DATABASE_PATH: &str = "database/database.sqlite";
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub struct ConnectionManager {
+    path: PathBuf,
+    /// Only enforced when the existing data doesn't already violate the references
+    foreign_keys: bool,
+}
+
+impl ManageConnection for ConnectionManager {
+    type Connection = rusqlite::Connection;
+    type Error = AppError;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        let conn = rusqlite::Connection::open(&self.path)?;
+
+        // NOTE: Read the Docs before changing something about these pragmas
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", self.foreign_keys)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        Ok(conn)
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        conn.query_row("SELECT 1", [], |_r| Ok(())).convert_err()
+    }
+
+    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+// Requests and streaming sessions only hold a connection briefly, while the indexing holds one for its entire run.
+// Every new session also probes its file with ffmpeg before it gives its connection back,
+// so the pool should stay comfortably larger than the amount of sessions that are expected to start at once
+#[derive(Clone, Copy, Debug, clap::Args)]
+pub struct PoolConfig {
+    /// Maximum amount of open database connections
+    #[arg(long = "db-pool-size", env = "MRE_DB_POOL_SIZE", default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_size: u32,
+    /// Connections that are kept open while idle. Defaults to the pool size
+    #[arg(long = "db-min-idle", env = "MRE_DB_MIN_IDLE")]
+    pub min_idle: Option<u32>,
+    /// Seconds to wait for a free database connection before a request fails with 503
+    #[arg(long = "db-timeout", env = "MRE_DB_TIMEOUT", default_value_t = 5)]
+    pub acquire_timeout: u64,
+}
+
+#[derive(Clone)]
+pub struct Database(Pool<ConnectionManager>);
+pub type Connection = PooledConnection<ConnectionManager>;
+
+impl Database {
+    pub fn new(config: PoolConfig) -> AppResult<Self> {
+        Self::open(Path::new(DATABASE_PATH), config)
+    }
+
+    /// Sets up the database at the path if it is new and opens a pool of connections to it
+    fn open(path: &Path, config: PoolConfig) -> AppResult<Self> {
+        let foreign_keys = {
+            let connection = rusqlite::Connection::open(path)?;
+            Database::db_init(&connection).expect(
+                "Database initialization failed, when this happens something has gone horribly wrong",
+            );
+            Database::references_are_consistent(&connection)?
+        };
+
+        let pool = Pool::builder()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(Duration::from_secs(config.acquire_timeout))
+            .build(ConnectionManager {
+                path: path.to_owned(),
+                foreign_keys,
+            })?;
+        Ok(Self(pool))
+    }
+
+    /// A single connection that can't write anything, for looking at the data without risking to change it
+    pub fn read_only() -> AppResult<rusqlite::Connection> {
+        let conn = rusqlite::Connection::open_with_flags(
+            DATABASE_PATH,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
+
+    fn references_are_consistent(conn: &rusqlite::Connection) -> AppResult<bool> {
+        let violations = conn
+            .prepare("PRAGMA foreign_key_check")?
+            .query_map([], |_| Ok(()))?
+            .count();
+
+        if violations > 0 {
+            warn!("The database contains {violations} broken references, foreign keys are not going to be enforced");
+        }
+
+        Ok(violations == 0)
+    }
+
+    /// Regularly moves the write-ahead log back into the database, so it doesn't keep growing during long indexing runs
+    pub async fn checkpoint_task(self, shutdown: Shutdown) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(CHECKPOINT_INTERVAL) => {}
+                _ = shutdown.cancelled() => return,
+            }
+
+            let db = self.clone();
+            let checkpoint = tokio::task::spawn_blocking(move || -> AppResult<bool> {
+                let busy = db
+                    .get()?
+                    .query_row_get::<bool>("PRAGMA wal_checkpoint(TRUNCATE)", [])?;
+                Ok(busy)
+            });
+
+            match checkpoint.await {
+                Ok(Ok(true)) => {
+                    debug!("Could not fully checkpoint the database, it is still in use")
+                }
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) => warn!("Failed to checkpoint the database: {e}"),
+                Err(e) => warn!("Failed to wait for the database checkpoint: {e}"),
+            }
+        }
+    }
+
+    fn db_init(conn: &rusqlite::Connection) -> AppResult<()> {
+        let initialized =
+            conn.query_row_get::<bool>("SELECT exists(SELECT 1 FROM sqlite_master)", [])?;
+
+        if !initialized {
+            info!("Setting up database for the first time");
+
+            const USER_INIT_REQUEST: &str = include_str!("../../../database/sql/init/users.sql");
+            const DATA_INIT_REQUEST: &str = include_str!("../../../database/sql/init/data.sql");
+
+            if let Err(err) = conn.execute_batch(USER_INIT_REQUEST) {
+                error!("Failed to initialize user data into the database");
+                return Err(AppError::Database(err));
+            }
+
+            if let Err(err) = conn.execute_batch(DATA_INIT_REQUEST) {
+                error!("Failed to initialize recommendataion data into the database");
+                return Err(AppError::Database(err));
+            }
+
+            // The init scripts already describe the newest schema
+            conn.pragma_update(None, "user_version", migrations::current_version())?;
+        }
+
+        if let Err(err) = migrations::migrate(conn) {
+            error!("Failed to migrate the database to the current schema");
+            return Err(AppError::Database(err));
+        }
+
+        Ok(())
+    }
+}
+
+/// A new database with the current schema that only exists until the connection is dropped
+#[cfg(test)]
+pub fn in_memory() -> rusqlite::Connection {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.pragma_update(None, "foreign_keys", true).unwrap();
+    Database::db_init(&conn).unwrap();
+    conn
+}
+
+/// A pool for a new database at the path, for code that needs a whole `Database` instead of a single connection
+#[cfg(test)]
+pub fn in_file(path: &std::path::Path) -> Database {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    Database::db_init(&conn).unwrap();
+
+    let pool = Pool::builder()
+        .max_size(4)
+        .build(ConnectionManager {
+            path: path.to_owned(),
+            foreign_keys: true,
+        })
+        .unwrap();
+    Database(pool)
+}
+
+impl Deref for Database {
+    type Target = Pool<ConnectionManager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Database").finish()
+    }
+}
+
+type Mapfn<T> = for<'a, 'b> fn(&'a rusqlite::Row<'b>) -> Result<T, rusqlite::Error>;
+
+pub trait QueryRowIntoStmtExt<P>
+where
+    P: rusqlite::Params,
+{
+    fn query_map_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error>;
+
+    fn query_row_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error>;
+}
+
+impl<P> QueryRowIntoStmtExt<P> for rusqlite::Statement<'_>
+where
+    P: rusqlite::Params,
+{
+    // Executes the prepared statement and tries to convert each row into the provided type
+    fn query_map_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error> {
+        fn map_row<T>(row: &rusqlite::Row<'_>) -> Result<T, rusqlite::Error>
+        where
+            T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>,
+        {
+            row.try_into()
+        }
+
+        self.query_map(params, map_row)
+    }
+
+    fn query_row_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(params, |row| row.try_into())
+    }
+}
+
+impl<P> QueryRowIntoStmtExt<P> for rusqlite::CachedStatement<'_>
+where
+    P: rusqlite::Params,
+{
+    fn query_map_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error> {
+        fn map_row<T>(row: &rusqlite::Row<'_>) -> Result<T, rusqlite::Error>
+        where
+            T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>,
+        {
+            row.try_into()
+        }
+
+        self.query_map(params, map_row)
+    }
+
+    fn query_row_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(params, |row| row.try_into())
+    }
+}
+
+pub trait QueryRowIntoConnExt<P>
+where
+    P: rusqlite::Params,
+{
+    fn query_row_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<T, rusqlite::Error>;
+}
+
+impl<P> QueryRowIntoConnExt<P> for rusqlite::Connection
+where
+    P: rusqlite::Params,
+{
+    /// Executes the provided sql and tries to convert the first row into the provided type
+    fn query_row_into<T: for<'a> TryFrom<&'a rusqlite::Row<'a>, Error = rusqlite::Error>>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(sql, params, |row| row.try_into())
+    }
+}
+
+pub trait QueryRowGetStmtExt<P>
+where
+    P: rusqlite::Params,
+{
+    fn query_row_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error>;
+    fn query_map_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error>;
+}
+
+impl<P> QueryRowGetStmtExt<P> for rusqlite::Statement<'_>
+where
+    P: rusqlite::Params,
+{
+    /// Executes the prepared statement and gets the first column of the first row
+    fn query_row_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(params, |row| row.get(0))
+    }
+
+    /// Executes the prepared statement and gets the first column of each row
+    fn query_map_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error> {
+        fn map_row<T>(row: &rusqlite::Row<'_>) -> Result<T, rusqlite::Error>
+        where
+            T: rusqlite::types::FromSql,
+        {
+            row.get(0)
+        }
+
+        self.query_map(params, map_row)
+    }
+}
+
+impl<P> QueryRowGetStmtExt<P> for rusqlite::CachedStatement<'_>
+where
+    P: rusqlite::Params,
+{
+    fn query_row_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(params, |row| row.get(0))
+    }
+
+    fn query_map_get<T: rusqlite::types::FromSql>(
+        &mut self,
+        params: P,
+    ) -> Result<rusqlite::MappedRows<'_, Mapfn<T>>, rusqlite::Error> {
+        fn map_row<T>(row: &rusqlite::Row<'_>) -> Result<T, rusqlite::Error>
+        where
+            T: rusqlite::types::FromSql,
+        {
+            row.get(0)
+        }
+
+        self.query_map(params, map_row)
+    }
+}
+
+pub trait QueryRowGetConnExt<P>
+where
+    P: rusqlite::Params,
+{
+    fn query_row_get<T: rusqlite::types::FromSql>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<T, rusqlite::Error>;
+}
+
+impl<P> QueryRowGetConnExt<P> for rusqlite::Connection
+where
+    P: rusqlite::Params,
+{
+    /// Executes the provided sql and gets the first column of the first row
+    fn query_row_get<T: rusqlite::types::FromSql>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<T, rusqlite::Error> {
+        self.query_row(sql, params, |row| row.get(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    use super::*;
+    use crate::utils::TempDir;
+
+    /// A pool that only gives out one connection and gives up on waiting for it after a second
+    fn single_connection() -> PoolConfig {
+        PoolConfig {
+            max_size: 1,
+            min_idle: None,
+            acquire_timeout: 1,
+        }
+    }
+
+    #[test]
+    fn an_exhausted_pool_fails_with_service_unavailable() {
+        let dir = TempDir::new("pool-exhausted");
+        let db = Database::open(&dir.path().join("database.sqlite"), single_connection()).unwrap();
+        let _held = db.get().unwrap();
+
+        let waiting = Instant::now();
+        let err = AppError::from(db.get().unwrap_err());
+        assert!(waiting.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn connections_use_the_configured_pragmas() {
+        let dir = TempDir::new("pragmas");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let conn = db.get().unwrap();
+
+        let journal_mode: String = conn.query_row_get("PRAGMA journal_mode", []).unwrap();
+        assert_eq!(journal_mode, "wal");
+        // 1 is NORMAL
+        let synchronous: u64 = conn.query_row_get("PRAGMA synchronous", []).unwrap();
+        assert_eq!(synchronous, 1);
+        let busy_timeout: u64 = conn.query_row_get("PRAGMA busy_timeout", []).unwrap();
+        assert_eq!(Duration::from_millis(busy_timeout), BUSY_TIMEOUT);
+        let foreign_keys: bool = conn.query_row_get("PRAGMA foreign_keys", []).unwrap();
+        assert!(foreign_keys);
+    }
+
+    #[test]
+    fn broken_references_leave_foreign_keys_off() {
+        let dir = TempDir::new("broken-references");
+        let path = dir.path().join("database.sqlite");
+
+        let db = Database::open(&path, single_connection()).unwrap();
+        let enforced = |db: &Database| -> bool {
+            db.get()
+                .unwrap()
+                .query_row_get("PRAGMA foreign_keys", [])
+                .unwrap()
+        };
+        assert!(enforced(&db));
+        drop(db);
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        conn.execute(
+            "INSERT INTO content (last_changed, hash, data_id, type, part)
+                VALUES (0, x'00', 999, 0, 0)",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Database::open(&path, single_connection()).unwrap();
+        assert!(!enforced(&db));
+    }
+
+    /// The values of a single column query
+    fn column(conn: &rusqlite::Connection, sql: &str) -> Vec<u64> {
+        conn.prepare(sql)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn deleting_content_removes_only_what_belongs_to_it() {
+        let conn = in_memory();
+        // Two movies in one franchise, the franchise and both movies have a theme and metadata
+        conn.execute_batch(
+            "INSERT INTO movie (id, title) VALUES (1, 'Alien'), (2, 'Aliens');
+            INSERT INTO content (id, last_changed, hash, type, reference, part)
+                VALUES (1, 0, x'01', 1, 1, 0), (2, 0, x'02', 1, 2, 0);
+            INSERT INTO franchise (id, title) VALUES (1, 'Alien');
+            INSERT INTO collection (id, type, reference) VALUES (1, 1, 1);
+            INSERT INTO collection_contains (collection_id, type, reference)
+                VALUES (1, 1, 1), (1, 1, 2);
+            INSERT INTO theme (id, type, theme_target) VALUES (1, 1, 1), (2, 1, 2), (3, 0, 1);
+            INSERT INTO metadata (id, type, reference) VALUES (1, 1, 1), (2, 1, 2), (3, 0, 1);",
+        )
+        .unwrap();
+
+        conn.execute("DELETE FROM content WHERE id = 1", [])
+            .unwrap();
+
+        assert_eq!(
+            column(&conn, "SELECT reference FROM collection_contains"),
+            [2]
+        );
+        assert_eq!(column(&conn, "SELECT id FROM theme ORDER BY id"), [2, 3]);
+        assert_eq!(column(&conn, "SELECT id FROM metadata ORDER BY id"), [2, 3]);
+        assert_eq!(column(&conn, "SELECT id FROM movie"), [2]);
+        assert_eq!(column(&conn, "SELECT id FROM collection"), [1]);
+    }
+}
@@ -1,59 +1,94 @@
-use std::{collections::HashSet, ffi::OsStr, path::Path};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use rusqlite::{params, OptionalExtension};
 use tracing::warn;
 
 use crate::{
-    database::{
-        Connection, QueryRowGetConnExt, QueryRowGetStmtExt, QueryRowIntoConnExt,
-        QueryRowIntoStmtExt,
-    },
+    database::{QueryRowGetConnExt, QueryRowGetStmtExt, QueryRowIntoConnExt, QueryRowIntoStmtExt},
     state::AppResult,
     utils::{Ignore, ParseBetween, ParseUntil},
 };
 
 use super::{
-    db::{CollectionType, ContentType, TableId},
+    db::{CollectionType, Confidence, ContentType, TableId},
     file_handling::{AsDBString, FileType, PathExt},
+    nfo::Nfo,
 };
 
 pub struct Classification {
     pub title: String,
+    /// The release year from names like "Movie (2020)", helps telling remakes apart
+    pub year: Option<u32>,
+    /// Counted from 0, names count from 1, so "CD1" and "- s1e2p1" are both the part that is played
     pub part: u64,
     pub category: ClassificationCategory,
     pub collectionhint: CollectionHint,
+    pub quality: Quality,
+    pub nfo: Option<Nfo>,
+    /// Set for movies split into directories like "CD1" and "CD2", all parts in it belong to the same movie
+    pub parts_directory: Option<PathBuf>,
+    /// For extras, the directory their extras folder is in, which is also where a movie they belong to is
+    pub extras_owner: Option<PathBuf>,
+    /// How sure the collection hint is
+    pub confidence: Confidence,
 }
 
 impl Classification {
     fn empty() -> Self {
         Classification {
             title: String::new(),
+            year: None,
             part: 0,
             category: ClassificationCategory::Other,
             collectionhint: CollectionHint::None,
+            quality: Quality::default(),
+            nfo: None,
+            parts_directory: None,
+            extras_owner: None,
+            confidence: Confidence::Ambiguous,
         }
     }
 
     fn new(
         title: String,
         category: ClassificationCategory,
-        collectionhint: CollectionHint,
+        (collectionhint, confidence): (CollectionHint, Confidence),
     ) -> Self {
         Classification {
             title,
+            year: None,
             part: 0,
             category,
             collectionhint,
+            quality: Quality::default(),
+            nfo: None,
+            parts_directory: None,
+            extras_owner: None,
+            confidence,
         }
     }
 }
 
+/// Resolution and source as found in release names like "Movie (2020) 1080p BluRay"
+#[derive(Default)]
+pub struct Quality {
+    /// The vertical resolution, 1080 for "1080p"
+    pub resolution: Option<u64>,
+    pub source: Option<&'static str>,
+}
+
 pub enum ClassificationCategory {
     Other,
     Movie,
     Episode { episode: u64 },
     Song,
+    Extra,
 }
 
 pub enum CollectionHint {
@@ -85,6 +120,25 @@ impl CollectionHint {
             series,
         })
     }
+
+    /// Whether both point to the same place, only the innermost collection is compared
+    fn agrees_with(&self, other: &CollectionHint) -> bool {
+        match (self, other) {
+            (CollectionHint::Movie(a), CollectionHint::Movie(b)) => {
+                a.title.eq_ignore_ascii_case(&b.title)
+            }
+            (CollectionHint::Franchise(a), CollectionHint::Franchise(b)) => {
+                a.title.eq_ignore_ascii_case(&b.title)
+            }
+            (CollectionHint::Series(a), CollectionHint::Series(b)) => {
+                a.title.eq_ignore_ascii_case(&b.title)
+            }
+            (CollectionHint::Season(a), CollectionHint::Season(b)) => {
+                a.season == b.season && a.title.eq_ignore_ascii_case(&b.title)
+            }
+            _ => false,
+        }
+    }
 }
 
 pub struct Franchise {
@@ -108,17 +162,102 @@ pub struct Season {
 }
 
 impl Classification {
+    /// A curated .nfo file knows better than the file name, so its title replaces the inferred one for movies
+    /// Episode titles are left alone, they are what identifies the episode inside of its season
+    pub fn apply_nfo(&mut self, nfo: Nfo) {
+        if let (ClassificationCategory::Movie, Some(title)) = (&self.category, &nfo.title) {
+            self.title.clone_from(title);
+        }
+        self.nfo = Some(nfo);
+    }
+
     pub fn content_type(&self) -> ContentType {
         match self.category {
             ClassificationCategory::Other { .. } => ContentType::Other,
             ClassificationCategory::Movie { .. } => ContentType::Movie,
             ClassificationCategory::Episode { .. } => ContentType::Episode,
             ClassificationCategory::Song { .. } => ContentType::Song,
+            ClassificationCategory::Extra => ContentType::Extra,
+        }
+    }
+}
+
+/// Something like "Episode 3 \"Pilot\" in Series \"Show\" > Season 1 (Guessed)", for checking what indexing would do
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.category {
+            ClassificationCategory::Other => write!(f, "Other")?,
+            ClassificationCategory::Movie => write!(f, "Movie")?,
+            ClassificationCategory::Episode { episode } => write!(f, "Episode {episode}")?,
+            ClassificationCategory::Song => write!(f, "Song")?,
+            ClassificationCategory::Extra => write!(f, "Extra")?,
+        }
+        write!(f, " {:?}", self.title)?;
+        if self.part != 0 {
+            write!(f, " part {}", self.part + 1)?;
+        }
+        write!(
+            f,
+            " in {} ({})",
+            self.collectionhint,
+            self.confidence.as_str()
+        )
+    }
+}
+
+impl fmt::Display for CollectionHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionHint::None => write!(f, "no collection"),
+            CollectionHint::Movie(movie) => write!(f, "{movie}"),
+            CollectionHint::Franchise(franchise) => write!(f, "{franchise}"),
+            CollectionHint::Series(series) => write!(f, "{series}"),
+            CollectionHint::Season(season) => write!(f, "{season}"),
+            CollectionHint::ThemeTarget { inner } => write!(f, "the theme of {inner}"),
+        }
+    }
+}
+
+impl fmt::Display for Franchise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Franchise {:?}", self.title)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(franchise) = &self.franchise {
+            write!(f, "{franchise} > ")?;
+        }
+        write!(f, "Movie {:?}", self.title)
+    }
+}
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(franchise) = &self.franchise {
+            write!(f, "{franchise} > ")?;
+        }
+        write!(f, "Series {:?}", self.title)
+    }
+}
+
+impl fmt::Display for Season {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.series {
+            Some(series) => write!(f, "{series} > Season {}", self.season),
+            None => write!(f, "Season {} of {:?}", self.season, self.title),
         }
     }
 }
 
-pub fn classify(path: &Path, db: &Connection) -> AppResult<Classification> {
+/// The location is the storage location the file was found in, only directories below it are taken into account
+pub fn classify(
+    path: &Path,
+    location: &Path,
+    db: &rusqlite::Connection,
+    extras_folders: &[String],
+) -> AppResult<Classification> {
     let Some(file_type) = path.file_type() else {
         warn!("Faulty file path: \"{path:?}\"");
         let mut classification = Classification::empty();
@@ -130,36 +269,59 @@ pub fn classify(path: &Path, db: &Connection) -> AppResult<Classification> {
     };
 
     match file_type {
-        FileType::Video => classify_video(path, db),
+        FileType::Video => match extras_folder(path, location, extras_folders) {
+            Some(folder) => classify_extra(path, folder, db),
+            None => classify_video(path, db),
+        },
         FileType::Audio => classify_audio(path, db),
         FileType::Unknown => Ok(classify_unknown(path, db)),
     }
 }
 
-fn classify_audio(path: &Path, db: &Connection) -> AppResult<Classification> {
+fn classify_audio(path: &Path, db: &rusqlite::Connection) -> AppResult<Classification> {
     let file_name = path.file_stem().unwrap_or_default().as_db_string();
 
-    let collection = if file_name.contains("theme") {
-        let hint = infer_collection(path, db)?;
+    let (hint, confidence) = infer_collection(path, db)?;
+    let hint = if file_name.contains("theme") {
         CollectionHint::ThemeTarget {
             inner: Box::new(hint),
         }
     } else {
-        infer_collection(path, db)?
+        hint
     };
 
     let (title, _year) = strip_year(&file_name);
     Ok(Classification::new(
         title.to_owned(),
         ClassificationCategory::Song,
-        collection,
+        (hint, confidence),
     ))
 }
 
-fn classify_video(path: &Path, db: &Connection) -> AppResult<Classification> {
+fn classify_video(path: &Path, db: &rusqlite::Connection) -> AppResult<Classification> {
+    // Box sets put episodes into "Season 1/Disc 1/...", those are episodes and not parts of a movie
+    let part = part_directory(path).filter(|_| !is_episode_name(path));
+    if let Some((part, movie_directory)) = part {
+        // File names inside of part directories are often meaningless, "Movie/CD1/video.mkv" is classified like "Movie/Movie.mkv"
+        let mut stand_in = movie_directory.join(movie_directory.file_name().unwrap_or_default());
+        if let Some(extension) = path.extension() {
+            stand_in.as_mut_os_string().push(".");
+            stand_in.as_mut_os_string().push(extension);
+        }
+
+        let mut classification = classify_video(&stand_in, db)?;
+        if matches!(classification.category, ClassificationCategory::Movie) {
+            classification.part = part;
+            classification.parts_directory = Some(movie_directory.to_owned());
+        }
+        return Ok(classification);
+    }
+
     let title = path.file_stem().unwrap_or_default().as_db_string();
+    // This has to happen first, tags like "WEB-DL" would otherwise be mistaken for episode info
+    let (title, quality) = strip_quality(&title);
     let (title, info) = strip_info(&title);
-    let (title, _year) = strip_year(title);
+    let (title, year) = strip_year(title);
 
     let mut c_part = 0;
     let mut c_season = None;
@@ -170,8 +332,9 @@ fn classify_video(path: &Path, db: &Connection) -> AppResult<Classification> {
             episode: Some(episode),
             part,
         } => {
+            // Same as part directories, "p1" is the first part
             if let Some(part) = part {
-                c_part = part;
+                c_part = part.saturating_sub(1);
             }
             c_season = season;
             ClassificationCategory::Episode { episode }
@@ -179,7 +342,20 @@ fn classify_video(path: &Path, db: &Connection) -> AppResult<Classification> {
         _ => ClassificationCategory::Movie,
     };
 
-    let mut hint = infer_collection(path, db)?;
+    let (mut hint, mut confidence) = infer_collection(path, db)?;
+    if matches!(category, ClassificationCategory::Episode { .. })
+        && !matches!(hint, CollectionHint::Season(_))
+    {
+        if let Some(implicit) = implicit_season(path, c_season.unwrap_or(1)) {
+            hint = implicit;
+            confidence = confidence.max(Confidence::Guessed);
+        }
+    }
+    // Movies on their own, like in a flat library, don't belong to anything, so there is nothing to be unsure about
+    if matches!(category, ClassificationCategory::Movie) && matches!(hint, CollectionHint::None) {
+        confidence = Confidence::Certain;
+    }
+
     if let CollectionHint::Season(Season {
         ref mut season,
         title: _,
@@ -192,34 +368,167 @@ fn classify_video(path: &Path, db: &Connection) -> AppResult<Classification> {
     }
     Ok(Classification {
         title: title.to_owned(),
+        year,
         part: c_part,
         category,
         collectionhint: hint,
+        quality,
+        nfo: None,
+        parts_directory: None,
+        extras_owner: None,
+        confidence,
+    })
+}
+
+/// Recognizes directories like "CD1", "Disc 2" or "Part 1", returns the part and the directory of the whole movie
+/// Parts are counted from 0, so the first one is what gets played like any other movie
+pub fn part_directory(path: &Path) -> Option<(u64, &Path)> {
+    let directory = path.parent()?;
+    let part = disc_number(&directory.file_name()?.as_db_string())?.checked_sub(1)?;
+    Some((part, directory.parent()?))
+}
+
+/// The number in directory names like "CD1", "Disc 2" or "Part 1"
+fn disc_number(name: &str) -> Option<u64> {
+    let name = name.to_lowercase();
+    let number = ["cd", "disc", "disk", "part"]
+        .into_iter()
+        .find_map(|prefix| name.strip_prefix(prefix))?
+        .trim();
+
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    number.parse().ok()
+}
+
+/// Whether the file name alone says that this is an episode, like "Show S01E02.mkv"
+fn is_episode_name(path: &Path) -> bool {
+    let title = path.file_stem().unwrap_or_default().as_db_string();
+    let (title, _) = strip_quality(&title);
+    let (_, info) = strip_info(&title);
+    info.episode.is_some()
+}
+
+/// The closest directory between the file and its storage location that is named like one of the extras folders
+fn extras_folder<'a>(
+    path: &'a Path,
+    location: &Path,
+    extras_folders: &[String],
+) -> Option<&'a Path> {
+    // A location like "/mnt/Extras/Movies" doesn't make everything in it an extra
+    let mut below_location = path
+        .ancestors()
+        .skip(1)
+        .take_while(|directory| directory.starts_with(location) && *directory != location);
+    below_location.find(|directory| {
+        directory.file_name().is_some_and(|name| {
+            let name = name.as_db_string();
+            extras_folders
+                .iter()
+                .any(|folder| folder.trim().eq_ignore_ascii_case(name.trim()))
+        })
     })
 }
 
+/// Extras belong to whatever the directory containing the extras folder belongs to
+fn classify_extra(
+    path: &Path,
+    folder: &Path,
+    db: &rusqlite::Connection,
+) -> AppResult<Classification> {
+    let title = path.file_stem().unwrap_or_default().as_db_string();
+    let (title, quality) = strip_quality(&title);
+    let (title, _year) = strip_year(&title);
+
+    // "Movie/Extras/Featurette.mkv" is inferred like "Movie/Movie", which is where the movie itself would be
+    let owner = folder
+        .parent()
+        .and_then(|parent| Some(parent.join(parent.file_name()?)));
+    let hint = match owner {
+        Some(owner) => infer_collection(&owner, db)?,
+        None => (CollectionHint::None, Confidence::Ambiguous),
+    };
+
+    let mut classification =
+        Classification::new(title.to_owned(), ClassificationCategory::Extra, hint);
+    classification.quality = quality;
+    classification.extras_owner = folder.parent().map(Path::to_path_buf);
+    Ok(classification)
+}
+
+/// Episodes that are not inside of a season directory get a season named after their number,
+/// the directory they are in is treated like the series directory of a regular season
+fn implicit_season(path: &Path, season: u64) -> Option<CollectionHint> {
+    let mut directories = path
+        .ancestors()
+        .skip(1)
+        .filter_map(Path::file_name)
+        .map(OsStr::as_db_string)
+        .take_while(|s| !s.contains("!noclassify"))
+        .filter(|s| disc_number(s).is_none());
+
+    let series = directories.next()?.to_string();
+    let franchise = directories
+        .next()
+        .map_or_else(|| series.clone(), |franchise| franchise.to_string());
+
+    Some(CollectionHint::season(
+        format!("Season {season}"),
+        season,
+        Some(Series {
+            title: series,
+            franchise: Some(Franchise { title: franchise }),
+        }),
+    ))
+}
+
 fn classify_unknown(path: &Path, _db: &rusqlite::Connection) -> Classification {
     warn!("Could not handle \"{path:?}\"");
     Classification::empty()
 }
 
-fn infer_collection(path: &Path, db: &Connection) -> AppResult<CollectionHint> {
+/// The hint is only certain when the database and the path don't contradict each other
+fn infer_collection(
+    path: &Path,
+    db: &rusqlite::Connection,
+) -> AppResult<(CollectionHint, Confidence)> {
     let database_inferred = infer_collection_from_database(db, path)?;
     let path_inferred = infer_collection_from_path(path)?;
 
-    match (database_inferred, path_inferred) {
-        (CollectionHint::None, path_inferred) => Ok(path_inferred),
-        (database_inferred, CollectionHint::None) => Ok(database_inferred),
+    let confidence = match (&database_inferred, &path_inferred) {
+        (CollectionHint::None, CollectionHint::None) => Confidence::Ambiguous,
+        (CollectionHint::None, _) | (_, CollectionHint::None) => Confidence::Certain,
+        (database_inferred, path_inferred)
+            if std::mem::discriminant(database_inferred)
+                == std::mem::discriminant(path_inferred) =>
+        {
+            if database_inferred.agrees_with(path_inferred) {
+                Confidence::Certain
+            } else {
+                warn!("The database and the path disagree about where {path:?} belongs");
+                Confidence::Ambiguous
+            }
+        }
+        // Being next to a movie is only assumed to mean belonging to it
+        (CollectionHint::Movie(_), _) | (_, CollectionHint::Movie(_)) => Confidence::Ambiguous,
+        // One of them is just more granular than the other
+        _ => Confidence::Certain,
+    };
+
+    let hint = match (database_inferred, path_inferred) {
+        (CollectionHint::None, path_inferred) => path_inferred,
+        (database_inferred, CollectionHint::None) => database_inferred,
         (CollectionHint::Movie(_), hint @ CollectionHint::Movie(_))
         | (CollectionHint::Franchise(_), hint @ CollectionHint::Franchise(_))
         | (CollectionHint::Series(_), hint @ CollectionHint::Series(_))
         | (CollectionHint::Season(_), hint @ CollectionHint::Season(_)) => {
-            // The path is just assumed to be the ground truth for now
-            // There might need to be more logic here
-            Ok(hint)
+            // The path is just assumed to be the ground truth for now, disagreements are flagged above
+            hint
         }
         // If only a movie as is found, that is probably movie adjacent content (theme or similar)
-        (hint @ CollectionHint::Movie(_), _) | (_, hint @ CollectionHint::Movie(_)) => Ok(hint),
+        (hint @ CollectionHint::Movie(_), _) | (_, hint @ CollectionHint::Movie(_)) => hint,
         // Season is more granular than series and series is more granular than franchise
         (
             hint @ CollectionHint::Season(_),
@@ -230,14 +539,19 @@ fn infer_collection(path: &Path, db: &Connection) -> AppResult<CollectionHint> {
             hint @ CollectionHint::Season(_),
         )
         | (CollectionHint::Franchise(_), hint @ CollectionHint::Series(_))
-        | (hint @ CollectionHint::Series(_), CollectionHint::Franchise(_)) => Ok(hint),
+        | (hint @ CollectionHint::Series(_), CollectionHint::Franchise(_)) => hint,
         (CollectionHint::ThemeTarget { .. }, _) | (_, CollectionHint::ThemeTarget { .. }) => {
             unreachable!("This should be excluded by the database query")
         }
-    }
+    };
+
+    Ok((hint, confidence))
 }
 
-fn infer_collection_from_database(db: &Connection, path: &Path) -> AppResult<CollectionHint> {
+fn infer_collection_from_database(
+    db: &rusqlite::Connection,
+    path: &Path,
+) -> AppResult<CollectionHint> {
     let mut all_is_movie = db.prepare_cached(
         "SELECT DISTINCT content.id FROM content, data_file
         WHERE content.data_id = data_file.id
@@ -367,15 +681,18 @@ fn infer_collection_from_database(db: &Connection, path: &Path) -> AppResult<Col
 
 fn infer_collection_from_path(path: &Path) -> AppResult<CollectionHint> {
     let preserved_title = path.file_stem().unwrap_or_default().as_db_string();
-    let (title, _) = strip_info(&preserved_title);
+    let (title, _) = strip_quality(&preserved_title);
+    let (title, _) = strip_info(&title);
     let (original_title, _) = strip_year(title);
 
+    // Disc directories of box sets like "Season 1/Disc 1/..." say nothing about where the file belongs
     let mut directories = path
         .ancestors()
         .skip(1)
         .filter_map(Path::file_name)
         .map(OsStr::as_db_string)
-        .take_while(|s| !s.contains("!noclassify"));
+        .take_while(|s| !s.contains("!noclassify"))
+        .filter(|s| disc_number(s).is_none());
 
     /*
     The current format is very strict:
@@ -459,7 +776,8 @@ fn infer_collection_from_path(path: &Path) -> AppResult<CollectionHint> {
     Ok(hint)
 }
 
-fn strip_year(title: &str) -> (&str, Option<u32>) {
+/// Splits "Title (2020)" into the title and the year
+pub fn strip_year(title: &str) -> (&str, Option<u32>) {
     let Some((left, right)) = title.rsplit_once('(') else {
         return (title, None);
     };
@@ -471,6 +789,71 @@ fn strip_year(title: &str) -> (&str, Option<u32>) {
     (title, None)
 }
 
+/// Removes quality tags from the title wherever they are, "Movie 1080p (2020) BluRay" becomes "Movie (2020)"
+fn strip_quality(title: &str) -> (String, Quality) {
+    let is_separator = |c: char| c.is_whitespace() || c == '.';
+
+    let mut quality = Quality::default();
+    let mut stripped = String::with_capacity(title.len());
+
+    // Every word is kept together with the separator in front of it, so removing one doesn't leave two behind
+    let ends = title
+        .match_indices(is_separator)
+        .map(|(index, _)| index)
+        .chain([title.len()]);
+    let mut start = 0;
+    for end in ends {
+        let segment = &title[start..end];
+        start = end;
+
+        let tag = segment
+            .trim_start_matches(is_separator)
+            .trim_matches(|c: char| matches!(c, '[' | ']' | '(' | ')'))
+            .to_lowercase();
+
+        if let Some(resolution) = parse_resolution(&tag) {
+            quality.resolution.get_or_insert(resolution);
+        } else if let Some(source) = parse_source(&tag) {
+            quality.source.get_or_insert(source);
+        } else {
+            stripped.push_str(segment);
+        }
+    }
+
+    (stripped.trim().to_owned(), quality)
+}
+
+fn parse_resolution(tag: &str) -> Option<u64> {
+    match tag {
+        "4k" | "uhd" => return Some(2160),
+        "8k" => return Some(4320),
+        _ => {}
+    }
+
+    let resolution = tag
+        .strip_suffix('p')
+        .or_else(|| tag.strip_suffix('i'))?
+        .parse()
+        .ok()?;
+
+    [360, 480, 576, 720, 1080, 1440, 2160, 4320]
+        .contains(&resolution)
+        .then_some(resolution)
+}
+
+fn parse_source(tag: &str) -> Option<&'static str> {
+    let source = match tag {
+        "bluray" | "blu-ray" | "bdrip" | "brrip" => "BluRay",
+        "remux" => "Remux",
+        "web-dl" | "webdl" => "WEB-DL",
+        "webrip" => "WEBRip",
+        "hdtv" => "HDTV",
+        "dvd" | "dvdrip" => "DVD",
+        _ => return None,
+    };
+    Some(source)
+}
+
 struct Info {
     season: Option<u64>,
     episode: Option<u64>,
@@ -509,7 +892,10 @@ fn strip_info(title: &str) -> (&str, Info) {
 }
 
 // Get the series data for a collection that contains that season
-fn get_series_with_collection(db: &Connection, collection_id: u64) -> AppResult<Option<Series>> {
+fn get_series_with_collection(
+    db: &rusqlite::Connection,
+    collection_id: u64,
+) -> AppResult<Option<Series>> {
     let mut get_info = db.prepare_cached(
         "
     SELECT collection.id, series.title
@@ -538,7 +924,7 @@ fn get_series_with_collection(db: &Connection, collection_id: u64) -> AppResult<
 
 // Get the franchise data for the franchise that contains that collection
 fn get_franchise_with_collection(
-    db: &Connection,
+    db: &rusqlite::Connection,
     collection_id: u64,
 ) -> AppResult<Option<Franchise>> {
     let mut get_info = db.prepare_cached(
@@ -561,3 +947,185 @@ fn get_franchise_with_collection(
 
     Ok(info.map(|title| Franchise { title }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::in_memory,
+        indexing::{index_fixture, FIXTURE_ROOT},
+    };
+
+    fn quality(title: &str) -> (String, Option<u64>, Option<&'static str>) {
+        let (title, quality) = strip_quality(title);
+        (title, quality.resolution, quality.source)
+    }
+
+    #[test]
+    fn quality_tags_are_stripped_from_release_names() {
+        assert_eq!(
+            quality("Movie (2020) 1080p BluRay"),
+            ("Movie (2020)".to_owned(), Some(1080), Some("BluRay"))
+        );
+        assert_eq!(
+            quality("Movie.2020.2160p.WEB-DL.x265"),
+            ("Movie.2020.x265".to_owned(), Some(2160), Some("WEB-DL"))
+        );
+        assert_eq!(
+            quality("Movie [720p] [WEBRip]"),
+            ("Movie".to_owned(), Some(720), Some("WEBRip"))
+        );
+        assert_eq!(
+            quality("Movie 4K Remux"),
+            ("Movie".to_owned(), Some(2160), Some("Remux"))
+        );
+    }
+
+    #[test]
+    fn quality_tags_are_stripped_anywhere_in_the_title() {
+        assert_eq!(
+            quality("Movie 1080p (2020)"),
+            ("Movie (2020)".to_owned(), Some(1080), None)
+        );
+        assert_eq!(
+            quality("Show HDTV 720p - s1e2"),
+            ("Show - s1e2".to_owned(), Some(720), Some("HDTV"))
+        );
+        assert_eq!(
+            quality("1080p Movie"),
+            ("Movie".to_owned(), Some(1080), None)
+        );
+    }
+
+    #[test]
+    fn titles_without_quality_tags_are_left_alone() {
+        assert_eq!(quality("S.W.A.T."), ("S.W.A.T.".to_owned(), None, None));
+        // Numbers that aren't a known resolution are part of the title
+        assert_eq!(quality("Apollo 13"), ("Apollo 13".to_owned(), None, None));
+        assert_eq!(quality("Room 237p"), ("Room 237p".to_owned(), None, None));
+    }
+
+    #[test]
+    fn the_first_tag_of_a_kind_wins() {
+        assert_eq!(
+            quality("Movie 1080p 720p DVD BluRay"),
+            ("Movie".to_owned(), Some(1080), Some("DVD"))
+        );
+    }
+
+    fn extras(path: &str, location: &str) -> Option<PathBuf> {
+        let folders = ["Extras".to_owned(), "Behind the Scenes".to_owned()];
+        extras_folder(Path::new(path), Path::new(location), &folders).map(Path::to_path_buf)
+    }
+
+    #[test]
+    fn extras_folders_are_found_below_the_location() {
+        assert_eq!(
+            extras("/media/Heat/Extras/Making of.mp4", "/media"),
+            Some(PathBuf::from("/media/Heat/Extras"))
+        );
+        assert_eq!(
+            extras(
+                "/media/Heat/behind the scenes/Shootout/Take 2.mp4",
+                "/media"
+            ),
+            Some(PathBuf::from("/media/Heat/behind the scenes"))
+        );
+        assert_eq!(extras("/media/Heat/Heat.mp4", "/media"), None);
+    }
+
+    #[test]
+    fn directories_above_the_location_are_not_extras_folders() {
+        assert_eq!(
+            extras("/mnt/Extras/Movies/Heat/Heat.mp4", "/mnt/Extras/Movies"),
+            None
+        );
+        // Not even the location itself
+        assert_eq!(extras("/mnt/Extras/Heat/Heat.mp4", "/mnt/Extras"), None);
+        assert_eq!(
+            extras("/mnt/Extras/Heat/Extras/Making of.mp4", "/mnt/Extras"),
+            Some(PathBuf::from("/mnt/Extras/Heat/Extras"))
+        );
+    }
+
+    #[test]
+    fn disc_numbers_are_read_from_directory_names() {
+        assert_eq!(disc_number("CD1"), Some(1));
+        assert_eq!(disc_number("Disc 2"), Some(2));
+        assert_eq!(disc_number("disk10"), Some(10));
+        assert_eq!(disc_number("Part 3"), Some(3));
+
+        assert_eq!(disc_number("CD"), None);
+        assert_eq!(disc_number("CD1a"), None);
+        assert_eq!(disc_number("Partners"), None);
+        assert_eq!(disc_number("Season 1"), None);
+    }
+
+    #[test]
+    fn parts_are_counted_the_same_for_directories_and_names() {
+        let conn = crate::database::in_memory();
+        let part = |path: &str| {
+            let path = Path::new(path);
+            classify(path, Path::new("/media"), &conn, &[])
+                .unwrap()
+                .part
+        };
+
+        assert_eq!(part("/media/Kill Bill/CD1/Kill Bill.mp4"), 0);
+        assert_eq!(part("/media/Kill Bill/CD2/Kill Bill.mp4"), 1);
+        assert_eq!(part("/media/Lost/Season 1/Pilot - s1e1p1.mp4"), 0);
+        assert_eq!(part("/media/Lost/Season 1/Pilot - s1e1p2.mp4"), 1);
+        assert_eq!(part("/media/Lost/Season 1/Tabula Rasa - s1e3.mp4"), 0);
+    }
+
+    fn confidence(conn: &rusqlite::Connection, path: &str) -> &'static str {
+        let location = Path::new(FIXTURE_ROOT);
+        classify(&location.join(path), location, conn, &[])
+            .unwrap()
+            .confidence
+            .as_str()
+    }
+
+    #[test]
+    fn clearly_named_files_are_certain() {
+        let conn = in_memory();
+        assert_eq!(
+            confidence(&conn, "Lost/Season 1/Pilot - s1e1.mp4"),
+            "Certain"
+        );
+        assert_eq!(confidence(&conn, "Heat/Heat (1995).mp4"), "Certain");
+        assert_eq!(confidence(&conn, "Heat (1995).mp4"), "Certain");
+    }
+
+    #[test]
+    fn paths_contradicting_the_library_are_ambiguous() {
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &["Lost/Season 1/Pilot - s1e1.mp4"]);
+
+        assert_eq!(
+            confidence(&conn, "Lost/Season 1/Tabula Rasa - s1e2.mp4"),
+            "Certain"
+        );
+        assert_eq!(
+            confidence(&conn, "Lost/Season 1 - Castaways/Tabula Rasa - s1e2.mp4"),
+            "Ambiguous"
+        );
+    }
+
+    #[test]
+    fn part_directories_belong_to_the_movie_above_them() {
+        assert_eq!(
+            part_directory(Path::new("/media/Kill Bill/Disc 2/Kill Bill.mp4")),
+            Some((1, Path::new("/media/Kill Bill")))
+        );
+        assert_eq!(
+            part_directory(Path::new("/media/Kill Bill/Kill Bill.mp4")),
+            None
+        );
+        // "CD0" can't be a part when the first one is "CD1"
+        assert_eq!(
+            part_directory(Path::new("/media/Kill Bill/CD0/Kill Bill.mp4")),
+            None
+        );
+    }
+}
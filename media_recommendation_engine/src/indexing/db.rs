@@ -1,12 +1,14 @@
 use rusqlite::{types::FromSql, ToSql};
+use serde::Serialize;
 
 /// The things content can be, other means it has to be manually assigned or doesn't exist yet
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum ContentType {
     Other,
     Movie,
     Episode,
     Song,
+    Extra,
 }
 
 impl FromSql for ContentType {
@@ -17,6 +19,7 @@ impl FromSql for ContentType {
                 1 => Ok(ContentType::Movie),
                 2 => Ok(ContentType::Episode),
                 3 => Ok(ContentType::Song),
+                4 => Ok(ContentType::Extra),
                 _ => Err(rusqlite::types::FromSqlError::InvalidType),
             },
             _ => Err(rusqlite::types::FromSqlError::InvalidType),
@@ -33,7 +36,7 @@ impl ToSql for ContentType {
 }
 
 /// All things that can be inside collections
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TableId {
     Collection,
     Content,
@@ -60,8 +63,50 @@ impl ToSql for TableId {
     }
 }
 
+/// How sure classification was about where content belongs, anything but certain deserves a manual look
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Certain,
+    /// Only inferred from a loose naming convention, like an episode without a season directory
+    Guessed,
+    /// The database and the path disagreed or nothing could be inferred at all
+    Ambiguous,
+}
+
+impl Confidence {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Confidence::Certain => "Certain",
+            Confidence::Guessed => "Guessed",
+            Confidence::Ambiguous => "Ambiguous",
+        }
+    }
+}
+
+impl FromSql for Confidence {
+    fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Self> {
+        match value {
+            rusqlite::types::ValueRef::Integer(i) => match i {
+                0 => Ok(Confidence::Certain),
+                1 => Ok(Confidence::Guessed),
+                2 => Ok(Confidence::Ambiguous),
+                _ => Err(rusqlite::types::FromSqlError::InvalidType),
+            },
+            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for Confidence {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(rusqlite::types::ToSqlOutput::Owned(
+            rusqlite::types::Value::Integer(*self as i64),
+        ))
+    }
+}
+
 /// All different types of collections
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CollectionType {
     UserCollection,
     Franchise,
@@ -0,0 +1,228 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tracing::warn;
+
+use crate::{state::AppResult, utils::HandleErr};
+
+use super::{
+    file_handling::{scan_dir, HashFile, PathExt},
+    probe::{probe, TechnicalDetails},
+};
+
+/// Where indexing gets the current time from, so it doesn't have to be the system clock
+pub trait Clock {
+    /// Seconds since the unix epoch
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .log_err_with_msg("Failed to get current time")
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Everything indexing needs to know about the files, so it can run against something other than the disk
+pub trait FileSystem {
+    /// All files in the directory, including those in subdirectories if recurse is set
+    /// Fails when the directory itself can't be read, like a network mount that is currently down
+    fn scan_dir(&self, path: &Path, recurse: bool) -> AppResult<Vec<PathBuf>>;
+    /// Seconds since the unix epoch
+    fn last_modified(&self, path: &Path) -> Option<u64>;
+    fn hash_file(&self, path: &Path) -> AppResult<Vec<u8>>;
+    /// In bytes
+    fn file_size(&self, path: &Path) -> Option<u64>;
+    /// None if the file doesn't exist or can't be read
+    fn read_to_string(&self, path: &Path) -> Option<String>;
+    /// Opens the file with ffmpeg to see what streams it contains
+    fn probe(&self, path: &Path) -> AppResult<TechnicalDetails>;
+}
+
+/// The actual filesystem of this machine
+pub struct LocalFileSystem;
+
+impl FileSystem for LocalFileSystem {
+    fn scan_dir(&self, path: &Path, recurse: bool) -> AppResult<Vec<PathBuf>> {
+        scan_dir(path, recurse)
+    }
+
+    fn last_modified(&self, path: &Path) -> Option<u64> {
+        path.last_modified()
+    }
+
+    fn hash_file(&self, path: &Path) -> AppResult<Vec<u8>> {
+        path.hash_file()
+    }
+
+    fn file_size(&self, path: &Path) -> Option<u64> {
+        path.metadata()
+            .map(|metadata| metadata.len())
+            .log_warn_with_msg(&format!("Failed to get the size of {path:?}"))
+    }
+
+    fn read_to_string(&self, path: &Path) -> Option<String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Failed to read {path:?}: {e}");
+                None
+            }
+        }
+    }
+
+    fn probe(&self, path: &Path) -> AppResult<TechnicalDetails> {
+        probe(path)
+    }
+}
+
+/// Stand-ins that only exist in memory, so tests can decide what is on disk and what time it is
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::{BTreeMap, BTreeSet},
+        path::{Path, PathBuf},
+    };
+
+    use crate::{state::AppResult, utils::bail};
+
+    use super::{Clock, FileSystem, TechnicalDetails};
+
+    /// A clock that only moves when told to
+    pub struct FakeClock(Cell<u64>);
+
+    impl FakeClock {
+        pub fn new(now: u64) -> Self {
+            Self(Cell::new(now))
+        }
+
+        pub fn advance(&self, seconds: u64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct FakeFile {
+        content: String,
+        modified: u64,
+    }
+
+    /// Files are hashed by their content, so two files with the same content are identical copies
+    /// Probing fails for every file that wasn't given technical details
+    #[derive(Default)]
+    pub struct FakeFileSystem {
+        files: RefCell<BTreeMap<PathBuf, FakeFile>>,
+        details: RefCell<BTreeMap<PathBuf, TechnicalDetails>>,
+        /// Scanning these or anything inside of them fails, like a network mount that is down
+        unreachable: RefCell<BTreeSet<PathBuf>>,
+    }
+
+    impl FakeFileSystem {
+        /// Adds the file or replaces what was there before
+        pub fn write(&self, path: &str, content: &str, modified: u64) {
+            let file = FakeFile {
+                content: content.to_owned(),
+                modified,
+            };
+            self.files.borrow_mut().insert(PathBuf::from(path), file);
+        }
+
+        pub fn remove(&self, path: &str) {
+            self.files.borrow_mut().remove(Path::new(path));
+        }
+
+        /// Moves the file without changing it, like a rename keeps the modification time
+        pub fn rename(&self, from: &str, to: &str) {
+            let mut files = self.files.borrow_mut();
+            let file = files
+                .remove(Path::new(from))
+                .expect("only existing files are renamed");
+            files.insert(PathBuf::from(to), file);
+        }
+
+        pub fn set_unreachable(&self, path: &str, unreachable: bool) {
+            let mut paths = self.unreachable.borrow_mut();
+            if unreachable {
+                paths.insert(PathBuf::from(path));
+            } else {
+                paths.remove(Path::new(path));
+            }
+        }
+
+        /// What probing the file finds from now on
+        pub fn set_details(&self, path: &str, details: TechnicalDetails) {
+            self.details
+                .borrow_mut()
+                .insert(PathBuf::from(path), details);
+        }
+    }
+
+    impl FileSystem for FakeFileSystem {
+        fn scan_dir(&self, path: &Path, recurse: bool) -> AppResult<Vec<PathBuf>> {
+            if self
+                .unreachable
+                .borrow()
+                .iter()
+                .any(|unreachable| path.starts_with(unreachable))
+            {
+                bail!("{path:?} can't be reached");
+            }
+
+            let files = self
+                .files
+                .borrow()
+                .keys()
+                .filter(|file| file.starts_with(path) && (recurse || file.parent() == Some(path)))
+                .cloned()
+                .collect();
+            Ok(files)
+        }
+
+        fn last_modified(&self, path: &Path) -> Option<u64> {
+            self.files.borrow().get(path).map(|file| file.modified)
+        }
+
+        fn hash_file(&self, path: &Path) -> AppResult<Vec<u8>> {
+            match self.files.borrow().get(path) {
+                Some(file) => Ok(file.content.as_bytes().to_vec()),
+                None => bail!("{path:?} doesn't exist"),
+            }
+        }
+
+        fn file_size(&self, path: &Path) -> Option<u64> {
+            self.files
+                .borrow()
+                .get(path)
+                .map(|file| file.content.len() as u64)
+        }
+
+        fn read_to_string(&self, path: &Path) -> Option<String> {
+            self.files
+                .borrow()
+                .get(path)
+                .map(|file| file.content.clone())
+        }
+
+        fn probe(&self, path: &Path) -> AppResult<TechnicalDetails> {
+            match self.details.borrow().get(path) {
+                Some(details) => Ok(details.clone()),
+                None => bail!("{path:?} has no streams"),
+            }
+        }
+    }
+}
@@ -1,38 +1,186 @@
 use std::{
     borrow::{Borrow, Cow},
+    collections::{HashMap, HashSet},
     ffi::OsStr,
     io::{Read, Seek},
-    path::{Path, PathBuf},
+    path::{Path, PathBuf, MAIN_SEPARATOR},
     time::SystemTime,
 };
 
 use anyhow::Context;
 use sha2::Digest;
-use tracing::warn;
+use tracing::{debug, warn};
 
 use crate::{state::AppResult, utils::HandleErr};
 
-pub fn scan_dir(path: &Path, recurse: bool) -> Vec<PathBuf> {
-    path.read_dir().map_or(Vec::new(), |read_dir| {
-        let mut out = Vec::new();
-
-        for entry in read_dir {
-            if let Some(entry) =
-                entry.log_err_with_msg("Encountered IO Error while scanning directory")
-            {
-                let path = entry.path();
-                let is_dir = path.is_dir();
-                if is_dir && recurse {
-                    out.extend(scan_dir(&path, true));
-                } else if !is_dir {
-                    out.push(path);
-                }
-            }
+/// Fails if the directory itself can't be read, so an empty result always means the directory is empty
+/// Subdirectories and entries that can't be read are skipped with a warning instead of failing the whole scan
+/// Every directory is only visited once, so symlinks pointing back up the tree don't make this loop forever
+pub fn scan_dir(path: &Path, recurse: bool) -> AppResult<Vec<PathBuf>> {
+    let mut visited = HashSet::new();
+    let mut out = Vec::new();
+    scan_dir_inner(path, recurse, &mut visited, &mut out)?;
+    Ok(out)
+}
+
+/// What indexing a location would find, without writing anything
+pub struct ScanSummary {
+    /// Files that would be indexed, ignored ones are not included
+    pub files: usize,
+    pub ignored: usize,
+    pub video: usize,
+    pub audio: usize,
+    /// The most common file extensions and how often they occur, most common first
+    pub extensions: Vec<(String, usize)>,
+}
+
+pub fn scan_summary(
+    path: &Path,
+    recurse: bool,
+    ignored_patterns: &[String],
+) -> AppResult<ScanSummary> {
+    const SHOWN_EXTENSIONS: usize = 5;
+
+    let (files, ignored): (Vec<_>, Vec<_>) = scan_dir(path, recurse)?
+        .into_iter()
+        .partition(|file| !is_ignored(file, ignored_patterns));
+
+    let (mut video, mut audio) = (0, 0);
+    let mut extensions = HashMap::new();
+    for file in &files {
+        match file.file_type() {
+            Some(FileType::Video) => video += 1,
+            Some(FileType::Audio) => audio += 1,
+            _ => {}
+        }
+
+        let extension = match file.extension() {
+            Some(extension) => extension.as_db_string().to_lowercase(),
+            None => "no extension".to_owned(),
+        };
+        *extensions.entry(extension).or_insert(0) += 1;
+    }
+
+    let mut extensions = extensions.into_iter().collect::<Vec<_>>();
+    extensions.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+    extensions.truncate(SHOWN_EXTENSIONS);
+
+    Ok(ScanSummary {
+        files: files.len(),
+        ignored: ignored.len(),
+        video,
+        audio,
+        extensions,
+    })
+}
+
+fn scan_dir_inner(
+    path: &Path,
+    recurse: bool,
+    visited: &mut HashSet<PathBuf>,
+    out: &mut Vec<PathBuf>,
+) -> AppResult<()> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve the directory {path:?}"))?;
+    if !visited.insert(canonical) {
+        debug!("Skipping {path:?}, it was already scanned through another path");
+        return Ok(());
+    }
+
+    let read_dir = path
+        .read_dir()
+        .with_context(|| format!("Failed to read the directory {path:?}"))?;
+
+    for entry in read_dir {
+        let Some(entry) = entry
+            .with_context(|| format!("Encountered IO Error while scanning {path:?}"))
+            .log_warn()
+        else {
+            continue;
+        };
+        let path = entry.path();
+
+        let is_dir = path.is_dir();
+        if is_dir && recurse {
+            scan_dir_inner(&path, true, visited, out)
+                .log_warn_with_msg(&format!("Skipping the unreadable directory {path:?}"));
+        } else if !is_dir {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file matches any of the patterns, where "*" stands for any amount of characters and "?" for exactly one
+/// Patterns containing a "/" are matched against the whole path, all others only against the file name
+/// Matching is case insensitive so "Sample.MKV" is caught by "sample.*"
+pub fn is_ignored(path: &Path, patterns: &[String]) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.as_db_string().to_lowercase();
+    let full_path = path.as_db_string().to_lowercase().replace('\\', "/");
+
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        if pattern.contains('/') {
+            matches_glob(&pattern, &full_path)
+        } else {
+            matches_glob(&pattern, &name)
         }
-        out
     })
 }
 
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (
+        pattern.chars().collect::<Vec<_>>(),
+        text.chars().collect::<Vec<_>>(),
+    );
+    let (mut p, mut t) = (0, 0);
+    // Where to continue from when the characters after the last "*" stop matching
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A LIKE pattern for everything inside of the directory, "%" and "_" in the path only match themselves
+/// It has to be used with ESCAPE '\'
+pub fn inside_directory_pattern(directory: &Path) -> String {
+    let mut pattern = String::new();
+    for c in directory.as_db_string().chars().chain([MAIN_SEPARATOR]) {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
 /// A trait so i stay consistent with the conversions
 pub trait AsDBString {
     fn as_db_string(&self) -> Cow<'_, str>;
@@ -124,3 +272,131 @@ impl PathExt for Path {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn scan(path: &Path, recurse: bool) -> Vec<PathBuf> {
+        let mut files = scan_dir(path, recurse).unwrap();
+        files.sort();
+        files
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_patterns_only_match_inside_the_directory() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let inside = |path: &str, directory: &str| -> bool {
+            conn.query_row(
+                "SELECT ?1 LIKE ?2 ESCAPE '\\'",
+                [path, &inside_directory_pattern(Path::new(directory))],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        assert!(inside("/media/Alien/CD1/Alien.mp4", "/media/Alien"));
+        assert!(!inside("/media/Aliens/CD1/Aliens.mp4", "/media/Alien"));
+        assert!(!inside("/media/Alien", "/media/Alien"));
+
+        assert!(inside("/media/100%_Heat/CD1/Heat.mp4", "/media/100%_Heat"));
+        assert!(!inside(
+            "/media/100 Days of Heat/CD1/Heat.mp4",
+            "/media/100%_Heat"
+        ));
+    }
+
+    #[test]
+    fn an_unreadable_location_fails_the_scan() {
+        let dir = TempDir::new("missing-location");
+        assert!(scan_dir(&dir.path().join("missing"), true).is_err());
+    }
+
+    #[test]
+    fn subdirectories_are_only_scanned_when_recursing() {
+        let dir = TempDir::new("recurse");
+        let movie = dir.file("Heat.mp4");
+        let nested = dir.file("Alien/Aliens/Aliens.mp4");
+
+        assert_eq!(scan(dir.path(), true), vec![nested, movie.clone()]);
+        assert_eq!(scan(dir.path(), false), vec![movie]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_scanned_once() {
+        let dir = TempDir::new("symlink-loop");
+        let movie = dir.file("Heat/Heat.mp4");
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("Heat/loop")).unwrap();
+
+        assert_eq!(scan(dir.path(), true), vec![movie]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_subdirectories_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("unreadable-subdirectory");
+        let movie = dir.file("Heat/Heat.mp4");
+        dir.file("Locked/Ronin.mp4");
+        let locked = dir.path().join("Locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Permissions don't stop root, there is nothing unreadable to skip then
+        let readable = locked.read_dir().is_ok();
+        let files = scan_dir(dir.path(), true);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            return;
+        }
+
+        assert_eq!(files.unwrap(), vec![movie]);
+    }
+
+    #[test]
+    fn globs_match_any_amount_of_characters() {
+        assert!(matches_glob("sample.*", "sample.mkv"));
+        assert!(matches_glob("*.part", "movie.mkv.part"));
+        assert!(matches_glob("*-sample.*", "heat-sample.mp4"));
+        assert!(matches_glob("movie.mp?", "movie.mp4"));
+        assert!(!matches_glob("movie.mp?", "movie.mkv"));
+        assert!(!matches_glob("sample.*", "not a sample.mkv"));
+        assert!(matches_glob("*", ""));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_match_the_whole_path() {
+        let patterns = ["sample.*".to_owned(), "*/samples/*".to_owned()];
+        assert!(is_ignored(Path::new("/media/Heat/Sample.MKV"), &patterns));
+        assert!(is_ignored(
+            Path::new("/media/Heat/samples/Heat.mkv"),
+            &patterns
+        ));
+        assert!(!is_ignored(Path::new("/media/Heat/Heat.mkv"), &patterns));
+        // Only the name is compared when there is no "/", so directories named like that don't count
+        assert!(!is_ignored(
+            Path::new("/media/sample.d/Heat.mkv"),
+            &patterns
+        ));
+    }
+
+    #[test]
+    fn ignored_files_are_left_out_of_the_scan() {
+        let dir = TempDir::new("scan-ignored");
+        dir.file("Heat/Heat.mp4");
+        dir.file("Heat/Heat.mp4.part");
+        dir.file("Heat/Heat.mp4.!ut");
+        dir.file("Heat/samples/Heat.mp4");
+        let patterns = ["*.part", "*.!ut", "*/samples/*"].map(str::to_owned);
+
+        let summary = scan_summary(dir.path(), true, &patterns).unwrap();
+        assert_eq!((summary.files, summary.ignored), (1, 3));
+        assert_eq!(summary.video, 1);
+        assert_eq!(summary.extensions, vec![("mp4".to_owned(), 1)]);
+    }
+}
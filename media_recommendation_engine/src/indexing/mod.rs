@@ -1,44 +1,87 @@
 mod classify;
 mod db;
+mod environment;
 mod file_handling;
+mod nfo;
+mod probe;
 
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
-    time::SystemTime,
 };
 
 use classify::{ClassificationCategory, CollectionHint, Franchise, Movie, Season, Series};
 use rusqlite::{params, OptionalExtension};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, span, trace, warn, Level};
 
 use crate::{
-    database::{Connection, Database, QueryRowGetConnExt, QueryRowGetStmtExt, QueryRowIntoStmtExt},
+    database::{
+        Database, QueryRowGetConnExt, QueryRowGetStmtExt, QueryRowIntoConnExt, QueryRowIntoStmtExt,
+    },
     indexing::{
-        classify::{classify, Classification},
-        file_handling::{scan_dir, AsDBString, HashFile, PathExt},
+        classify::{classify, part_directory},
+        environment::{Clock, FileSystem, LocalFileSystem, SystemClock},
+        file_handling::{inside_directory_pattern, is_ignored, AsDBString},
     },
-    state::{AppResult, IndexingTrigger, Shutdown},
+    metadata::fetch_metadata,
+    state::{AppError, AppResult, ContentUpdates, IndexingTrigger, Shutdown},
     utils::{HandleErr, ServerSettings},
 };
 
-pub use db::{CollectionType, ContentType, TableId};
+pub use classify::strip_year;
+pub use db::{CollectionType, Confidence, ContentType, TableId};
+pub use file_handling::{scan_summary, ScanSummary};
 
 pub async fn periodic_indexing(
     db: Database,
     settings: ServerSettings,
     trigger: IndexingTrigger,
+    updates: ContentUpdates,
     shutdown: Shutdown,
 ) {
     span!(Level::DEBUG, "Indexing");
+    let mut metadata_task: Option<JoinHandle<()>> = None;
     loop {
-        let db = db.clone();
+        let indexing_db = db.clone();
+        let extras_folders = settings.extras_folders();
+        let ignored_patterns = settings.ignored_patterns();
+        let retention = settings.missing_content_retention();
         let task = tokio::task::spawn_blocking(move || {
-            indexing(&db).log_err_with_msg("Failed the indexing");
+            indexing_db
+                .get()
+                .map_err(AppError::from)
+                .and_then(|mut conn| {
+                    indexing(
+                        &mut conn,
+                        &LocalFileSystem,
+                        &SystemClock,
+                        &extras_folders,
+                        &ignored_patterns,
+                        retention,
+                    )
+                })
+                .log_err_with_msg("Failed the indexing")
+                .unwrap_or_default()
         });
 
-        task.await
-            .log_err_with_msg("Failed to wait for indexing task to finish");
+        let changed = task
+            .await
+            .log_err_with_msg("Failed to wait for indexing task to finish")
+            .unwrap_or_default();
+
+        if changed {
+            updates.notify_changed();
+        }
+
+        // Metadata lookups are slow, so they happen in the background and only one at a time
+        if metadata_task.as_ref().is_none_or(JoinHandle::is_finished) {
+            metadata_task = Some(tokio::spawn(fetch_metadata(
+                db.clone(),
+                settings.clone(),
+                shutdown.clone(),
+            )));
+        }
 
         tokio::select! {
             _ = settings.wait_configured_time() => {}
@@ -48,19 +91,77 @@ pub async fn periodic_indexing(
     }
 }
 
-// NOTE: There are some oversights in this entire process. I will iron it out as I use it more
-fn indexing(db: &Database) -> AppResult<()> {
-    let mut conn = db.get()?;
+/// How every file below the path would be classified, without adding anything to the database
+/// Files that fail to classify are listed with the reason instead
+pub fn dry_run(
+    conn: &rusqlite::Connection,
+    path: &Path,
+    extras_folders: &[String],
+    ignored_patterns: &[String],
+) -> AppResult<Vec<(PathBuf, String)>> {
+    let fs = LocalFileSystem;
+    let mut files = fs
+        .scan_dir(path, true)?
+        .into_iter()
+        .filter(|file| !is_ignored(file, ignored_patterns))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let described = files
+        .into_iter()
+        .map(|file| {
+            let description = match classify(&file, path, conn, extras_folders) {
+                Ok(mut classification) => {
+                    if let Some(nfo) = nfo::find_nfo(&file, &classification.category, &fs) {
+                        classification.apply_nfo(nfo);
+                    }
+                    classification.to_string()
+                }
+                Err(e) => format!("Failed to classify: {e}"),
+            };
+            (file, description)
+        })
+        .collect();
+
+    Ok(described)
+}
 
-    let filesystem = conn
+// NOTE: There are some oversights in this entire process. I will iron it out as I use it more
+/// Returns whether any content was added, removed or changed
+fn indexing(
+    conn: &mut rusqlite::Connection,
+    fs: &impl FileSystem,
+    clock: &impl Clock,
+    extras_folders: &[String],
+    ignored_patterns: &[String],
+    retention_days: u64,
+) -> AppResult<bool> {
+    let locations = conn
         .prepare("SELECT path, recurse FROM storage_locations")?
         .query_map_into::<(String, bool)>([])?
-        .filter_map(|res| {
-            res.log_warn()
-                .map(|(path, recurse)| scan_dir(Path::new(&path), recurse))
-        })
-        .flatten()
-        .collect::<HashSet<PathBuf>>();
+        .filter_map(|res| res.log_warn())
+        .collect::<Vec<_>>();
+
+    // Locations that can't be read right now are left alone entirely instead of treating all their files as deleted
+    let (mut filesystem, mut unreachable) = (HashSet::new(), Vec::new());
+    for (path, recurse) in &locations {
+        match fs.scan_dir(Path::new(path), *recurse) {
+            // Ignored files that were indexed before now count as deleted
+            Ok(files) => filesystem.extend(
+                files
+                    .into_iter()
+                    .filter(|file| !is_ignored(file, ignored_patterns)),
+            ),
+            Err(e) => {
+                warn!("Skipping the unreachable storage location {path:?} this time: {e}");
+                unreachable.push(PathBuf::from(path));
+            }
+        }
+    }
+    let locations = locations
+        .into_iter()
+        .map(|(path, _)| PathBuf::from(path))
+        .collect::<Vec<_>>();
 
     let tx = conn.transaction()?;
 
@@ -79,29 +180,59 @@ fn indexing(db: &Database) -> AppResult<()> {
         .filter_map(|res| res.log_warn().map(|(id, path)| (id, PathBuf::from(path))))
         .collect::<Vec<_>>()
         .into_iter()
+        .filter(|(_, path)| {
+            !unreachable
+                .iter()
+                .any(|location| path.starts_with(location))
+        })
         .partition(|(_, path)| filesystem.contains(path));
 
-    // Delete everything that is only in the database and update unassigned content entries
+    // Writing many single rows is a lot faster inside of one transaction than with one implicit transaction each
+    // unchecked_transaction lets the helpers keep using the pooled connection, an early return rolls it back
+    let tx = conn.unchecked_transaction()?;
 
-    let mut delete_stmt = conn.prepare("DELETE FROM data_file WHERE path = ?1 RETURNING id")?;
-    let deleted_ids = only_database
-        .iter()
-        .map(|(_, file)| delete_stmt.query_row_get::<u64>([file.as_db_string()]))
-        .collect::<Result<Vec<_>, _>>()?;
-    drop(delete_stmt);
+    // Delete everything that is only in the database and update unassigned content entries
 
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .log_err_with_msg("Failed to get current time")
-        .unwrap_or_default()
-        .as_secs();
+    let now = clock.now();
 
+    // The content has to be updated first, deleting the data_file already unlinks it
     let mut update_stmt =
         conn.prepare("UPDATE content SET data_id = NULL, last_changed = ?1 WHERE data_id = ?2")?;
-    for id in deleted_ids {
-        update_stmt.execute([now, id])?;
+    let mut delete_stmt = conn.prepare("DELETE FROM data_file WHERE id = ?1")?;
+    for (id, _) in &only_database {
+        update_stmt.execute([now, *id])?;
+        delete_stmt.execute([id])?;
     }
     drop(update_stmt);
+    drop(delete_stmt);
+
+    // Content that lost its file is played from one of its identical copies instead, if there is one
+    conn.execute(
+        "UPDATE content SET data_id = (
+            SELECT id FROM data_file WHERE duplicate_of = content.id ORDER BY id LIMIT 1
+        ) WHERE data_id IS NULL AND EXISTS (SELECT 1 FROM data_file WHERE duplicate_of = content.id)",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE data_file SET duplicate_of = NULL
+            WHERE id IN (SELECT data_id FROM content WHERE data_id IS NOT NULL)",
+        [],
+    )?;
+
+    let purged = purge_missing(conn, now, retention_days)?;
+
+    // Known copies of other content don't need to be hashed and classified again
+    let mut is_duplicate_stmt =
+        conn.prepare("SELECT duplicate_of IS NOT NULL FROM data_file WHERE id = ?1")?;
+    let both = both
+        .into_iter()
+        .filter(|(id, _)| {
+            !is_duplicate_stmt
+                .query_row_get::<bool>([id])
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    drop(is_duplicate_stmt);
 
     // Seperate out which files have content associated with them
     let mut has_content_stmt = conn.prepare("SELECT CASE WHEN EXISTS (SELECT 1 FROM content LEFT JOIN data_file ON content.data_id = data_file.id WHERE data_file.path = ?1) THEN 1 ELSE 0 END")?;
@@ -116,11 +247,11 @@ fn indexing(db: &Database) -> AppResult<()> {
     // Check stuff with valid file paths for changes
     // This aggressively removes anything that changed
     let mut get_content_stmt = conn.prepare("SELECT content.id, content.last_changed FROM content, data_file WHERE content.data_id = data_file.id AND data_file.path = ?1")?;
-    for (_, path) in has_content {
+    for (data_id, path) in has_content {
         let (content_id, last_changed) =
             get_content_stmt.query_row_into::<(u64, u64)>([path.as_db_string()])?;
 
-        let Some(last_modified) = path.last_modified() else {
+        let Some(last_modified) = fs.last_modified(&path) else {
             warn!("Failed to get last modified time for {path:?}");
             continue;
         };
@@ -128,20 +259,20 @@ fn indexing(db: &Database) -> AppResult<()> {
         if last_changed == last_modified {
             continue;
         } else {
-            //Remove the link between content and data_file and add the content to the no_content vec
-            let data_id: u64 = conn
-                .prepare_cached("UPDATE content SET data_id = NULL WHERE id = ?1 RETURNING id")?
-                .query_row_get([content_id])?;
-
-            let removed_path: String = conn
-                .prepare_cached("SELECT path FROM data_file WHERE id = ?1")?
-                .query_row_get([data_id])?;
+            // Remove the link between content and data_file and add the file to the no_content vec
+            // The old content counts as missing from now on, like content whose file was deleted
+            conn.prepare_cached(
+                "UPDATE content SET data_id = NULL, last_changed = ?1 WHERE id = ?2",
+            )?
+            .execute([now, content_id])?;
 
-            no_content.push((data_id, PathBuf::from(removed_path)));
+            no_content.push((data_id, path));
         }
     }
     drop(get_content_stmt);
 
+    tx.commit()?;
+
     let len = no_content.len();
     let (mut hashes, mut classifications) = (vec![vec![]; len], Vec::with_capacity(len));
 
@@ -150,41 +281,120 @@ fn indexing(db: &Database) -> AppResult<()> {
     // Try to reassign unassigned content or just create new content entries
     hashes.iter_mut().enumerate().for_each(|(i, entry)| {
         trace!("Hashing {:?}", no_content[i].1);
-        *entry = no_content[i]
-            .1
-            .hash_file()
+        *entry = fs
+            .hash_file(&no_content[i].1)
             .log_err_with_msg(&format!("failed to hash file: {:?}", no_content[i].1))
             .unwrap_or_default();
     });
 
     trace!("Started Classifying");
     for (_, path) in &no_content {
-        classifications.push(classify(path, &conn));
+        let location = location_of(path, &locations);
+        classifications.push(classify(path, location, conn, extras_folders));
     }
 
-    let classifications: Vec<Classification> = classifications
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()
-        .log_err_with_msg("Failed to generate classifications")
-        .unwrap_or_default();
-
-    // The path, hash and classification for all data files that don't have valid content
+    // The path, hash, classification and technical details for all data files that don't have valid content
+    // A file that failed to classify is left without content, so it is tried again next time
+    // Until then it is listed for review with the reason it failed
+    let mut failed = Vec::new();
+    let mut known_hash_stmt =
+        conn.prepare("SELECT EXISTS (SELECT 1 FROM content WHERE hash = ?1)")?;
     let info = no_content
         .into_iter()
         .zip(hashes.into_iter().zip(classifications))
+        .filter_map(|((data_id, path), (hash, classification))| {
+            let mut classification = match classification {
+                Ok(classification) => classification,
+                Err(e) => {
+                    warn!("Failed to classify {path:?}, skipping it for now: {e}");
+                    failed.push((data_id, e.to_string()));
+                    return None;
+                }
+            };
+            if let Some(nfo) = nfo::find_nfo(&path, &classification.category, fs) {
+                classification.apply_nfo(nfo);
+            }
+
+            // Probing opens the whole file, so it happens here instead of while holding the write transaction
+            // Files that are given existing content keep the details that were stored for it
+            let probed = matches!(
+                classification.category,
+                ClassificationCategory::Movie
+                    | ClassificationCategory::Episode { .. }
+                    | ClassificationCategory::Extra
+            ) && (hash.is_empty()
+                || !known_hash_stmt
+                    .query_row_get::<bool>([&hash])
+                    .unwrap_or_default());
+            // Files that can't be probed just don't show any technical details
+            let details = probed
+                .then(|| {
+                    fs.probe(&path).log_warn_with_msg(&format!(
+                        "Failed to probe {path:?} for technical details"
+                    ))
+                })
+                .flatten();
+
+            Some(((data_id, path), (hash, classification, details)))
+        })
         .collect::<Vec<_>>();
+    drop(known_hash_stmt);
+
+    let changed = !only_database.is_empty() || !info.is_empty() || purged;
+
+    let tx = conn.unchecked_transaction()?;
+
+    let mut failed_stmt =
+        conn.prepare("UPDATE data_file SET classification_error = ?1 WHERE id = ?2")?;
+    for (data_id, error) in &failed {
+        failed_stmt.execute(params![error, data_id])?;
+    }
+    drop(failed_stmt);
+
+    // Files that were given existing content already are where they belong
+    let mut reused = HashSet::new();
 
     // This tries to, as best as it can, reassign or update anything previously removed
-    for ((data_id, path), (hash, classification)) in &info {
-        let content_id = conn
-            .query_row_get::<u64>("SELECT id FROM content WHERE hash = ?1", [hash])
-            .optional()?;
+    for ((data_id, path), (hash, classification, details)) in &info {
+        conn.prepare_cached(
+            "UPDATE data_file SET size = ?1, classification_error = NULL WHERE id = ?2",
+        )?
+        .execute(params![fs.file_size(path), data_id])?;
 
-        // This should capture renaming
-        if let Some(content_id) = content_id {
-            let mut link_content =
-                conn.prepare_cached("UPDATE content SET data_id = ?1 WHERE id = ?2")?;
-            link_content.execute([data_id, &content_id])?;
+        // A failed hash is empty and would match every other failed one
+        let existing = if hash.is_empty() {
+            None
+        } else {
+            conn.query_row_into::<(u64, Option<u64>)>(
+                "SELECT id, data_id FROM content WHERE hash = ?1",
+                [hash],
+            )
+            .optional()?
+        };
+
+        match existing {
+            // This should capture renaming
+            Some((content_id, None)) => {
+                conn.prepare_cached(
+                    "UPDATE content SET data_id = ?1, last_changed = ?2 WHERE id = ?3",
+                )?
+                .execute(params![
+                    data_id,
+                    fs.last_modified(path).unwrap_or_default(),
+                    content_id
+                ])?;
+                reused.insert(*data_id);
+                continue;
+            }
+            // An identical file is already played, this one is only kept track of as a copy
+            Some((content_id, Some(_))) => {
+                debug!("{path:?} is a copy of the content {content_id}");
+                conn.prepare_cached("UPDATE data_file SET duplicate_of = ?1 WHERE id = ?2")?
+                    .execute([content_id, *data_id])?;
+                reused.insert(*data_id);
+                continue;
+            }
+            None => {}
         }
 
         trace!("trying to assign {path:?}");
@@ -193,9 +403,36 @@ fn indexing(db: &Database) -> AppResult<()> {
         let reference_id: Option<u64> = match classification.category {
             ClassificationCategory::Other => None,
             ClassificationCategory::Movie => {
-                let mut stmt =
-                    conn.prepare_cached("INSERT INTO movie (title) VALUES (?1) RETURNING id")?;
-                Some(stmt.query_row_get([&classification.title])?)
+                // Every part of a multipart movie shares the movie entry, only the first one creates it
+                let existing_part: Option<u64> = match &classification.parts_directory {
+                    Some(directory) => conn
+                        .prepare_cached(
+                            "SELECT movie.id FROM movie, content, data_file
+                                WHERE content.reference = movie.id
+                                AND content.type = ?1
+                                AND content.data_id = data_file.id
+                                AND movie.title = ?2
+                                AND data_file.path LIKE ?3 ESCAPE '\\'",
+                        )?
+                        .query_row_get(params![
+                            ContentType::Movie,
+                            &classification.title,
+                            inside_directory_pattern(directory)
+                        ])
+                        .optional()?,
+                    None => None,
+                };
+
+                match existing_part {
+                    Some(movie_id) => Some(movie_id),
+                    None => {
+                        let mut stmt = conn.prepare_cached(
+                            "INSERT INTO movie (title, year) VALUES (?1, ?2) RETURNING id",
+                        )?;
+                        let title = &classification.title;
+                        Some(stmt.query_row_get(params![title, classification.year])?)
+                    }
+                }
             }
             ClassificationCategory::Episode { episode } => {
                 let mut stmt = conn.prepare_cached(
@@ -208,17 +445,42 @@ fn indexing(db: &Database) -> AppResult<()> {
                     conn.prepare_cached("INSERT INTO song (title) VALUES (?1) RETURNING id")?;
                 Some(stmt.query_row_get([&classification.title])?)
             }
+            ClassificationCategory::Extra => {
+                // Extras of a single movie are found through the directory they share with it
+                let directory = match classification.collectionhint {
+                    CollectionHint::Movie(_) => classification
+                        .extras_owner
+                        .as_deref()
+                        .map(AsDBString::as_db_string),
+                    _ => None,
+                };
+                let mut stmt = conn.prepare_cached(
+                    "INSERT INTO extra (title, directory) VALUES (?1, ?2) RETURNING id",
+                )?;
+                Some(stmt.query_row_get(params![&classification.title, directory])?)
+            }
         };
 
-        let content_id: u64 =  conn.prepare_cached("INSERT INTO content (last_changed, hash, data_id, type, reference, part) VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id")?.query_row_get(params![
-            path.last_modified().unwrap_or_default(),
+        let content_id: u64 =  conn.prepare_cached("INSERT INTO content (last_changed, hash, data_id, type, reference, part, resolution, source, confidence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING id")?.query_row_get(params![
+            fs.last_modified(path).unwrap_or_default(),
             hash,
             data_id,
             classification.content_type(),
             reference_id,
-            classification.part
+            classification.part,
+            classification.quality.resolution,
+            classification.quality.source,
+            classification.confidence
         ])?;
 
+        if let Some(details) = details {
+            probe::store(conn, content_id, details)?;
+        }
+
+        if let Some(nfo) = &classification.nfo {
+            nfo::store(conn, content_id, nfo)?;
+        }
+
         // Collection assignment
 
         let collection_id: Option<u64> = match &classification.collectionhint {
@@ -227,24 +489,30 @@ fn indexing(db: &Database) -> AppResult<()> {
                 continue;
             }
             CollectionHint::Franchise(franchise) => {
-                Some(get_franchise_collection_or_insert_new(&conn, franchise)?)
+                Some(get_franchise_collection_or_insert_new(conn, franchise)?)
             }
             CollectionHint::Series(series) => {
-                Some(get_series_collection_or_insert_new(&conn, series)?)
+                Some(get_series_collection_or_insert_new(conn, series)?)
             }
             CollectionHint::Season(season) => {
-                Some(get_season_collection_or_insert_new(&conn, season)?)
+                Some(get_season_collection_or_insert_new(conn, season)?)
             }
             CollectionHint::ThemeTarget { .. } => {
                 // This is handled later
                 continue;
             }
+            // Extras of a single movie are linked to it directly afterwards instead of cluttering its franchise
+            CollectionHint::Movie(_)
+                if matches!(classification.category, ClassificationCategory::Extra) =>
+            {
+                None
+            }
             CollectionHint::Movie(Movie {
                 franchise,
                 title: _,
             }) => {
                 if let Some(franchise) = franchise {
-                    Some(get_franchise_collection_or_insert_new(&conn, franchise)?)
+                    Some(get_franchise_collection_or_insert_new(conn, franchise)?)
                 } else {
                     None
                 }
@@ -260,16 +528,22 @@ fn indexing(db: &Database) -> AppResult<()> {
     }
 
     // Try to find matches for themes after everything is assigned
-    for ((data_id, path), (_, classification)) in info {
+    for ((data_id, path), (_, classification, _)) in info {
         let CollectionHint::ThemeTarget { .. } = classification.collectionhint else {
             continue;
         };
 
-        let CollectionHint::ThemeTarget { inner } = classify(&path, &conn)?.collectionhint else {
+        if reused.contains(&data_id) {
+            continue;
+        }
+
+        let CollectionHint::ThemeTarget { inner } =
+            classify(&path, location_of(&path, &locations), conn, extras_folders)?.collectionhint
+        else {
             continue;
         };
 
-        let Some(collection_id) = get_theme_collection_or_insert_new(&conn, &inner)? else {
+        let Some(collection_id) = get_theme_collection_or_insert_new(conn, &inner)? else {
             continue;
         };
 
@@ -283,12 +557,59 @@ fn indexing(db: &Database) -> AppResult<()> {
         .execute(params![collection_id, TableId::Content, content_id])?;
     }
 
+    tx.commit()?;
+
     info!("Finished indexing once");
-    Ok(())
+    Ok(changed)
+}
+
+/// Removes content that has been without a file for longer than the retention period
+/// Collections indexing created are removed with it once they are empty, user collections are kept
+/// Returns whether anything was removed
+fn purge_missing(conn: &rusqlite::Connection, now: u64, retention_days: u64) -> AppResult<bool> {
+    if retention_days == 0 {
+        return Ok(false);
+    }
+    let cutoff = now.saturating_sub(retention_days * 24 * 60 * 60);
+
+    let purged = conn.execute(
+        "DELETE FROM content WHERE data_id IS NULL AND last_changed < ?1",
+        [cutoff],
+    )?;
+    if purged == 0 {
+        return Ok(false);
+    }
+    info!("Removed {purged} pieces of content whose files have been missing for over {retention_days} days");
+
+    // An emptied season can leave its series empty and that its franchise, so this repeats until nothing changes
+    let mut delete_empty = conn.prepare(
+        "DELETE FROM collection WHERE type != ?1
+            AND NOT EXISTS (SELECT 1 FROM collection_contains WHERE collection_id = collection.id)",
+    )?;
+    while delete_empty.execute([CollectionType::UserCollection])? > 0 {}
+
+    Ok(true)
+}
+
+/// The storage location the file was found in, the innermost one for nested locations
+fn location_of<'a>(path: &Path, locations: &'a [PathBuf]) -> &'a Path {
+    locations
+        .iter()
+        .filter(|location| path.starts_with(location))
+        .max_by_key(|location| location.components().count())
+        .map_or(Path::new(""), PathBuf::as_path)
+}
+
+/// The directory a movie is in, for movies split into parts like "Movie/CD1/..." that is "Movie"
+pub fn movie_directory(path: &Path) -> Option<&Path> {
+    match part_directory(path) {
+        Some((_, directory)) => Some(directory),
+        None => path.parent(),
+    }
 }
 
 fn get_franchise_collection_or_insert_new(
-    conn: &Connection,
+    conn: &rusqlite::Connection,
     franchise: &Franchise,
 ) -> AppResult<u64> {
     let franchise_id = conn
@@ -320,7 +641,10 @@ fn get_franchise_collection_or_insert_new(
     Ok(collection_id)
 }
 
-fn get_series_collection_or_insert_new(conn: &Connection, series: &Series) -> AppResult<u64> {
+fn get_series_collection_or_insert_new(
+    conn: &rusqlite::Connection,
+    series: &Series,
+) -> AppResult<u64> {
     let series_id: u64 = if let Some(franchise) = &series.franchise {
         let franchise_id = get_franchise_collection_or_insert_new(conn, franchise)?;
 
@@ -379,7 +703,10 @@ fn get_series_collection_or_insert_new(conn: &Connection, series: &Series) -> Ap
     Ok(series_id)
 }
 
-fn get_season_collection_or_insert_new(conn: &Connection, season: &Season) -> AppResult<u64> {
+fn get_season_collection_or_insert_new(
+    conn: &rusqlite::Connection,
+    season: &Season,
+) -> AppResult<u64> {
     let season_id: u64 = if let Some(series) = &season.series {
         let series_id = get_series_collection_or_insert_new(conn, series)?;
 
@@ -441,7 +768,7 @@ fn get_season_collection_or_insert_new(conn: &Connection, season: &Season) -> Ap
 }
 
 fn get_theme_collection_or_insert_new(
-    conn: &Connection,
+    conn: &rusqlite::Connection,
     target: &CollectionHint,
 ) -> AppResult<Option<u64>> {
     // Themes can only point at existing collections
@@ -620,7 +947,7 @@ fn get_theme_collection_or_insert_new(
 }
 
 pub fn resolve_video(
-    conn: &Connection,
+    conn: &rusqlite::Connection,
     data_id: u64,
     content_type: ContentType,
 ) -> Result<u64, rusqlite::Error> {
@@ -628,7 +955,886 @@ pub fn resolve_video(
         "SELECT content.id FROM content
             WHERE content.reference = ?1
             AND content.type = ?2
-            AND part = 0",
+            AND part = 0
+            ORDER BY resolution DESC NULLS LAST
+            LIMIT 1",
         params![data_id, content_type],
     )
 }
+
+/// All playable content in the collection and the collections inside it
+/// Episodes are in the order of their season, everything else in the order it was added
+pub fn playable_in_collection(
+    conn: &rusqlite::Connection,
+    collection_id: u64,
+) -> AppResult<Vec<u64>> {
+    let content = conn
+        .prepare(
+            "WITH RECURSIVE contained(type, reference) AS (
+                SELECT ?2, ?1
+                UNION
+                SELECT collection_contains.type, collection_contains.reference
+                    FROM collection_contains, contained
+                    WHERE contained.type = ?2
+                    AND collection_contains.collection_id = contained.reference
+            )
+            SELECT content.id FROM contained
+                JOIN content ON content.id = contained.reference
+                LEFT JOIN episode ON content.type = ?4 AND episode.id = content.reference
+                LEFT JOIN collection_contains AS parent
+                    ON parent.type = ?3 AND parent.reference = content.id
+                LEFT JOIN collection ON collection.id = parent.collection_id AND collection.type = ?5
+                LEFT JOIN season ON season.id = collection.reference
+                WHERE contained.type = ?3
+                AND content.data_id IS NOT NULL
+                AND content.part = 0
+                GROUP BY content.id
+                ORDER BY min(season.season), episode.episode, content.id",
+        )?
+        .query_map_get(params![
+            collection_id,
+            TableId::Collection,
+            TableId::Content,
+            ContentType::Episode,
+            CollectionType::Season
+        ])?
+        .collect::<Result<Vec<u64>, _>>()?;
+    Ok(content)
+}
+
+/// The name content is displayed with, independent of its type
+pub fn content_title(conn: &rusqlite::Connection, content_id: u64) -> AppResult<String> {
+    let (content_type, data_id): (ContentType, Option<u64>) = conn.query_row_into(
+        "SELECT type, reference FROM content WHERE id = ?1",
+        [content_id],
+    )?;
+
+    let title = match (content_type, data_id) {
+        (ContentType::Movie, Some(data_id)) => {
+            conn.query_row_get("SELECT title FROM movie WHERE id = ?1", [data_id])?
+        }
+        (ContentType::Episode, Some(data_id)) => {
+            let (title, episode): (String, u64) = conn.query_row_into(
+                "SELECT title, episode FROM episode WHERE id = ?1",
+                [data_id],
+            )?;
+            format!("{title} - Episode {episode}")
+        }
+        (ContentType::Song, Some(data_id)) => {
+            conn.query_row_get("SELECT title FROM song WHERE id = ?1", [data_id])?
+        }
+        (ContentType::Extra, Some(data_id)) => {
+            conn.query_row_get("SELECT title FROM extra WHERE id = ?1", [data_id])?
+        }
+        _ => format!("Content {content_id}"),
+    };
+
+    Ok(title)
+}
+
+#[cfg(test)]
+pub const FIXTURE_ROOT: &str = "/media!noclassify";
+
+/// Indexes files that are named relative to a storage location, like they were on disk, for tests elsewhere
+/// Every file has different content, so none of them are copies of each other
+#[cfg(test)]
+pub fn index_fixture(conn: &mut rusqlite::Connection, files: &[&str]) {
+    use environment::fake::{FakeClock, FakeFileSystem};
+
+    conn.execute(
+        "INSERT INTO storage_locations (path, recurse) VALUES (?1, 1)",
+        [FIXTURE_ROOT],
+    )
+    .unwrap();
+
+    let fs = FakeFileSystem::default();
+    for file in files {
+        fs.write(&format!("{FIXTURE_ROOT}/{file}"), file, 0);
+    }
+    indexing(
+        conn,
+        &fs,
+        &FakeClock::new(0),
+        &["Extras".to_owned()],
+        &[],
+        0,
+    )
+    .unwrap();
+}
+
+/// The id of the content that plays the file from `index_fixture`
+#[cfg(test)]
+pub fn fixture_content(conn: &rusqlite::Connection, file: &str) -> u64 {
+    conn.query_row_get(
+        "SELECT content.id FROM content, data_file
+            WHERE content.data_id = data_file.id AND data_file.path = ?1",
+        [format!("{FIXTURE_ROOT}/{file}")],
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::{
+        environment::fake::{FakeClock, FakeFileSystem},
+        *,
+    };
+    use crate::{database::in_memory, utils::TempDir};
+
+    // Directories containing "!noclassify" are not used for classification, so this stays out of every title
+    const ROOT: &str = "/media!noclassify";
+    const RETENTION_DAYS: u64 = 30;
+
+    fn library() -> rusqlite::Connection {
+        let conn = in_memory();
+        conn.execute(
+            "INSERT INTO storage_locations (path, recurse) VALUES (?1, 1)",
+            [ROOT],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn path(relative: &str) -> String {
+        format!("{ROOT}/{relative}")
+    }
+
+    fn index(conn: &mut rusqlite::Connection, fs: &FakeFileSystem, clock: &FakeClock) -> bool {
+        indexing(conn, fs, clock, &["Extras".to_owned()], &[], RETENTION_DAYS).unwrap()
+    }
+
+    /// The content currently played from the file
+    fn content_of(conn: &rusqlite::Connection, relative: &str) -> Option<u64> {
+        conn.query_row_get(
+            "SELECT content.id FROM content, data_file
+                WHERE content.data_id = data_file.id AND data_file.path = ?1",
+            [path(relative)],
+        )
+        .optional()
+        .unwrap()
+    }
+
+    /// The type and title of the collection the file was put into
+    fn collection_of(conn: &rusqlite::Connection, relative: &str) -> (CollectionType, String) {
+        let content_id = content_of(conn, relative).unwrap();
+        let (collection_type, reference): (CollectionType, u64) = conn
+            .query_row_into(
+                "SELECT collection.type, collection.reference FROM collection, collection_contains
+                    WHERE collection_contains.collection_id = collection.id
+                    AND collection_contains.type = ?1
+                    AND collection_contains.reference = ?2
+                    AND collection.type != ?3",
+                params![TableId::Content, content_id, CollectionType::Theme],
+            )
+            .unwrap();
+
+        let table = match collection_type {
+            CollectionType::Franchise => "franchise",
+            CollectionType::Series => "series",
+            CollectionType::Season => "season",
+            CollectionType::UserCollection => "user_collection",
+            CollectionType::Theme => unreachable!("themes are excluded"),
+        };
+        let title = conn
+            .query_row_get(
+                &format!("SELECT title FROM {table} WHERE id = ?1"),
+                [reference],
+            )
+            .unwrap();
+        (collection_type, title)
+    }
+
+    /// The collection that contains the collection with this id
+    fn parent_collection(conn: &rusqlite::Connection, collection_id: u64) -> u64 {
+        conn.query_row_get(
+            "SELECT collection_id FROM collection_contains WHERE type = ?1 AND reference = ?2",
+            params![TableId::Collection, collection_id],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn movies_are_grouped_into_their_franchise() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Alien/Alien.mp4"), "alien", 10);
+        fs.write(&path("Alien/Aliens/Aliens.mp4"), "aliens", 10);
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let alien = (CollectionType::Franchise, "Alien".to_owned());
+        assert_eq!(collection_of(&conn, "Alien/Alien.mp4"), alien);
+        assert_eq!(collection_of(&conn, "Alien/Aliens/Aliens.mp4"), alien);
+        // A movie on its own gets a franchise of its own
+        assert_eq!(
+            collection_of(&conn, "Heat/Heat.mp4"),
+            (CollectionType::Franchise, "Heat".to_owned())
+        );
+
+        let title: String = conn
+            .query_row_get(
+                "SELECT movie.title FROM movie, content WHERE content.reference = movie.id AND content.id = ?1",
+                [content_of(&conn, "Alien/Aliens/Aliens.mp4").unwrap()],
+            )
+            .unwrap();
+        assert_eq!(title, "Aliens");
+    }
+
+    #[test]
+    fn episodes_end_up_in_their_season_of_the_series() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Lost/Season 1/Pilot - s1e1.mp4"), "pilot", 10);
+        fs.write(
+            &path("Lost/Season 1/Tabula Rasa - s1e3.mp4"),
+            "tabula rasa",
+            10,
+        );
+        fs.write(
+            &path("Lost/Season 2/Man of Science - s2e1.mp4"),
+            "man of science",
+            10,
+        );
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let season_one = (CollectionType::Season, "Season 1".to_owned());
+        assert_eq!(
+            collection_of(&conn, "Lost/Season 1/Pilot - s1e1.mp4"),
+            season_one
+        );
+        assert_eq!(
+            collection_of(&conn, "Lost/Season 1/Tabula Rasa - s1e3.mp4"),
+            season_one
+        );
+        assert_eq!(
+            collection_of(&conn, "Lost/Season 2/Man of Science - s2e1.mp4"),
+            (CollectionType::Season, "Season 2".to_owned())
+        );
+
+        let episode: u64 = conn
+            .query_row_get(
+                "SELECT episode.episode FROM episode, content WHERE content.reference = episode.id AND content.id = ?1",
+                [content_of(&conn, "Lost/Season 1/Tabula Rasa - s1e3.mp4").unwrap()],
+            )
+            .unwrap();
+        assert_eq!(episode, 3);
+
+        // Both seasons are in the same series, which is in a franchise of the same name
+        let season_collection = |relative: &str| -> u64 {
+            conn.query_row_get(
+                "SELECT collection_id FROM collection_contains WHERE type = ?1 AND reference = ?2",
+                params![TableId::Content, content_of(&conn, relative).unwrap()],
+            )
+            .unwrap()
+        };
+        let series = parent_collection(&conn, season_collection("Lost/Season 1/Pilot - s1e1.mp4"));
+        assert_eq!(
+            series,
+            parent_collection(
+                &conn,
+                season_collection("Lost/Season 2/Man of Science - s2e1.mp4")
+            )
+        );
+        let franchise = parent_collection(&conn, series);
+        let (series_type, franchise_title): (CollectionType, String) = conn
+            .query_row_into(
+                "SELECT collection.type, franchise.title FROM collection, franchise
+                    WHERE collection.reference = franchise.id AND collection.id = ?1",
+                [franchise],
+            )
+            .unwrap();
+        assert_eq!(series_type, CollectionType::Franchise);
+        assert_eq!(franchise_title, "Lost");
+    }
+
+    #[test]
+    fn episodes_without_a_season_directory_end_up_in_the_only_season_of_the_series() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Firefly/Serenity - e1.mp4"), "serenity", 10);
+        fs.write(&path("Firefly/The Train Job - e2.mp4"), "the train job", 10);
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let season_one = (CollectionType::Season, "Season 1".to_owned());
+        assert_eq!(
+            collection_of(&conn, "Firefly/Serenity - e1.mp4"),
+            season_one
+        );
+        assert_eq!(
+            collection_of(&conn, "Firefly/The Train Job - e2.mp4"),
+            season_one
+        );
+
+        // A series with a single season shows its episodes directly
+        let season: u64 = conn
+            .query_row_get(
+                "SELECT collection_id FROM collection_contains WHERE type = ?1 AND reference = ?2",
+                params![
+                    TableId::Content,
+                    content_of(&conn, "Firefly/Serenity - e1.mp4").unwrap()
+                ],
+            )
+            .unwrap();
+        let series = parent_collection(&conn, season);
+        let (seasons, title): (u64, String) = conn
+            .query_row_into(
+                "SELECT COUNT(*), series.title FROM collection_contains, collection AS series_collection, series
+                    WHERE collection_contains.collection_id = ?1
+                    AND collection_contains.type = ?2
+                    AND series_collection.id = ?1
+                    AND series_collection.reference = series.id",
+                params![series, TableId::Collection],
+            )
+            .unwrap();
+        assert_eq!((seasons, title.as_str()), (1, "Firefly"));
+    }
+
+    #[test]
+    fn episodes_in_disc_directories_end_up_in_the_season_above() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Lost/Season 1/Pilot - s1e1.mp4"), "pilot", 10);
+        fs.write(
+            &path("Lost/Season 2/Disc 1/Man of Science - s2e1.mp4"),
+            "man of science",
+            10,
+        );
+        fs.write(
+            &path("Lost/Season 2/Disc 2/Adrift - s2e2.mp4"),
+            "adrift",
+            10,
+        );
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let season_two = (CollectionType::Season, "Season 2".to_owned());
+        for episode in [
+            "Lost/Season 2/Disc 1/Man of Science - s2e1.mp4",
+            "Lost/Season 2/Disc 2/Adrift - s2e2.mp4",
+        ] {
+            assert_eq!(collection_of(&conn, episode), season_two);
+            // Episodes are never parts of each other, even though the directories look like parts of a movie
+            let part: u64 = conn
+                .query_row_get(
+                    "SELECT part FROM content WHERE id = ?1",
+                    [content_of(&conn, episode).unwrap()],
+                )
+                .unwrap();
+            assert_eq!(part, 0);
+        }
+
+        // The disc directories aren't taken for the series, both seasons are in the same one
+        let season_collection = |relative: &str| -> u64 {
+            conn.query_row_get(
+                "SELECT collection_id FROM collection_contains WHERE type = ?1 AND reference = ?2",
+                params![TableId::Content, content_of(&conn, relative).unwrap()],
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            parent_collection(&conn, season_collection("Lost/Season 1/Pilot - s1e1.mp4")),
+            parent_collection(
+                &conn,
+                season_collection("Lost/Season 2/Disc 2/Adrift - s2e2.mp4")
+            )
+        );
+    }
+
+    #[test]
+    fn themes_point_at_the_collection_they_are_next_to() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Alien/Alien.mp4"), "alien", 10);
+        fs.write(&path("Alien/Aliens/Aliens.mp4"), "aliens", 10);
+        fs.write(&path("Alien/theme.mp3"), "theme", 10);
+
+        index(&mut conn, &fs, &clock);
+
+        let franchise: u64 = conn
+            .query_row_get(
+                "SELECT collection_id FROM collection_contains WHERE type = ?1 AND reference = ?2",
+                params![
+                    TableId::Content,
+                    content_of(&conn, "Alien/Alien.mp4").unwrap()
+                ],
+            )
+            .unwrap();
+        let (target_type, target): (TableId, u64) = conn
+            .query_row_into(
+                "SELECT theme.type, theme.theme_target FROM theme, collection, collection_contains
+                    WHERE collection.reference = theme.id
+                    AND collection.type = ?1
+                    AND collection_contains.collection_id = collection.id
+                    AND collection_contains.type = ?2
+                    AND collection_contains.reference = ?3",
+                params![
+                    CollectionType::Theme,
+                    TableId::Content,
+                    content_of(&conn, "Alien/theme.mp3").unwrap()
+                ],
+            )
+            .unwrap();
+        assert_eq!(target_type, TableId::Collection);
+        assert_eq!(target, franchise);
+    }
+
+    #[test]
+    fn technical_details_are_recorded_for_probed_files() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        fs.write(&path("Ronin/Ronin.mp4"), "ronin", 10);
+        fs.set_details(
+            &path("Heat/Heat.mp4"),
+            probe::TechnicalDetails {
+                container: "mov,mp4,m4a,3gp,3g2,mj2".to_owned(),
+                video_codec: Some("h264".to_owned()),
+                resolution: Some((1920, 800)),
+                audio: vec!["aac stereo (eng)".to_owned(), "ac3 5.1 (ger)".to_owned()],
+                bitrate: Some(8_000_000),
+            },
+        );
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        type Row = (
+            String,
+            Option<String>,
+            Option<u32>,
+            Option<u32>,
+            Option<String>,
+            Option<u64>,
+        );
+        let details: Row = conn
+            .query_row_into(
+                "SELECT container, video_codec, width, height, audio, bitrate
+                    FROM technical_details WHERE content_id = ?1",
+                [content_of(&conn, "Heat/Heat.mp4").unwrap()],
+            )
+            .unwrap();
+        assert_eq!(
+            details,
+            (
+                "mov,mp4,m4a,3gp,3g2,mj2".to_owned(),
+                Some("h264".to_owned()),
+                Some(1920),
+                Some(800),
+                Some("aac stereo (eng),ac3 5.1 (ger)".to_owned()),
+                Some(8_000_000)
+            )
+        );
+
+        // A file ffmpeg can't open is still indexed, just without details
+        let ronin = content_of(&conn, "Ronin/Ronin.mp4").unwrap();
+        let probed: bool = conn
+            .query_row_get(
+                "SELECT EXISTS (SELECT 1 FROM technical_details WHERE content_id = ?1)",
+                [ronin],
+            )
+            .unwrap();
+        assert!(!probed);
+    }
+
+    #[test]
+    fn a_location_inside_an_extras_folder_holds_regular_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        let location = "/srv/Extras/Movies!noclassify";
+        conn.execute(
+            "INSERT INTO storage_locations (path, recurse) VALUES (?1, 1)",
+            [location],
+        )
+        .unwrap();
+        fs.write(&format!("{location}/Heat/Heat.mp4"), "heat", 10);
+        fs.write(
+            &format!("{location}/Heat/Extras/Making of.mp4"),
+            "making of",
+            10,
+        );
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let content_type = |file: &str| -> ContentType {
+            conn.query_row_get(
+                "SELECT content.type FROM content, data_file
+                    WHERE content.data_id = data_file.id AND data_file.path = ?1",
+                [format!("{location}/{file}")],
+            )
+            .unwrap()
+        };
+        assert_eq!(content_type("Heat/Heat.mp4"), ContentType::Movie);
+        assert_eq!(
+            content_type("Heat/Extras/Making of.mp4"),
+            ContentType::Extra
+        );
+    }
+
+    /// Whether the content still exists and the file it is played from, if any
+    fn file_of(conn: &rusqlite::Connection, content_id: u64) -> Option<Option<u64>> {
+        conn.query_row_get("SELECT data_id FROM content WHERE id = ?1", [content_id])
+            .optional()
+            .unwrap()
+    }
+
+    #[test]
+    fn unchanged_files_keep_their_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        clock.advance(60);
+        assert!(!index(&mut conn, &fs, &clock));
+        assert_eq!(content_of(&conn, "Heat/Heat.mp4"), Some(heat));
+    }
+
+    #[test]
+    fn changed_files_get_new_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let old = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        fs.write(&path("Heat/Heat.mp4"), "heat, remastered", 20);
+        clock.advance(60);
+        assert!(index(&mut conn, &fs, &clock));
+
+        let new = content_of(&conn, "Heat/Heat.mp4").unwrap();
+        assert_ne!(new, old);
+        // The old content lost its file just now, so it is kept for the whole retention period
+        assert_eq!(file_of(&conn, old), Some(None));
+        let last_changed: u64 = conn
+            .query_row_get("SELECT last_changed FROM content WHERE id = ?1", [old])
+            .unwrap();
+        assert_eq!(last_changed, clock.now());
+    }
+
+    #[test]
+    fn new_files_are_added_to_what_is_there() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        fs.write(&path("Ronin/Ronin.mp4"), "ronin", 50);
+        clock.advance(60);
+        assert!(index(&mut conn, &fs, &clock));
+
+        assert_eq!(content_of(&conn, "Heat/Heat.mp4"), Some(heat));
+        assert!(content_of(&conn, "Ronin/Ronin.mp4").is_some());
+    }
+
+    #[test]
+    fn renamed_files_keep_their_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        fs.rename(&path("Heat/Heat.mp4"), &path("Heat/Heat (1995).mp4"));
+        clock.advance(60);
+        assert!(index(&mut conn, &fs, &clock));
+
+        assert_eq!(content_of(&conn, "Heat/Heat (1995).mp4"), Some(heat));
+    }
+
+    /// Every file that holds the content, the played one first
+    fn files_of(conn: &rusqlite::Connection, content_id: u64) -> Vec<String> {
+        conn.prepare(
+            "SELECT data_file.path FROM data_file, content
+                WHERE content.id = ?1
+                AND (data_file.id = content.data_id OR data_file.duplicate_of = content.id)
+                ORDER BY data_file.id != content.data_id, data_file.id",
+        )
+        .unwrap()
+        .query_map_get([content_id])
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn identical_files_are_one_content_with_every_file() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        fs.write(&path("Backup/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+
+        let content: u64 = conn
+            .query_row_get("SELECT COUNT(*) FROM content", [])
+            .unwrap();
+        assert_eq!(content, 1);
+
+        let heat: u64 = conn.query_row_get("SELECT id FROM content", []).unwrap();
+        let mut files = files_of(&conn, heat);
+        files.sort();
+        assert_eq!(files, [path("Backup/Heat.mp4"), path("Heat/Heat.mp4")]);
+    }
+
+    #[test]
+    fn a_copy_is_played_once_the_original_is_gone() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        fs.write(&path("Backup/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat: u64 = conn.query_row_get("SELECT id FROM content", []).unwrap();
+        let played = files_of(&conn, heat).remove(0);
+
+        fs.remove(&played);
+        clock.advance(60);
+        assert!(index(&mut conn, &fs, &clock));
+
+        let remaining = files_of(&conn, heat);
+        assert_eq!(remaining.len(), 1);
+        assert_ne!(remaining[0], played);
+        assert_eq!(
+            content_of(
+                &conn,
+                remaining[0].strip_prefix(&format!("{ROOT}/")).unwrap()
+            ),
+            Some(heat)
+        );
+    }
+
+    #[test]
+    fn missing_content_is_purged_once_the_retention_has_passed() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        fs.remove(&path("Heat/Heat.mp4"));
+        clock.advance(60);
+        assert!(index(&mut conn, &fs, &clock));
+        assert_eq!(file_of(&conn, heat), Some(None));
+
+        // Still within the retention period, the file might come back
+        clock.advance(RETENTION_DAYS * 24 * 60 * 60);
+        assert!(!index(&mut conn, &fs, &clock));
+        assert_eq!(file_of(&conn, heat), Some(None));
+
+        clock.advance(1);
+        assert!(index(&mut conn, &fs, &clock));
+        assert_eq!(file_of(&conn, heat), None);
+    }
+
+    #[test]
+    fn purging_one_part_keeps_the_movie_of_the_others() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Kill Bill/CD1/Kill Bill.mp4"), "kill bill 1", 10);
+        fs.write(&path("Kill Bill/CD2/Kill Bill.mp4"), "kill bill 2", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let existing_part = content_of(&conn, "Kill Bill/CD1/Kill Bill.mp4").unwrap();
+        let purged_part = content_of(&conn, "Kill Bill/CD2/Kill Bill.mp4").unwrap();
+        let kill_bill = movie_of(&conn, "Kill Bill/CD1/Kill Bill.mp4");
+
+        fs.remove(&path("Kill Bill/CD2/Kill Bill.mp4"));
+        assert!(index(&mut conn, &fs, &clock));
+        clock.advance(RETENTION_DAYS * 24 * 60 * 60 + 1);
+        assert!(index(&mut conn, &fs, &clock));
+        assert_eq!(file_of(&conn, purged_part), None);
+
+        assert_eq!(movie_of(&conn, "Kill Bill/CD1/Kill Bill.mp4"), kill_bill);
+        assert_eq!(content_title(&conn, existing_part).unwrap(), "Kill Bill");
+    }
+
+    #[test]
+    fn unreachable_locations_keep_their_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        assert!(index(&mut conn, &fs, &clock));
+        let heat = content_of(&conn, "Heat/Heat.mp4").unwrap();
+
+        fs.set_unreachable(ROOT, true);
+        clock.advance(RETENTION_DAYS * 24 * 60 * 60 * 2);
+        assert!(!index(&mut conn, &fs, &clock));
+        assert_eq!(content_of(&conn, "Heat/Heat.mp4"), Some(heat));
+
+        fs.set_unreachable(ROOT, false);
+        assert!(!index(&mut conn, &fs, &clock));
+        assert_eq!(content_of(&conn, "Heat/Heat.mp4"), Some(heat));
+    }
+
+    /// The movie the content is a part of
+    fn movie_of(conn: &rusqlite::Connection, relative: &str) -> u64 {
+        conn.query_row_get(
+            "SELECT reference FROM content WHERE id = ?1",
+            [content_of(conn, relative).unwrap()],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parts_only_join_movies_in_the_same_directory() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Alien/CD1/Alien.mp4"), "alien 1", 10);
+        fs.write(&path("Alien/CD2/Alien.mp4"), "alien 2", 10);
+        // Also titled "Alien" and its path starts with "Alien", but it is a different movie
+        fs.write(&path("Alien (1979)/CD1/Alien.mp4"), "alien 1979", 10);
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        let alien = movie_of(&conn, "Alien/CD1/Alien.mp4");
+        assert_eq!(movie_of(&conn, "Alien/CD2/Alien.mp4"), alien);
+        assert_ne!(movie_of(&conn, "Alien (1979)/CD1/Alien.mp4"), alien);
+    }
+
+    #[test]
+    fn ignored_files_produce_no_content() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        fs.write(&path("Heat/sample.mp4"), "heat sample", 10);
+
+        let patterns = ["sample.*".to_owned()];
+        assert!(indexing(&mut conn, &fs, &clock, &[], &patterns, RETENTION_DAYS).unwrap());
+        assert!(content_of(&conn, "Heat/Heat.mp4").is_some());
+        assert_eq!(content_of(&conn, "Heat/sample.mp4"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dry_runs_describe_files_without_indexing_them() {
+        let dir = TempDir::new("dry-run");
+        let location = dir.dir("media!noclassify");
+        dir.file("media!noclassify/Heat/Heat.mp4");
+        dir.file("media!noclassify/Lost/Season 1/Pilot - s1e1.mp4");
+        dir.file("media!noclassify/Lost/Season 1/Tabula Rasa - s1e2.mp4.part");
+        let conn = in_memory();
+
+        let described = dry_run(
+            &conn,
+            &location,
+            &["Extras".to_owned()],
+            &["*.part".to_owned()],
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(file, description)| {
+            (
+                file.strip_prefix(&location).unwrap().to_owned(),
+                description,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            described,
+            [
+                (
+                    PathBuf::from("Heat/Heat.mp4"),
+                    r#"Movie "Heat" in Franchise "Heat" > Movie "Heat" (Certain)"#.to_owned()
+                ),
+                (
+                    PathBuf::from("Lost/Season 1/Pilot - s1e1.mp4"),
+                    r#"Episode 1 "Pilot" in Franchise "Lost" > Series "Lost" > Season 1 (Certain)"#
+                        .to_owned()
+                ),
+            ]
+        );
+
+        let (content, files): (u64, u64) = conn
+            .query_row_into(
+                "SELECT (SELECT COUNT(*) FROM content), (SELECT COUNT(*) FROM data_file)",
+                [],
+            )
+            .unwrap();
+        assert_eq!((content, files), (0, 0));
+    }
+
+    #[test]
+    fn indexing_finishes_on_symlink_loops() {
+        let dir = TempDir::new("indexing-symlink-loop");
+        dir.file("Soundtrack/Theme.mp3");
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("Soundtrack/loop")).unwrap();
+
+        let mut conn = in_memory();
+        conn.execute(
+            "INSERT INTO storage_locations (path, recurse) VALUES (?1, 1)",
+            [dir.path().to_str().unwrap()],
+        )
+        .unwrap();
+
+        assert!(indexing(
+            &mut conn,
+            &LocalFileSystem,
+            &SystemClock,
+            &[],
+            &[],
+            RETENTION_DAYS
+        )
+        .unwrap());
+        let files: u64 = conn
+            .query_row_get("SELECT COUNT(*) FROM data_file", [])
+            .unwrap();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn a_file_that_fails_to_classify_is_recorded_and_leaves_the_others_alone() {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        fs.write(&path("Heat/Heat.mp4"), "heat", 10);
+        fs.write(&path("Lost/Season 1/Pilot - s1e1.mp4"), "pilot", 10);
+        // Looks like a season directory, but there is no number after "season"
+        fs.write(&path("Seasoned/Seasoned.mp4"), "seasoned", 10);
+
+        assert!(index(&mut conn, &fs, &clock));
+
+        assert_eq!(
+            collection_of(&conn, "Heat/Heat.mp4"),
+            (CollectionType::Franchise, "Heat".to_owned())
+        );
+        assert_eq!(
+            collection_of(&conn, "Lost/Season 1/Pilot - s1e1.mp4"),
+            (CollectionType::Season, "Season 1".to_owned())
+        );
+        assert_eq!(content_of(&conn, "Seasoned/Seasoned.mp4"), None);
+
+        let error = |relative: &str| -> Option<String> {
+            conn.query_row_get(
+                "SELECT classification_error FROM data_file WHERE path = ?1",
+                [path(relative)],
+            )
+            .unwrap()
+        };
+        assert!(error("Seasoned/Seasoned.mp4").unwrap().contains("Seasoned"));
+        assert_eq!(error("Heat/Heat.mp4"), None);
+    }
+
+    /// How many transactions indexing this many movies commits
+    fn commits_for(movies: usize) -> usize {
+        let (mut conn, fs, clock) = (library(), FakeFileSystem::default(), FakeClock::new(100));
+        for i in 0..movies {
+            fs.write(
+                &path(&format!("Movie {i}/Movie {i}.mp4")),
+                &format!("movie {i}"),
+                10,
+            );
+        }
+
+        let commits = Arc::new(AtomicUsize::new(0));
+        conn.commit_hook(Some({
+            let commits = commits.clone();
+            move || {
+                commits.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }));
+        assert!(index(&mut conn, &fs, &clock));
+        conn.commit_hook(None::<fn() -> bool>);
+
+        let indexed: usize = conn
+            .query_row_get(
+                "SELECT COUNT(*) FROM content WHERE type = ?1",
+                [ContentType::Movie],
+            )
+            .unwrap();
+        assert_eq!(indexed, movies);
+        commits.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn large_libraries_are_written_in_as_many_transactions_as_small_ones() {
+        assert_eq!(commits_for(1000), commits_for(10));
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use rusqlite::params;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::state::AppResult;
+
+use super::{classify::ClassificationCategory, environment::FileSystem, movie_directory};
+
+/// The parts of a Kodi style .nfo file that are used, the root element can be anything like <movie> or <episodedetails>
+#[derive(Deserialize)]
+pub struct Nfo {
+    pub title: Option<String>,
+    pub year: Option<u32>,
+    pub plot: Option<String>,
+}
+
+/// Looks for "<file name>.nfo" next to movies and episodes, movies can also have a "movie.nfo" in their own directory
+pub fn find_nfo(
+    path: &Path,
+    category: &ClassificationCategory,
+    fs: &impl FileSystem,
+) -> Option<Nfo> {
+    let mut candidates = vec![path.with_extension("nfo")];
+    match category {
+        // The directory above a movie is shared with others, like the one of its franchise
+        ClassificationCategory::Movie => {
+            candidates.extend(movie_directory(path).map(|directory| directory.join("movie.nfo")))
+        }
+        ClassificationCategory::Episode { .. } => {}
+        _ => return None,
+    }
+
+    candidates.into_iter().find_map(|candidate| {
+        let content = fs.read_to_string(&candidate)?;
+        Some(parse_nfo(&candidate, &content))
+    })?
+}
+
+/// Malformed files are ignored with a warning, the information is then inferred like without one
+fn parse_nfo(path: &Path, content: &str) -> Option<Nfo> {
+    let nfo: Nfo = quick_xml::de::from_str(content)
+        .map_err(|e| warn!("Ignoring malformed nfo file {path:?}: {e}"))
+        .ok()?;
+
+    // Empty elements are as good as missing ones
+    Some(Nfo {
+        title: nfo.title.filter(|title| !title.trim().is_empty()),
+        year: nfo.year,
+        plot: nfo.plot.filter(|plot| !plot.trim().is_empty()),
+    })
+}
+
+pub fn store(conn: &rusqlite::Connection, content_id: u64, nfo: &Nfo) -> AppResult<()> {
+    conn.prepare_cached("INSERT INTO nfo (content_id, title, year, plot) VALUES (?1, ?2, ?3, ?4)")?
+        .execute(params![content_id, nfo.title, nfo.year, nfo.plot])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::environment::fake::FakeFileSystem;
+
+    fn nfo(title: &str) -> String {
+        format!("<movie><title>{title}</title></movie>")
+    }
+
+    fn title(path: &str, category: &ClassificationCategory, fs: &FakeFileSystem) -> Option<String> {
+        find_nfo(Path::new(path), category, fs)?.title
+    }
+
+    #[test]
+    fn the_nfo_named_like_the_file_comes_first() {
+        let fs = FakeFileSystem::default();
+        fs.write("/media/Heat/Heat.nfo", &nfo("Heat (1995)"), 0);
+        fs.write("/media/Heat/movie.nfo", &nfo("Something else"), 0);
+        fs.write("/media/Lost/Pilot - s1e1.nfo", &nfo("Pilot"), 0);
+
+        let movie = ClassificationCategory::Movie;
+        let episode = ClassificationCategory::Episode { episode: 1 };
+        assert_eq!(
+            title("/media/Heat/Heat.mp4", &movie, &fs).as_deref(),
+            Some("Heat (1995)")
+        );
+        assert_eq!(
+            title("/media/Lost/Pilot - s1e1.mp4", &episode, &fs).as_deref(),
+            Some("Pilot")
+        );
+    }
+
+    #[test]
+    fn movie_nfo_is_only_read_from_the_directory_of_the_movie() {
+        let fs = FakeFileSystem::default();
+        fs.write("/media/Heat/movie.nfo", &nfo("Heat"), 0);
+        fs.write("/media/Alien/movie.nfo", &nfo("Alien"), 0);
+        fs.write("/media/Kill Bill/movie.nfo", &nfo("Kill Bill"), 0);
+
+        let movie = ClassificationCategory::Movie;
+        assert_eq!(
+            title("/media/Heat/Heat.mp4", &movie, &fs).as_deref(),
+            Some("Heat")
+        );
+        // Parts of a movie share the directory above the part directories
+        assert_eq!(
+            title("/media/Kill Bill/CD2/Kill Bill.mp4", &movie, &fs).as_deref(),
+            Some("Kill Bill")
+        );
+        // The franchise directory above is not the directory of this movie
+        assert_eq!(title("/media/Alien/Aliens/Aliens.mp4", &movie, &fs), None);
+    }
+
+    #[test]
+    fn movie_nfo_is_only_used_for_movies() {
+        let fs = FakeFileSystem::default();
+        fs.write("/media/Lost/movie.nfo", &nfo("Lost"), 0);
+        fs.write("/media/Lost/Season 1/movie.nfo", &nfo("Lost"), 0);
+        fs.write("/media/Lost/Extras/Interview.nfo", &nfo("Interview"), 0);
+
+        let episode = ClassificationCategory::Episode { episode: 1 };
+        assert_eq!(
+            title("/media/Lost/Season 1/Pilot - s1e1.mp4", &episode, &fs),
+            None
+        );
+        assert_eq!(
+            title(
+                "/media/Lost/Extras/Interview.mp4",
+                &ClassificationCategory::Extra,
+                &fs
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn malformed_and_empty_nfo_files_are_ignored() {
+        let fs = FakeFileSystem::default();
+        fs.write("/media/Heat/Heat.nfo", "<movie><title>Heat", 0);
+        fs.write(
+            "/media/Ronin/Ronin.nfo",
+            "<movie><title> </title><year>1998</year></movie>",
+            0,
+        );
+
+        let movie = ClassificationCategory::Movie;
+        assert!(find_nfo(Path::new("/media/Heat/Heat.mp4"), &movie, &fs).is_none());
+        let ronin = find_nfo(Path::new("/media/Ronin/Ronin.mp4"), &movie, &fs).unwrap();
+        assert_eq!((ronin.title, ronin.year), (None, Some(1998)));
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use ffmpeg::{codec, format::stream::Disposition, media};
+use rusqlite::params;
+
+use crate::state::AppResult;
+
+/// What ffmpeg knows about a file, mostly useful for troubleshooting playback
+#[derive(Debug, Clone, PartialEq)]
+pub struct TechnicalDetails {
+    pub container: String,
+    pub video_codec: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    /// Codec, channels and language of every audio stream, like "aac stereo (eng)", the default one first
+    pub audio: Vec<String>,
+    /// Bits per second
+    pub bitrate: Option<u64>,
+}
+
+pub fn probe(path: &Path) -> AppResult<TechnicalDetails> {
+    let context = ffmpeg::format::input(&path)?;
+
+    let container = context.format().name().to_owned();
+    let bitrate = u64::try_from(context.bit_rate())
+        .ok()
+        .filter(|bitrate| *bitrate > 0);
+
+    // ffmpeg's own pick of the best stream can be cover art that is stored as a video stream
+    // so those are left out and the real video with the most pixels is used
+    let (mut video_codec, mut resolution) = (None, None);
+    let mut most_pixels = 0;
+    for stream in context.streams().filter(|stream| {
+        stream.parameters().medium() == media::Type::Video
+            && !stream.disposition().contains(Disposition::ATTACHED_PIC)
+    }) {
+        let parameters = stream.parameters();
+        let name = parameters.id().name().to_owned();
+
+        let decoder = codec::context::Context::from_parameters(parameters)?
+            .decoder()
+            .video()?;
+        let pixels = u64::from(decoder.width()) * u64::from(decoder.height());
+
+        if video_codec.is_none() || pixels > most_pixels {
+            video_codec = Some(name);
+            resolution = Some((decoder.width(), decoder.height()));
+            most_pixels = pixels;
+        }
+    }
+
+    let mut audio = context
+        .streams()
+        .filter(|stream| stream.parameters().medium() == media::Type::Audio)
+        .map(|stream| {
+            let parameters = stream.parameters();
+            let mut description = parameters.id().name().to_owned();
+
+            // Streams without a decoder are still listed, just without their channels
+            let channels = codec::context::Context::from_parameters(parameters)
+                .and_then(|context| context.decoder().audio())
+                .map_or(0, |decoder| decoder.channels());
+            if let Some(channels) = channel_layout(channels) {
+                description.push(' ');
+                description.push_str(&channels);
+            }
+
+            if let Some(language) = stream.metadata().get("language") {
+                description.push_str(&format!(" ({language})"));
+            }
+            (
+                stream.disposition().contains(Disposition::DEFAULT),
+                description,
+            )
+        })
+        .collect::<Vec<_>>();
+    // Stable, so the rest keeps the order of the file
+    audio.sort_by_key(|(is_default, _)| !is_default);
+    let audio = audio
+        .into_iter()
+        .map(|(_, description)| description)
+        .collect();
+
+    Ok(TechnicalDetails {
+        container,
+        video_codec,
+        resolution,
+        audio,
+        bitrate,
+    })
+}
+
+/// The usual name for this many channels, None when there is no channel information
+fn channel_layout(channels: u16) -> Option<String> {
+    match channels {
+        0 => None,
+        1 => Some("mono".to_owned()),
+        2 => Some("stereo".to_owned()),
+        6 => Some("5.1".to_owned()),
+        8 => Some("7.1".to_owned()),
+        channels => Some(format!("{channels}ch")),
+    }
+}
+
+pub fn store(
+    conn: &rusqlite::Connection,
+    content_id: u64,
+    details: &TechnicalDetails,
+) -> AppResult<()> {
+    let audio = (!details.audio.is_empty()).then(|| details.audio.join(","));
+    let (width, height) = details.resolution.unzip();
+
+    conn.prepare_cached(
+        "INSERT INTO technical_details (content_id, container, video_codec, width, height, audio, bitrate)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?
+    .execute(params![
+        content_id,
+        details.container,
+        details.video_codec,
+        width,
+        height,
+        audio,
+        details.bitrate
+    ])?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{silence, silence_with_cover, TempDir};
+
+    #[test]
+    fn cover_art_is_not_taken_for_the_video() {
+        ffmpeg::init().unwrap();
+        let dir = TempDir::new("probe-cover");
+        let (plain, covered) = (dir.path().join("plain.wav"), dir.path().join("covered.wav"));
+        silence(&plain);
+        silence_with_cover(&covered);
+
+        // The cover is there, it just isn't the video
+        let context = ffmpeg::format::input(&covered).unwrap();
+        let video_streams = context
+            .streams()
+            .filter(|stream| stream.parameters().medium() == media::Type::Video)
+            .count();
+        assert_eq!(video_streams, 1);
+
+        let details = probe(&covered).unwrap();
+        assert_eq!(details.video_codec, None);
+        assert_eq!(details.resolution, None);
+        assert_eq!(details.audio, probe(&plain).unwrap().audio);
+    }
+}
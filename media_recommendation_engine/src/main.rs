@@ -2,36 +2,40 @@
 
 extern crate ffmpeg_next as ffmpeg;
 
-use std::collections::HashSet;
+use std::{collections::HashSet, path::PathBuf};
 
 use anyhow::Context;
-use axum::{middleware, response::Redirect, routing::get, Router};
+use axum::{middleware, routing::get, Router};
 
 use axum_login::{
     tower_sessions::{session_store::ExpiredDeletion, Expiry, SessionManagerLayer},
     AuthManagerLayerBuilder,
 };
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use futures_util::FutureExt;
 use state::{AppResult, Shutdown};
 use time::Duration;
 use tokio::{net::TcpListener, signal};
 
-use tower_sessions::cookie::Key;
-use tracing::{error, info};
+use tower_sessions::cookie::{Key, SameSite};
+use tracing::{error, info, warn};
 
 use crate::{
-    database::Database,
+    database::{Database, PoolConfig, QueryRowGetConnExt},
     indexing::periodic_indexing,
     routes::dynamic_content,
     state::AppState,
-    utils::{htmx, init_tracing, login_required, HandleErr, TraceLayerExt},
+    utils::{
+        base_path, create_user, csrf_protection, htmx, init_tracing, login_required, set_base_path,
+        validate_credentials, CredentialKind, HandleErr, ServerSettings, TraceLayerExt,
+    },
 };
 
 #[macro_use]
 mod utils;
 mod database;
 mod indexing;
+mod metadata;
 mod recommendation;
 mod routes;
 mod state;
@@ -40,7 +44,8 @@ mod state;
 async fn main() {
     let mut args = Args::parse();
 
-    init_tracing(args.logging);
+    let _log_guard = init_tracing(args.logging, args.log_format, args.log_dir.as_deref());
+    set_base_path(&args.base_path);
     ffmpeg::init().expect("failed to initialize ffmpeg");
 
     if let Err(err) = handle_data_delete(args.delete_data).await {
@@ -48,8 +53,15 @@ async fn main() {
         return;
     }
 
+    if let Some(command) = args.command {
+        if let Err(err) = handle_command(command, args.pool).await {
+            error!("{err}");
+        }
+        return;
+    }
+
     loop {
-        let should_restart = server(std::mem::take(&mut args.port), args.logging).await;
+        let should_restart = server(std::mem::take(&mut args.port), args.logging, args.pool).await;
         if !should_restart {
             break;
         }
@@ -59,8 +71,8 @@ async fn main() {
     info!("Suceessfully shut down");
 }
 
-async fn server(port: Option<u16>, logging: Logging) -> bool {
-    let db = Database::new().expect("failed to connect to database");
+async fn server(port: Option<u16>, logging: Logging, pool: PoolConfig) -> bool {
+    let db = Database::new(pool).expect("failed to connect to database");
 
     let session_store = db.clone();
 
@@ -70,35 +82,58 @@ async fn server(port: Option<u16>, logging: Logging) -> bool {
             .continuously_delete_expired(tokio::time::Duration::from_secs(60)),
     );
 
+    let (state, restart) = AppState::new(db.clone(), port).await;
+
+    let settings = state.serversettings.clone();
+
+    // Other sites can't make the browser send the cookie, a separate frontend allowed through CORS has to be on the same site
+    // like another port or subdomain of it, the routes that start or connect to sessions also check the Origin
     let session_layer = SessionManagerLayer::new(session_store.clone())
         .with_secure(false)
+        .with_same_site(SameSite::Strict)
         .with_expiry(Expiry::OnInactivity(Duration::days(1)))
         .with_signed(Key::generate());
 
     let auth = AuthManagerLayerBuilder::new(session_store, session_layer).build();
 
-    let (state, restart) = AppState::new(db.clone(), port).await;
-
-    let settings = state.serversettings.clone();
     let indexing_trigger = state.indexing_trigger.clone();
+    let content_updates = state.content_updates.clone();
     let shutdown = state.shutdown.clone();
 
     let app = Router::new()
         .route("/", get(routes::homepage))
         .merge(routes::library())
         .route("/explore", get(routes::explore))
-        .nest("/settings", routes::settings())
-        .nest("/video", routes::streaming())
+        .nest(
+            "/settings",
+            routes::settings().layer(middleware::from_fn(csrf_protection)),
+        )
+        .nest(
+            "/video",
+            routes::streaming(settings.clone()).layer(middleware::from_fn(csrf_protection)),
+        )
         .layer(middleware::from_fn(login_required))
+        .nest("/api/v1", routes::api(settings.clone()))
         .merge(htmx())
-        .merge(dynamic_content())
-        .nest("/auth", routes::login())
+        .merge(dynamic_content(settings.clone()))
+        .merge(routes::media())
+        .nest(
+            "/auth",
+            routes::login().layer(middleware::from_fn(csrf_protection)),
+        )
         .route("/error", get(routes::error))
-        .fallback(Redirect::permanent("/error?err=404"))
+        .route("/version", get(routes::version))
+        .route("/api/v1/version", get(routes::version))
+        .fallback(routes::not_found)
         .tracing_layer(logging)
         .with_state(state)
         .layer(auth);
 
+    let app = match base_path() {
+        "" => app,
+        base => Router::new().nest(base, app),
+    };
+
     if let Some(port) = port {
         settings.set_port(port);
     }
@@ -112,10 +147,13 @@ async fn server(port: Option<u16>, logging: Logging) -> bool {
 
     info!("Starting server on {ip}");
 
+    tokio::spawn(db.clone().checkpoint_task(shutdown.clone()));
+
     tokio::spawn(periodic_indexing(
         db,
         settings,
         indexing_trigger,
+        content_updates,
         shutdown.clone(),
     ));
 
@@ -196,9 +234,76 @@ async fn handle_data_delete(delete_data: Option<Vec<DeleteKind>>) -> AppResult<(
     Ok(())
 }
 
+/// Runs the command against the database and returns without starting the server
+async fn handle_command(command: Command, pool: PoolConfig) -> AppResult<()> {
+    match command {
+        Command::CreateUser {
+            username,
+            password,
+            owner,
+        } => {
+            let username = match validate_credentials(CredentialKind::Username, &username) {
+                Ok(username) => username.to_owned(),
+                Err(msg) => bail!(msg),
+            };
+            if let Err(msg) = validate_credentials(CredentialKind::Password, &password) {
+                bail!(msg);
+            }
+
+            let (admin, new_users) = ServerSettings::user_rules().await;
+            if owner && admin.is_some_and(|admin| admin != username) {
+                warn!("The config file also has an admin, that account stays an owner next to \"{username}\"");
+            }
+
+            let db = Database::new(pool)?;
+            let conn = db.get()?;
+
+            let user_exists = conn.query_row_get::<bool>(
+                "SELECT exists(SELECT 1 FROM users WHERE username = ?1)",
+                [&username],
+            )?;
+            if user_exists {
+                bail!(format!("The user \"{username}\" already exists"));
+            }
+
+            let password = tokio::task::spawn_blocking(|| password_auth::generate_hash(password))
+                .await
+                .context("Failed to generate password hash")?;
+
+            let user_id = create_user(&conn, &username, &password, &new_users)?;
+
+            if owner {
+                conn.execute(
+                    "INSERT INTO user_permissions (userid, permissionid) SELECT ?1, id FROM permissions WHERE name = 'owner'",
+                    [user_id],
+                )?;
+                info!("Created the owner \"{username}\"");
+            } else {
+                info!("Created the user \"{username}\"");
+            }
+        }
+        Command::DryRunIndex { path } => {
+            let (extras_folders, ignored_patterns) = ServerSettings::indexing_rules().await;
+
+            let described = tokio::task::spawn_blocking(move || {
+                let conn = Database::read_only()?;
+                indexing::dry_run(&conn, &path, &extras_folders, &ignored_patterns)
+            })
+            .await
+            .context("Failed to wait for the dry run")??;
+
+            for (file, description) in described {
+                println!("{}\n    {description}", file.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "Media Recommendation Engine")]
-#[command(version = "0.0.1")]
+#[command(version)]
 #[command(about = "Media Recommendation Engine", long_about = None, )]
 struct Args {
     /// Set the port on first startup. Defaults to 3000
@@ -218,6 +323,38 @@ struct Args {
     #[cfg_attr(debug_assertions, arg(default_value_t = Logging::Debug))]
     #[cfg_attr(not(debug_assertions), arg(default_value_t = Logging::Info))]
     logging: Logging,
+    /// Set the format that logs are written in
+    #[arg(long, value_enum, env = "MRE_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Additionally write logs into this directory, a new file is started every day
+    #[arg(long, env = "MRE_LOG_DIR")]
+    log_dir: Option<PathBuf>,
+    /// Serve everything under this path, like "/media" when a reverse proxy forwards https://host/media/ unchanged
+    #[arg(long, env = "MRE_BASE_PATH", default_value = "")]
+    base_path: String,
+    #[command(flatten)]
+    pool: PoolConfig,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates a user with the defaults for new users and exits, for setting up accounts without the web interface
+    /// Owners created here are kept next to the [admin] from the config file, leave that out to only have these
+    CreateUser {
+        #[arg(long)]
+        username: String,
+        /// Can also be passed through the environment, so it doesn't show up in the process list
+        #[arg(long, env = "MRE_CREATE_USER_PASSWORD", hide_env_values = true)]
+        password: String,
+        /// Gives the user the owner permission
+        #[arg(long)]
+        owner: bool,
+    },
+    /// Prints how every file below the path would be classified, without changing the database
+    /// Uses the extras folders and ignored patterns from the config file
+    DryRunIndex { path: PathBuf },
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, ValueEnum)]
@@ -247,3 +384,11 @@ enum Logging {
     /// Logs everything
     All,
 }
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One json object per line, for ingestion into other tools
+    Json,
+}
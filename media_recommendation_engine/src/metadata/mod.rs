@@ -0,0 +1,140 @@
+mod tmdb;
+
+use rusqlite::params;
+use tracing::{debug, info};
+
+use crate::{
+    database::{Database, QueryRowIntoStmtExt},
+    indexing::{strip_year, CollectionType, ContentType, TableId},
+    state::{AppResult, Shutdown},
+    utils::{HandleErr, ServerSettings},
+};
+
+use tmdb::Tmdb;
+
+/// Additional information about a movie or series that can't be derived from the filesystem
+pub struct Metadata {
+    pub overview: Option<String>,
+    pub poster_url: Option<String>,
+    pub genres: Vec<String>,
+}
+
+pub trait Provider {
+    async fn movie(&self, title: &str, year: Option<u32>) -> AppResult<Option<Metadata>>;
+    async fn series(&self, title: &str, year: Option<u32>) -> AppResult<Option<Metadata>>;
+}
+
+/// Looks up everything that doesn't have metadata yet, this is meant to run after indexing and never blocks it
+/// Entries that were looked up without a result are stored as empty, so they aren't requested again
+pub async fn fetch_metadata(db: Database, settings: ServerSettings, shutdown: Shutdown) {
+    let Some(api_key) = settings.tmdb_api_key() else {
+        debug!("No TMDB api key configured, skipping metadata lookup");
+        return;
+    };
+
+    let provider = Tmdb::new(api_key);
+
+    tokio::select! {
+        res = lookup_missing(&db, &provider) => {
+            res.log_warn_with_msg("Stopped looking up metadata");
+        }
+        _ = shutdown.cancelled() => {}
+    }
+}
+
+async fn lookup_missing(db: &Database, provider: &impl Provider) -> AppResult<()> {
+    let (movies, series) = {
+        let conn = db.get()?;
+
+        let movies = conn
+            .prepare(
+                "SELECT content.id, movie.title, COALESCE(nfo.year, movie.year), nfo.plot FROM content
+                    JOIN movie ON content.reference = movie.id
+                    LEFT JOIN nfo ON nfo.content_id = content.id
+                    WHERE content.type = ?1
+                    AND content.part = 0
+                    AND NOT EXISTS (SELECT 1 FROM metadata WHERE metadata.type = ?2 AND metadata.reference = content.id)",
+            )?
+            .query_map_into::<(u64, String, Option<u32>, Option<String>)>(params![
+                ContentType::Movie,
+                TableId::Content
+            ])?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let series = conn
+            .prepare(
+                "SELECT collection.id, series.title FROM collection, series
+                    WHERE collection.type = ?1
+                    AND collection.reference = series.id
+                    AND NOT EXISTS (SELECT 1 FROM metadata WHERE metadata.type = ?2 AND metadata.reference = collection.id)",
+            )?
+            .query_map_into::<(u64, String)>(params![CollectionType::Series, TableId::Collection])?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        (movies, series)
+    };
+
+    if movies.is_empty() && series.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "Looking up metadata for {} movies and {} series",
+        movies.len(),
+        series.len()
+    );
+
+    for (id, title, year, plot) in movies {
+        let mut metadata = provider.movie(&title, year).await?;
+        if metadata.is_none() {
+            debug!("Found no metadata for the movie \"{title}\"");
+        }
+
+        // The plot from an .nfo file was written for this library, so it wins over the looked up one
+        if let Some(plot) = plot {
+            metadata
+                .get_or_insert_with(|| Metadata {
+                    overview: None,
+                    poster_url: None,
+                    genres: Vec::new(),
+                })
+                .overview = Some(plot);
+        }
+        store(db, TableId::Content, id, metadata)?;
+    }
+
+    for (id, title) in series {
+        // Series titles come from directory names, which often look like "Series (2005)"
+        let (title, year) = strip_year(&title);
+        let title = title.trim();
+        let metadata = provider.series(title, year).await?;
+        if metadata.is_none() {
+            debug!("Found no metadata for the series \"{title}\"");
+        }
+        store(db, TableId::Collection, id, metadata)?;
+    }
+
+    Ok(())
+}
+
+fn store(
+    db: &Database,
+    table: TableId,
+    reference: u64,
+    metadata: Option<Metadata>,
+) -> AppResult<()> {
+    let metadata = metadata.unwrap_or(Metadata {
+        overview: None,
+        poster_url: None,
+        genres: Vec::new(),
+    });
+
+    let genres = (!metadata.genres.is_empty()).then(|| metadata.genres.join(","));
+
+    db.get()?.execute(
+        "INSERT INTO metadata (type, reference, overview, poster_url, genres) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![table, reference, metadata.overview, metadata.poster_url, genres],
+    )?;
+
+    Ok(())
+}
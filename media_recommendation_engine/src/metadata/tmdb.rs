@@ -0,0 +1,176 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{
+    sync::{Mutex, OnceCell},
+    time::Instant,
+};
+
+use crate::state::AppResult;
+
+use super::{Metadata, Provider};
+
+const API: &str = "https://api.themoviedb.org/3";
+const IMAGES: &str = "https://image.tmdb.org/t/p/w500";
+/// TMDB allows around 50 requests per second, this stays far below that
+const REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Movie,
+    Tv,
+}
+
+impl Kind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Kind::Movie => "movie",
+            Kind::Tv => "tv",
+        }
+    }
+
+    const fn year_key(self) -> &'static str {
+        match self {
+            Kind::Movie => "year",
+            Kind::Tv => "first_air_date_year",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    overview: Option<String>,
+    poster_path: Option<String>,
+    #[serde(default)]
+    genre_ids: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+struct GenreResponse {
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize)]
+struct Genre {
+    id: u64,
+    name: String,
+}
+
+pub struct Tmdb {
+    client: reqwest::Client,
+    api_key: String,
+    last_request: Mutex<Option<Instant>>,
+    movie_genres: OnceCell<HashMap<u64, String>>,
+    tv_genres: OnceCell<HashMap<u64, String>>,
+}
+
+impl Tmdb {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key,
+            last_request: Mutex::new(None),
+            movie_genres: OnceCell::new(),
+            tv_genres: OnceCell::new(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> AppResult<T> {
+        {
+            // Holding the lock while sleeping makes sure that requests are spaced out
+            let mut last_request = self.last_request.lock().await;
+            if let Some(last_request) = *last_request {
+                tokio::time::sleep_until(last_request + REQUEST_INTERVAL).await;
+            }
+            *last_request = Some(Instant::now());
+        }
+
+        let response = self
+            .client
+            .get(format!("{API}{path}"))
+            .query(&[("api_key", self.api_key.as_str())])
+            .query(query)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach TMDB for \"{path}\""))?
+            .error_for_status()
+            .with_context(|| format!("TMDB rejected the request for \"{path}\""))?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse the TMDB response for \"{path}\""))?;
+
+        Ok(response)
+    }
+
+    async fn genres(&self, kind: Kind) -> AppResult<&HashMap<u64, String>> {
+        let cell = match kind {
+            Kind::Movie => &self.movie_genres,
+            Kind::Tv => &self.tv_genres,
+        };
+
+        cell.get_or_try_init(|| async {
+            let response: GenreResponse = self
+                .get(&format!("/genre/{}/list", kind.as_str()), &[])
+                .await?;
+            Ok(response
+                .genres
+                .into_iter()
+                .map(|genre| (genre.id, genre.name))
+                .collect())
+        })
+        .await
+    }
+
+    async fn search(
+        &self,
+        kind: Kind,
+        title: &str,
+        year: Option<u32>,
+    ) -> AppResult<Option<Metadata>> {
+        let year = year.map(|year| year.to_string());
+        let mut query = vec![("query", title)];
+        if let Some(year) = year.as_deref() {
+            query.push((kind.year_key(), year));
+        }
+
+        let response: SearchResponse = self
+            .get(&format!("/search/{}", kind.as_str()), &query)
+            .await?;
+
+        let Some(result) = response.results.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let genres = self.genres(kind).await?;
+
+        Ok(Some(Metadata {
+            overview: result.overview.filter(|overview| !overview.is_empty()),
+            poster_url: result
+                .poster_path
+                .map(|poster_path| format!("{IMAGES}{poster_path}")),
+            genres: result
+                .genre_ids
+                .iter()
+                .filter_map(|id| genres.get(id).cloned())
+                .collect(),
+        }))
+    }
+}
+
+impl Provider for Tmdb {
+    async fn movie(&self, title: &str, year: Option<u32>) -> AppResult<Option<Metadata>> {
+        self.search(Kind::Movie, title, year).await
+    }
+
+    async fn series(&self, title: &str, year: Option<u32>) -> AppResult<Option<Metadata>> {
+        self.search(Kind::Tv, title, year).await
+    }
+}
@@ -1,145 +1,540 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use rusqlite::{params, OptionalExtension};
+use tokio::sync::{mpsc, oneshot, watch};
 
 use crate::{
-    database::{Connection, Database, QueryRowGetConnExt, QueryRowIntoConnExt},
+    database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt, QueryRowIntoStmtExt},
     indexing::{CollectionType, ContentType, TableId},
-    state::AppResult,
-    utils::{pseudo_random_range, templates::RecommendationPopup, HandleErr},
+    state::{AppResult, ContentUpdates, Shutdown},
+    utils::{
+        pseudo_random_range, templates::RecommendationPopup, HandleErr, Ignore, ServerSettings,
+    },
 };
 
-// Probably spawn a recommendation Engine and have a mpsc channel in appstate, to be able to make request to the recommendation engine, which responds with a future. This entire things makes it so there is one global state for the recommendor
+/// Handle to the one task that answers all recommendation requests, cheap to clone
+#[derive(Clone)]
+pub struct Recommender {
+    requests: mpsc::Sender<Request>,
+}
+
+struct Request {
+    content_id: u64,
+    reply: oneshot::Sender<AppResult<RecommendationPopup>>,
+}
+
+impl Recommender {
+    /// Requests are answered one after another, so many sessions ending at once don't all query the database at the same time
+    pub fn spawn(
+        db: Database,
+        settings: ServerSettings,
+        updates: &ContentUpdates,
+        shutdown: Shutdown,
+    ) -> Self {
+        let (requests, receiver) = mpsc::channel(32);
+        tokio::spawn(Self::engine(
+            db,
+            settings,
+            updates.subscribe(),
+            shutdown,
+            receiver,
+        ));
+        Self { requests }
+    }
+
+    async fn engine(
+        db: Database,
+        settings: ServerSettings,
+        mut updates: watch::Receiver<()>,
+        shutdown: Shutdown,
+        mut requests: mpsc::Receiver<Request>,
+    ) {
+        // Watch parties and popular content ask for the same recommendation over and over
+        // Anything in here can be outdated once indexing changed the library, so it is cleared then
+        // Only the episode that comes next is kept, None when there is none, random picks would stay the same otherwise
+        let mut cache: HashMap<(u64, bool), Option<RecommendationPopup>> = HashMap::new();
+
+        loop {
+            // Changes are handled first, so nothing from before them is answered with after they happened
+            let request = tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => return,
+                res = updates.changed() => {
+                    if res.is_err() {
+                        return;
+                    }
+                    cache.clear();
+                    continue;
+                }
+                request = requests.recv() => request,
+            };
+            let Some(Request { content_id, reply }) = request else {
+                return;
+            };
+
+            let skip_gaps = settings.skip_episode_gaps();
+            let next = match cache.get(&(content_id, skip_gaps)) {
+                Some(next) => Ok(next.clone()),
+                None => {
+                    let next = RecommendationPopup::from_query(db.clone(), move |conn| {
+                        next_episode(conn, content_id, skip_gaps)
+                    })
+                    .await;
+                    if let Ok(next) = &next {
+                        cache.insert((content_id, skip_gaps), next.clone());
+                    }
+                    next
+                }
+            };
+
+            let popup = match next {
+                // TODO: This doesn't recognize movies properly
+                // This is not the end goal, just something to make it kinda work
+                Ok(None) => {
+                    RecommendationPopup::from_query(db.clone(), |conn| {
+                        Recommendation::random(conn).map(Some)
+                    })
+                    .await
+                }
+                next => next,
+            }
+            .and_then(|popup| match popup {
+                Some(popup) => Ok(popup),
+                None => bail!("No recommendations could be made"),
+            });
+
+            // The requester not waiting anymore is fine, the session probably switched content
+            reply.send(popup).ignore();
+        }
+    }
+
+    pub async fn recommend(&self, content_id: u64) -> AppResult<RecommendationPopup> {
+        let (reply, response) = oneshot::channel();
+        if self
+            .requests
+            .send(Request { content_id, reply })
+            .await
+            .is_err()
+        {
+            bail!("The recommendation engine is not running");
+        }
+
+        match response.await {
+            Ok(popup) => popup,
+            Err(_) => bail!("The recommendation engine stopped before answering"),
+        }
+    }
+}
 
 impl RecommendationPopup {
-    pub async fn new(db: Database, content_id: u64) -> AppResult<Self> {
+    /// Runs the query on a blocking thread, None when it found nothing to recommend
+    async fn from_query<F>(db: Database, query: F) -> AppResult<Option<Self>>
+    where
+        F: FnOnce(&rusqlite::Connection) -> AppResult<Option<Recommendation>> + Send + 'static,
+    {
         let recommendation = tokio::task::spawn_blocking(move || {
             let conn = db.get()?;
-            Self::recommend(&conn, content_id)
+            query(&conn)
         });
 
         let Some(output) = recommendation
             .await
             .log_err_with_msg("failed to resolve tokio thread for recommendation")
-            .transpose()?
         else {
             bail!("No recommendations could be made");
         };
 
-        Ok(RecommendationPopup {
+        Ok(output?.map(|output| RecommendationPopup {
             id: output.id,
             image: String::new(),
             title: output.title,
-        })
+            queued: false,
+        }))
     }
+}
 
-    // TODO: This doesn't recognize movies properly
-    // This is not the end goal, just something to make it kinda work
-    fn recommend(conn: &Connection, content_id: u64) -> AppResult<Recommendation> {
-        let this_episode: Option<u64> = conn
-            .query_row_get(
-                "SELECT episode.episode FROM content, episode
+/// Which way to go from an episode
+#[derive(Clone, Copy)]
+pub enum Step {
+    Next,
+    Previous,
+}
+
+/// The id of the episode before or after this one in its series, None if there is none
+pub fn adjacent_episode(
+    conn: &rusqlite::Connection,
+    content_id: u64,
+    step: Step,
+    skip_gaps: bool,
+) -> AppResult<Option<u64>> {
+    let episode = match step {
+        Step::Next => next_episode(conn, content_id, skip_gaps)?,
+        Step::Previous => previous_episode(conn, content_id, skip_gaps)?,
+    };
+    Ok(episode.map(|episode| episode.id))
+}
+
+/// Where an episode is, the id, number and title of its season and its own number
+/// None if the content isn't an episode in a season
+fn episode_position(
+    conn: &rusqlite::Connection,
+    content_id: u64,
+) -> AppResult<Option<(u64, u64, String, u64)>> {
+    let this_episode: Option<u64> = conn
+        .query_row_get(
+            "SELECT episode.episode FROM content, episode
                     WHERE content.type = ?1
                     AND content.reference = episode.id
                     AND content.id = ?2",
-                params![ContentType::Episode, content_id],
-            )
-            .optional()?;
+            params![ContentType::Episode, content_id],
+        )
+        .optional()?;
 
-        let maybe_season_id: Option<(u64, u64, String)> = conn
-            .query_row_into(
-                "SELECT collection.id, season.season, season.title FROM collection_contains, collection, season
-                WHERE collection_contains.collection_id = collection.id
-                AND collection_contains.type = ?1
-                AND collection_contains.reference = ?2
-                AND collection.type = ?3
-                AND collection.reference = season.id",
-                params![TableId::Content, content_id, CollectionType::Season],
-            )
-            .optional()?;
-
-        let (Some((season_id, season, season_title)), Some(episode)) =
-            (maybe_season_id, this_episode)
-        else {
-            return Recommendation::random(conn);
-        };
-
-        let maybe_next_episode: Option<(u64, String, u64)> = conn
-            .query_row_into(
-                "SELECT content.id, episode.title, episode.episode FROM collection_contains, episode, content
-                    WHERE collection_contains.collection_id = ?1
-                    AND collection_contains.type = ?2
-                    AND collection_contains.reference = content.id
-                    AND content.type = ?3
-                    AND content.reference = episode.id
-                    AND episode.episode = ?4",
-                params![season_id, TableId::Content, ContentType::Episode, episode + 1],
-            )
-            .optional()?;
+    let maybe_season_id: Option<(u64, u64, String)> = conn
+        .query_row_into(
+            "SELECT collection.id, season.season, season.title FROM collection_contains, collection, season
+            WHERE collection_contains.collection_id = collection.id
+            AND collection_contains.type = ?1
+            AND collection_contains.reference = ?2
+            AND collection.type = ?3
+            AND collection.reference = season.id",
+            params![TableId::Content, content_id, CollectionType::Season],
+        )
+        .optional()?;
 
-        if let Some((next_episode_id, title, episode)) = maybe_next_episode {
-            return Ok(Recommendation {
-                id: next_episode_id,
-                title: format!("{title} - {season_title} - Season {season} - Episode {episode}"),
-            });
-        }
+    let (Some((season_id, season, season_title)), Some(episode)) = (maybe_season_id, this_episode)
+    else {
+        return Ok(None);
+    };
+    Ok(Some((season_id, season, season_title, episode)))
+}
 
-        let maybe_series_id: Option<u64> = conn.query_row_get(
+/// The series a season belongs to
+fn series_of(conn: &rusqlite::Connection, season_id: u64) -> AppResult<Option<u64>> {
+    let series_id = conn
+        .query_row_get(
             "SELECT collection.id FROM collection, collection_contains
                 WHERE collection.id = collection_contains.collection_id
                 AND collection_contains.type = ?1
                 AND collection_contains.reference = ?2
                 AND collection.type = ?3",
             params![TableId::Collection, season_id, CollectionType::Series],
-        )?;
+        )
+        .optional()?;
+    Ok(series_id)
+}
 
-        let Some(series_id) = maybe_series_id else {
-            return Recommendation::random(conn);
-        };
+/// The episode after this one, continuing with the next season once this one is over
+/// None if the content isn't an episode or it was the last one of its series
+fn next_episode(
+    conn: &rusqlite::Connection,
+    content_id: u64,
+    skip_gaps: bool,
+) -> AppResult<Option<Recommendation>> {
+    let Some((season_id, season, season_title, episode)) = episode_position(conn, content_id)?
+    else {
+        return Ok(None);
+    };
+
+    let maybe_next_episode: Option<(u64, String, u64)> = conn
+        .query_row_into(
+            "SELECT content.id, episode.title, episode.episode FROM collection_contains, episode, content
+                WHERE collection_contains.collection_id = ?1
+                AND collection_contains.type = ?2
+                AND collection_contains.reference = content.id
+                AND content.type = ?3
+                AND content.reference = episode.id
+                AND content.part = 0
+                AND episode.episode > ?4
+                AND (?5 OR episode.episode = ?4 + 1)
+                ORDER BY episode.episode ASC
+                LIMIT 1",
+            params![
+                season_id,
+                TableId::Content,
+                ContentType::Episode,
+                episode,
+                skip_gaps
+            ],
+        )
+        .optional()?;
+
+    if let Some((next_episode_id, title, episode)) = maybe_next_episode {
+        return Ok(Some(Recommendation {
+            id: next_episode_id,
+            title: format!("{title} - {season_title} - Season {season} - Episode {episode}"),
+        }));
+    }
+
+    let Some(series_id) = series_of(conn, season_id)? else {
+        return Ok(None);
+    };
 
-        let maybe_next_season: Option<u64> = conn
-            .query_row_get(
-                "SELECT collection.id FROM collection, collection_contains, season
-                    WHERE collection.id = collection_contains.collection_id
+    // Like episodes, missing season numbers are jumped over unless gaps should end the series
+    let maybe_next_season: Option<u64> = conn
+        .query_row_get(
+            "SELECT collection.id FROM collection_contains, collection, season
+                    WHERE collection_contains.collection_id = ?4
                     AND collection_contains.type = ?1
-                    AND collection_contains.reference = season.id
+                    AND collection_contains.reference = collection.id
                     AND collection.type = ?2
-                    AND season.season = ?3
                     AND collection.reference = season.id
-                    AND collection_contains.collection_id = ?4",
-                params![
-                    TableId::Collection,
-                    CollectionType::Season,
-                    season + 1,
-                    series_id
-                ],
-            )
-            .optional()?;
+                    AND season.season > ?3
+                    AND (?5 OR season.season = ?3 + 1)
+                    ORDER BY season.season ASC
+                    LIMIT 1",
+            params![
+                TableId::Collection,
+                CollectionType::Season,
+                season,
+                series_id,
+                skip_gaps
+            ],
+        )
+        .optional()?;
 
-        let Some(next_season_id) = maybe_next_season else {
-            return Recommendation::random(conn);
-        };
+    let Some(next_season_id) = maybe_next_season else {
+        return Ok(None);
+    };
 
-        let maybe_first_episode: Option<(u64, String)> = conn
-            .query_row_into(
-                "SELECT content.id, episode.title FROM collection_contains, episode, content
-                    WHERE collection_contains.collection_id = ?1
-                    AND collection_contains.type = ?2
-                    AND collection_contains.reference = content.id
-                    AND content.type = ?3
-                    AND content.reference = episode.id
-                    AND episode.episode = 1",
-                params![next_season_id, TableId::Content, ContentType::Episode],
-            )
-            .optional()?;
+    let maybe_first_episode: Option<(u64, String, u64)> = conn
+        .query_row_into(
+            "SELECT content.id, episode.title, episode.episode FROM collection_contains, episode, content
+                WHERE collection_contains.collection_id = ?1
+                AND collection_contains.type = ?2
+                AND collection_contains.reference = content.id
+                AND content.type = ?3
+                AND content.reference = episode.id
+                AND content.part = 0
+                AND (?4 OR episode.episode = 1)
+                ORDER BY episode.episode ASC
+                LIMIT 1",
+            params![next_season_id, TableId::Content, ContentType::Episode, skip_gaps],
+        )
+        .optional()?;
 
-        if let Some((id, title)) = maybe_first_episode {
-            Ok(Recommendation {
-                id,
-                title: format!("{title} - Episode 1"),
-            })
-        } else {
-            Recommendation::random(conn)
-        }
+    Ok(
+        maybe_first_episode.map(|(id, title, episode)| Recommendation {
+            id,
+            title: format!("{title} - Episode {episode}"),
+        }),
+    )
+}
+
+/// The episode before this one, going back to the last episode of the previous season at the start of a season
+/// None if the content isn't an episode or it was the first one of its series
+fn previous_episode(
+    conn: &rusqlite::Connection,
+    content_id: u64,
+    skip_gaps: bool,
+) -> AppResult<Option<Recommendation>> {
+    let Some((season_id, season, season_title, episode)) = episode_position(conn, content_id)?
+    else {
+        return Ok(None);
+    };
+
+    let maybe_previous_episode: Option<(u64, String, u64)> = conn
+        .query_row_into(
+            "SELECT content.id, episode.title, episode.episode FROM collection_contains, episode, content
+                WHERE collection_contains.collection_id = ?1
+                AND collection_contains.type = ?2
+                AND collection_contains.reference = content.id
+                AND content.type = ?3
+                AND content.reference = episode.id
+                AND content.part = 0
+                AND episode.episode < ?4
+                AND (?5 OR episode.episode = ?4 - 1)
+                ORDER BY episode.episode DESC
+                LIMIT 1",
+            params![
+                season_id,
+                TableId::Content,
+                ContentType::Episode,
+                episode,
+                skip_gaps
+            ],
+        )
+        .optional()?;
+
+    if let Some((previous_episode_id, title, episode)) = maybe_previous_episode {
+        return Ok(Some(Recommendation {
+            id: previous_episode_id,
+            title: format!("{title} - {season_title} - Season {season} - Episode {episode}"),
+        }));
     }
+
+    let Some(series_id) = series_of(conn, season_id)? else {
+        return Ok(None);
+    };
+
+    let maybe_previous_season: Option<u64> = conn
+        .query_row_get(
+            "SELECT collection.id FROM collection_contains, collection, season
+                    WHERE collection_contains.collection_id = ?4
+                    AND collection_contains.type = ?1
+                    AND collection_contains.reference = collection.id
+                    AND collection.type = ?2
+                    AND collection.reference = season.id
+                    AND season.season < ?3
+                    AND (?5 OR season.season = ?3 - 1)
+                    ORDER BY season.season DESC
+                    LIMIT 1",
+            params![
+                TableId::Collection,
+                CollectionType::Season,
+                season,
+                series_id,
+                skip_gaps
+            ],
+        )
+        .optional()?;
+
+    let Some(previous_season_id) = maybe_previous_season else {
+        return Ok(None);
+    };
+
+    // How many episodes the previous season has isn't known, so this is always its highest number
+    let maybe_last_episode: Option<(u64, String, u64)> = conn
+        .query_row_into(
+            "SELECT content.id, episode.title, episode.episode FROM collection_contains, episode, content
+                WHERE collection_contains.collection_id = ?1
+                AND collection_contains.type = ?2
+                AND collection_contains.reference = content.id
+                AND content.type = ?3
+                AND content.reference = episode.id
+                AND content.part = 0
+                ORDER BY episode.episode DESC
+                LIMIT 1",
+            params![previous_season_id, TableId::Content, ContentType::Episode],
+        )
+        .optional()?;
+
+    Ok(
+        maybe_last_episode.map(|(id, title, episode)| Recommendation {
+            id,
+            title: format!("{title} - Episode {episode}"),
+        }),
+    )
+}
+
+/// Remembers that the user just played this, nothing happens for content that isn't an episode of a series
+/// The series is continued at this episode until [`record_series_watched`] moves past it
+pub fn record_series_activity(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    content_id: u64,
+) -> AppResult<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    conn.execute(
+        "INSERT INTO series_activity (user_id, series_id, content_id, resume_id, last_active)
+            SELECT ?1, series.id, content.id, content.id, ?3
+            FROM content, collection_contains AS in_season, collection AS season,
+                collection_contains AS in_series, collection AS series
+            WHERE content.id = ?2
+            AND content.type = ?4
+            AND in_season.type = ?5
+            AND in_season.reference = content.id
+            AND in_season.collection_id = season.id
+            AND season.type = ?6
+            AND in_series.type = ?7
+            AND in_series.reference = season.id
+            AND in_series.collection_id = series.id
+            AND series.type = ?8
+            ON CONFLICT (user_id, series_id)
+            DO UPDATE SET content_id = excluded.content_id,
+                resume_id = excluded.resume_id,
+                last_active = excluded.last_active",
+        params![
+            user_id,
+            content_id,
+            now,
+            ContentType::Episode,
+            TableId::Content,
+            CollectionType::Season,
+            TableId::Collection,
+            CollectionType::Series
+        ],
+    )?;
+    Ok(())
+}
+
+/// The user watched enough of this episode to continue with the one after it, or not at all after the last one
+pub fn record_series_watched(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    content_id: u64,
+    skip_gaps: bool,
+) -> AppResult<()> {
+    let resume_id = next_episode(conn, content_id, skip_gaps)?.map(|next| next.id);
+
+    conn.execute(
+        "UPDATE series_activity SET resume_id = ?3
+            WHERE user_id = ?1
+            AND content_id = ?2",
+        params![user_id, content_id, resume_id],
+    )?;
+    Ok(())
+}
+
+/// A series the user is watching and the episode to continue it with
+pub struct NextUp {
+    pub series_id: u64,
+    pub content_id: u64,
+    pub title: String,
+}
+
+/// Series the user played something of, most recently played first
+/// Series where the last episode was already watched are left out
+pub fn continue_series(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    limit: u64,
+    offset: u64,
+) -> AppResult<Vec<NextUp>> {
+    let next_up = conn
+        .prepare(
+            "SELECT series_activity.series_id, content.id, episode.title, episode.episode, season.season, season.title
+                FROM series_activity, content, episode, collection_contains, collection, season
+                WHERE series_activity.user_id = ?1
+                AND series_activity.resume_id = content.id
+                AND content.type = ?2
+                AND content.reference = episode.id
+                AND collection_contains.type = ?3
+                AND collection_contains.reference = content.id
+                AND collection_contains.collection_id = collection.id
+                AND collection.type = ?4
+                AND collection.reference = season.id
+                ORDER BY series_activity.last_active DESC
+                LIMIT ?5 OFFSET ?6",
+        )?
+        .query_map_into::<(u64, u64, String, u64, u64, String)>(params![
+            user_id,
+            ContentType::Episode,
+            TableId::Content,
+            CollectionType::Season,
+            limit,
+            offset
+        ])?
+        .map(|row| {
+            row.map(
+                |(series_id, content_id, title, episode, season, season_title)| NextUp {
+                    series_id,
+                    content_id,
+                    title: format!(
+                        "{title} - {season_title} - Season {season} - Episode {episode}"
+                    ),
+                },
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(next_up)
 }
 
 struct Recommendation {
@@ -148,7 +543,7 @@ struct Recommendation {
 }
 
 impl Recommendation {
-    fn random(conn: &Connection) -> AppResult<Self> {
+    fn random(conn: &rusqlite::Connection) -> AppResult<Self> {
         // get a random movie or episode
         let maybe_random_episode: Option<(u64, String, u64)> = conn
             .query_row_into(
@@ -194,3 +589,261 @@ impl Recommendation {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{in_file, in_memory},
+        indexing::{fixture_content, index_fixture},
+        utils::TempDir,
+    };
+
+    const PILOT: &str = "Lost/Season 1/Pilot - s1e1.mp4";
+    const TABULA_RASA: &str = "Lost/Season 1/Tabula Rasa - s1e2.mp4";
+
+    /// A library in its own database file, the directory has to be kept around as long as the database is used
+    fn library(name: &str, files: &[&str]) -> (TempDir, Database) {
+        let dir = TempDir::new(name);
+        let db = in_file(&dir.path().join("database.sqlite"));
+        index_fixture(&mut db.get().unwrap(), files);
+        (dir, db)
+    }
+
+    /// An engine like the one of the server, sending through the returned sender is what indexing does after changes
+    fn engine(db: Database) -> (Recommender, watch::Sender<()>) {
+        let (changed, updates) = watch::channel(());
+        let (requests, receiver) = mpsc::channel(32);
+        let (shutdown, _) = Shutdown::new();
+        tokio::spawn(Recommender::engine(
+            db,
+            ServerSettings::in_memory(),
+            updates,
+            shutdown,
+            receiver,
+        ));
+        (Recommender { requests }, changed)
+    }
+
+    #[tokio::test]
+    async fn requests_through_the_channel_are_answered() {
+        let (_dir, db) = library("recommender-channel", &[PILOT, TABULA_RASA]);
+        let conn = db.get().unwrap();
+        let (pilot, tabula_rasa) = (
+            fixture_content(&conn, PILOT),
+            fixture_content(&conn, TABULA_RASA),
+        );
+
+        let (recommender, _changed) = engine(db.clone());
+        let popup = recommender.recommend(pilot).await.unwrap();
+        assert_eq!(popup.id, tabula_rasa);
+        assert!(!popup.queued);
+    }
+
+    /// Changes the title without telling anyone, like a library that changed while nothing was indexed yet
+    fn rename_episode(db: &Database, content_id: u64) {
+        db.get()
+            .unwrap()
+            .execute(
+                "UPDATE episode SET title = 'Renamed'
+                    WHERE id = (SELECT reference FROM content WHERE id = ?1)",
+                [content_id],
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_next_episode_is_cached_until_the_library_changes() {
+        let (_dir, db) = library("recommender-cache", &[PILOT, TABULA_RASA]);
+        let conn = db.get().unwrap();
+        let (pilot, tabula_rasa) = (
+            fixture_content(&conn, PILOT),
+            fixture_content(&conn, TABULA_RASA),
+        );
+
+        let (recommender, changed) = engine(db.clone());
+        let first = recommender.recommend(pilot).await.unwrap();
+        rename_episode(&db, tabula_rasa);
+
+        let second = recommender.recommend(pilot).await.unwrap();
+        assert_eq!(second.title, first.title);
+
+        changed.send_replace(());
+        let after_change = recommender.recommend(pilot).await.unwrap();
+        assert_eq!(after_change.id, tabula_rasa);
+        assert!(after_change.title.starts_with("Renamed"));
+    }
+
+    #[tokio::test]
+    async fn random_picks_are_not_cached() {
+        let (_dir, db) = library("recommender-random", &[PILOT]);
+        let pilot = fixture_content(&db.get().unwrap(), PILOT);
+
+        // Nothing comes after the only episode, so the only content there is gets picked at random
+        let (recommender, _changed) = engine(db.clone());
+        let first = recommender.recommend(pilot).await.unwrap();
+        assert_eq!(first.id, pilot);
+        rename_episode(&db, pilot);
+
+        let second = recommender.recommend(pilot).await.unwrap();
+        assert!(second.title.starts_with("Renamed"));
+    }
+
+    /// The library with a user that can watch it
+    fn watching(files: &[&str]) -> rusqlite::Connection {
+        let mut conn = in_memory();
+        index_fixture(&mut conn, files);
+        conn.execute(
+            "INSERT INTO users (id, username, password) VALUES (1, 'user', '')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn continued(conn: &rusqlite::Connection) -> Vec<u64> {
+        continue_series(conn, 1, 100, 0)
+            .unwrap()
+            .into_iter()
+            .map(|next| next.content_id)
+            .collect()
+    }
+
+    #[test]
+    fn unfinished_episodes_are_continued_themselves() {
+        let conn = watching(&[PILOT, TABULA_RASA]);
+        let pilot = fixture_content(&conn, PILOT);
+
+        record_series_activity(&conn, 1, pilot).unwrap();
+        assert_eq!(continued(&conn), [pilot]);
+    }
+
+    #[test]
+    fn watched_episodes_are_continued_with_the_next_one() {
+        let conn = watching(&[PILOT, TABULA_RASA]);
+        let (pilot, tabula_rasa) = (
+            fixture_content(&conn, PILOT),
+            fixture_content(&conn, TABULA_RASA),
+        );
+
+        record_series_activity(&conn, 1, pilot).unwrap();
+        record_series_watched(&conn, 1, pilot, false).unwrap();
+        assert_eq!(continued(&conn), [tabula_rasa]);
+
+        let next = &continue_series(&conn, 1, 100, 0).unwrap()[0];
+        assert_eq!(next.title, "Tabula Rasa - Season 1 - Season 1 - Episode 2");
+    }
+
+    #[test]
+    fn series_watched_to_the_end_are_left_out() {
+        let conn = watching(&[PILOT, TABULA_RASA]);
+        let tabula_rasa = fixture_content(&conn, TABULA_RASA);
+
+        record_series_activity(&conn, 1, tabula_rasa).unwrap();
+        record_series_watched(&conn, 1, tabula_rasa, false).unwrap();
+        assert!(continued(&conn).is_empty());
+    }
+
+    #[test]
+    fn continue_series_is_paginated_most_recent_first() {
+        const ALIAS: &str = "Alias/Season 1/Truth Be Told - s1e1.mp4";
+
+        let conn = watching(&[ALIAS, PILOT]);
+        let (alias, pilot) = (fixture_content(&conn, ALIAS), fixture_content(&conn, PILOT));
+        record_series_activity(&conn, 1, alias).unwrap();
+        record_series_activity(&conn, 1, pilot).unwrap();
+        // Both happened within the same second
+        conn.execute(
+            "UPDATE series_activity SET last_active = last_active - 1 WHERE content_id = ?1",
+            [alias],
+        )
+        .unwrap();
+
+        let page = |offset| {
+            continue_series(&conn, 1, 1, offset)
+                .unwrap()
+                .into_iter()
+                .map(|next| next.content_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(page(0), [pilot]);
+        assert_eq!(page(1), [alias]);
+        assert!(page(2).is_empty());
+    }
+
+    fn next_of(conn: &rusqlite::Connection, file: &str, skip_gaps: bool) -> Option<u64> {
+        next_episode(conn, fixture_content(conn, file), skip_gaps)
+            .unwrap()
+            .map(|next| next.id)
+    }
+
+    #[test]
+    fn missing_episodes_are_skipped_over() {
+        const NUMBER_FOUR: &str = "Lost/Season 1/Walkabout - s1e4.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[PILOT, TABULA_RASA, NUMBER_FOUR]);
+
+        let walkabout = fixture_content(&conn, NUMBER_FOUR);
+        assert_eq!(next_of(&conn, TABULA_RASA, true), Some(walkabout));
+        assert_eq!(next_of(&conn, TABULA_RASA, false), None);
+    }
+
+    #[test]
+    fn missing_seasons_are_skipped_over() {
+        const SEASON_THREE: &str = "Lost/Season 3/A Tale of Two Cities - s3e1.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[PILOT, TABULA_RASA, SEASON_THREE]);
+
+        let season_three = fixture_content(&conn, SEASON_THREE);
+        assert_eq!(next_of(&conn, TABULA_RASA, true), Some(season_three));
+        assert_eq!(next_of(&conn, TABULA_RASA, false), None);
+    }
+
+    #[test]
+    fn episodes_in_parts_continue_with_the_first_part() {
+        const PILOT_1: &str = "Lost/Season 1/Pilot - s1e1p1.mp4";
+        const PILOT_2: &str = "Lost/Season 1/Pilot - s1e1p2.mp4";
+        const TABULA_RASA_1: &str = "Lost/Season 1/Tabula Rasa - s1e2p1.mp4";
+        // Sorts before the first part, so it is indexed first and would be picked without looking at the part
+        const TABULA_RASA_2: &str = "Lost/Season 1/Tabula Rasa (Conclusion) - s1e2p2.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[PILOT_1, PILOT_2, TABULA_RASA_1, TABULA_RASA_2]);
+        let (pilot, tabula_rasa) = (
+            fixture_content(&conn, PILOT_1),
+            fixture_content(&conn, TABULA_RASA_1),
+        );
+
+        assert_eq!(next_of(&conn, PILOT_1, false), Some(tabula_rasa));
+        assert_eq!(next_of(&conn, PILOT_2, false), Some(tabula_rasa));
+        assert_eq!(next_of(&conn, TABULA_RASA_1, false), None);
+
+        let previous = |file| {
+            adjacent_episode(&conn, fixture_content(&conn, file), Step::Previous, false).unwrap()
+        };
+        assert_eq!(previous(TABULA_RASA_2), Some(pilot));
+    }
+
+    #[test]
+    fn stepping_crosses_seasons_but_not_the_ends_of_the_series() {
+        const ORIENTATION: &str = "Lost/Season 2/Orientation - s2e1.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[PILOT, TABULA_RASA, ORIENTATION]);
+        let step =
+            |file, step| adjacent_episode(&conn, fixture_content(&conn, file), step, true).unwrap();
+        let (tabula_rasa, orientation) = (
+            fixture_content(&conn, TABULA_RASA),
+            fixture_content(&conn, ORIENTATION),
+        );
+
+        assert_eq!(step(TABULA_RASA, Step::Next), Some(orientation));
+        assert_eq!(step(ORIENTATION, Step::Previous), Some(tabula_rasa));
+
+        // The first episode of the first season and the last one of the last season
+        assert_eq!(step(PILOT, Step::Previous), None);
+        assert_eq!(step(ORIENTATION, Step::Next), None);
+    }
+}
@@ -0,0 +1,595 @@
+use axum::{
+    extract::{Query, State},
+    http::{header::CONTENT_TYPE, Method, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{
+    database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt, QueryRowIntoStmtExt},
+    indexing::{content_title, CollectionType, ContentType, TableId},
+    routes::{json_errors, library::Pagination},
+    state::{AppResult, AppState, Shutdown},
+    utils::{
+        login_required,
+        streaming::{SessionState, StreamingSessions},
+        with_base, AuthSession, HandleErr, ServerSettings, User,
+    },
+};
+
+/// JSON versions of what the web interface can do, authenticated through the same session cookie
+/// CORS is checked before the login, because preflight requests are sent without cookies
+pub fn api(settings: ServerSettings) -> Router<AppState> {
+    logged_in_json(
+        Router::new()
+            .route("/sessions", get(list_sessions).post(create_session))
+            .route("/content", get(list_content)),
+    )
+    .layer(cors(settings))
+}
+
+/// Errors are turned into json outside of the login, so being logged out is reported as json as well
+fn logged_in_json<S: Clone + Send + Sync + 'static>(routes: Router<S>) -> Router<S> {
+    routes
+        .fallback(unknown)
+        .layer(middleware::from_fn(login_required))
+        .layer(middleware::from_fn(json_errors))
+}
+
+/// Without this, unknown api routes would get the html page of the main router
+async fn unknown() -> AppResult<()> {
+    status!(StatusCode::NOT_FOUND);
+}
+
+/// Only the configured origins get the headers, browsers keep everyone else to the same origin
+fn cors(settings: ServerSettings) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| settings.cors_allows(origin))
+        }))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([CONTENT_TYPE])
+        .allow_credentials(true)
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    id: u32,
+    title: String,
+    participants: usize,
+    /// The id of the content that is currently playing
+    content_id: u64,
+    state: SessionState,
+}
+
+async fn list_sessions(
+    State(sessions): State<StreamingSessions>,
+    auth: AuthSession,
+) -> Json<Vec<SessionInfo>> {
+    Json(joinable_sessions(&sessions, auth.user.as_ref()).await)
+}
+
+/// Only the sessions the user is allowed to join
+async fn joinable_sessions(sessions: &StreamingSessions, user: Option<&User>) -> Vec<SessionInfo> {
+    let mut infos = Vec::new();
+    for (id, session) in sessions.all().await {
+        let allowed = match user {
+            Some(user) => session.may_join(user.id).await,
+            None => false,
+        };
+        if !allowed {
+            continue;
+        }
+
+        let content_id = session.video_id().await;
+        let title = session
+            .title()
+            .await
+            .log_warn_with_msg("Failed to resolve the title of a session")
+            .unwrap_or_else(|| format!("Session {id}"));
+
+        infos.push(SessionInfo {
+            id,
+            title,
+            participants: session.receiver_count().await,
+            content_id,
+            state: session.get_state().await,
+        });
+    }
+    infos
+}
+
+#[derive(Deserialize)]
+struct NewSession {
+    content_id: u64,
+    /// Only users with the invite code can find and join the session
+    #[serde(default)]
+    invite_only: bool,
+}
+
+#[derive(Serialize)]
+struct CreatedSession {
+    id: u32,
+    /// Opens the session in the web interface
+    join_url: String,
+    /// Anyone with this link can join, also for invite only sessions
+    invite_url: String,
+}
+
+async fn create_session(
+    State(mut sessions): State<StreamingSessions>,
+    State(db): State<Database>,
+    State(shutdown): State<Shutdown>,
+    auth: AuthSession,
+    Json(new): Json<NewSession>,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let created = start_session(&mut sessions, &db, shutdown, &user, new).await?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// Only content the api lists can be played, the first part of something with a file
+async fn start_session(
+    sessions: &mut StreamingSessions,
+    db: &Database,
+    shutdown: Shutdown,
+    user: &User,
+    new: NewSession,
+) -> AppResult<CreatedSession> {
+    let playable = db.get()?.query_row_get::<bool>(
+        "SELECT exists(SELECT 1 FROM content WHERE id = ?1 AND data_id IS NOT NULL AND part = 0)",
+        [new.content_id],
+    )?;
+    if !playable {
+        status!(StatusCode::NOT_FOUND);
+    }
+
+    let id = sessions
+        .new_session(new.content_id, db, shutdown, user, new.invite_only)
+        .await?;
+    let invite_code = match sessions.get(&id).await {
+        Some(session) => session.invite_code().to_owned(),
+        None => status!(StatusCode::NOT_FOUND),
+    };
+
+    Ok(CreatedSession {
+        id,
+        join_url: with_base(&format!("/?all=/video/session/{id}")),
+        invite_url: with_base(&format!("/video/join/{invite_code}")),
+    })
+}
+
+/// Without per_page everything is returned at once
+#[derive(Deserialize)]
+#[serde(try_from = "RawContentPage")]
+struct ContentPage(Option<Pagination>);
+
+#[derive(Deserialize)]
+struct RawContentPage {
+    #[serde(default)]
+    page: u64,
+    per_page: Option<u64>,
+}
+
+impl TryFrom<RawContentPage> for ContentPage {
+    type Error = &'static str;
+
+    fn try_from(raw: RawContentPage) -> Result<Self, Self::Error> {
+        raw.per_page
+            .map(|per_page| Pagination::new(raw.page, per_page))
+            .transpose()
+            .map(Self)
+    }
+}
+
+#[derive(Serialize)]
+struct ContentInfo {
+    id: u64,
+    #[serde(rename = "type")]
+    content_type: ContentType,
+    title: String,
+    /// The titles of the collections the content is in, from the outermost to the innermost
+    collection_path: Vec<String>,
+}
+
+/// Everything that can currently be played
+async fn list_content(
+    State(db): State<Database>,
+    Query(page): Query<ContentPage>,
+) -> AppResult<Json<Vec<ContentInfo>>> {
+    Ok(Json(content_page(&db.get()?, &page)?))
+}
+
+fn content_page(conn: &rusqlite::Connection, page: &ContentPage) -> AppResult<Vec<ContentInfo>> {
+    // A negative limit means no limit to sqlite
+    let (limit, offset) = match &page.0 {
+        Some(pagination) => (pagination.per_page as i64, pagination.offset()),
+        None => (-1, 0),
+    };
+
+    conn.prepare(
+        "SELECT id, type FROM content
+            WHERE data_id IS NOT NULL
+            AND part = 0
+            ORDER BY id ASC
+            LIMIT ?1 OFFSET ?2",
+    )?
+    .query_map_into::<(u64, ContentType)>(params![limit, offset])?
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .map(|(id, content_type)| {
+        Ok(ContentInfo {
+            id,
+            content_type,
+            title: content_title(conn, id)?,
+            collection_path: collection_path(conn, id)?,
+        })
+    })
+    .collect()
+}
+
+fn collection_path(conn: &rusqlite::Connection, content_id: u64) -> AppResult<Vec<String>> {
+    // Guards against collections that somehow contain each other
+    const MAX_DEPTH: usize = 8;
+
+    let mut path = Vec::new();
+    let mut current = (TableId::Content, content_id);
+    while path.len() < MAX_DEPTH {
+        // User collections and themes only point at the content, it isn't really in them
+        let parent: Option<(u64, CollectionType, u64)> = conn
+            .query_row_into(
+                "SELECT collection.id, collection.type, collection.reference
+                    FROM collection_contains, collection
+                    WHERE collection_contains.collection_id = collection.id
+                    AND collection_contains.type = ?1
+                    AND collection_contains.reference = ?2
+                    AND collection.type IN (?3, ?4, ?5)
+                    LIMIT 1",
+                params![
+                    current.0,
+                    current.1,
+                    CollectionType::Franchise,
+                    CollectionType::Series,
+                    CollectionType::Season
+                ],
+            )
+            .optional()?;
+
+        let Some((collection_id, collection_type, reference)) = parent else {
+            break;
+        };
+
+        let title: String = match collection_type {
+            CollectionType::Franchise => {
+                conn.query_row_get("SELECT title FROM franchise WHERE id = ?1", [reference])?
+            }
+            CollectionType::Series => {
+                conn.query_row_get("SELECT title FROM series WHERE id = ?1", [reference])?
+            }
+            _ => conn.query_row_get("SELECT title FROM season WHERE id = ?1", [reference])?,
+        };
+        path.push(title);
+
+        // Franchises are always the outermost collection
+        if matches!(collection_type, CollectionType::Franchise) {
+            break;
+        }
+        current = (TableId::Collection, collection_id);
+    }
+
+    path.reverse();
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{
+            header::{
+                ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN,
+                ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+            },
+            Request,
+        },
+    };
+    use axum_login::AuthManagerLayerBuilder;
+    use tower::ServiceExt;
+    use tower_sessions::SessionManagerLayer;
+
+    use super::*;
+    use crate::{
+        database::{in_file, in_memory},
+        indexing::{fixture_content, index_fixture},
+        routes::library::MAX_PER_PAGE,
+        state::AppError,
+        utils::{silence, TempDir},
+    };
+
+    fn everything() -> ContentPage {
+        ContentPage(None)
+    }
+
+    #[test]
+    fn all_playable_content_is_listed_with_its_type() {
+        let mut conn = in_memory();
+        index_fixture(
+            &mut conn,
+            &[
+                "Heat/Heat.mp4",
+                "Lost/Season 1/Pilot - s1e1.mp4",
+                "Lost/Season 1/Tabula Rasa - s1e3.mp4",
+                "Kill Bill/CD1/Kill Bill.mp4",
+                "Kill Bill/CD2/Kill Bill.mp4",
+            ],
+        );
+
+        let mut listed = content_page(&conn, &everything())
+            .unwrap()
+            .into_iter()
+            .map(|content| (content.title, content.content_type, content.collection_path))
+            .collect::<Vec<_>>();
+        listed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let path = |titles: &[&str]| titles.iter().map(|title| title.to_string()).collect();
+        // Only the first part of a movie is listed, the others are played after it
+        assert_eq!(
+            listed,
+            vec![
+                ("Heat".to_owned(), ContentType::Movie, path(&["Heat"])),
+                (
+                    "Kill Bill".to_owned(),
+                    ContentType::Movie,
+                    path(&["Kill Bill"])
+                ),
+                (
+                    "Pilot - Episode 1".to_owned(),
+                    ContentType::Episode,
+                    path(&["Lost", "Lost", "Season 1"])
+                ),
+                (
+                    "Tabula Rasa - Episode 3".to_owned(),
+                    ContentType::Episode,
+                    path(&["Lost", "Lost", "Season 1"])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn content_can_be_listed_in_pages() {
+        let mut conn = in_memory();
+        index_fixture(
+            &mut conn,
+            &["Heat/Heat.mp4", "Ronin/Ronin.mp4", "Alien/Alien.mp4"],
+        );
+
+        let ids = |page: ContentPage| -> Vec<u64> {
+            content_page(&conn, &page)
+                .unwrap()
+                .into_iter()
+                .map(|content| content.id)
+                .collect()
+        };
+        let all = ids(everything());
+        assert_eq!(all.len(), 3);
+
+        let page = |page, per_page| ContentPage(Some(Pagination::new(page, per_page).unwrap()));
+        assert_eq!(ids(page(0, 2)), all[..2]);
+        assert_eq!(ids(page(1, 2)), all[2..]);
+        assert!(ids(page(2, 2)).is_empty());
+    }
+
+    fn content_query(query: &str) -> Option<ContentPage> {
+        let uri = format!("/content?{query}").parse().unwrap();
+        Query::<ContentPage>::try_from_uri(&uri)
+            .ok()
+            .map(|Query(page)| page)
+    }
+
+    #[test]
+    fn content_pages_are_checked_like_library_pages() {
+        let page = content_query("page=0&per_page=1000").unwrap().0.unwrap();
+        assert_eq!(page.per_page, MAX_PER_PAGE);
+
+        assert!(content_query("page=0&per_page=0").is_none());
+        assert!(content_query(&format!("page={}&per_page=2", u64::MAX)).is_none());
+        assert!(content_query(&format!("page={}&per_page={}", i64::MAX, i64::MAX)).is_none());
+
+        // The page doesn't matter when everything is returned
+        assert!(content_query(&format!("page={}", u64::MAX))
+            .unwrap()
+            .0
+            .is_none());
+    }
+
+    /// The response of a route behind the CORS layer to a request from that origin
+    async fn from_origin(origin: &str, method: Method) -> axum::response::Response {
+        let settings = ServerSettings::in_memory();
+        settings.set_cors_allowed_origins(vec!["https://example.com/".to_owned()]);
+
+        let request = Request::builder()
+            .method(method)
+            .uri("/")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        Router::new()
+            .route("/", get(|| async {}).post(|| async {}))
+            .layer(cors(settings))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_may_send_the_cookie() {
+        for method in [Method::OPTIONS, Method::GET] {
+            let response = from_origin("https://example.com", method).await;
+            let headers = response.headers();
+            assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://example.com");
+            assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        }
+    }
+
+    #[tokio::test]
+    async fn other_origins_get_no_cors_headers() {
+        for method in [Method::OPTIONS, Method::GET] {
+            let response = from_origin("https://elsewhere.example.com", method).await;
+            assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        }
+    }
+
+    /// Status and body of a response that should be json
+    async fn json(response: axum::response::Response) -> (StatusCode, serde_json::Value) {
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn logged_out_clients_get_json_instead_of_the_login_page() {
+        let dir = TempDir::new("api-logged-out");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let auth = AuthManagerLayerBuilder::new(db.clone(), SessionManagerLayer::new(db)).build();
+        let app = Router::new()
+            .nest(
+                "/api/v1",
+                logged_in_json(Router::new().route("/content", get(|| async {}))),
+            )
+            .layer(auth);
+
+        let response = app.oneshot(get_request("/api/v1/content")).await.unwrap();
+        assert_eq!(
+            json(response).await,
+            (
+                StatusCode::UNAUTHORIZED,
+                serde_json::json!({ "status": 401, "error": "Unauthorized" })
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_api_routes_are_json_404s() {
+        let app = Router::new()
+            .fallback(unknown)
+            .layer(middleware::from_fn(json_errors));
+
+        let response = app.oneshot(get_request("/nothing")).await.unwrap();
+        assert_eq!(
+            json(response).await,
+            (
+                StatusCode::NOT_FOUND,
+                serde_json::json!({ "status": 404, "error": "Not Found" })
+            )
+        );
+    }
+
+    fn user(db: &Database, name: &str) -> User {
+        let conn = db.get().unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password) VALUES (?1, '')",
+            [name],
+        )
+        .unwrap();
+        conn.query_row_into(
+            "SELECT id, username, password FROM users WHERE id = ?1",
+            [conn.last_insert_rowid()],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn created_sessions_are_listed_for_their_owner() {
+        ffmpeg::init().unwrap();
+
+        let dir = TempDir::new("api-create-session");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let mut conn = db.get().unwrap();
+        index_fixture(&mut conn, &["Heat/Heat.mp4"]);
+        // The indexed path is made up, so it is pointed at a file that exists
+        let heat = fixture_content(&conn, "Heat/Heat.mp4");
+        let path = dir.path().join("heat.wav");
+        silence(&path);
+        conn.execute(
+            "UPDATE data_file SET path = ?1 WHERE id = (SELECT data_id FROM content WHERE id = ?2)",
+            params![path.to_str().unwrap(), heat],
+        )
+        .unwrap();
+        drop(conn);
+
+        let (shutdown, _) = Shutdown::new();
+        let mut sessions = StreamingSessions::with_defaults(&db, &shutdown);
+        let owner = user(&db, "owner");
+        let new = NewSession {
+            content_id: heat,
+            invite_only: true,
+        };
+        let created = start_session(&mut sessions, &db, shutdown, &owner, new)
+            .await
+            .unwrap();
+
+        let listed = joinable_sessions(&sessions, Some(&owner)).await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, created.id);
+        assert_eq!(listed[0].title, "Heat");
+        assert_eq!(listed[0].content_id, heat);
+
+        // Invite only sessions stay hidden from everyone else
+        let guest = user(&db, "guest");
+        assert!(joinable_sessions(&sessions, Some(&guest)).await.is_empty());
+        assert!(joinable_sessions(&sessions, None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_can_only_be_created_with_playable_content() {
+        let dir = TempDir::new("api-unplayable-session");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let mut conn = db.get().unwrap();
+        let files = [
+            "Kill Bill/CD1/Kill Bill.mp4",
+            "Kill Bill/CD2/Kill Bill.mp4",
+            "Heat/Heat.mp4",
+        ];
+        index_fixture(&mut conn, &files);
+        let second_part = fixture_content(&conn, files[1]);
+        let without_file = fixture_content(&conn, files[2]);
+        conn.execute(
+            "UPDATE content SET data_id = NULL WHERE id = ?1",
+            [without_file],
+        )
+        .unwrap();
+        drop(conn);
+
+        let (shutdown, _) = Shutdown::new();
+        let mut sessions = StreamingSessions::with_defaults(&db, &shutdown);
+        let owner = user(&db, "owner");
+        for content_id in [9999, second_part, without_file] {
+            let new = NewSession {
+                content_id,
+                invite_only: false,
+            };
+            let result = start_session(&mut sessions, &db, shutdown.clone(), &owner, new).await;
+            assert!(
+                matches!(result, Err(AppError::Status(StatusCode::NOT_FOUND))),
+                "content {content_id} was accepted"
+            );
+        }
+        assert!(sessions.all().await.next().is_none());
+    }
+}
@@ -1,25 +1,131 @@
 use askama::Template;
 use askama_axum::IntoResponse;
-use axum::extract::Query;
-use serde::Deserialize;
+use axum::{
+    extract::{Query, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
 
 use crate::{
-    state::AppResult,
+    state::{AppResult, ErrorStatus},
     utils::{
-        frontend_redirect_explicit,
+        csrf_token, frontend_redirect_explicit,
         templates::{Error, Index},
-        HXTarget,
+        with_base, HXTarget,
     },
 };
 
 #[derive(Deserialize)]
 pub struct Err {
     err: String,
+    /// Which [`ErrorDetail`] to show below the description, for details the status code alone doesn't carry
+    msg: Option<String>,
+}
+
+/// Explanations the server sends users to the error page with
+/// Links only name one of these, so nobody can make the page show a message of their own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorDetail {
+    InviteUnknown,
+    InviteAttempts,
 }
 
-pub async fn error(Query(err): Query<Err>) -> AppResult<impl IntoResponse> {
+impl ErrorDetail {
+    const ALL: [Self; 2] = [Self::InviteUnknown, Self::InviteAttempts];
+
+    fn key(self) -> &'static str {
+        match self {
+            Self::InviteUnknown => "invite_unknown",
+            Self::InviteAttempts => "invite_attempts",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::InviteUnknown => "The invite code is unknown or its session has ended.",
+            Self::InviteAttempts => {
+                "Too many invite codes were tried, wait a minute and try again."
+            }
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|detail| detail.key() == key)
+    }
+}
+
+/// The error page for the status with the detail below it
+pub fn error_url(status: StatusCode, detail: ErrorDetail) -> String {
+    with_base(&format!(
+        "/error?err={}&msg={}",
+        status.as_u16(),
+        detail.key()
+    ))
+}
+
+pub async fn error(Query(err): Query<Err>, session: Session) -> AppResult<impl IntoResponse> {
+    let status = err
+        .err
+        .parse::<u16>()
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .filter(|status| status.is_client_error() || status.is_server_error())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Anything that isn't a known detail is left out
+    let message = err
+        .msg
+        .as_deref()
+        .and_then(ErrorDetail::from_key)
+        .map(ErrorDetail::message);
+    let page = error_page(status, message, csrf_token(&session).await?)?;
+    Ok((status, page))
+}
+
+/// Everything that doesn't match a route, rendered in place so the url stays what the user typed
+pub async fn not_found(session: Session) -> AppResult<impl IntoResponse> {
+    let status = StatusCode::NOT_FOUND;
+    Ok((
+        status,
+        error_page(status, None, csrf_token(&session).await?)?,
+    ))
+}
+
+/// A full page explaining what went wrong, depending on who is at fault
+pub fn error_page(
+    status: StatusCode,
+    message: Option<&str>,
+    csrf_token: String,
+) -> AppResult<Index> {
+    let (title, description) = match status {
+        StatusCode::NOT_FOUND => (
+            "Not found",
+            "The page or content you were looking for doesn't exist (anymore).",
+        ),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => (
+            "Forbidden",
+            "You don't have the permissions required to see this.",
+        ),
+        StatusCode::SERVICE_UNAVAILABLE => (
+            "Unavailable",
+            "The server is too busy right now, try again in a moment.",
+        ),
+        status if status.is_server_error() => (
+            "Something broke",
+            "The server failed to handle this, the logs contain more information.",
+        ),
+        _ => ("Error", "The request could not be handled."),
+    };
+
     let body = Error {
-        err: &err.err,
+        code: status.as_u16(),
+        title,
+        description,
+        message,
         redirect: &frontend_redirect_explicit("/", HXTarget::All, Some("/")),
     }
     .render()?;
@@ -27,5 +133,75 @@ pub async fn error(Query(err): Query<Err>) -> AppResult<impl IntoResponse> {
     Ok(Index {
         body,
         all: HXTarget::All.as_str().to_owned(),
+        csrf_token,
     })
 }
+
+#[derive(Serialize)]
+struct JsonError {
+    status: u16,
+    error: &'static str,
+}
+
+/// Api clients can't do anything with the error page, so errors are turned into json for them
+pub async fn json_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let Some(&ErrorStatus(status)) = response.extensions().get::<ErrorStatus>() else {
+        return response;
+    };
+
+    (
+        status,
+        Json(JsonError {
+            status: status.as_u16(),
+            error: status.canonical_reason().unwrap_or("Unknown error"),
+        }),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_pages_explain_that_they_are_gone() {
+        let page = error_page(StatusCode::NOT_FOUND, None, String::new()).unwrap();
+        assert!(page.body.contains("404 - Not found"));
+        assert!(page.body.contains("exist (anymore)."));
+        assert!(!page.body.contains("error_message"));
+    }
+
+    #[test]
+    fn error_links_can_only_name_known_details() {
+        for detail in ErrorDetail::ALL {
+            let url = error_url(StatusCode::NOT_FOUND, detail).parse().unwrap();
+            let Query(err) = Query::<Err>::try_from_uri(&url).unwrap();
+            assert_eq!(err.err, "404");
+            assert_eq!(
+                err.msg.as_deref().and_then(ErrorDetail::from_key),
+                Some(detail)
+            );
+        }
+
+        assert_eq!(
+            ErrorDetail::from_key("Your account was locked, call 555-0100"),
+            None
+        );
+        assert_eq!(ErrorDetail::from_key(""), None);
+    }
+
+    #[test]
+    fn forbidden_pages_explain_why_with_the_message() {
+        let page = error_page(
+            StatusCode::FORBIDDEN,
+            Some("Only owners can change this"),
+            "token".to_owned(),
+        )
+        .unwrap();
+        assert!(page.body.contains("403 - Forbidden"));
+        assert!(page.body.contains("have the permissions required"));
+        assert!(page.body.contains("Only owners can change this"));
+        assert_eq!(page.csrf_token, "token");
+    }
+}
@@ -2,11 +2,12 @@ use askama::Template;
 use askama_axum::IntoResponse;
 use axum::extract::Query;
 use serde::Deserialize;
+use tower_sessions::Session;
 
 use crate::{
     state::AppResult,
     utils::{
-        frontend_redirect,
+        csrf_token, frontend_redirect,
         templates::{Homepage, Index},
         HXTarget,
     },
@@ -19,7 +20,10 @@ pub enum Location {
     All { all: String },
 }
 
-pub async fn homepage(location: Option<Query<Location>>) -> AppResult<impl IntoResponse> {
+pub async fn homepage(
+    location: Option<Query<Location>>,
+    session: Session,
+) -> AppResult<impl IntoResponse> {
     let mut body_html = Homepage {
         redirect_library: &frontend_redirect("/library", HXTarget::Content),
         redirect_explore: &frontend_redirect("/explore", HXTarget::Content),
@@ -47,6 +51,7 @@ pub async fn homepage(location: Option<Query<Location>>) -> AppResult<impl IntoR
     Ok(Index {
         body,
         all: HXTarget::All.as_str().to_owned(),
+        csrf_token: csrf_token(&session).await?,
     }
     .into_response())
 }
@@ -1,7 +1,8 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, fmt::Display, str::FromStr};
 
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     response::{
         sse::{Event, KeepAlive},
         IntoResponse, Sse,
@@ -10,24 +11,26 @@ use axum::{
     Router,
 };
 
-use futures_util::{Stream, StreamExt};
+use futures_util::StreamExt;
 use rusqlite::{params, OptionalExtension};
 use serde::Deserialize;
+use tracing::debug;
 
 use crate::{
     database::{
         Connection, Database, QueryRowGetConnExt, QueryRowGetStmtExt, QueryRowIntoConnExt,
         QueryRowIntoStmtExt,
     },
-    indexing::{resolve_video, CollectionType, ContentType, TableId},
-    state::{AppError, AppResult, AppState, Shutdown},
+    indexing::{movie_directory, resolve_video, CollectionType, ContentType, TableId},
+    recommendation::continue_series,
+    state::{AppError, AppResult, AppState, ContentUpdates, Shutdown},
     utils::{
-        frontend_redirect, frontend_redirect_explicit,
+        format_size, frontend_redirect, frontend_redirect_explicit, html_event,
         streaming::StreamingSessions,
         templates::{
             GridElement, LargeImage, Library, LoadNext, PaginationResponse, PreviewTemplate,
         },
-        HXTarget, WatchStream,
+        utf8_sse, AuthSession, HXTarget, ServerSettings, WatchStream,
     },
 };
 
@@ -35,119 +38,705 @@ pub fn library() -> Router<AppState> {
     Router::new()
         .route("/library", get(get_library))
         .route("/sessions", get(stream_sessions))
+        .route("/library/updates", get(stream_content_updates))
+        .route("/library/continue", get(get_continue_series))
+        .route("/library/recent", get(get_recent_franchises))
         .route("/preview/:preview/:id", get(preview))
         .route("/library/:preview/:id", get(get_preview_items))
+        .route("/library/Movie/:id/extras", get(get_movie_extras))
+}
+
+pub(super) const MAX_PER_PAGE: u64 = 100;
+// Keeps the offset representable for sqlite
+const MAX_PAGE: u64 = i64::MAX as u64 / MAX_PER_PAGE;
+
+#[derive(Deserialize)]
+#[serde(try_from = "RawPagination")]
+pub(super) struct Pagination {
+    page: u64,
+    pub(super) per_page: u64,
+    restore: u64,
 }
 
 #[derive(Deserialize)]
-struct Pagination {
+struct RawPagination {
     page: u64,
     per_page: u64,
+    #[serde(default)]
+    restore: u64,
+}
+
+impl TryFrom<RawPagination> for Pagination {
+    type Error = &'static str;
+
+    fn try_from(raw: RawPagination) -> Result<Self, Self::Error> {
+        if raw.per_page == 0 {
+            return Err("per_page has to be at least 1");
+        }
+
+        if raw.page > MAX_PAGE {
+            return Err("page is out of range");
+        }
+
+        Ok(Self {
+            page: raw.page,
+            per_page: raw.per_page.min(MAX_PER_PAGE),
+            restore: raw.restore,
+        })
+    }
+}
+
+/// How many pages of each row the client had loaded before navigating away, these get loaded again right away.
+/// The rows are listed as "route:pages", separated by commas
+#[derive(Deserialize)]
+struct Restore {
+    #[serde(default)]
+    pages: String,
+}
+
+impl Restore {
+    /// The loaded pages of the row that loads from this route, rows the client didn't scroll start at 0
+    fn of(&self, route: &str) -> u64 {
+        self.pages
+            .split(',')
+            .filter_map(|row| row.rsplit_once(':'))
+            .find(|(row, _)| *row == route)
+            .and_then(|(_, pages)| pages.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+impl Pagination {
+    /// Checked like a page that comes in through the query
+    pub(super) fn new(page: u64, per_page: u64) -> Result<Self, &'static str> {
+        Self::try_from(RawPagination {
+            page,
+            per_page,
+            restore: 0,
+        })
+    }
+
+    pub(super) fn offset(&self) -> u64 {
+        self.page * self.per_page
+    }
+}
+
+/// The rows the library page can be configured to show
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LibrarySection {
+    ContinueSeries,
+    Franchises,
+    /// The same franchises, the ones the user watched something in most recently first
+    RecentFranchises,
+    Collections,
+}
+
+impl FromStr for LibrarySection {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Continue Series" => Ok(Self::ContinueSeries),
+            "Franchises" => Ok(Self::Franchises),
+            "Recent Franchises" => Ok(Self::RecentFranchises),
+            "Collections" => Ok(Self::Collections),
+            _ => Err("unknown library section"),
+        }
+    }
+}
+
+impl LibrarySection {
+    /// The heading and the first page of the row, None if it has nothing to show
+    fn load(
+        self,
+        conn: &rusqlite::Connection,
+        user_id: i64,
+        restore: &Restore,
+    ) -> AppResult<Option<(&'static str, LoadNext)>> {
+        let (heading, collection_type, route) = match self {
+            Self::ContinueSeries => {
+                let has_any = conn.query_row_get::<bool>(
+                    "SELECT exists(SELECT 1 FROM series_activity WHERE user_id = ?1)",
+                    [user_id],
+                )?;
+                return Ok(has_any.then(|| {
+                    let route = "/library/continue";
+                    let load_next = LoadNext::new(route.to_owned(), 0, 20);
+                    ("Continue Series", load_next.restore(restore.of(route)))
+                }));
+            }
+            Self::Franchises => (
+                "Franchises",
+                CollectionType::Franchise,
+                format!("/library/{}/0", Preview::Franchise),
+            ),
+            Self::RecentFranchises => (
+                "Recent Franchises",
+                CollectionType::Franchise,
+                "/library/recent".to_owned(),
+            ),
+            Self::Collections => (
+                "Collections",
+                CollectionType::UserCollection,
+                format!("/library/{}/0", Preview::Collection),
+            ),
+        };
+
+        let has_any = conn.query_row_get::<bool>(
+            "SELECT exists(SELECT 1 FROM collection WHERE type = ?1)",
+            [collection_type],
+        )?;
+
+        Ok(has_any.then(|| {
+            let pages = restore.of(&route);
+            (heading, LoadNext::new(route, 0, 20).restore(pages))
+        }))
+    }
+}
+
+async fn get_library(
+    State(db): State<Database>,
+    State(settings): State<ServerSettings>,
+    Query(restore): Query<Restore>,
+    auth: AuthSession,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    library_page(&db.get()?, &settings.homepage_sections(), user.id, &restore)
 }
 
-async fn get_library() -> AppResult<impl IntoResponse> {
+/// The rows are listed by their names in the settings, in the order they are shown
+fn library_page(
+    conn: &rusqlite::Connection,
+    section_names: &[String],
+    user_id: i64,
+    restore: &Restore,
+) -> AppResult<Library> {
+    let (mut sections, mut shown) = (Vec::new(), Vec::new());
+    for name in section_names {
+        let Ok(section) = name.parse::<LibrarySection>() else {
+            debug!("Skipping the unknown library section \"{name}\"");
+            continue;
+        };
+
+        // Listing a section twice would only show the same row again
+        if shown.contains(&section) {
+            continue;
+        }
+        shown.push(section);
+
+        sections.extend(section.load(conn, user_id, restore)?);
+    }
+
+    let empty = !conn.query_row_get::<bool>(
+        "SELECT exists(SELECT 1 FROM collection WHERE type IN (?1, ?2))",
+        params![CollectionType::Franchise, CollectionType::UserCollection],
+    )?;
+
     Ok(Library {
-        load_next: LoadNext::new("/library/Franchise/0".to_string(), 0, 20),
+        sections,
+        empty,
+        redirect_settings: frontend_redirect("/settings", HXTarget::All),
     })
 }
 
+/// The episode to continue every series the user is watching with, the most recently played series first
+async fn get_continue_series(
+    State(db): State<Database>,
+    Query(pagination): Query<Pagination>,
+    auth: AuthSession,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+    let conn = db.get()?;
+
+    let elements = continue_series(&conn, user.id, pagination.per_page, pagination.offset())?
+        .into_iter()
+        .map(|next| GridElement {
+            title: next.title,
+            redirect_entire: String::new(),
+            redirect_img: frontend_redirect_explicit(
+                &format!("/video/{}", next.content_id),
+                HXTarget::All,
+                None,
+            ),
+            redirect_title: frontend_redirect(
+                &format!("/preview/{}/{}", Preview::Series, next.series_id),
+                HXTarget::Content,
+            ),
+        })
+        .collect::<Vec<_>>();
+
+    let load_next = (elements.len() >= pagination.per_page as usize).then(|| {
+        LoadNext::new(
+            "/library/continue".to_owned(),
+            pagination.page + 1,
+            pagination.per_page,
+        )
+        .restore(pagination.restore)
+    });
+
+    let empty = (pagination.page == 0 && elements.is_empty()).then_some("Nothing here yet");
+
+    Ok(PaginationResponse {
+        elements,
+        load_next,
+        empty,
+    })
+}
+
+async fn get_recent_franchises(
+    State(db): State<Database>,
+    Query(pagination): Query<Pagination>,
+    auth: AuthSession,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let elements = recent_franchises(&db.get()?, user.id, &pagination)?
+        .into_iter()
+        .map(|(id, title)| franchise_element(id, title))
+        .collect::<Vec<_>>();
+
+    let load_next = (elements.len() >= pagination.per_page as usize).then(|| {
+        LoadNext::new(
+            "/library/recent".to_owned(),
+            pagination.page + 1,
+            pagination.per_page,
+        )
+        .restore(pagination.restore)
+    });
+
+    Ok(PaginationResponse {
+        elements,
+        load_next,
+        empty: None,
+    })
+}
+
+/// Franchises ordered by the last time the user played an episode of a series in them, the rest follow by title
+fn recent_franchises(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    pagination: &Pagination,
+) -> AppResult<Vec<(u64, String)>> {
+    let franchises = conn
+        .prepare(
+            "SELECT collection.id, franchise.title FROM collection
+                JOIN franchise ON collection.reference = franchise.id
+                LEFT JOIN collection_contains ON collection_contains.collection_id = collection.id
+                    AND collection_contains.type = ?2
+                LEFT JOIN series_activity ON series_activity.series_id = collection_contains.reference
+                    AND series_activity.user_id = ?3
+                WHERE collection.type = ?1
+                GROUP BY collection.id
+                ORDER BY MAX(series_activity.last_active) IS NULL,
+                    MAX(series_activity.last_active) DESC,
+                    franchise.title ASC
+                LIMIT ?4 OFFSET ?5",
+        )?
+        .query_map_into(params![
+            CollectionType::Franchise,
+            TableId::Collection,
+            user_id,
+            pagination.per_page,
+            pagination.offset()
+        ])?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(franchises)
+}
+
+fn franchise_element(id: u64, title: String) -> GridElement {
+    GridElement {
+        title,
+        redirect_entire: frontend_redirect(
+            &format!("/preview/{}/{id}", Preview::Franchise),
+            HXTarget::Content,
+        ),
+        redirect_img: String::new(),
+        redirect_title: String::new(),
+    }
+}
+
 async fn stream_sessions(
     State(sessions): State<StreamingSessions>,
     State(shutdown): State<Shutdown>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+) -> impl IntoResponse {
     let resolve = |shutdown: Shutdown| async move { shutdown.cancelled().await };
     let stream = WatchStream::new(sessions.render_receiver())
-        .map(|content| {
-            let content = content.replace('\r', "");
-            Ok(Event::default().data(content))
-        })
+        .map(|content| Ok::<_, Infallible>(html_event(&content)))
         .take_until(resolve(shutdown));
-    Sse::new(stream).keep_alive(KeepAlive::default())
+    utf8_sse(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+/// Sends an event whenever indexing changed the content, the library view refreshes itself on it
+async fn stream_content_updates(
+    State(updates): State<ContentUpdates>,
+    State(shutdown): State<Shutdown>,
+) -> impl IntoResponse {
+    let resolve = |shutdown: Shutdown| async move { shutdown.cancelled().await };
+    // The first item is the current state, which the client already shows
+    let stream = WatchStream::new(updates.subscribe())
+        .skip(1)
+        .map(|()| Ok::<_, Infallible>(Event::default().event("updated").data("")))
+        .take_until(resolve(shutdown));
+    utf8_sse(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Preview {
     Franchise,
     Movie,
     Series,
     Season,
     Episode,
+    Collection,
+    Extra,
+}
+
+impl Preview {
+    /// Every variant, parsing only accepts what is listed here
+    const ALL: [Self; 7] = [
+        Self::Franchise,
+        Self::Movie,
+        Self::Series,
+        Self::Season,
+        Self::Episode,
+        Self::Collection,
+        Self::Extra,
+    ];
+
+    /// The form used in routes like "/preview/Movie/1"
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Franchise => "Franchise",
+            Self::Movie => "Movie",
+            Self::Series => "Series",
+            Self::Season => "Season",
+            Self::Episode => "Episode",
+            Self::Collection => "Collection",
+            Self::Extra => "Extra",
+        }
+    }
+}
+
+impl Display for Preview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Preview {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|preview| preview.as_str() == s)
+            .ok_or("invalid preview")
+    }
+}
+
+impl<'de> Deserialize<'de> for Preview {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
 }
 
 async fn preview(
     State(db): State<Database>,
     Path((prev, id)): Path<(Preview, u64)>,
+    Query(restore): Query<Restore>,
 ) -> AppResult<impl IntoResponse> {
-    Ok(PreviewTemplate {
-        top: top_preview(db.clone(), id, prev)?,
-        categories: preview_categories(&db, id, prev)?,
-    })
-}
+    let Some(top) = top_preview(&db.get()?, id, prev)? else {
+        status!(StatusCode::NOT_FOUND);
+    };
 
-fn top_preview(conn: Database, id: u64, prev: Preview) -> AppResult<LargeImage> {
-    let conn = conn.get()?;
+    let categories = preview_categories(&db, id, prev)?
+        .into_iter()
+        .map(|(heading, load_next)| {
+            let pages = restore.of(&load_next.route);
+            (heading, load_next.restore(pages))
+        })
+        .collect();
 
-    let (title, image_interaction) = match prev {
-        Preview::Franchise => (
-            conn.query_row_get(
+    Ok(PreviewTemplate { top, categories })
+}
+
+/// None if there is nothing with that id
+fn top_preview(
+    conn: &rusqlite::Connection,
+    id: u64,
+    prev: Preview,
+) -> AppResult<Option<LargeImage>> {
+    let title: Option<String> = match prev {
+        Preview::Franchise => conn
+            .query_row_get(
                 "SELECT franchise.title FROM franchise, collection
                 WHERE collection.reference = franchise.id
                 AND collection.id=?1
                 AND collection.type = ?2",
                 params![id, CollectionType::Franchise],
-            )?,
-            String::new(),
-        ),
-        Preview::Movie => {
-            let title: String =
-                conn.query_row_get("SELECT movie.title FROM movie WHERE movie.id=?1", [id])?;
-
-            let video_id = resolve_video(&conn, id, ContentType::Movie)?;
-            (
-                title,
-                frontend_redirect_explicit(&format!("/video/{video_id}"), HXTarget::All, None),
             )
-        }
-        Preview::Series => (
-            conn.query_row_get(
+            .optional()?,
+        Preview::Movie => conn
+            .query_row_get("SELECT movie.title FROM movie WHERE movie.id=?1", [id])
+            .optional()?,
+        Preview::Series => conn
+            .query_row_get(
                 "SELECT series.title FROM series, collection
                     WHERE collection.reference = series.id
                     AND collection.type = ?1
                     AND collection.id = ?2",
                 params![CollectionType::Series, id],
-            )?,
-            String::new(),
-        ),
-        Preview::Season => {
-            let title = conn.query_row_get(
+            )
+            .optional()?,
+        Preview::Season => conn
+            .query_row_get(
                 "SELECT season.title FROM season, collection
                     WHERE collection.reference = season.id
                     AND collection.type = ?1
                     AND collection.id = ?2",
                 params![CollectionType::Season, id],
-            )?;
-
-            (title, String::new())
-        }
-        Preview::Episode => {
-            let (title, episode): (String, u64) = conn.query_row_into(
+            )
+            .optional()?,
+        Preview::Episode => conn
+            .query_row_into::<(String, u64)>(
                 "SELECT episode.title, episode.episode FROM episode WHERE episode.id = ?1",
                 [id],
-            )?;
+            )
+            .optional()?
+            .map(|(title, episode)| format!("{title} - Episode {episode}")),
+        Preview::Collection => conn
+            .query_row_get(
+                "SELECT user_collection.title FROM user_collection, collection
+                    WHERE collection.reference = user_collection.id
+                    AND collection.type = ?1
+                    AND collection.id = ?2",
+                params![CollectionType::UserCollection, id],
+            )
+            .optional()?,
+        Preview::Extra => conn
+            .query_row_get("SELECT extra.title FROM extra WHERE extra.id=?1", [id])
+            .optional()?,
+    };
+
+    let Some(title) = title else {
+        return Ok(None);
+    };
 
-            let video_id = resolve_video(&conn, id, ContentType::Episode)?;
+    let content_type = match prev {
+        Preview::Movie => Some(ContentType::Movie),
+        Preview::Episode => Some(ContentType::Episode),
+        Preview::Extra => Some(ContentType::Extra),
+        _ => None,
+    };
 
-            (
-                format!("{title} - Episode {episode}"),
-                frontend_redirect_explicit(&format!("/video/{video_id}"), HXTarget::All, None),
-            )
-        }
+    // Content whose file is currently missing is still shown, it just can't be played
+    let video_id = match content_type {
+        Some(content_type) => resolve_video(conn, id, content_type).optional()?,
+        None => None,
+    };
+
+    let image_interaction = video_id
+        .map(|video_id| {
+            frontend_redirect_explicit(&format!("/video/{video_id}"), HXTarget::All, None)
+        })
+        .unwrap_or_default();
+
+    let is_collection = matches!(
+        prev,
+        Preview::Franchise | Preview::Series | Preview::Season | Preview::Collection
+    );
+
+    // Collections are exported and played with everything inside them, even what is nested further down
+    let export = match video_id {
+        Some(video_id) => Some(format!("/video/export/content/{video_id}")),
+        None if is_collection => Some(format!("/video/export/collection/{id}")),
+        None => None,
+    };
+
+    let play_all = is_collection
+        .then(|| frontend_redirect_explicit(&format!("/video/queue/{id}"), HXTarget::All, None));
+
+    let (badge, stream_summary, technical_details) = match video_id {
+        Some(video_id) => (
+            quality_badge(conn, video_id)?,
+            Some(stream_summary(conn, video_id)?),
+            technical_details(conn, video_id)?,
+        ),
+        None => (None, None, Vec::new()),
     };
 
-    Ok(LargeImage {
+    Ok(Some(LargeImage {
         title,
         image_interaction,
-    })
+        badge,
+        stream_summary,
+        technical_details,
+        export,
+        play_all,
+    }))
+}
+
+/// Starts playing the content, nothing happens for content whose file is currently missing
+fn play_redirect(conn: &Connection, data_id: u64, content_type: ContentType) -> AppResult<String> {
+    let redirect = resolve_video(conn, data_id, content_type)
+        .optional()?
+        .map(|video_id| {
+            frontend_redirect_explicit(&format!("/video/{video_id}"), HXTarget::All, None)
+        })
+        .unwrap_or_default();
+    Ok(redirect)
+}
+
+/// Something like "1080p BluRay", if anything about the quality of the video is known
+fn quality_badge(conn: &rusqlite::Connection, video_id: u64) -> AppResult<Option<String>> {
+    let (resolution, source): (Option<u64>, Option<String>) = conn.query_row_into(
+        "SELECT resolution, source FROM content WHERE id = ?1",
+        [video_id],
+    )?;
+
+    let badge = match (resolution, source) {
+        (Some(resolution), Some(source)) => Some(format!("{resolution}p {source}")),
+        (Some(resolution), None) => Some(format!("{resolution}p")),
+        (None, source) => source,
+    };
+    Ok(badge)
+}
+
+/// Resolution and codecs of the main video and audio stream, separated by dots
+fn stream_summary(conn: &rusqlite::Connection, video_id: u64) -> AppResult<String> {
+    let Some((video_codec, width, height, audio)) = conn
+        .query_row_into::<(Option<String>, Option<u32>, Option<u32>, Option<String>)>(
+            "SELECT video_codec, width, height, audio FROM technical_details WHERE content_id = ?1",
+            [video_id],
+        )
+        .optional()?
+    else {
+        return Ok("unknown".to_owned());
+    };
+
+    // The default audio stream is listed first, the language is left out to keep it short
+    let audio = audio.as_deref().and_then(|audio| {
+        let first = audio.split(',').next()?;
+        Some(
+            first
+                .split_once(" (")
+                .map_or(first, |(codec, _)| codec)
+                .to_owned(),
+        )
+    });
+
+    let parts = [
+        width
+            .zip(height)
+            .map(|(width, height)| resolution_label(width, height)),
+        video_codec,
+        audio,
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        return Ok("unknown".to_owned());
+    }
+    Ok(parts.join(" • "))
+}
+
+/// The usual name of a resolution, wide formats like 1920x800 are named after their width
+fn resolution_label(width: u32, height: u32) -> String {
+    let label = match (width, height) {
+        (width, height) if width >= 3200 || height >= 1800 => "2160p",
+        (width, height) if width >= 2400 || height >= 1300 => "1440p",
+        (width, height) if width >= 1700 || height >= 1000 => "1080p",
+        (width, height) if width >= 1200 || height >= 700 => "720p",
+        _ => return format!("{height}p"),
+    };
+    label.to_owned()
+}
+
+/// Label and value of everything that was probed during indexing, only the size is known if the file couldn't be probed
+fn technical_details(
+    conn: &rusqlite::Connection,
+    video_id: u64,
+) -> AppResult<Vec<(&'static str, String)>> {
+    type Details = (
+        String,
+        Option<String>,
+        Option<u32>,
+        Option<u32>,
+        Option<String>,
+        Option<u64>,
+    );
+
+    let size = conn
+        .query_row_get::<Option<u64>>(
+            "SELECT data_file.size FROM content, data_file
+                WHERE content.id = ?1 AND content.data_id = data_file.id",
+            [video_id],
+        )
+        .optional()?
+        .flatten();
+
+    let mut details = Vec::new();
+    if let Some(size) = size {
+        details.push(("Size", format_size(size)));
+    }
+
+    let copies = conn.query_row_get::<u64>(
+        "SELECT COUNT(*) FROM data_file WHERE duplicate_of = ?1",
+        [video_id],
+    )?;
+    if copies > 0 {
+        details.push(("Copies", copies.to_string()));
+    }
+
+    let Some((container, video_codec, width, height, audio, bitrate)) = conn
+        .query_row_into::<Details>(
+            "SELECT container, video_codec, width, height, audio, bitrate
+                FROM technical_details WHERE content_id = ?1",
+            [video_id],
+        )
+        .optional()?
+    else {
+        return Ok(details);
+    };
+
+    details.push(("Container", container));
+
+    let video = match (video_codec, width.zip(height)) {
+        (Some(codec), Some((width, height))) => Some(format!("{codec} {width}x{height}")),
+        (Some(codec), None) => Some(codec),
+        (None, Some((width, height))) => Some(format!("{width}x{height}")),
+        (None, None) => None,
+    };
+    if let Some(video) = video {
+        details.push(("Video", video));
+    }
+
+    if let Some(audio) = audio {
+        details.push(("Audio", audio.replace(',', ", ")));
+    }
+
+    if let Some(bitrate) = bitrate {
+        details.push((
+            "Bitrate",
+            format!("{:.1} Mbit/s", bitrate as f64 / 1_000_000.),
+        ));
+    }
+
+    Ok(details)
 }
 
 fn preview_categories(
@@ -160,10 +749,10 @@ fn preview_categories(
         id: u64,
         prev: Preview,
     ) -> AppResult<Vec<(&'static str, LoadNext)>> {
-        let mut out = Vec::new();
-
-        match prev {
+        let mut out = match prev {
             Preview::Franchise => {
+                let mut out = Vec::new();
+
                 let movie_count: u64 = conn.query_row_get(
                     "SELECT COUNT(*) FROM movie, collection, collection_contains, content
                                 WHERE content.reference = movie.id
@@ -184,7 +773,7 @@ fn preview_categories(
                 if movie_count > 0 {
                     out.push((
                         "<h1> Movies </h1>",
-                        LoadNext::new(format!("/library/Movie/{id}"), 0, 20),
+                        LoadNext::new(format!("/library/{}/{id}", Preview::Movie), 0, 20),
                     ));
                 }
 
@@ -209,12 +798,12 @@ fn preview_categories(
                     2.. => {
                         out.push((
                             "<h1> Series </h1>",
-                            LoadNext::new(format!("/library/Series/{id}"), 0, 20),
+                            LoadNext::new(format!("/library/{}/{id}", Preview::Series), 0, 20),
                         ));
                     }
                 };
 
-                Ok(out)
+                out
             }
             Preview::Series => {
                 let season_count: u64 = conn.query_row_get(
@@ -227,7 +816,7 @@ fn preview_categories(
                 )?;
 
                 match season_count {
-                    0 => Ok(Vec::new()),
+                    0 => Vec::new(),
                     1 => {
                         let season_id: u64 = conn.query_row_get(
                             "SELECT id FROM collection, collection_contains
@@ -237,20 +826,64 @@ fn preview_categories(
                                 AND collection_contains.reference = collection.id",
                             params![id, TableId::Collection, CollectionType::Season],
                         )?;
-                        inner(conn, season_id, Preview::Season)
+                        inner(conn, season_id, Preview::Season)?
                     }
-                    2.. => Ok(vec![(
+                    2.. => vec![(
                         "<h2> Seasons </h2>",
-                        LoadNext::new(format!("/library/Season/{id}"), 0, 20),
-                    )]),
+                        LoadNext::new(format!("/library/{}/{id}", Preview::Season), 0, 20),
+                    )],
                 }
             }
-            Preview::Season => Ok(vec![(
+            Preview::Season => vec![(
                 "<h2> Episodes </h2>",
-                LoadNext::new(format!("/library/Episode/{id}"), 0, 20),
-            )]),
-            Preview::Episode | Preview::Movie => Ok(Vec::new()),
+                LoadNext::new(format!("/library/{}/{id}", Preview::Episode), 0, 20),
+            )],
+            // Extras are only ever put into the collections indexing creates, so user collections show them with everything else
+            Preview::Collection => {
+                return Ok(vec![(
+                    "<h2> Contents </h2>",
+                    LoadNext::new(format!("/library/{}/{id}", Preview::Collection), 0, 20),
+                )])
+            }
+            Preview::Movie => {
+                let Some(directory) = movie_extras_directory(conn, id)? else {
+                    return Ok(Vec::new());
+                };
+                let extra_count: u64 = conn.query_row_get(
+                    "SELECT COUNT(*) FROM extra WHERE directory = ?1",
+                    [directory],
+                )?;
+
+                return Ok(if extra_count > 0 {
+                    vec![(
+                        "<h2> Extras </h2>",
+                        LoadNext::new(format!("/library/Movie/{id}/extras"), 0, 20),
+                    )]
+                } else {
+                    Vec::new()
+                });
+            }
+            Preview::Episode | Preview::Extra => return Ok(Vec::new()),
+        };
+
+        // Extras get their own row at the end, so they don't clutter the main content
+        let extra_count: u64 = conn.query_row_get(
+            "SELECT COUNT(*) FROM collection_contains, content
+                WHERE collection_contains.collection_id = ?1
+                AND collection_contains.type = ?2
+                AND collection_contains.reference = content.id
+                AND content.type = ?3",
+            params![id, TableId::Content, ContentType::Extra],
+        )?;
+
+        if extra_count > 0 {
+            out.push((
+                "<h2> Extras </h2>",
+                LoadNext::new(format!("/library/{}/{id}", Preview::Extra), 0, 20),
+            ));
         }
+
+        Ok(out)
     }
 
     let conn = db.get()?;
@@ -277,7 +910,7 @@ async fn get_preview_items(
                 .query_map_into(params![
                     CollectionType::Franchise,
                     pagination.per_page,
-                    pagination.page * pagination.per_page
+                    pagination.offset()
                 ])
                 .optional()?
                 .map_or_else(
@@ -285,15 +918,7 @@ async fn get_preview_items(
                     |rows| rows.collect::<Result<Vec<(u64, String)>, _>>(),
                 )?
                 .into_iter()
-                .map(|(id, title)| GridElement {
-                    title: title.clone(),
-                    redirect_entire: frontend_redirect(
-                        &format!("/preview/Franchise/{id}"),
-                        HXTarget::Content,
-                    ),
-                    redirect_img: String::new(),
-                    redirect_title: String::new(),
-                })
+                .map(|(id, title)| franchise_element(id, title))
                 .collect::<Vec<_>>();
 
             Ok(franchises)
@@ -301,7 +926,7 @@ async fn get_preview_items(
         Preview::Movie => {
             let items = conn
                 .prepare(
-                    "SELECT movie.title, movie.id FROM movie, collection_contains, content, collection
+                    "SELECT DISTINCT movie.title, movie.id FROM movie, collection_contains, content, collection
                         WHERE content.reference = movie.id
                         AND content.type = ?1
                         AND collection.type = ?2
@@ -318,23 +943,18 @@ async fn get_preview_items(
                     id,
                     TableId::Content,
                     pagination.per_page,
-                    pagination.page * pagination.per_page
+                    pagination.offset()
                 ])
                 .optional()?
                 .map_or_else(|| Ok(Vec::new()), |rows| rows.collect())?
                 .into_iter()
                 .map(|(title, movie_id)| {
-                    let video_id = resolve_video(&conn, movie_id, ContentType::Movie)?;
                     Ok(GridElement {
                         title,
                         redirect_entire: String::new(),
-                        redirect_img: frontend_redirect_explicit(
-                            &format!("/video/{video_id}"),
-                            HXTarget::All,
-                            None,
-                        ),
+                        redirect_img: play_redirect(&conn, movie_id, ContentType::Movie)?,
                         redirect_title: frontend_redirect(
-                            &format!("/preview/Movie/{movie_id}"),
+                            &format!("/preview/{}/{movie_id}", Preview::Movie),
                             HXTarget::Content,
                         ),
                     })
@@ -352,14 +972,14 @@ async fn get_preview_items(
                         AND collection_contains.reference = collection.id
                         ORDER BY series.title ASC
                         LIMIT ?4 OFFSET ?5")?
-            .query_map_into(params![CollectionType::Series, id, TableId::Collection, pagination.per_page, pagination.page * pagination.per_page])?
+            .query_map_into(params![CollectionType::Series, id, TableId::Collection, pagination.per_page, pagination.offset()])?
             .collect::<Result<Vec<(u64, String)>, _>>()?
             .into_iter()
             .map(|(series_id, title)| {
                 GridElement {
                     title,
                     redirect_entire: frontend_redirect(
-                        &format!("/preview/Series/{series_id}"),
+                        &format!("/preview/{}/{series_id}", Preview::Series),
                         HXTarget::Content,
                     ),
                     redirect_img: String::new(),
@@ -380,7 +1000,7 @@ async fn get_preview_items(
                             AND collection.reference = season.id
                             ORDER BY season.season ASC
                             LIMIT ?4 OFFSET ?5")?
-                .query_map_into::<(u64, String)>(params![id, TableId::Collection, CollectionType::Season, pagination.per_page, pagination.page * pagination.per_page])
+                .query_map_into::<(u64, String)>(params![id, TableId::Collection, CollectionType::Season, pagination.per_page, pagination.offset()])
                 .optional()?
                 .map_or_else(|| Ok(Vec::new()), |rows| rows.collect())?
                 .into_iter()
@@ -388,7 +1008,7 @@ async fn get_preview_items(
                         GridElement {
                             title,
                             redirect_entire: frontend_redirect(
-                                &format!("/preview/Season/{season_id}"),
+                                &format!("/preview/{}/{season_id}", Preview::Season),
                                 HXTarget::Content,
                             ),
                             redirect_img: String::new(),
@@ -410,23 +1030,18 @@ async fn get_preview_items(
                 AND collection_contains.reference = content.id
                 ORDER BY episode.episode ASC
                 LIMIT ?5 OFFSET ?6")?
-            .query_map_into::<(u64, String, u64)>(params![CollectionType::Season, id, TableId::Content, ContentType::Episode, pagination.per_page, pagination.page * pagination.per_page])
+            .query_map_into::<(u64, String, u64)>(params![CollectionType::Season, id, TableId::Content, ContentType::Episode, pagination.per_page, pagination.offset()])
             .optional()?
             .map_or_else(|| Ok(Vec::new()), |rows| rows.collect())?
             .into_iter()
             .map(|(data_id, name, episode)| {
                 let name = format!("{name} - Episode {episode}");
-                let video_id = resolve_video(&conn, data_id, ContentType::Episode)?;
                 Ok(GridElement {
                     title: name,
                     redirect_entire: String::new(),
-                    redirect_img: frontend_redirect_explicit(
-                        &format!("/video/{video_id}"),
-                        HXTarget::All,
-                        None,
-                    ),
+                    redirect_img: play_redirect(&conn, data_id, ContentType::Episode)?,
                     redirect_title: frontend_redirect(
-                        &format!("/preview/Episode/{data_id}"),
+                        &format!("/preview/{}/{data_id}", Preview::Episode),
                         HXTarget::Content,
                     ),
                 })
@@ -434,28 +1049,477 @@ async fn get_preview_items(
             .collect::<AppResult<Vec<_>>>()?;
             Ok(items)
         }
+        Preview::Extra => {
+            let items = conn
+                .prepare(
+                    "SELECT extra.id, extra.title FROM extra, collection_contains, content
+                        WHERE content.reference = extra.id
+                        AND content.type = ?1
+                        AND collection_contains.collection_id = ?2
+                        AND collection_contains.type = ?3
+                        AND collection_contains.reference = content.id
+                        ORDER BY extra.title ASC
+                        LIMIT ?4 OFFSET ?5",
+                )?
+                .query_map_into::<(u64, String)>(params![
+                    ContentType::Extra,
+                    id,
+                    TableId::Content,
+                    pagination.per_page,
+                    pagination.offset()
+                ])?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|(extra_id, title)| extra_element(&conn, extra_id, title))
+                .collect::<AppResult<Vec<_>>>()?;
+            Ok(items)
+        }
+        // Like with franchises, the top level of the library lists all collections
+        Preview::Collection if id == 0 => {
+            let items = conn
+                .prepare(
+                    "SELECT collection.id, user_collection.title FROM collection, user_collection
+                        WHERE collection.reference = user_collection.id
+                        AND collection.type = ?1
+                        ORDER BY user_collection.title ASC
+                        LIMIT ?2 OFFSET ?3",
+                )?
+                .query_map_into::<(u64, String)>(params![
+                    CollectionType::UserCollection,
+                    pagination.per_page,
+                    pagination.offset()
+                ])?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|(collection_id, title)| GridElement {
+                    title,
+                    redirect_entire: frontend_redirect(
+                        &format!("/preview/{}/{collection_id}", Preview::Collection),
+                        HXTarget::Content,
+                    ),
+                    redirect_img: String::new(),
+                    redirect_title: String::new(),
+                })
+                .collect::<Vec<_>>();
+            Ok(items)
+        }
+        Preview::Collection => {
+            let items = conn
+                .prepare(
+                    "SELECT type, reference FROM collection_contains
+                        WHERE collection_id = ?1
+                        ORDER BY rowid ASC
+                        LIMIT ?2 OFFSET ?3",
+                )?
+                .query_map_into::<(TableId, u64)>(params![
+                    id,
+                    pagination.per_page,
+                    pagination.offset()
+                ])?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .map(|(table, reference)| collection_entry(&conn, table, reference))
+                .collect::<AppResult<Vec<_>>>()?;
+            Ok(items)
+        }
     }?;
 
     let load_next = if elements.len() < pagination.per_page as usize {
         None
     } else {
-        let preview = match returned {
-            Preview::Franchise => "Franchise",
-            Preview::Movie => "Movie",
-            Preview::Series => "Series",
-            Preview::Season => "Season",
-            Preview::Episode => "Episode",
-        };
+        Some(
+            LoadNext::new(
+                format!("/library/{returned}/{id}"),
+                pagination.page + 1,
+                pagination.per_page,
+            )
+            .restore(pagination.restore),
+        )
+    };
+
+    let empty = (pagination.page == 0 && elements.is_empty()).then_some("Nothing here yet");
+
+    Ok(PaginationResponse {
+        elements,
+        load_next,
+        empty,
+    })
+}
+
+/// The directory extras of this movie have to be in, None if the movie has no file right now
+fn movie_extras_directory(conn: &Connection, movie_id: u64) -> AppResult<Option<String>> {
+    let path = conn
+        .query_row_get::<String>(
+            "SELECT data_file.path FROM content, data_file
+                WHERE content.data_id = data_file.id
+                AND content.type = ?1
+                AND content.reference = ?2
+                AND content.part = 0",
+            params![ContentType::Movie, movie_id],
+        )
+        .optional()?;
+
+    Ok(path.and_then(|path| {
+        movie_directory(std::path::Path::new(&path))
+            .map(|directory| directory.to_string_lossy().into_owned())
+    }))
+}
+
+async fn get_movie_extras(
+    State(db): State<Database>,
+    Path(id): Path<u64>,
+    Query(pagination): Query<Pagination>,
+) -> AppResult<impl IntoResponse> {
+    let conn = db.get()?;
 
-        Some(LoadNext::new(
-            format!("/library/{preview}/{id}"),
+    let elements = match movie_extras_directory(&conn, id)? {
+        Some(directory) => conn
+            .prepare(
+                "SELECT extra.id, extra.title FROM extra
+                    WHERE extra.directory = ?1
+                    ORDER BY extra.title ASC
+                    LIMIT ?2 OFFSET ?3",
+            )?
+            .query_map_into::<(u64, String)>(params![
+                directory,
+                pagination.per_page,
+                pagination.offset()
+            ])?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(extra_id, title)| extra_element(&conn, extra_id, title))
+            .collect::<AppResult<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    let load_next = (elements.len() >= pagination.per_page as usize).then(|| {
+        LoadNext::new(
+            format!("/library/Movie/{id}/extras"),
             pagination.page + 1,
             pagination.per_page,
-        ))
-    };
+        )
+        .restore(pagination.restore)
+    });
+
+    let empty = (pagination.page == 0 && elements.is_empty()).then_some("Nothing here yet");
 
     Ok(PaginationResponse {
         elements,
         load_next,
+        empty,
     })
 }
+
+fn extra_element(conn: &Connection, extra_id: u64, title: String) -> AppResult<GridElement> {
+    Ok(GridElement {
+        title,
+        redirect_entire: String::new(),
+        redirect_img: play_redirect(conn, extra_id, ContentType::Extra)?,
+        redirect_title: frontend_redirect(
+            &format!("/preview/{}/{extra_id}", Preview::Extra),
+            HXTarget::Content,
+        ),
+    })
+}
+
+/// Renders anything that can be inside a collection, used for collections that can contain arbitrary things
+fn collection_entry(conn: &Connection, table: TableId, reference: u64) -> AppResult<GridElement> {
+    match table {
+        TableId::Content => {
+            let (content_type, data_id): (ContentType, Option<u64>) = conn.query_row_into(
+                "SELECT type, reference FROM content WHERE id = ?1",
+                [reference],
+            )?;
+
+            let (title, preview) = match (content_type, data_id) {
+                (ContentType::Movie, Some(data_id)) => (
+                    conn.query_row_get("SELECT title FROM movie WHERE id = ?1", [data_id])?,
+                    Some(format!("/preview/{}/{data_id}", Preview::Movie)),
+                ),
+                (ContentType::Episode, Some(data_id)) => {
+                    let (title, episode): (String, u64) = conn.query_row_into(
+                        "SELECT title, episode FROM episode WHERE id = ?1",
+                        [data_id],
+                    )?;
+                    (
+                        format!("{title} - Episode {episode}"),
+                        Some(format!("/preview/{}/{data_id}", Preview::Episode)),
+                    )
+                }
+                (ContentType::Song, Some(data_id)) => (
+                    conn.query_row_get("SELECT title FROM song WHERE id = ?1", [data_id])?,
+                    None,
+                ),
+                (ContentType::Extra, Some(data_id)) => (
+                    conn.query_row_get("SELECT title FROM extra WHERE id = ?1", [data_id])?,
+                    Some(format!("/preview/{}/{data_id}", Preview::Extra)),
+                ),
+                _ => (format!("Unknown Content {reference}"), None),
+            };
+
+            Ok(GridElement {
+                title,
+                redirect_entire: String::new(),
+                redirect_img: frontend_redirect_explicit(
+                    &format!("/video/{reference}"),
+                    HXTarget::All,
+                    None,
+                ),
+                redirect_title: preview
+                    .map(|preview| frontend_redirect(&preview, HXTarget::Content))
+                    .unwrap_or_default(),
+            })
+        }
+        TableId::Collection => {
+            let (collection_type, data_id): (CollectionType, u64) = conn.query_row_into(
+                "SELECT type, reference FROM collection WHERE id = ?1",
+                [reference],
+            )?;
+
+            let (title, preview) = match collection_type {
+                CollectionType::Franchise => (
+                    conn.query_row_get("SELECT title FROM franchise WHERE id = ?1", [data_id])?,
+                    Some("Franchise"),
+                ),
+                CollectionType::Series => (
+                    conn.query_row_get("SELECT title FROM series WHERE id = ?1", [data_id])?,
+                    Some("Series"),
+                ),
+                CollectionType::Season => (
+                    conn.query_row_get("SELECT title FROM season WHERE id = ?1", [data_id])?,
+                    Some("Season"),
+                ),
+                CollectionType::UserCollection => (
+                    conn.query_row_get(
+                        "SELECT title FROM user_collection WHERE id = ?1",
+                        [data_id],
+                    )?,
+                    Some("Collection"),
+                ),
+                CollectionType::Theme => ("Theme".to_owned(), None),
+            };
+
+            Ok(GridElement {
+                title,
+                redirect_entire: preview
+                    .map(|preview| {
+                        frontend_redirect(
+                            &format!("/preview/{preview}/{reference}"),
+                            HXTarget::Content,
+                        )
+                    })
+                    .unwrap_or_default(),
+                redirect_img: String::new(),
+                redirect_title: String::new(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use super::*;
+    use crate::{
+        database::{in_file, in_memory},
+        indexing::{fixture_content, index_fixture},
+        recommendation::record_series_activity,
+        utils::TempDir,
+    };
+
+    fn no_restore() -> Restore {
+        Restore {
+            pages: String::new(),
+        }
+    }
+
+    #[test]
+    fn an_empty_library_explains_how_to_fill_it() {
+        let conn = in_memory();
+        let sections = ["Continue Series", "Franchises", "Collections"].map(str::to_owned);
+
+        let library = library_page(&conn, &sections, 1, &no_restore()).unwrap();
+        assert!(library.empty);
+        assert!(library.sections.is_empty());
+        assert!(library.render().unwrap().contains("Nothing here yet"));
+        assert_eq!(library.into_response().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn watching_an_episode_moves_its_franchise_up() {
+        const ALIAS: &str = "Alias/Season 1/Truth Be Told - s1e1.mp4";
+        const LOST: &str = "Lost/Season 1/Pilot - s1e1.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[ALIAS, LOST]);
+        conn.execute(
+            "INSERT INTO users (id, username, password) VALUES (1, 'user', '')",
+            [],
+        )
+        .unwrap();
+
+        let titles = |conn: &rusqlite::Connection| {
+            let everything = Pagination {
+                page: 0,
+                per_page: MAX_PER_PAGE,
+                restore: 0,
+            };
+            recent_franchises(conn, 1, &everything)
+                .unwrap()
+                .into_iter()
+                .map(|(_, title)| title)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(titles(&conn), ["Alias", "Lost"]);
+
+        record_series_activity(&conn, 1, fixture_content(&conn, LOST)).unwrap();
+        assert_eq!(titles(&conn), ["Lost", "Alias"]);
+    }
+
+    #[test]
+    fn previews_of_missing_ids_are_not_found() {
+        let conn = in_memory();
+        for preview in Preview::ALL {
+            assert!(top_preview(&conn, 1, preview).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn content_without_a_file_is_shown_but_not_playable() {
+        let conn = in_memory();
+        conn.execute("INSERT INTO movie (id, title) VALUES (1, 'Heat')", [])
+            .unwrap();
+
+        let top = top_preview(&conn, 1, Preview::Movie).unwrap().unwrap();
+        assert_eq!(top.title, "Heat");
+        assert!(top.image_interaction.is_empty());
+        assert!(top.export.is_none());
+    }
+
+    #[test]
+    fn resolutions_are_named_like_their_format() {
+        assert_eq!(resolution_label(3840, 2160), "2160p");
+        assert_eq!(resolution_label(2560, 1440), "1440p");
+        assert_eq!(resolution_label(1920, 1080), "1080p");
+        assert_eq!(resolution_label(1920, 800), "1080p");
+        assert_eq!(resolution_label(1440, 1080), "1080p");
+        assert_eq!(resolution_label(1280, 536), "720p");
+        assert_eq!(resolution_label(720, 576), "576p");
+    }
+
+    #[test]
+    fn stream_summaries_name_the_resolution_and_the_default_audio() {
+        let conn = in_memory();
+        assert_eq!(stream_summary(&conn, 1).unwrap(), "unknown");
+
+        conn.execute_batch(
+            "INSERT INTO content (id, last_changed, hash, type, part) VALUES (1, 0, x'00', 1, 0);
+            INSERT INTO technical_details (content_id, container, video_codec, width, height, audio)
+                VALUES (1, 'matroska,webm', 'h264', 1920, 800, 'eac3 5.1 (eng),aac stereo (ger)');",
+        )
+        .unwrap();
+        assert_eq!(stream_summary(&conn, 1).unwrap(), "1080p • h264 • eac3 5.1");
+    }
+
+    fn pagination(query: &str) -> Option<Pagination> {
+        let uri = format!("/library/continue?{query}").parse().unwrap();
+        Query::<Pagination>::try_from_uri(&uri)
+            .ok()
+            .map(|Query(pagination)| pagination)
+    }
+
+    #[test]
+    fn pages_without_items_are_rejected() {
+        assert!(pagination("page=0&per_page=0").is_none());
+    }
+
+    #[test]
+    fn large_pages_are_cut_down() {
+        assert_eq!(
+            pagination("page=0&per_page=1000").unwrap().per_page,
+            MAX_PER_PAGE
+        );
+    }
+
+    #[test]
+    fn offsets_stay_representable_for_sqlite() {
+        let last = pagination(&format!("page={MAX_PAGE}&per_page={MAX_PER_PAGE}")).unwrap();
+        assert!(i64::try_from(last.offset()).is_ok());
+
+        assert!(pagination(&format!("page={}&per_page=1", MAX_PAGE + 1)).is_none());
+        assert!(pagination(&format!("page={}&per_page=1", u64::MAX)).is_none());
+    }
+
+    #[test]
+    fn restore_is_tracked_per_row() {
+        let restore = Restore {
+            pages: "/library/Franchise/0:3,/library/continue:1".to_owned(),
+        };
+
+        assert_eq!(restore.of("/library/Franchise/0"), 3);
+        assert_eq!(restore.of("/library/continue"), 1);
+        assert_eq!(restore.of("/library/Collection/0"), 0);
+    }
+
+    #[test]
+    fn every_preview_is_listed_once() {
+        // Adding a variant breaks this match, the assert then catches a missing entry in ALL
+        let position = |preview: Preview| -> usize {
+            match preview {
+                Preview::Franchise => 0,
+                Preview::Movie => 1,
+                Preview::Series => 2,
+                Preview::Season => 3,
+                Preview::Episode => 4,
+                Preview::Collection => 5,
+                Preview::Extra => 6,
+            }
+        };
+        assert!(Preview::ALL
+            .into_iter()
+            .map(position)
+            .eq(0..Preview::ALL.len()));
+    }
+
+    #[test]
+    fn previews_round_trip() {
+        for preview in Preview::ALL {
+            assert_eq!(preview.to_string().parse(), Ok(preview));
+        }
+    }
+
+    #[test]
+    fn unknown_previews_are_rejected() {
+        assert!("movie".parse::<Preview>().is_err());
+        assert!("Trailer".parse::<Preview>().is_err());
+        assert!("".parse::<Preview>().is_err());
+    }
+
+    #[test]
+    fn series_without_seasons_show_their_episodes_directly() {
+        let dir = TempDir::new("seasonless");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        index_fixture(
+            &mut db.get().unwrap(),
+            &[
+                "Firefly/Serenity - e1.mp4",
+                "Firefly/The Train Job - e2.mp4",
+            ],
+        );
+        let series: u64 = db
+            .get()
+            .unwrap()
+            .query_row_get(
+                "SELECT id FROM collection WHERE type = ?1",
+                [CollectionType::Series],
+            )
+            .unwrap();
+
+        let categories = preview_categories(&db, series, Preview::Series).unwrap();
+        assert_eq!(categories.len(), 1);
+        let (heading, episodes) = &categories[0];
+        assert_eq!(*heading, "<h2> Episodes </h2>");
+        assert!(episodes.route.contains("/library/Episode/"));
+    }
+}
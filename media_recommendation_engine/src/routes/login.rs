@@ -9,12 +9,14 @@ use axum::{
     Form, Router,
 };
 use serde::{de, Deserialize, Deserializer};
+use tower_sessions::Session;
 
 use crate::{
     state::{AppResult, AppState},
     utils::{
+        csrf_token,
         templates::{Index, LoginPage, SwapIn},
-        AuthSession, Credentials, HandleErr,
+        with_base, AuthSession, Credentials, HandleErr,
     },
 };
 
@@ -50,7 +52,10 @@ struct Params {
     next: Option<String>,
 }
 
-async fn login_page(Query(params): Query<Params>) -> AppResult<impl IntoResponse> {
+async fn login_page(
+    Query(params): Query<Params>,
+    session: Session,
+) -> AppResult<impl IntoResponse> {
     let next = params.next;
 
     let post_url = &match next {
@@ -68,6 +73,7 @@ async fn login_page(Query(params): Query<Params>) -> AppResult<impl IntoResponse
     Ok(Index {
         body,
         all: String::new(),
+        csrf_token: csrf_token(&session).await?,
     })
 }
 
@@ -96,14 +102,15 @@ async fn login_form(
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
 
-    let redirect = next.next.unwrap_or("/".to_owned());
+    // The next route comes from the browser's url, so it already contains the base path
+    let redirect = next.next.unwrap_or_else(|| with_base("/"));
 
     (StatusCode::OK, [("HX-Redirect", redirect)]).into_response()
 }
 
 async fn logout(mut auth: AuthSession) -> impl IntoResponse {
     match auth.logout().await {
-        Ok(_) => ([("HX-Redirect", "/auth/login")], "").into_response(),
+        Ok(_) => ([("HX-Redirect", with_base("/auth/login"))], "").into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
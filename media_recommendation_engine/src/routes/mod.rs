@@ -1,3 +1,4 @@
+mod api;
 mod error;
 mod explore;
 mod homepage;
@@ -5,49 +6,225 @@ mod library;
 mod login;
 mod settings;
 mod streaming;
+mod version;
 
-pub use error::error;
+pub use api::api;
+pub use error::{error, error_page, error_url, json_errors, not_found, ErrorDetail};
 pub use explore::explore;
 pub use homepage::homepage;
 pub use library::library;
 pub use login::login;
 pub use settings::settings;
-pub use streaming::streaming;
+pub use streaming::{media, streaming};
+pub use version::version;
 
 pub use settings::Section;
 
-use crate::state::AppState;
+use std::path::Path;
+
+use crate::{
+    state::AppState,
+    utils::{HandleErr, ServerSettings},
+};
 use axum::{
-    http::{HeaderName, HeaderValue},
+    extract::{Request, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        HeaderValue, Response,
+    },
+    middleware::{self, Next},
+    response::IntoResponse,
+    routing::get,
     Router,
 };
-use tower::ServiceBuilder;
-use tower_http::{services::ServeDir, set_header::SetResponseHeaderLayer};
-
-pub fn dynamic_content() -> Router<AppState> {
-    let styles = ServiceBuilder::new()
-        .layer(SetResponseHeaderLayer::overriding(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("text/css; charset=UTF-8"),
-        ))
-        .service(ServeDir::new("frontend/styles"));
+use tower_http::services::ServeDir;
 
-    let scripts = ServiceBuilder::new()
-        .layer(SetResponseHeaderLayer::overriding(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("application/javascript; charset=UTF-8"),
-        ))
-        .service(ServeDir::new("frontend/scripts"));
+pub fn dynamic_content(settings: ServerSettings) -> Router<AppState> {
+    static_files(Path::new("frontend"), settings).route("/custom.css", get(custom_css))
+}
 
-    let icons = ServiceBuilder::new()
-        .layer(SetResponseHeaderLayer::overriding(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("image/svg+xml; charset=UTF-8"),
+/// The styles, scripts and icons from the frontend directory
+fn static_files<S>(frontend: &Path, settings: ServerSettings) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .nest_service("/styles", ServeDir::new(frontend.join("styles")))
+        .nest_service("/scripts", ServeDir::new(frontend.join("scripts")))
+        .nest_service("/icons", ServeDir::new(frontend.join("icons")))
+        .layer(middleware::from_fn_with_state(
+            settings,
+            static_content_type,
         ))
-        .service(ServeDir::new("frontend/icons"));
+}
 
-    Router::new()
-        .nest_service("/styles", styles)
-        .nest_service("/scripts", scripts)
-        .nest_service("/icons", icons)
+/// Extensions that ServeDir doesn't know a content type for, it only sends application/octet-stream for these
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("woff2", "font/woff2"),
+    ("woff", "font/woff"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    ("avif", "image/avif"),
+    ("webp", "image/webp"),
+    ("mjs", "text/javascript"),
+];
+
+/// ServeDir guesses the content type from the file extension, configured types replace that guess
+/// and the table above fills in what it couldn't guess
+async fn static_content_type(
+    State(settings): State<ServerSettings>,
+    request: Request,
+    next: Next,
+) -> Response<axum::body::Body> {
+    let extension = Path::new(request.uri().path())
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let mut response = next.run(request).await;
+
+    if let Some(extension) = extension.filter(|_| response.status().is_success()) {
+        let guessed = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|content_type| *content_type != "application/octet-stream");
+
+        let content_type = settings.content_type(&extension).or_else(|| {
+            CONTENT_TYPES
+                .iter()
+                .find(|(known, _)| *known == extension)
+                .filter(|_| !guessed)
+                .map(|(_, content_type)| (*content_type).to_owned())
+        });
+
+        if let Some(value) = content_type.and_then(|ct| HeaderValue::from_str(&ct).ok()) {
+            response.headers_mut().insert(CONTENT_TYPE, value);
+        }
+    }
+
+    utf8_text(response)
+}
+
+/// Every page links this last, it is empty unless a custom stylesheet is configured
+/// The file is read on every request, so edits show up without a restart
+async fn custom_css(State(settings): State<ServerSettings>) -> impl IntoResponse {
+    let css = match settings.custom_css() {
+        Some(path) => tokio::fs::read_to_string(&path)
+            .await
+            .log_warn_with_msg(&format!("Failed to read the custom stylesheet {path:?}"))
+            .unwrap_or_default(),
+        None => String::new(),
+    };
+
+    (
+        [
+            (CONTENT_TYPE, "text/css; charset=UTF-8"),
+            (CACHE_CONTROL, "no-cache"),
+        ],
+        css,
+    )
+}
+
+/// Marks text based responses as UTF-8, binary content is left untouched
+fn utf8_text<B>(mut response: Response<B>) -> Response<B> {
+    let Some(content_type) = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+    else {
+        return response;
+    };
+
+    let is_text = content_type.starts_with("text/")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("image/svg+xml");
+
+    if is_text && !content_type.contains("charset") {
+        if let Ok(value) = HeaderValue::from_str(&format!("{content_type}; charset=UTF-8")) {
+            response.headers_mut().insert(CONTENT_TYPE, value);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+    use axum::{
+        body::{to_bytes, Body},
+        http::Request,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utils::{templates::Index, TempDir};
+
+    async fn served_css(settings: ServerSettings) -> String {
+        let response = custom_css(State(settings)).await.into_response();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_configured_stylesheet_is_served() {
+        let dir = TempDir::new("custom-css");
+        let path = dir.path().join("custom.css");
+        std::fs::write(&path, "body { color: red; }").unwrap();
+
+        let settings = ServerSettings::in_memory();
+        assert_eq!(served_css(settings.clone()).await, "");
+
+        settings.set_custom_css(Some(path.to_str().unwrap().to_owned()));
+        assert_eq!(served_css(settings).await, "body { color: red; }");
+    }
+
+    #[test]
+    fn pages_link_the_custom_stylesheet_after_the_defaults() {
+        let page = Index {
+            body: String::new(),
+            all: String::new(),
+            csrf_token: String::new(),
+        }
+        .render()
+        .unwrap();
+
+        let default = page.find("/styles/default.css").unwrap();
+        let custom = page.find("/custom.css").unwrap();
+        assert!(default < custom);
+    }
+
+    /// The content type an icon with this name is served with
+    async fn icon_content_type(name: &str) -> String {
+        let dir = TempDir::new(&format!("icon-{name}"));
+        std::fs::create_dir(dir.path().join("icons")).unwrap();
+        std::fs::write(dir.path().join("icons").join(name), "icon").unwrap();
+
+        let response = static_files::<()>(dir.path(), ServerSettings::in_memory())
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/icons/{name}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[tokio::test]
+    async fn icons_are_served_with_the_type_of_their_extension() {
+        assert_eq!(icon_content_type("logo.png").await, "image/png");
+        assert_eq!(
+            icon_content_type("logo.svg").await,
+            "image/svg+xml; charset=UTF-8"
+        );
+    }
 }
@@ -1,24 +1,35 @@
+use std::{convert::Infallible, fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use askama::Template;
 use askama_axum::IntoResponse;
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
+    response::{sse::KeepAlive, Sse},
     routing::{delete, get, patch, post},
     Form, Router,
 };
 
+use futures_util::{stream, StreamExt};
 use rusqlite::params;
 use serde::Deserialize;
+use tracing::Level;
 
 use crate::{
-    database::{Database, QueryRowGetConnExt, QueryRowIntoStmtExt},
+    database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt, QueryRowIntoStmtExt},
+    indexing::{content_title, scan_summary, CollectionType, Confidence, ContentType, TableId},
     state::{AppError, AppResult, AppState, IndexingTrigger, Shutdown},
     utils::{
-        frontend_redirect,
+        base_path, create_user, current_log_file, format_size, frontend_redirect, html_event,
+        log_line_matches, read_log_lines, set_shows_recommendations, shows_recommendations,
         templates::{
-            AccountSettings, AdminSettings, AsDisplay, Creation, CreationInput, LocationEntry,
-            ProfileSettings, Setting, Settings, SwapIn, UserEntry,
+            AccountSettings, AdminSettings, AsDisplay, CollectionEntry, Creation, CreationInput,
+            LocationEntry, LocationPreview, LogLines, LogView, ProfileSettings, ReportEntry,
+            ReviewEntry, Setting, Settings, SwapIn, UserEntry,
         },
-        AuthExt, AuthSession, HXTarget, HandleErr, ServerSettings,
+        utf8_sse, validate_credentials, AuthExt, AuthSession, CredentialKind, HXTarget, HandleErr,
+        ServerSettings,
     },
 };
 
@@ -32,11 +43,20 @@ pub fn settings() -> Router<AppState> {
         .route("/restart", post(restart))
         .route("/username", patch(username))
         .route("/password", patch(password))
+        .route("/recommendations", patch(recommendations))
         .route("/user", post(add_user))
         .route("/user/:id", delete(remove_user))
         .route("/location", post(add_location))
+        .route("/location/preview", post(preview_location))
         .route("/location/:id", delete(remove_location))
         .route("/location/recurse/:id", patch(recurse_location))
+        .route("/collection", post(add_collection))
+        .route("/collection/:id", delete(remove_collection))
+        .route("/collection/:id/items", post(add_to_collection))
+        .route("/report/:id", delete(remove_report))
+        .route("/review/:id", patch(confirm_classification))
+        .route("/logs", get(logs))
+        .route("/logs/follow", get(follow_logs))
 }
 
 #[derive(Deserialize)]
@@ -52,6 +72,7 @@ impl Default for Location {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Section {
     Profile,
     Admin,
@@ -59,6 +80,9 @@ pub enum Section {
 }
 
 impl Section {
+    /// Every variant, parsing only accepts what is listed here
+    const ALL: [Self; 3] = [Self::Profile, Self::Admin, Self::Account];
+
     fn serialize(&self) -> &'static str {
         match self {
             Self::Profile => "/settings/profile",
@@ -68,19 +92,31 @@ impl Section {
     }
 }
 
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.serialize())
+    }
+}
+
+impl FromStr for Section {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|section| section.serialize() == s)
+            .ok_or("invalid location")
+    }
+}
+
 impl<'de> Deserialize<'de> for Section {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-
-        Ok(match s.as_str() {
-            "/settings/profile" => Self::Profile,
-            "/settings/admin" => Self::Admin,
-            "/settings/account" => Self::Account,
-            _ => return Err(serde::de::Error::custom("invalid location")),
-        })
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -92,7 +128,8 @@ async fn settings_page(
 
     fn into_section(section: Section) -> String {
         format!(
-            r#"hx-get="{route}" hx-target=#section hx-push-url="/?all=/settings?content={route}""#,
+            r#"hx-get="{base}{route}" hx-target=#section hx-push-url="{base}/?all=/settings?content={route}""#,
+            base = base_path(),
             route = section.serialize(),
         )
     }
@@ -113,10 +150,15 @@ async fn settings_page(
     })
 }
 
-async fn profile_section(auth: AuthSession) -> AppResult<impl IntoResponse> {
+async fn profile_section(
+    auth: AuthSession,
+    State(db): State<Database>,
+) -> AppResult<impl IntoResponse> {
+    // This route has logged in as a wrapper
+    let user = auth.user.unwrap();
     Ok(ProfileSettings {
-        // This route has logged in as a wrapper
-        name: auth.user.unwrap().username,
+        show_recommendations: shows_recommendations(&db.get()?, user.id)?,
+        name: user.username,
     })
 }
 
@@ -125,12 +167,172 @@ async fn admin_section(
     State(db): State<Database>,
 ) -> AppResult<impl IntoResponse> {
     let admin_settings = if auth.has_perm("owner").await? {
-        vec![location_addition(&db)?, user_creation(&db)?]
+        vec![
+            location_addition(&db)?,
+            user_creation(&db)?,
+            collection_creation(&db)?,
+            playback_reports(&db)?,
+            classification_review(&db)?,
+        ]
     } else {
         status!(StatusCode::UNAUTHORIZED);
     };
 
-    Ok(AdminSettings { admin_settings })
+    Ok(AdminSettings {
+        library_size: library_size(&db)?,
+        admin_settings,
+    })
+}
+
+fn library_size(db: &Database) -> AppResult<String> {
+    let (files, bytes) = db.get()?.query_row_into::<(u64, u64)>(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM data_file",
+        [],
+    )?;
+
+    // Thousands separators, so large libraries stay readable
+    let digits = files.to_string();
+    let mut files = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            files.push(',');
+        }
+        files.push(digit);
+    }
+
+    Ok(format!(
+        "Library: {} across {files} files",
+        format_size(bytes)
+    ))
+}
+
+/// How much of the end of the log file is looked at, so huge files don't have to be read completely
+const LOG_TAIL_BYTES: u64 = 4 * 1024 * 1024;
+const LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const LOG_LEVELS: &[&str] = &["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+#[derive(Deserialize)]
+struct LogQuery {
+    lines: Option<usize>,
+    level: Option<String>,
+    follow: Option<bool>,
+    /// Where following starts, so nothing is missed between loading the page and connecting
+    offset: Option<u64>,
+}
+
+impl LogQuery {
+    fn level(&self) -> Level {
+        self.level
+            .as_deref()
+            .and_then(|level| Level::from_str(level).ok())
+            .unwrap_or(Level::TRACE)
+    }
+}
+
+/// Only lines at the level or above, newest last
+fn filter_log_lines(lines: &str, level: Level, count: usize) -> String {
+    let lines = lines
+        .lines()
+        .filter(|line| log_line_matches(line, level))
+        .collect::<Vec<_>>();
+    let mut shown = lines[lines.len().saturating_sub(count)..].join("\n");
+    if !shown.is_empty() {
+        shown.push('\n');
+    }
+    shown
+}
+
+/// The end of the current log file, this never reads anything outside of the log directory
+async fn logs(auth: AuthSession, Query(query): Query<LogQuery>) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let level = query.level();
+    let count = query.lines.unwrap_or(200).clamp(1, 5000);
+
+    let Some(path) = current_log_file() else {
+        return Ok(LogView {
+            file: None,
+            lines: String::new(),
+            level: level.as_str(),
+            levels: LOG_LEVELS,
+            count,
+            follow: false,
+            offset: 0,
+        });
+    };
+
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let (lines, offset) =
+        tokio::task::spawn_blocking(move || read_log_lines(&path, 0, LOG_TAIL_BYTES))
+            .await
+            .map_err(|_| AppError::Status(StatusCode::INTERNAL_SERVER_ERROR))?
+            .context("Failed to read the log file")?;
+
+    Ok(LogView {
+        file,
+        lines: filter_log_lines(&lines, level, count),
+        level: level.as_str(),
+        levels: LOG_LEVELS,
+        count,
+        follow: query.follow.unwrap_or_default(),
+        offset,
+    })
+}
+
+/// Sends new lines as they are written, continuing in the next file when the day changes
+async fn follow_logs(
+    auth: AuthSession,
+    State(shutdown): State<Shutdown>,
+    Query(query): Query<LogQuery>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let level = query.level();
+    let start = (current_log_file(), query.offset.unwrap_or_default());
+
+    let stream = stream::unfold(start, move |(mut path, mut offset)| async move {
+        loop {
+            tokio::time::sleep(LOG_POLL_INTERVAL).await;
+
+            let current = current_log_file();
+            if current != path {
+                path = current;
+                offset = 0;
+            }
+            let Some(file) = path.clone() else {
+                continue;
+            };
+
+            let read =
+                tokio::task::spawn_blocking(move || read_log_lines(&file, offset, LOG_TAIL_BYTES))
+                    .await;
+            let Some((lines, new_offset)) = read.ok().and_then(Result::ok) else {
+                continue;
+            };
+            offset = new_offset;
+
+            let lines = filter_log_lines(&lines, level, usize::MAX);
+            if lines.is_empty() {
+                continue;
+            }
+
+            let rendered = LogLines { lines }
+                .render()
+                .log_err_with_msg("Failed to render log lines")
+                .unwrap_or_default();
+            return Some((Ok::<_, Infallible>(html_event(&rendered)), (path, offset)));
+        }
+    });
+
+    let resolve = |shutdown: Shutdown| async move { shutdown.cancelled().await };
+    let stream = stream.take_until(resolve(shutdown));
+    Ok(utf8_sse(Sse::new(stream).keep_alive(KeepAlive::default())))
 }
 
 async fn account_section() -> AppResult<impl IntoResponse> {
@@ -164,6 +366,18 @@ async fn restart(
     }
 }
 
+fn invalid_input(swap_id: &str, msg: &str) -> axum::response::Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        SwapIn {
+            swap_id,
+            swap_method: None,
+            content: msg,
+        },
+    )
+        .into_response()
+}
+
 #[derive(Deserialize)]
 struct ChangeUsername {
     name: String,
@@ -179,9 +393,12 @@ async fn username(
         status!(StatusCode::UNAUTHORIZED);
     };
 
-    let conn = db.get()?;
+    let new_name = match validate_credentials(CredentialKind::Username, &new_name.name) {
+        Ok(new_name) => new_name,
+        Err(msg) => return Ok(invalid_input("error", msg)),
+    };
 
-    let new_name = &new_name.name;
+    let conn = db.get()?;
 
     let user_exists = conn.query_row_get::<bool>(
         "SELECT exists(SELECT 1 FROM users WHERE username = ?1)",
@@ -189,27 +406,19 @@ async fn username(
     )?;
 
     if user_exists {
-        return Ok((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            SwapIn {
-                swap_id: "user_error",
-                swap_method: None,
-                content: "That Username is not available!",
-            },
-        )
-            .into_response());
+        return Ok(invalid_input("error", "That Username is not available!"));
     }
 
-    if settings.admin().username == user.username {
+    if settings.is_config_admin(&user.username) {
         settings.update_admin_username(new_name);
     } else {
         conn.execute(
             "UPDATE users SET username = ?1 WHERE username = ?2",
-            [new_name, &user.username],
+            params![new_name, user.username],
         )?;
     }
 
-    Ok(new_name.clone().into_response())
+    Ok(new_name.to_owned().into_response())
 }
 
 #[derive(Deserialize)]
@@ -227,11 +436,15 @@ async fn password(
         status!(StatusCode::UNAUTHORIZED);
     };
 
-    let conn = db.get()?;
+    let new_password = match validate_credentials(CredentialKind::Password, &new_password.password)
+    {
+        Ok(new_password) => new_password.to_owned(),
+        Err(msg) => return Ok(invalid_input("error", msg)),
+    };
 
-    let new_password = new_password.password.clone();
+    let conn = db.get()?;
 
-    if settings.admin().username == user.username {
+    if settings.is_config_admin(&user.username) {
         settings.update_admin_password(&new_password)
     } else {
         let new_pw =
@@ -244,6 +457,25 @@ async fn password(
         )?;
     }
 
+    Ok(StatusCode::OK.into_response())
+}
+
+#[derive(Deserialize)]
+struct ShowRecommendations {
+    /// Unchecked checkboxes aren't sent at all
+    show: Option<String>,
+}
+
+async fn recommendations(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Form(form): Form<ShowRecommendations>,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    set_shows_recommendations(&db.get()?, user.id, form.show.is_some())?;
     Ok(StatusCode::OK)
 }
 
@@ -256,29 +488,34 @@ struct NewUser {
 async fn add_user(
     auth: AuthSession,
     State(db): State<Database>,
+    State(settings): State<ServerSettings>,
     Form(new_user): Form<NewUser>,
 ) -> AppResult<impl IntoResponse> {
     if !auth.has_perm("owner").await.unwrap_or_default() {
         status!(StatusCode::UNAUTHORIZED);
     }
 
+    let username = match validate_credentials(CredentialKind::Username, &new_user.username) {
+        Ok(username) => username.to_owned(),
+        Err(msg) => return Ok(invalid_input("user_error", msg)),
+    };
+
+    if let Err(msg) = validate_credentials(CredentialKind::Password, &new_user.password) {
+        return Ok(invalid_input("user_error", msg));
+    }
+
     let conn = db.get()?;
 
     let user_exists = conn.query_row_get::<bool>(
         "SELECT exists(SELECT 1 FROM users WHERE username = ?1)",
-        [&new_user.username],
+        [&username],
     )?;
 
     if user_exists {
-        return Ok((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            SwapIn {
-                swap_id: "user_error",
-                swap_method: None,
-                content: "That Username is not available!",
-            },
-        )
-            .into_response());
+        return Ok(invalid_input(
+            "user_error",
+            "That Username is not available!",
+        ));
     }
 
     let password = tokio::task::spawn_blocking(|| password_auth::generate_hash(new_user.password))
@@ -286,17 +523,14 @@ async fn add_user(
         .log_err_with_msg("Failed to generate password hash")
         .unwrap_or_default();
 
-    let id = conn.query_row_get::<u64>(
-        "INSERT INTO users (username, password) VALUES (?1, ?2) RETURNING id",
-        params![new_user.username, password],
-    )?;
+    let id = create_user(&conn, &username, &password, &settings.new_users())?;
 
     Ok(SwapIn {
         swap_id: "user_list",
         swap_method: Some("beforeend"),
         content: UserEntry {
             user_id: id,
-            name: new_user.username,
+            name: username,
             can_delete: true,
         },
     }
@@ -324,6 +558,7 @@ async fn remove_user(
         bail!("This user can't be deleted");
     }
 
+    conn.execute("DELETE FROM user_preferences WHERE user_id = ?1", [user_id])?;
     conn.execute("DELETE FROM users WHERE id = ?1", [user_id])?;
     conn.execute("DELETE FROM user_permissions WHERE userid = ?1", [user_id])?;
     conn.execute("DELETE FROM user_groups WHERE userid = ?1", [user_id])?;
@@ -374,6 +609,142 @@ fn user_creation(db: &Database) -> AppResult<Setting> {
     })
 }
 
+fn playback_reports(db: &Database) -> AppResult<Setting> {
+    let conn = db.get()?;
+
+    let reports = conn
+        .prepare(
+            "SELECT playback_reports.id, content_id, users.username, video_time, description
+                FROM playback_reports LEFT JOIN users ON playback_reports.user_id = users.id
+                ORDER BY created DESC",
+        )?
+        .query_map_into::<(u64, u64, Option<String>, f64, String)>([])?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(
+            |(report_id, content_id, username, video_time, description)| {
+                ReportEntry {
+                    report_id,
+                    title: content_title(&conn, content_id)
+                        .unwrap_or_else(|_| format!("Content {content_id}")),
+                    username: username.unwrap_or_else(|| "a deleted user".to_owned()),
+                    video_time: format_video_time(video_time),
+                    description,
+                }
+                .to_box()
+            },
+        )
+        .collect();
+
+    Ok(Setting::List {
+        title: "Playback Reports",
+        list_id: "report_list",
+        entries: reports,
+    })
+}
+
+/// Content indexing wasn't sure about, most doubtful first
+/// Files it couldn't classify at all come before everything else
+fn classification_review(db: &Database) -> AppResult<Setting> {
+    let conn = db.get()?;
+
+    let failed = conn
+        .prepare(
+            "SELECT path, classification_error FROM data_file
+                WHERE classification_error IS NOT NULL
+                ORDER BY path",
+        )?
+        .query_map_into::<(String, String)>([])?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(path, error)| {
+            ReviewEntry {
+                content_id: None,
+                title: std::path::Path::new(&path)
+                    .file_name()
+                    .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned()),
+                path,
+                confidence: "Failed",
+                error: Some(error),
+            }
+            .to_box()
+        });
+
+    let uncertain = conn
+        .prepare(
+            "SELECT content.id, data_file.path, content.confidence
+                FROM content LEFT JOIN data_file ON content.data_id = data_file.id
+                WHERE content.confidence != ?1 AND content.type != ?2
+                ORDER BY content.confidence DESC, data_file.path",
+        )?
+        .query_map_into::<(u64, Option<String>, Confidence)>(params![
+            Confidence::Certain,
+            ContentType::Other
+        ])?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(content_id, path, confidence)| {
+            ReviewEntry {
+                content_id: Some(content_id),
+                title: content_title(&conn, content_id)
+                    .unwrap_or_else(|_| format!("Content {content_id}")),
+                path: path.unwrap_or_else(|| "Missing file".to_owned()),
+                confidence: confidence.as_str(),
+                error: None,
+            }
+            .to_box()
+        });
+
+    Ok(Setting::List {
+        title: "Uncertain Classifications",
+        list_id: "review_list",
+        entries: failed.chain(uncertain).collect(),
+    })
+}
+
+/// Marks where the content was put as correct, so it isn't listed anymore
+async fn confirm_classification(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Path(id): Path<u64>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    db.get()?.execute(
+        "UPDATE content SET confidence = ?1 WHERE id = ?2",
+        params![Confidence::Certain, id],
+    )?;
+
+    Ok(())
+}
+
+fn format_video_time(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours == 0 {
+        format!("{minutes}:{seconds:0>2}")
+    } else {
+        format!("{hours}:{minutes:0>2}:{seconds:0>2}")
+    }
+}
+
+async fn remove_report(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Path(id): Path<u64>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    db.get()?
+        .execute("DELETE FROM playback_reports WHERE id = ?1", [id])?;
+
+    Ok(())
+}
+
 fn location_addition(db: &Database) -> AppResult<Setting> {
     let conn = db.get()?;
 
@@ -410,6 +781,10 @@ fn location_addition(db: &Database) -> AppResult<Setting> {
                     name: "recurse",
                     value: "true",
                 },
+                CreationInput::Button {
+                    label: "Preview",
+                    post_addr: "/settings/location/preview",
+                },
             ],
         },
     })
@@ -425,7 +800,8 @@ async fn add_location(
     auth: AuthSession,
     State(db): State<Database>,
     State(trigger): State<IndexingTrigger>,
-    Form(location): Form<AddLocation>,
+    State(settings): State<ServerSettings>,
+    Form(mut location): Form<AddLocation>,
 ) -> AppResult<impl IntoResponse> {
     if !auth.has_perm("owner").await.unwrap_or_default() {
         status!(StatusCode::UNAUTHORIZED);
@@ -433,17 +809,10 @@ async fn add_location(
 
     let conn = db.get()?;
 
-    if !std::path::Path::new(&location.path).exists() {
-        return Ok((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            SwapIn {
-                swap_id: "location_error",
-                swap_method: None,
-                content: format!("Could not access the location: \"{}\"", location.path),
-            },
-        )
-            .into_response());
-    }
+    location.path = match validate_location(&location.path, &settings.allowed_location_roots()) {
+        Ok(path) => path,
+        Err(msg) => return Ok(invalid_input("location_error", &msg)),
+    };
 
     let already_exists = conn.query_row_get::<bool>(
         "SELECT exists(SELECT 1 FROM storage_locations WHERE path = ?1)",
@@ -451,15 +820,10 @@ async fn add_location(
     )?;
 
     if already_exists {
-        return Ok((
-            StatusCode::UNPROCESSABLE_ENTITY,
-            SwapIn {
-                swap_id: "location_error",
-                swap_method: None,
-                content: format!("The location \"{}\" already exists", location.path),
-            },
-        )
-            .into_response());
+        return Ok(invalid_input(
+            "location_error",
+            &format!("The location \"{}\" already exists", location.path),
+        ));
     }
 
     let recurse = location.recurse.unwrap_or_default();
@@ -483,6 +847,81 @@ async fn add_location(
     .into_response())
 }
 
+/// Shows what adding the location would index, nothing is stored
+async fn preview_location(
+    auth: AuthSession,
+    State(settings): State<ServerSettings>,
+    Form(location): Form<AddLocation>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let path = match validate_location(&location.path, &settings.allowed_location_roots()) {
+        Ok(path) => path,
+        Err(msg) => return Ok(invalid_input("location_error", &msg)),
+    };
+
+    let recurse = location.recurse.unwrap_or_default();
+    let ignored_patterns = settings.ignored_patterns();
+    let scan_path = path.clone();
+    let summary = tokio::task::spawn_blocking(move || {
+        scan_summary(std::path::Path::new(&scan_path), recurse, &ignored_patterns)
+    })
+    .await
+    .map_err(|_| AppError::Status(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(e) => {
+            return Ok(invalid_input(
+                "location_error",
+                &format!("Failed to scan the location \"{path}\": {e}"),
+            ))
+        }
+    };
+
+    Ok(SwapIn {
+        swap_id: "location_list_preview",
+        swap_method: None,
+        content: LocationPreview { path, summary },
+    }
+    .into_response())
+}
+
+/// Resolves the path to the canonical directory that gets stored, the error is meant for the user
+fn validate_location(path: &str, allowed_roots: &[String]) -> Result<String, String> {
+    let Ok(canonical) = std::fs::canonicalize(path) else {
+        return Err(format!("Could not access the location: \"{path}\""));
+    };
+
+    if !canonical.is_dir() {
+        return Err(format!("The location \"{path}\" is not a directory"));
+    }
+
+    if canonical.parent().is_none() {
+        return Err("The root of the filesystem can't be a location".to_owned());
+    }
+
+    // Roots that don't exist can't contain anything, so they are simply skipped
+    let allowed = allowed_roots.is_empty()
+        || allowed_roots
+            .iter()
+            .filter_map(|root| std::fs::canonicalize(root).ok())
+            .any(|root| canonical.starts_with(root));
+
+    if !allowed {
+        return Err(format!(
+            "The location \"{path}\" is outside of the allowed directories"
+        ));
+    }
+
+    canonical
+        .into_os_string()
+        .into_string()
+        .map_err(|_| format!("The location \"{path}\" is not valid unicode"))
+}
+
 async fn remove_location(
     auth: AuthSession,
     State(db): State<Database>,
@@ -555,3 +994,324 @@ async fn recurse_location(
 
     Ok(().into_response())
 }
+
+fn collection_creation(db: &Database) -> AppResult<Setting> {
+    let conn = db.get()?;
+
+    let collections = conn
+        .prepare(
+            "SELECT collection.id, user_collection.title FROM collection, user_collection
+                WHERE collection.reference = user_collection.id
+                AND collection.type = ?1
+                ORDER BY user_collection.title ASC",
+        )?
+        .query_map_into::<(u64, String)>([CollectionType::UserCollection])?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(collection_id, title)| {
+            CollectionEntry {
+                collection_id,
+                title,
+            }
+            .to_box()
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Setting::CreationMenu {
+        creation: Creation {
+            title: "Collections",
+            list_id: "collection_list",
+            error_id: "collection_error",
+            post_addr: "/settings/collection",
+            entries: collections,
+            inputs: vec![CreationInput::Text {
+                typ: "text",
+                name: "title",
+                placeholder: "Title",
+            }],
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct NewCollection {
+    title: String,
+}
+
+async fn add_collection(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Form(collection): Form<NewCollection>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let title = collection.title.trim();
+    if title.is_empty() {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            SwapIn {
+                swap_id: "collection_error",
+                swap_method: None,
+                content: "A collection needs a title".to_owned(),
+            },
+        )
+            .into_response());
+    }
+
+    let mut conn = db.get()?;
+    let tx = conn.transaction()?;
+
+    let user_collection_id = tx.query_row_get::<u64>(
+        "INSERT INTO user_collection (title) VALUES (?1) RETURNING id",
+        [title],
+    )?;
+    let collection_id = tx.query_row_get::<u64>(
+        "INSERT INTO collection (type, reference) VALUES (?1, ?2) RETURNING id",
+        params![CollectionType::UserCollection, user_collection_id],
+    )?;
+
+    tx.commit()?;
+
+    Ok(SwapIn {
+        swap_id: "collection_list",
+        swap_method: Some("beforeend"),
+        content: CollectionEntry {
+            collection_id,
+            title: title.to_owned(),
+        },
+    }
+    .into_response())
+}
+
+async fn remove_collection(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Path(id): Path<u64>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut conn = db.get()?;
+    let tx = conn.transaction()?;
+
+    let deletion_amount = tx.execute(
+        "DELETE FROM user_collection WHERE id = (SELECT reference FROM collection WHERE id = ?1 AND type = ?2)",
+        params![id, CollectionType::UserCollection],
+    )?;
+
+    if deletion_amount == 0 {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            SwapIn {
+                swap_id: "collection_error",
+                swap_method: None,
+                content: "Failed to delete requested collection".to_owned(),
+            },
+        )
+            .into_response());
+    }
+
+    tx.execute("DELETE FROM collection WHERE id = ?1", [id])?;
+    tx.execute(
+        "DELETE FROM collection_contains WHERE collection_id = ?1",
+        [id],
+    )?;
+    tx.execute(
+        "DELETE FROM collection_contains WHERE type = ?1 AND reference = ?2",
+        params![TableId::Collection, id],
+    )?;
+
+    tx.commit()?;
+
+    Ok(().into_response())
+}
+
+#[derive(Deserialize)]
+enum CollectionItem {
+    Content,
+    Collection,
+}
+
+#[derive(Deserialize)]
+struct AddToCollection {
+    kind: CollectionItem,
+    reference: u64,
+}
+
+async fn add_to_collection(
+    auth: AuthSession,
+    State(db): State<Database>,
+    Path(id): Path<u64>,
+    Form(item): Form<AddToCollection>,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::UNAUTHORIZED);
+    }
+
+    let conn = db.get()?;
+
+    let is_user_collection = conn.query_row_get::<bool>(
+        "SELECT exists(SELECT 1 FROM collection WHERE id = ?1 AND type = ?2)",
+        params![id, CollectionType::UserCollection],
+    )?;
+
+    let (table, exists) = match item.kind {
+        CollectionItem::Content => (
+            TableId::Content,
+            conn.query_row_get::<bool>(
+                "SELECT exists(SELECT 1 FROM content WHERE id = ?1)",
+                [item.reference],
+            )?,
+        ),
+        CollectionItem::Collection => (
+            TableId::Collection,
+            item.reference != id
+                && conn.query_row_get::<bool>(
+                    "SELECT exists(SELECT 1 FROM collection WHERE id = ?1)",
+                    [item.reference],
+                )?,
+        ),
+    };
+
+    if !is_user_collection || !exists {
+        return Ok((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            SwapIn {
+                swap_id: "collection_error",
+                swap_method: None,
+                content: format!("Could not add {} to this collection", item.reference),
+            },
+        )
+            .into_response());
+    }
+
+    conn.execute(
+        "INSERT INTO collection_contains (collection_id, type, reference) VALUES (?1, ?2, ?3)",
+        params![id, table, item.reference],
+    )?;
+
+    Ok(StatusCode::OK.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::in_file,
+        indexing::{index_fixture, FIXTURE_ROOT},
+        utils::TempDir,
+    };
+
+    #[test]
+    fn every_section_is_listed_once() {
+        // Adding a variant breaks this match, the assert then catches a missing entry in ALL
+        let position = |section: Section| -> usize {
+            match section {
+                Section::Profile => 0,
+                Section::Admin => 1,
+                Section::Account => 2,
+            }
+        };
+        assert!(Section::ALL
+            .into_iter()
+            .map(position)
+            .eq(0..Section::ALL.len()));
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        for section in Section::ALL {
+            assert_eq!(section.to_string().parse(), Ok(section));
+        }
+    }
+
+    #[test]
+    fn unknown_sections_are_rejected() {
+        assert!("/settings/unknown".parse::<Section>().is_err());
+        assert!("profile".parse::<Section>().is_err());
+        assert!("".parse::<Section>().is_err());
+    }
+
+    #[test]
+    fn locations_have_to_be_existing_directories() {
+        let dir = TempDir::new("location-kinds");
+        let file = dir.file("Heat.mp4");
+
+        let missing = dir.path().join("missing");
+        assert!(validate_location(missing.to_str().unwrap(), &[]).is_err());
+        assert!(validate_location(file.to_str().unwrap(), &[]).is_err());
+        assert!(validate_location("/", &[]).is_err());
+    }
+
+    #[test]
+    fn locations_are_stored_canonicalized() {
+        let dir = TempDir::new("location-canonical");
+        let movies = dir.dir("Movies");
+        dir.dir("Shows");
+
+        let roundabout = dir.path().join("Shows/../Movies");
+        let stored = validate_location(roundabout.to_str().unwrap(), &[]).unwrap();
+        assert_eq!(
+            std::path::Path::new(&stored),
+            movies.canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn locations_outside_of_the_allowed_roots_are_rejected() {
+        let dir = TempDir::new("location-roots");
+        let allowed = dir.dir("media");
+        let inside = dir.dir("media/Movies");
+        let outside = dir.dir("private");
+        let roots = [allowed.to_str().unwrap().to_owned()];
+
+        assert!(validate_location(inside.to_str().unwrap(), &roots).is_ok());
+        assert!(validate_location(allowed.to_str().unwrap(), &roots).is_ok());
+        assert!(validate_location(outside.to_str().unwrap(), &roots).is_err());
+        // Only whole directory names count, "media2" is not inside of "media"
+        let sibling = dir.dir("media2");
+        assert!(validate_location(sibling.to_str().unwrap(), &roots).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_allowed_roots_are_rejected() {
+        let dir = TempDir::new("location-symlink");
+        let allowed = dir.dir("media");
+        let outside = dir.dir("private");
+        let link = allowed.join("link");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+        let roots = [allowed.to_str().unwrap().to_owned()];
+
+        assert!(validate_location(link.to_str().unwrap(), &roots).is_err());
+    }
+
+    #[test]
+    fn files_that_failed_to_classify_are_reviewed_first() {
+        let dir = TempDir::new("review-failed");
+        let db = in_file(&dir.path().join("database.sqlite"));
+        index_fixture(
+            &mut db.get().unwrap(),
+            &["Heat/Heat.mp4", "Seasoned/Seasoned.mp4"],
+        );
+        db.get()
+            .unwrap()
+            .execute("UPDATE content SET confidence = ?1", [Confidence::Guessed])
+            .unwrap();
+
+        let Setting::List { entries, .. } = classification_review(&db).unwrap() else {
+            panic!("The review is a list");
+        };
+        let entries = entries.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].contains("(Failed)"));
+        assert!(entries[0].contains(&format!("{FIXTURE_ROOT}/Seasoned/Seasoned.mp4")));
+        // Only classified content can be confirmed, the failed file is tried again by the next indexing run
+        assert!(!entries[0].contains("Confirm"));
+        assert!(entries[1].contains("Heat"));
+        assert!(entries[1].contains("Confirm"));
+    }
+}
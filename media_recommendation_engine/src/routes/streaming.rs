@@ -1,87 +1,264 @@
+use std::time::{Duration, SystemTime};
+
 use askama::Template;
 use axum::{
     body::Body,
     extract::{
         ws::{Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
+    },
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, HOST, ORIGIN},
+        HeaderMap, HeaderValue, Request, StatusCode,
     },
-    http::{Request, StatusCode},
-    response::{IntoResponse, Redirect},
-    routing::get,
-    Router,
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Json, Router,
 };
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::debug;
 
 use crate::{
-    database::Database,
+    database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt},
+    indexing::{content_title, playable_in_collection},
+    routes::{error_url, ErrorDetail},
     state::{AppResult, AppState, Shutdown},
     utils::{
+        media_token,
         streaming::{Session, StreamingSessions},
         templates::{Notification, Video},
-        AuthSession, HandleErr,
+        with_base, AuthExt, AuthSession, HandleErr, MediaAccess, ServerSettings,
     },
 };
 
-pub fn streaming() -> Router<AppState> {
-    Router::new()
-        .route("/content/:id", get(content))
+pub fn streaming(settings: ServerSettings) -> Router<AppState> {
+    // These start or connect to a session with a plain GET, so the csrf check doesn't cover them
+    let from_this_site = Router::new()
         .route("/:id", get(new_session))
-        .route("/session/:id", get(session))
+        .route("/queue/:id", get(play_all))
+        .route("/join/:code", get(join))
         .route("/session/ws/:id", get(ws_session))
+        .route_layer(middleware::from_fn_with_state(settings, this_site_only));
+
+    Router::new()
+        .merge(from_this_site)
+        .route("/session/:id", get(session))
+        .route("/session/:id/report", post(report))
+        .route("/session/:id/playback_info", get(playback_info))
+        .route("/session/:id/errors", get(errors))
+        .route("/session/:id/link", get(link))
+        .route("/export/:target/:id", get(export))
+}
+
+/// The routes that serve video data, they accept a token instead of the login cookie for players that can't keep one
+pub fn media() -> Router<AppState> {
+    Router::new()
+        .route("/video/content/:id", get(content))
+        .route("/video/direct/:id", get(direct))
 }
 
 async fn content(
     Path(id): Path<u32>,
     State(sessions): State<StreamingSessions>,
     State(shutdown): State<Shutdown>,
+    access: MediaAccess,
     request: Request<Body>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
     let Some(session) = sessions.get(&id).await else {
         return Err((StatusCode::FORBIDDEN).into_response());
     };
 
+    let allowed =
+        access.allows(session.video_id().await) && session.may_join(access.user_id()).await;
+    if !allowed {
+        return Err((StatusCode::FORBIDDEN).into_response());
+    }
+
     tokio::select! {
         resp = session.stream(request) => Ok(resp),
         _  = shutdown.cancelled() => Err(StatusCode::REQUEST_TIMEOUT.into_response())
     }
 }
 
+#[derive(Deserialize)]
+struct NewSession {
+    /// Hides the session from the session list, others can only join through the invite code
+    #[serde(default)]
+    invite_only: bool,
+}
+
+/// Starts a new session for the content, this is also the link to share for playing something directly
 async fn new_session(
     Path(id): Path<u64>,
+    Query(new): Query<NewSession>,
     State(mut sessions): State<StreamingSessions>,
     State(db): State<Database>,
     State(shutdown): State<Shutdown>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let session_id = sessions
+        .new_session(id, &db, shutdown, &user, new.invite_only)
+        .await?;
+
+    open_session(&sessions, session_id, &headers).await
+}
+
+/// Starts a new session with the first playable content of the collection, the rest plays after it in order
+async fn play_all(
+    Path(id): Path<u64>,
+    Query(new): Query<NewSession>,
+    State(mut sessions): State<StreamingSessions>,
+    State(db): State<Database>,
+    State(shutdown): State<Shutdown>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let mut queue = playable_in_collection(&db.get()?, id)?.into_iter();
+    let Some(first) = queue.next() else {
+        status!(StatusCode::NOT_FOUND);
+    };
+
+    let session_id = sessions
+        .new_session(first, &db, shutdown, &user, new.invite_only)
+        .await?;
+    if let Some(session) = sessions.get(&session_id).await {
+        session.set_queue(queue.collect()).await;
+    }
+
+    open_session(&sessions, session_id, &headers).await
+}
+
+async fn open_session(
+    sessions: &StreamingSessions,
+    session_id: u32,
+    headers: &HeaderMap,
+) -> AppResult<Response> {
+    let session_url = session_url(session_id);
+
+    // htmx swaps the player in place, so only the address bar has to follow
+    if is_htmx(headers) {
+        Ok((
+            [("HX-Push-Url", session_url)],
+            video(sessions, session_id).await?,
+        )
+            .into_response())
+    } else {
+        Ok(Redirect::temporary(&session_url).into_response())
+    }
+}
+
+/// Loaded directly, like from a shared link, the player still needs the page around it
+async fn session(
+    Path(id): Path<u32>,
+    State(sessions): State<StreamingSessions>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    if !is_htmx(&headers) {
+        return Ok(Redirect::temporary(&session_url(id)).into_response());
+    }
+
+    // Sessions that don't exist anymore still get the player, it tells the user once it connects
+    if let (Some(session), Some(user)) = (sessions.get(&id).await, &auth.user) {
+        if !session.may_join(user.id).await {
+            status!(StatusCode::FORBIDDEN);
+        }
+    }
+
+    Ok(video(&sessions, id).await?.into_response())
+}
+
+/// Lets the user into the session the code belongs to, even if it is invite only
+async fn join(
+    Path(code): Path<String>,
+    State(sessions): State<StreamingSessions>,
+    auth: AuthSession,
 ) -> AppResult<impl IntoResponse> {
-    let session_id = sessions.new_session(id, &db, shutdown).await?;
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    // Invite links are opened directly, so the error page says what went wrong
+    if !sessions.try_invite_attempt(user.id).await {
+        return Ok(Redirect::temporary(&error_url(
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorDetail::InviteAttempts,
+        )));
+    }
+
+    let Some(id) = sessions.join_with_code(user.id, &code).await else {
+        return Ok(Redirect::temporary(&error_url(
+            StatusCode::NOT_FOUND,
+            ErrorDetail::InviteUnknown,
+        )));
+    };
+
+    Ok(Redirect::temporary(&session_url(id)))
+}
 
-    Ok(Redirect::temporary(&format!(
-        "/?all=/video/session/{session_id}"
-    )))
+async fn video(sessions: &StreamingSessions, id: u32) -> AppResult<Video> {
+    let (invite_code, recommendations) = match sessions.get(&id).await {
+        Some(session) => (
+            session.invite_code().to_owned(),
+            session.recommendations_enabled(),
+        ),
+        None => (String::new(), true),
+    };
+
+    Ok(Video {
+        id: u64::from(id),
+        invite_code,
+        recommendations,
+    })
+}
+
+fn session_url(id: u32) -> String {
+    with_base(&format!("/?all=/video/session/{id}"))
 }
 
-async fn session(Path(id): Path<u64>) -> impl IntoResponse {
-    Video { id }
+fn is_htmx(headers: &HeaderMap) -> bool {
+    headers.get("HX-Request").is_some()
 }
 
 async fn ws_session(
     ws: WebSocketUpgrade,
     Path(id): Path<u32>,
     State(sessions): State<StreamingSessions>,
+    State(shutdown): State<Shutdown>,
     auth: AuthSession,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| ws_session_callback(socket, id, sessions, auth))
+    ws.on_upgrade(move |socket| ws_session_callback(socket, id, sessions, shutdown, auth))
 }
 
 async fn ws_session_callback(
     mut socket: WebSocket,
     id: u32,
     mut sessions: StreamingSessions,
+    shutdown: Shutdown,
     auth: AuthSession,
 ) {
     let Some(user) = auth.user else {
         return;
     };
 
-    let Some(session) = sessions.get(&id).await else {
+    let session = match sessions.get(&id).await {
+        Some(session) if session.may_join(user.id).await => Some(session),
+        _ => None,
+    };
+    let Some(session) = session else {
         socket
             .send(Message::Text(
                 Notification {
@@ -97,9 +274,624 @@ async fn ws_session_callback(
         return;
     };
 
-    let is_empty = Session::handle_user(session, user, socket).await;
+    let is_empty = Session::handle_user(session.clone(), user, socket).await;
 
+    // A dropped connection usually comes right back, the session should still be there then
     if is_empty {
-        sessions.remove(&id).await;
+        sessions.remove_abandoned(id, &session, &shutdown).await;
+    }
+}
+
+const MAX_REPORT_LENGTH: usize = 1000;
+/// How many reports a user can send within REPORT_WINDOW
+const MAX_REPORTS: u64 = 5;
+const REPORT_WINDOW: u64 = 60 * 10;
+
+#[derive(Deserialize)]
+struct Report {
+    video_time: f64,
+    description: String,
+}
+
+async fn report(
+    Path(id): Path<u32>,
+    State(sessions): State<StreamingSessions>,
+    State(db): State<Database>,
+    auth: AuthSession,
+    Form(report): Form<Report>,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let Some(session) = sessions.get(&id).await else {
+        status!(StatusCode::NOT_FOUND);
+    };
+
+    if !session.may_join(user.id).await {
+        status!(StatusCode::FORBIDDEN);
+    }
+
+    let description = report.description.trim();
+    if description.is_empty() || description.chars().count() > MAX_REPORT_LENGTH {
+        return Ok(report_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The description has to be between 1 and {MAX_REPORT_LENGTH} characters"),
+        ));
+    }
+
+    if !report.video_time.is_finite() || report.video_time < 0. {
+        status!(StatusCode::BAD_REQUEST);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .log_err_with_msg("Failed to get current time")
+        .unwrap_or_default()
+        .as_secs();
+
+    let conn = db.get()?;
+    let content_id = session.video_id().await;
+    if !store_report(&conn, user.id, id, content_id, &report, now)? {
+        return Ok(report_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "You sent a lot of reports recently, please wait a bit".to_owned(),
+        ));
+    }
+
+    Ok(report_response(
+        StatusCode::OK,
+        "Thanks, the problem was reported".to_owned(),
+    ))
+}
+
+/// Returns false without storing anything when the user already sent MAX_REPORTS within REPORT_WINDOW
+fn store_report(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    session_id: u32,
+    content_id: u64,
+    report: &Report,
+    now: u64,
+) -> AppResult<bool> {
+    let recent_reports = conn.query_row_get::<u64>(
+        "SELECT count(*) FROM playback_reports WHERE user_id = ?1 AND created > ?2",
+        params![user_id, now.saturating_sub(REPORT_WINDOW)],
+    )?;
+
+    if recent_reports >= MAX_REPORTS {
+        return Ok(false);
+    }
+
+    conn.execute(
+        "INSERT INTO playback_reports (content_id, session_id, user_id, video_time, description, created)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            content_id,
+            session_id,
+            user_id,
+            report.video_time,
+            report.description.trim(),
+            now
+        ],
+    )?;
+    Ok(true)
+}
+
+#[derive(Serialize)]
+struct PlaybackInfo {
+    video_codec: Option<String>,
+    /// Like "aac (eng)" for every audio stream
+    audio: Vec<String>,
+}
+
+/// What the currently playing file contains, so the player can explain why a browser can't play it
+async fn playback_info(
+    Path(id): Path<u32>,
+    State(sessions): State<StreamingSessions>,
+    State(db): State<Database>,
+    auth: AuthSession,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let Some(session) = sessions.get(&id).await else {
+        status!(StatusCode::NOT_FOUND);
+    };
+    if !session.may_join(user.id).await {
+        status!(StatusCode::FORBIDDEN);
+    }
+
+    // Files that couldn't be probed just have nothing to say about their codecs
+    let (video_codec, audio) = db
+        .get()?
+        .query_row_into::<(Option<String>, Option<String>)>(
+            "SELECT video_codec, audio FROM technical_details WHERE content_id = ?1",
+            [session.video_id().await],
+        )
+        .optional()?
+        .unwrap_or_default();
+
+    Ok(Json(PlaybackInfo {
+        video_codec,
+        audio: audio
+            .map(|audio| audio.split(',').map(str::to_owned).collect())
+            .unwrap_or_default(),
+    }))
+}
+
+/// What ffmpeg reported while the session tried to open its files, for admins looking into files that won't play
+async fn errors(
+    Path(id): Path<u32>,
+    State(sessions): State<StreamingSessions>,
+    auth: AuthSession,
+) -> AppResult<impl IntoResponse> {
+    if !auth.has_perm("owner").await? {
+        status!(StatusCode::FORBIDDEN);
+    }
+
+    let Some(session) = sessions.get(&id).await else {
+        status!(StatusCode::NOT_FOUND);
+    };
+
+    Ok(Json(session.errors().await))
+}
+
+/// How long a link copied from the player keeps working
+const LINK_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 6);
+
+/// A link to what the session is playing right now, for players that can't log in like TV apps or embeds
+async fn link(
+    Path(id): Path<u32>,
+    State(sessions): State<StreamingSessions>,
+    State(settings): State<ServerSettings>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    let Some(session) = sessions.get(&id).await else {
+        status!(StatusCode::NOT_FOUND);
+    };
+    if !session.may_join(user.id).await {
+        status!(StatusCode::FORBIDDEN);
+    }
+
+    Ok(direct_url(
+        &origin(&settings, &headers)?,
+        &settings.media_token_secret(),
+        user.id,
+        session.video_id().await,
+        LINK_TOKEN_LIFETIME,
+    ))
+}
+
+fn report_response(status: StatusCode, msg: String) -> axum::response::Response {
+    (status, Notification { msg, script: "" }).into_response()
+}
+
+/// How long the links in an exported playlist keep working
+const EXPORT_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExportTarget {
+    Content,
+    Collection,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    M3u,
+    /// Just the link to a single piece of content, like Kodi expects it
+    Strm,
+}
+
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// A playlist of links that play without logging in, each only for the user that exported it and a single piece of content
+async fn export(
+    Path((target, id)): Path<(ExportTarget, u64)>,
+    Query(export): Query<Export>,
+    State(db): State<Database>,
+    State(settings): State<ServerSettings>,
+    auth: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<impl IntoResponse> {
+    let Some(user) = auth.user else {
+        status!(StatusCode::UNAUTHORIZED);
+    };
+
+    // A .strm file can only point at a single video
+    if let (ExportTarget::Collection, ExportFormat::Strm) = (target, export.format) {
+        status!(StatusCode::BAD_REQUEST);
+    }
+
+    let conn = db.get()?;
+
+    let content = match target {
+        ExportTarget::Content => {
+            let playable = conn.query_row_get::<bool>(
+                "SELECT exists(SELECT 1 FROM content
+                    WHERE id = ?1 AND data_id IS NOT NULL AND part = 0)",
+                [id],
+            )?;
+            if playable {
+                vec![id]
+            } else {
+                Vec::new()
+            }
+        }
+        ExportTarget::Collection => playable_in_collection(&conn, id)?,
+    };
+
+    if content.is_empty() {
+        status!(StatusCode::NOT_FOUND);
+    }
+
+    let origin = origin(&settings, &headers)?;
+    let secret = settings.media_token_secret();
+    let url =
+        |content_id: u64| direct_url(&origin, &secret, user.id, content_id, EXPORT_TOKEN_LIFETIME);
+
+    let (body, content_type, extension) = match export.format {
+        ExportFormat::M3u => (m3u(&conn, &content, url)?, "audio/x-mpegurl", "m3u"),
+        ExportFormat::Strm => (
+            format!("{}\n", url(id)),
+            "text/plain; charset=utf-8",
+            "strm",
+        ),
+    };
+
+    let target = match target {
+        ExportTarget::Content => "content",
+        ExportTarget::Collection => "collection",
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{target}-{id}.{extension}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// Every entry is titled like it is in the library
+fn m3u(
+    conn: &rusqlite::Connection,
+    content: &[u64],
+    url: impl Fn(u64) -> String,
+) -> AppResult<String> {
+    let mut playlist = String::from("#EXTM3U\n");
+    for &content_id in content {
+        // A line break in the title would end up as a separate entry
+        let title = content_title(conn, content_id)?.replace(['\r', '\n'], " ");
+        playlist.push_str(&format!("#EXTINF:-1,{title}\n{}\n", url(content_id)));
+    }
+    Ok(playlist)
+}
+
+/// External players need the whole address, not just the path
+/// The configured public url is trusted over what the client says it asked for
+fn origin(settings: &ServerSettings, headers: &HeaderMap) -> AppResult<String> {
+    if let Some(url) = settings.public_url() {
+        return Ok(url);
+    }
+
+    let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) else {
+        status!(StatusCode::BAD_REQUEST);
+    };
+    let scheme = headers
+        .get("X-Forwarded-Proto")
+        .and_then(|scheme| scheme.to_str().ok())
+        .unwrap_or("http");
+    Ok(format!("{scheme}://{host}"))
+}
+
+/// Rejects requests that pages of other sites made the browser send, browsers always name the page they come from
+/// Plain navigations can leave out the Origin, those are let through
+async fn this_site_only(
+    State(settings): State<ServerSettings>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let allowed = match request.headers().get(ORIGIN) {
+        Some(sent) => is_this_site(sent, &settings, request.headers()),
+        None => true,
+    };
+    if !allowed {
+        debug!(
+            "Rejected {} from {:?}",
+            request.uri().path(),
+            request.headers().get(ORIGIN)
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Pages are either opened through the host the request was sent to or the public url
+/// Only the hosts are compared, which scheme the client used isn't always known behind a proxy
+fn is_this_site(sent: &HeaderValue, settings: &ServerSettings, headers: &HeaderMap) -> bool {
+    let host = |url: &str| {
+        url.split_once("://")
+            .and_then(|(_, rest)| rest.split('/').next())
+            .map(str::to_ascii_lowercase)
+    };
+
+    let Some(sent) = sent.to_str().ok().and_then(host) else {
+        return false;
+    };
+    let requested = headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(str::to_ascii_lowercase);
+    let public = settings.public_url().as_deref().and_then(host);
+    requested.as_ref() == Some(&sent) || public == Some(sent)
+}
+
+/// Plays the content without logging in, only until the token expires
+fn direct_url(
+    origin: &str,
+    secret: &str,
+    user_id: i64,
+    content_id: u64,
+    lifetime: Duration,
+) -> String {
+    let token = media_token(secret, user_id, content_id, lifetime);
+    format!(
+        "{origin}{}",
+        with_base(&format!("/video/direct/{content_id}?token={token}"))
+    )
+}
+
+/// The file of the content without a session around it, for external players and embedding
+async fn direct(
+    Path(id): Path<u64>,
+    State(db): State<Database>,
+    access: MediaAccess,
+    request: Request<Body>,
+) -> AppResult<Response> {
+    if !access.allows(id) {
+        status!(StatusCode::FORBIDDEN);
+    }
+
+    let file_path = db
+        .get()?
+        .query_row_get::<String>(
+            "SELECT data_file.path FROM content, data_file
+                WHERE content.data_id = data_file.id
+                AND content.id = ?1
+                AND part = 0",
+            [id],
+        )
+        .optional()?;
+
+    let Some(file_path) = file_path else {
+        status!(StatusCode::NOT_FOUND);
+    };
+
+    Ok(ServeFile::new(file_path)
+        .oneshot(request)
+        .await
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::header::LOCATION};
+
+    use super::*;
+    use crate::{
+        database::{in_file, in_memory},
+        indexing::{fixture_content, index_fixture},
+        utils::{generate_secret, verify_media_token, TempDir},
+    };
+
+    #[test]
+    fn reports_are_stored_with_their_content_and_time() {
+        let conn = in_memory();
+        conn.execute(
+            "INSERT INTO users (id, username, password) VALUES (1, 'viewer', '')",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO content (id, last_changed, hash, type, part) VALUES (7, 0, x'00', 0, 0)",
+            [],
+        )
+        .unwrap();
+
+        let report = Report {
+            video_time: 83.5,
+            description: "  The audio is out of sync \n".to_owned(),
+        };
+        assert!(store_report(&conn, 1, 3, 7, &report, 1000).unwrap());
+
+        let stored = conn
+            .query_row_into::<(u64, u32, i64, f64, String, u64)>(
+                "SELECT content_id, session_id, user_id, video_time, description, created FROM playback_reports",
+                [],
+            )
+            .unwrap();
+        assert_eq!(
+            stored,
+            (7, 3, 1, 83.5, "The audio is out of sync".to_owned(), 1000)
+        );
+    }
+
+    #[test]
+    fn reports_are_limited_per_window() {
+        let conn = in_memory();
+        conn.execute(
+            "INSERT INTO users (id, username, password) VALUES (1, 'viewer', '')",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO content (id, last_changed, hash, type, part) VALUES (7, 0, x'00', 0, 0)",
+            [],
+        )
+        .unwrap();
+        let report = Report {
+            video_time: 0.,
+            description: "Stutters".to_owned(),
+        };
+
+        for _ in 0..MAX_REPORTS {
+            assert!(store_report(&conn, 1, 3, 7, &report, 1000).unwrap());
+        }
+        assert!(!store_report(&conn, 1, 3, 7, &report, 1000).unwrap());
+        // Once the window has passed the earlier reports don't count anymore
+        assert!(store_report(&conn, 1, 3, 7, &report, 1000 + REPORT_WINDOW).unwrap());
+    }
+
+    /// Sessions like the server has them, the directory holds their database
+    fn sessions(name: &str) -> (TempDir, StreamingSessions) {
+        let dir = TempDir::new(name);
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let (shutdown, _) = Shutdown::new();
+        let sessions = StreamingSessions::with_defaults(&db, &shutdown);
+        (dir, sessions)
+    }
+
+    #[tokio::test]
+    async fn sessions_opened_directly_load_the_whole_page() {
+        let (_dir, sessions) = sessions("open-direct");
+
+        let response = open_session(&sessions, 7, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/?all=/video/session/7");
+    }
+
+    #[tokio::test]
+    async fn sessions_opened_from_htmx_only_get_the_player() {
+        let (_dir, sessions) = sessions("open-htmx");
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Request", "true".parse().unwrap());
+
+        let response = open_session(&sessions, 7, &headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["HX-Push-Url"], "/?all=/video/session/7");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"data-session="7""#));
+        assert!(!body.contains("<html"));
+    }
+
+    #[test]
+    fn exported_playlists_link_every_item_with_its_own_token() {
+        const PILOT: &str = "Lost/Season 1/Pilot - s1e1.mp4";
+        const TABULA_RASA: &str = "Lost/Season 1/Tabula Rasa - s1e2.mp4";
+
+        let mut conn = in_memory();
+        index_fixture(&mut conn, &[PILOT, TABULA_RASA]);
+        let content = [
+            fixture_content(&conn, PILOT),
+            fixture_content(&conn, TABULA_RASA),
+        ];
+        let secret = generate_secret();
+
+        let playlist = m3u(&conn, &content, |content_id| {
+            direct_url(
+                "https://example.com",
+                &secret,
+                3,
+                content_id,
+                EXPORT_TOKEN_LIFETIME,
+            )
+        })
+        .unwrap();
+
+        let urls = playlist
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(urls.len(), content.len());
+        for (url, content_id) in urls.into_iter().zip(content) {
+            let (path, token) = url.split_once("?token=").unwrap();
+            assert_eq!(
+                path,
+                format!("https://example.com/video/direct/{content_id}")
+            );
+
+            let token = verify_media_token(&secret, token).unwrap();
+            assert_eq!((token.user_id, token.content_id), (3, content_id));
+        }
+    }
+
+    /// The status of a request to a route that only accepts requests from this site
+    async fn from_origin(settings: &ServerSettings, origin: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().uri("/").header(HOST, "media.local:3000");
+        if let Some(origin) = origin {
+            request = request.header(ORIGIN, origin);
+        }
+        Router::new()
+            .route("/", get(|| async {}))
+            .route_layer(middleware::from_fn_with_state(
+                settings.clone(),
+                this_site_only,
+            ))
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn requests_from_other_sites_are_rejected() {
+        let settings = ServerSettings::in_memory();
+        for origin in ["http://media.local:3000", "https://Media.Local:3000"] {
+            assert_eq!(from_origin(&settings, Some(origin)).await, StatusCode::OK);
+        }
+        // Navigations don't always say where they come from
+        assert_eq!(from_origin(&settings, None).await, StatusCode::OK);
+
+        for origin in [
+            "https://elsewhere.example.com",
+            "http://media.local:3001",
+            "http://media.local:3000.example.com",
+            "null",
+        ] {
+            assert_eq!(
+                from_origin(&settings, Some(origin)).await,
+                StatusCode::FORBIDDEN,
+                "{origin} was let through"
+            );
+        }
+
+        settings.set_public_url(Some("https://example.com/media/".to_owned()));
+        for origin in ["https://example.com", "http://media.local:3000"] {
+            assert_eq!(from_origin(&settings, Some(origin)).await, StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn links_use_the_public_url_over_the_requested_host() {
+        let settings = ServerSettings::in_memory();
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "spoofed.example.com".parse().unwrap());
+        assert_eq!(
+            origin(&settings, &headers).unwrap(),
+            "http://spoofed.example.com"
+        );
+
+        settings.set_public_url(Some("https://example.com/".to_owned()));
+        assert_eq!(origin(&settings, &headers).unwrap(), "https://example.com");
     }
 }
@@ -0,0 +1,36 @@
+use axum::Json;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Version {
+    version: &'static str,
+    commit: &'static str,
+    /// Seconds since the unix epoch
+    build_timestamp: &'static str,
+}
+
+pub async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("MRE_GIT_COMMIT"),
+        build_timestamp: env!("MRE_BUILD_TIMESTAMP"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_crate_version_is_returned() {
+        let Json(version) = version().await;
+        let json = serde_json::to_value(version).unwrap();
+
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["commit"]
+            .as_str()
+            .is_some_and(|commit| !commit.is_empty()));
+        let timestamp = json["build_timestamp"].as_str().unwrap();
+        assert!(timestamp.parse::<u64>().is_ok());
+    }
+}
@@ -2,18 +2,16 @@ use std::{
     error::Error,
     fmt::Display,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use axum::{
-    extract::FromRef,
-    http::{self, StatusCode},
-    response::IntoResponse,
-};
-use tokio::sync::{oneshot, Notify};
+use axum::{extract::FromRef, http::StatusCode, response::IntoResponse};
+use tokio::sync::{oneshot, watch, Notify};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
     database::Database,
+    recommendation::Recommender,
     utils::{streaming::StreamingSessions, ServerSettings},
 };
 
@@ -24,13 +22,22 @@ pub struct AppState {
     pub shutdown: Shutdown,
     pub serversettings: ServerSettings,
     pub indexing_trigger: IndexingTrigger,
+    pub content_updates: ContentUpdates,
 }
 
 impl AppState {
     pub async fn new(database: Database, port: Option<u16>) -> (Self, oneshot::Receiver<bool>) {
         let (shutdown, restart_receiver) = Shutdown::new();
-        let streaming_sessions = StreamingSessions::new(shutdown.clone());
         let serversettings = ServerSettings::new(shutdown.clone(), database.clone(), port).await;
+        let content_updates = ContentUpdates::new(shutdown.clone());
+        let recommender = Recommender::spawn(
+            database.clone(),
+            serversettings.clone(),
+            &content_updates,
+            shutdown.clone(),
+        );
+        let streaming_sessions =
+            StreamingSessions::new(shutdown.clone(), serversettings.clone(), recommender);
         let indexing_trigger = IndexingTrigger::new();
         (
             Self {
@@ -39,6 +46,7 @@ impl AppState {
                 shutdown,
                 serversettings,
                 indexing_trigger,
+                content_updates,
             },
             restart_receiver,
         )
@@ -92,6 +100,66 @@ impl IndexingTrigger {
     }
 }
 
+impl FromRef<AppState> for ContentUpdates {
+    fn from_ref(state: &AppState) -> ContentUpdates {
+        state.content_updates.clone()
+    }
+}
+
+/// Tells open library views that indexing changed the content, so they can refresh
+#[derive(Clone)]
+pub struct ContentUpdates {
+    changed: Arc<watch::Sender<()>>,
+    refresh: watch::Receiver<()>,
+}
+
+impl ContentUpdates {
+    /// Changes within this time of each other only cause one refresh
+    const DEBOUNCE: Duration = Duration::from_secs(5);
+
+    pub fn new(shutdown: Shutdown) -> Self {
+        let (changed, changed_recv) = watch::channel(());
+        let (refresh, refresh_recv) = watch::channel(());
+
+        tokio::spawn(Self::debounce(changed_recv, refresh, shutdown));
+
+        Self {
+            changed: Arc::new(changed),
+            refresh: refresh_recv,
+        }
+    }
+
+    async fn debounce(
+        mut changed: watch::Receiver<()>,
+        refresh: watch::Sender<()>,
+        shutdown: Shutdown,
+    ) {
+        loop {
+            tokio::select! {
+                res = changed.changed() => if res.is_err() { return; },
+                _ = shutdown.cancelled() => return,
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(Self::DEBOUNCE) => {}
+                _ = shutdown.cancelled() => return,
+            }
+
+            // Everything that changed while waiting is covered by this refresh
+            changed.borrow_and_update();
+            refresh.send_replace(());
+        }
+    }
+
+    pub fn notify_changed(&self) {
+        self.changed.send_replace(());
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.refresh.clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct Shutdown {
     cancellation: CancellationToken,
@@ -99,7 +167,7 @@ pub struct Shutdown {
 }
 
 impl Shutdown {
-    fn new() -> (Self, oneshot::Receiver<bool>) {
+    pub fn new() -> (Self, oneshot::Receiver<bool>) {
         let (restart_sender, restart_receiver) = oneshot::channel();
         let cancellation = CancellationToken::new();
         let shutdown = Self {
@@ -136,6 +204,19 @@ impl Shutdown {
     pub async fn cancelled(&self) {
         self.cancellation.cancelled().await;
     }
+
+    /// Creates a handle that is cancelled with the application, but can also be cancelled on its own using `close`
+    pub fn child(&self) -> Self {
+        Self {
+            cancellation: self.cancellation.child_token(),
+            restart_sender: self.restart_sender.clone(),
+        }
+    }
+
+    /// Only cancels this handle and its children, the application keeps running
+    pub fn close(&self) {
+        self.cancellation.cancel();
+    }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -198,15 +279,42 @@ impl From<anyhow::Error> for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            // Getting a connection only fails when none became available in time
+            AppError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Status(status) => status,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        // htmx requests would otherwise swallow the error, so they are sent to the error page as well
         #[cfg(not(debug_assertions))]
-        return (http::StatusCode::INTERNAL_SERVER_ERROR).into_response();
+        // The redirect loads the page again with a csrf token, this one is only seen without htmx
+        let mut response = match crate::routes::error_page(status, None, String::new()) {
+            Ok(page) => (
+                status,
+                [(
+                    "HX-Redirect",
+                    crate::utils::with_base(&format!("/error?err={}", status.as_u16())),
+                )],
+                page,
+            )
+                .into_response(),
+            Err(_) => status.into_response(),
+        };
         #[cfg(debug_assertions)]
-        return (
-            http::StatusCode::INTERNAL_SERVER_ERROR,
+        let mut response = (
+            status,
             crate::utils::templates::DebugError {
                 err: &format!("{self:?}"),
             },
         )
             .into_response();
+
+        response.extensions_mut().insert(ErrorStatus(status));
+        response
     }
 }
+
+/// Marks responses that were created from an [`AppError`], so they can be rendered differently depending on the route
+#[derive(Clone, Copy)]
+pub struct ErrorStatus(pub StatusCode);
@@ -1,4 +1,8 @@
-use std::{collections::HashSet, convert::Infallible, ops::Deref};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    ops::{Deref, RangeInclusive},
+};
 
 use anyhow::Context;
 use axum::{
@@ -23,17 +27,76 @@ use futures_util::stream;
 use rusqlite::{params, OptionalExtension};
 use serde::Deserialize;
 use time::OffsetDateTime;
+use tracing::warn;
 
 use crate::{
     database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt, QueryRowIntoStmtExt},
     state::{AppError, AppResult},
-    utils::bail,
+    utils::{bail, settings::NewUserDefaults},
 };
 
-use super::ConvertErr;
+use super::{html_event, utf8_sse, with_base, ConvertErr};
 
 pub type AuthSession = axum_login::AuthSession<Database>;
 
+/// Inserts the user together with their default permissions and groups, either all of it is stored or nothing
+/// Unknown names are skipped, the owner permission is never handed out this way
+pub fn create_user(
+    conn: &rusqlite::Connection,
+    username: &str,
+    password_hash: &str,
+    defaults: &NewUserDefaults,
+) -> AppResult<u64> {
+    let tx = conn.unchecked_transaction()?;
+
+    let id = tx.query_row_get::<u64>(
+        "INSERT INTO users (username, password) VALUES (?1, ?2) RETURNING id",
+        params![username, password_hash],
+    )?;
+
+    for permission in &defaults.permissions {
+        if permission == "owner" {
+            warn!("Not giving new users the owner permission, it can't be a default");
+            continue;
+        }
+        let granted = tx.execute(
+            "INSERT OR IGNORE INTO user_permissions (userid, permissionid)
+                SELECT ?1, id FROM permissions WHERE name = ?2",
+            params![id, permission],
+        )?;
+        if granted == 0 {
+            warn!("The default permission \"{permission}\" doesn't exist");
+        }
+    }
+
+    for group in &defaults.groups {
+        let grants_owner = tx.query_row_get::<bool>(
+            "SELECT EXISTS (SELECT 1 FROM groups, group_permissions, permissions
+                WHERE groups.name = ?1
+                AND groups.id = group_permissions.groupid
+                AND group_permissions.permissionid = permissions.id
+                AND permissions.name = 'owner')",
+            [group],
+        )?;
+        if grants_owner {
+            warn!("Not adding new users to the group \"{group}\", it carries the owner permission");
+            continue;
+        }
+
+        let joined = tx.execute(
+            "INSERT OR IGNORE INTO user_groups (userid, groupid)
+                SELECT ?1, id FROM groups WHERE name = ?2",
+            params![id, group],
+        )?;
+        if joined == 0 {
+            warn!("The default group \"{group}\" doesn't exist");
+        }
+    }
+
+    tx.commit()?;
+    Ok(id)
+}
+
 pub trait AuthExt {
     async fn has_perm(&self, perm: impl Into<Permission>) -> AppResult<bool>;
 }
@@ -95,6 +158,44 @@ pub struct Credentials {
     pub password: String,
 }
 
+const USERNAME_LENGTH: RangeInclusive<usize> = 1..=64;
+const PASSWORD_LENGTH: RangeInclusive<usize> = 4..=256;
+
+#[derive(Clone, Copy)]
+pub enum CredentialKind {
+    Username,
+    Password,
+}
+
+/// The rules for every place that creates or changes usernames and passwords
+/// Usernames are trimmed, the returned value is what should be stored
+pub fn validate_credentials(kind: CredentialKind, value: &str) -> Result<&str, &'static str> {
+    match kind {
+        CredentialKind::Username => {
+            let username = value.trim();
+            if !USERNAME_LENGTH.contains(&username.chars().count()) {
+                return Err("Usernames have to be between 1 and 64 characters long!");
+            }
+            if !username
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ' '))
+            {
+                return Err("Usernames can only contain letters, numbers, spaces and _ - .");
+            }
+            Ok(username)
+        }
+        CredentialKind::Password => {
+            if !PASSWORD_LENGTH.contains(&value.chars().count()) {
+                return Err("Passwords have to be between 4 and 256 characters long!");
+            }
+            if value.chars().any(char::is_control) {
+                return Err("Passwords can't contain control characters!");
+            }
+            Ok(value)
+        }
+    }
+}
+
 struct SessionStoreError(session_store::Error);
 
 impl Deref for SessionStoreError {
@@ -376,6 +477,10 @@ pub async fn login_required(
     if auth.user.is_some() {
         return next.run(request).await.into_response();
     }
+    // Api clients can't log in through a redirect, the json error layer around the api turns this into json
+    if uri.path().starts_with(&with_base("/api/")) {
+        return AppError::Status(StatusCode::UNAUTHORIZED).into_response();
+    }
     // TODO: There needs to be a better way to do all this
     let htmx_enabled = hm.get("HX-Request").is_some();
     let is_sse = hm
@@ -394,7 +499,7 @@ pub async fn login_required(
             .split_once('/')
             .unwrap_or(("", ""))
             .1;
-        let redirect = format!("/auth/login?next=/{path}");
+        let redirect = format!("{}?next=/{path}", with_base("/auth/login"));
 
         (StatusCode::UNAUTHORIZED, [("HX-Redirect", redirect)]).into_response()
     } else if is_sse {
@@ -412,14 +517,112 @@ pub async fn login_required(
 
         async fn meta_redirect(path: String) -> Result<Event, Infallible> {
             let content = format!(
-                "<div> <meta http-equiv=\"refresh\" content=\"0; url=/auth/login?next=/{path}\" /> </div>"
+                "<div> <meta http-equiv=\"refresh\" content=\"0; url={login}?next=/{path}\" /> </div>",
+                login = with_base("/auth/login")
             );
-            Ok(Event::default().data(content))
+            Ok(html_event(&content))
         }
         let stream = stream::once(meta_redirect(path.to_owned()));
-        Sse::new(stream).into_response()
+        utf8_sse(Sse::new(stream))
     } else {
-        let redirect = format!("/auth/login?next={uri}");
+        let redirect = format!("{}?next={uri}", with_base("/auth/login"));
         (StatusCode::SEE_OTHER, [(LOCATION, redirect)]).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::in_memory;
+
+    fn defaults(permissions: &[&str], groups: &[&str]) -> NewUserDefaults {
+        NewUserDefaults {
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    fn count(conn: &rusqlite::Connection, table: &str, user: u64) -> u64 {
+        conn.query_row_get(
+            &format!("SELECT COUNT(*) FROM {table} WHERE userid = ?1"),
+            [user],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn defaults_are_given_to_new_users() {
+        let conn = in_memory();
+        conn.execute_batch(
+            "INSERT INTO permissions (name) VALUES ('upload');
+            INSERT INTO groups (name) VALUES ('family');",
+        )
+        .unwrap();
+
+        let id = create_user(&conn, "user", "", &defaults(&["upload"], &["family"])).unwrap();
+        assert_eq!(count(&conn, "user_permissions", id), 1);
+        assert_eq!(count(&conn, "user_groups", id), 1);
+    }
+
+    #[test]
+    fn owner_is_never_a_default() {
+        let conn = in_memory();
+        conn.execute_batch(
+            "INSERT INTO groups (name) VALUES ('admins');
+            INSERT INTO group_permissions (groupid, permissionid)
+                SELECT groups.id, permissions.id FROM groups, permissions
+                WHERE groups.name = 'admins' AND permissions.name = 'owner';",
+        )
+        .unwrap();
+
+        let id = create_user(&conn, "user", "", &defaults(&["owner"], &["admins"])).unwrap();
+        assert_eq!(count(&conn, "user_permissions", id), 0);
+        assert_eq!(count(&conn, "user_groups", id), 0);
+    }
+
+    #[test]
+    fn bad_credentials_are_rejected() {
+        use CredentialKind::{Password, Username};
+
+        let cases = [
+            (Username, String::new()),
+            (Username, "   ".to_owned()),
+            (Username, "a".repeat(65)),
+            (Username, "line\nbreak".to_owned()),
+            (Username, "nul\0byte".to_owned()),
+            (Username, "<script>".to_owned()),
+            (Username, "semi;colon".to_owned()),
+            (Password, String::new()),
+            (Password, "abc".to_owned()),
+            (Password, "a".repeat(257)),
+            (Password, "line\nbreak".to_owned()),
+            (Password, "nul\0byte".to_owned()),
+            (Password, "delete\u{7f}".to_owned()),
+        ];
+        for (kind, value) in cases {
+            assert!(
+                validate_credentials(kind, &value).is_err(),
+                "{value:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn good_credentials_are_accepted_and_usernames_trimmed() {
+        use CredentialKind::{Password, Username};
+
+        assert_eq!(
+            validate_credentials(Username, "  Jane Doe-2.0_ "),
+            Ok("Jane Doe-2.0_")
+        );
+        let longest = "\u{e9}".repeat(64);
+        assert_eq!(
+            validate_credentials(Username, &longest),
+            Ok(longest.as_str())
+        );
+        assert_eq!(
+            validate_credentials(Password, " p@ss; <w\u{f6}rd> "),
+            Ok(" p@ss; <w\u{f6}rd> ")
+        );
+    }
+}
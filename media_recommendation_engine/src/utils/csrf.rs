@@ -0,0 +1,65 @@
+use anyhow::Context;
+use axum::{
+    extract::Request,
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_sessions::Session;
+use tracing::debug;
+
+use crate::state::AppResult;
+
+use super::generate_secret;
+
+const CSRF_SESSION_KEY: &str = "csrf_token";
+/// htmx sends this with every request, the value comes from the hx-headers on the body
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The token of this browser session, it is created the first time a page is loaded
+pub async fn csrf_token(session: &Session) -> AppResult<String> {
+    let existing = session
+        .get::<String>(CSRF_SESSION_KEY)
+        .await
+        .context("Failed to read the csrf token from the session")?;
+
+    if let Some(token) = existing {
+        return Ok(token);
+    }
+
+    let token = generate_secret();
+    session
+        .insert(CSRF_SESSION_KEY, &token)
+        .await
+        .context("Failed to store the csrf token in the session")?;
+    Ok(token)
+}
+
+/// Rejects requests that change something unless they carry the token of their session
+/// Other sites can make the browser send the cookie, but they can't read the token from the page
+pub async fn csrf_protection(session: Session, request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let expected = session.get::<String>(CSRF_SESSION_KEY).await.ok().flatten();
+    let sent = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|token| token.to_str().ok());
+
+    match (expected, sent) {
+        (Some(expected), Some(sent)) if expected == sent => next.run(request).await,
+        _ => {
+            debug!(
+                "Rejected {} {} without a valid csrf token",
+                request.method(),
+                request.uri().path()
+            );
+            StatusCode::FORBIDDEN.into_response()
+        }
+    }
+}
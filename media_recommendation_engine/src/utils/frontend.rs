@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 use askama_axum::IntoResponse;
 use axum::{routing::get, Router};
 
@@ -28,6 +30,29 @@ impl HXTarget {
     }
 }
 
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// Only the first call has an effect, it has to happen before anything generates urls
+pub fn set_base_path(base_path: &str) {
+    let base_path = base_path.trim_matches('/');
+    let base_path = if base_path.is_empty() {
+        String::new()
+    } else {
+        format!("/{base_path}")
+    };
+    BASE_PATH.get_or_init(|| base_path);
+}
+
+/// The path the app is hosted under behind a reverse proxy, like "/media", empty when it owns the root
+pub fn base_path() -> &'static str {
+    BASE_PATH.get().map_or("", String::as_str)
+}
+
+/// Routes are written without the base path, this is for anything the browser ends up requesting
+pub fn with_base(route: &str) -> String {
+    format!("{}{route}", base_path())
+}
+
 pub fn frontend_redirect(route: &str, target: HXTarget) -> String {
     frontend_redirect_explicit(
         route,
@@ -40,13 +65,13 @@ pub fn frontend_redirect_explicit(route: &str, target: HXTarget, push_url: Optio
     match push_url {
         Some(push_url) => format!(
             r#"hx-get="{route}" hx-target={target} hx-push-url="{push_url}""#,
-            route = route,
+            route = with_base(route),
             target = target.as_target(),
-            push_url = push_url
+            push_url = with_base(push_url)
         ),
         None => format!(
             r#"hx-get="{route}" hx-target={target}"#,
-            route = route,
+            route = with_base(route),
             target = target.as_target()
         ),
     }
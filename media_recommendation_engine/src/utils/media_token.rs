@@ -0,0 +1,197 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tower_sessions::cookie::Key;
+
+use crate::{
+    database::{Database, QueryRowGetConnExt},
+    state::AppError,
+    utils::{auth::User, AuthSession, ServerSettings},
+};
+
+/// A fresh random secret to sign media tokens with, hex encoded so it fits into the config file
+pub fn generate_secret() -> String {
+    Key::generate()
+        .master()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Lets the user play a single piece of content without a session cookie until it expires
+/// Looks like "{user_id}.{content_id}.{expires}.{signature}", the expiry is in seconds since the unix epoch
+pub fn media_token(secret: &str, user_id: i64, content_id: u64, lifetime: Duration) -> String {
+    let expires = (SystemTime::now() + lifetime)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let signature = sign(secret, user_id, content_id, expires);
+    format!("{user_id}.{content_id}.{expires}.{signature}")
+}
+
+/// What a valid token allows
+pub struct MediaToken {
+    pub user_id: i64,
+    pub content_id: u64,
+}
+
+/// Only unexpired tokens that were signed with this secret are valid
+pub fn verify_media_token(secret: &str, token: &str) -> Option<MediaToken> {
+    let mut parts = token.splitn(4, '.');
+    let user_id = parts.next()?.parse::<i64>().ok()?;
+    let content_id = parts.next()?.parse::<u64>().ok()?;
+    let expires = parts.next()?.parse::<u64>().ok()?;
+    let signature = parts.next()?;
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if expires <= now {
+        return None;
+    }
+
+    let signature = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    // Takes the same time no matter where the first difference is, to not leak how much of the signature was right
+    mac(secret, user_id, content_id, expires)
+        .verify_slice(&signature)
+        .ok()?;
+
+    Some(MediaToken {
+        user_id,
+        content_id,
+    })
+}
+
+/// Who is playing something, either logged in through the cookie or with a token in the query
+pub enum MediaAccess {
+    User(User),
+    Token(MediaToken),
+}
+
+impl MediaAccess {
+    pub fn user_id(&self) -> i64 {
+        match self {
+            MediaAccess::User(user) => user.id,
+            MediaAccess::Token(token) => token.user_id,
+        }
+    }
+
+    /// Tokens only allow the content they were created for
+    pub fn allows(&self, content_id: u64) -> bool {
+        match self {
+            MediaAccess::User(_) => true,
+            MediaAccess::Token(token) => token.content_id == content_id,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for MediaAccess
+where
+    S: Send + Sync,
+    Database: FromRef<S>,
+    ServerSettings: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = AuthSession::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::Status(StatusCode::INTERNAL_SERVER_ERROR))?;
+        if let Some(user) = auth.user {
+            return Ok(MediaAccess::User(user));
+        }
+
+        let Ok(Query(query)) = Query::<TokenQuery>::from_request_parts(parts, state).await else {
+            status!(StatusCode::UNAUTHORIZED);
+        };
+
+        let secret = ServerSettings::from_ref(state).media_token_secret();
+        let Some(token) = verify_media_token(&secret, &query.token) else {
+            status!(StatusCode::FORBIDDEN);
+        };
+
+        // Deleting a user takes away everything they exported
+        let user_exists = Database::from_ref(state).get()?.query_row_get::<bool>(
+            "SELECT exists(SELECT 1 FROM users WHERE id = ?1)",
+            [token.user_id],
+        )?;
+        if !user_exists {
+            status!(StatusCode::FORBIDDEN);
+        }
+
+        Ok(MediaAccess::Token(token))
+    }
+}
+
+fn sign(secret: &str, user_id: i64, content_id: u64, expires: u64) -> String {
+    mac(secret, user_id, content_id, expires)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn mac(secret: &str, user_id: i64, content_id: u64, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC should accept keys of any length");
+    mac.update(format!("{user_id}.{content_id}.{expires}").as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn token() -> String {
+        media_token(SECRET, 4, 9, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn valid_tokens_allow_their_content() {
+        let token = verify_media_token(SECRET, &token()).unwrap();
+        assert_eq!((token.user_id, token.content_id), (4, 9));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let expired = media_token(SECRET, 4, 9, Duration::ZERO);
+        assert!(verify_media_token(SECRET, &expired).is_none());
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = token();
+        let (claims, signature) = token.rsplit_once('.').unwrap();
+
+        let other_content = token.replacen(".9.", ".10.", 1);
+        assert!(verify_media_token(SECRET, &other_content).is_none());
+
+        let flipped = if signature.starts_with('0') { '1' } else { '0' };
+        let other_signature = format!("{claims}.{flipped}{}", &signature[1..]);
+        assert!(verify_media_token(SECRET, &other_signature).is_none());
+
+        assert!(verify_media_token(SECRET, &format!("{claims}.")).is_none());
+        assert!(verify_media_token("another secret", &token).is_none());
+    }
+}
@@ -7,24 +7,53 @@ mod parsing;
 pub use parsing::{ParseBetween, ParseUntil};
 
 mod tracing;
-pub use tracing::{init_tracing, TraceLayerExt};
+pub use tracing::{
+    current_log_file, init_tracing, log_line_matches, read_log_lines, TraceLayerExt,
+};
 
 mod frontend;
-pub use frontend::{frontend_redirect, frontend_redirect_explicit, htmx, HXTarget};
+pub use frontend::{
+    base_path, frontend_redirect, frontend_redirect_explicit, htmx, set_base_path, with_base,
+    HXTarget,
+};
 
 mod auth;
-pub use auth::{login_required, AuthExt, AuthSession, Credentials};
+pub use auth::{
+    create_user, login_required, validate_credentials, AuthExt, AuthSession, CredentialKind,
+    Credentials, User,
+};
+
+mod csrf;
+pub use csrf::{csrf_protection, csrf_token};
+
+mod media_token;
+pub use media_token::{generate_secret, media_token, verify_media_token, MediaAccess};
+
+mod preferences;
+pub use preferences::{set_shows_recommendations, shows_recommendations};
 
 pub mod templates;
 
 mod settings;
 pub use settings::ServerSettings;
 
+mod sse;
+pub use sse::{html_event, utf8_sse};
+
 pub mod streaming;
 
 mod watchstream;
 pub use watchstream::WatchStream;
 
+#[cfg(test)]
+mod temp_dir;
+#[cfg(test)]
+pub use temp_dir::TempDir;
+#[cfg(test)]
+mod wav;
+#[cfg(test)]
+pub use wav::{silence, silence_with_cover};
+
 macro_rules! relative {
     ($path:expr) => {
         if cfg!(windows) {
@@ -52,6 +81,24 @@ macro_rules! status {
     };
 }
 
+/// Human readable amount of bytes like "4.2 GB", using powers of 1000
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000. && unit < UNITS.len() - 1 {
+        size /= 1000.;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
 pub fn pseudo_random() -> u32 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -0,0 +1,26 @@
+use rusqlite::{params, OptionalExtension};
+
+use crate::{
+    database::{Connection, QueryRowGetConnExt},
+    state::AppResult,
+};
+
+/// Whether the user wants to be offered something new when a video is almost over, which is the default
+pub fn shows_recommendations(conn: &Connection, user_id: i64) -> AppResult<bool> {
+    let show = conn
+        .query_row_get::<bool>(
+            "SELECT show_recommendations FROM user_preferences WHERE user_id = ?1",
+            [user_id],
+        )
+        .optional()?;
+    Ok(show.unwrap_or(true))
+}
+
+pub fn set_shows_recommendations(conn: &Connection, user_id: i64, show: bool) -> AppResult<()> {
+    conn.execute(
+        "INSERT INTO user_preferences (user_id, show_recommendations) VALUES (?1, ?2)
+            ON CONFLICT (user_id) DO UPDATE SET show_recommendations = excluded.show_recommendations",
+        params![user_id, show],
+    )?;
+    Ok(())
+}
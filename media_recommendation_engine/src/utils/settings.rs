@@ -1,10 +1,11 @@
-use std::{path::Path, sync::Arc, time::SystemTime};
+use std::{collections::BTreeMap, path::Path, sync::Arc, time::SystemTime};
 
 use crate::{
-    database::{Database, QueryRowGetConnExt, QueryRowGetStmtExt},
+    database::{Database, QueryRowGetConnExt, QueryRowIntoConnExt},
     state::{AppResult, Shutdown},
 };
 
+use rusqlite::{params, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use tokio::{
     io::AsyncWriteExt,
@@ -12,13 +13,140 @@ use tokio::{
 };
 use tracing::{debug, error, info, warn};
 
-use super::HandleErr;
+use super::{bail, create_user, generate_secret, validate_credentials, CredentialKind, HandleErr};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFile {
     port: u16,
     index_wait: f64,
-    admin: AdminCredentials,
+    /// This account always exists and is an owner, owners created in other ways like the create-user command are kept
+    #[serde(default)]
+    admin: Option<AdminCredentials>,
+    /// Whether "next episode" recommendations jump over missing episode and season numbers
+    #[serde(default = "default_skip_episode_gaps")]
+    skip_episode_gaps: bool,
+    /// Enables looking up metadata on TMDB when set
+    #[serde(default)]
+    tmdb_api_key: Option<String>,
+    /// Minutes without any playback activity after which a session is closed, 0 disables this
+    #[serde(default = "default_session_idle_timeout")]
+    session_idle_timeout: u64,
+    /// Seconds a session is kept after the last viewer left, so reconnecting ends up in the same one, 0 closes it right away
+    #[serde(default = "default_session_reconnect_grace")]
+    session_reconnect_grace: u64,
+    /// How far into a video, as a fraction above 0.5 and up to 1, it counts as watched and the next thing is recommended
+    #[serde(default = "default_watched_threshold")]
+    watched_threshold: f64,
+    /// Directories with these names hold extras like featurettes instead of movies or episodes, compared case insensitively
+    #[serde(default = "default_extras_folders")]
+    extras_folders: Vec<String>,
+    /// The rows of the library page in order, unknown names are skipped
+    /// These are "Continue Series", "Franchises", "Recent Franchises" and "Collections"
+    #[serde(default = "default_homepage_sections")]
+    homepage_sections: Vec<String>,
+    /// Storage locations have to be inside one of these directories, any directory is allowed when empty
+    #[serde(default)]
+    allowed_location_roots: Vec<String>,
+    /// Files matching one of these are never indexed, "*" matches anything and "?" a single character
+    /// Patterns with a "/" like "*/samples/*" are compared to the whole path instead of just the file name
+    #[serde(default = "default_ignored_patterns")]
+    ignored_patterns: Vec<String>,
+    /// Days content stays in the library after its file disappeared, so an unmounted drive doesn't lose everything, 0 keeps it forever
+    #[serde(default = "default_missing_content_retention")]
+    missing_content_retention: u64,
+    #[serde(default)]
+    new_users: NewUserDefaults,
+    /// A stylesheet loaded after all bundled ones, so a deployment can change the look without editing them
+    #[serde(default)]
+    custom_css: Option<String>,
+    /// Signs the links handed to external players, changing it invalidates all of them
+    #[serde(default)]
+    media_token_secret: String,
+    /// The address the server is reached at from outside without the base path, like "https://example.com", used in links for other programs
+    /// The address the client asked for is used when this isn't set, that can be spoofed
+    #[serde(default)]
+    public_url: Option<String>,
+    /// Origins like "https://example.com" that may use the JSON API from another origin, only the same origin can when empty
+    /// The session cookie is only sent along from the same site, like another port or subdomain of this one
+    #[serde(default)]
+    cors_allowed_origins: Vec<String>,
+    /// Content types for static files by extension without the dot, like woff2 = "font/woff2", these replace the guessed ones
+    #[serde(default)]
+    content_types: BTreeMap<String, String>,
+}
+
+fn default_skip_episode_gaps() -> bool {
+    true
+}
+
+fn default_session_idle_timeout() -> u64 {
+    60
+}
+
+fn default_session_reconnect_grace() -> u64 {
+    30
+}
+
+fn default_watched_threshold() -> f64 {
+    0.95
+}
+
+/// Anything earlier would interrupt most of the video, so it has to be past the middle
+fn is_valid_watched_threshold(threshold: f64) -> bool {
+    threshold > 0.5 && threshold <= 1.
+}
+
+fn default_homepage_sections() -> Vec<String> {
+    vec![
+        "Continue Series".to_owned(),
+        "Franchises".to_owned(),
+        "Collections".to_owned(),
+    ]
+}
+
+fn default_missing_content_retention() -> u64 {
+    30
+}
+
+fn default_ignored_patterns() -> Vec<String> {
+    [
+        "sample.*",
+        "*-sample.*",
+        "*.sample.*",
+        ".DS_Store",
+        "._*",
+        "*.part",
+        "*.partial",
+        "*.crdownload",
+        "*.!qb",
+        "*.!ut",
+        "*.tmp",
+        "*/sample/*",
+        "*/samples/*",
+    ]
+    .map(str::to_owned)
+    .to_vec()
+}
+
+fn default_extras_folders() -> Vec<String> {
+    [
+        "Extras",
+        "Featurettes",
+        "Behind The Scenes",
+        "Deleted Scenes",
+        "Trailers",
+    ]
+    .map(str::to_owned)
+    .to_vec()
+}
+
+/// What every user created through the settings page starts out with, referenced by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct NewUserDefaults {
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -27,12 +155,34 @@ pub struct AdminCredentials {
     password: String,
 }
 
+/// Empty means unset, a trailing slash would double up with the routes appended to it
+fn normalized_url(url: Option<String>) -> Option<String> {
+    url.map(|url| url.trim().trim_end_matches('/').to_owned())
+        .filter(|url| !url.is_empty())
+}
+
 impl Default for ConfigFile {
     fn default() -> Self {
         Self {
             port: 3000,
             index_wait: 300.,
-            admin: AdminCredentials::default(),
+            admin: Some(AdminCredentials::default()),
+            skip_episode_gaps: default_skip_episode_gaps(),
+            tmdb_api_key: None,
+            session_idle_timeout: default_session_idle_timeout(),
+            session_reconnect_grace: default_session_reconnect_grace(),
+            watched_threshold: default_watched_threshold(),
+            extras_folders: default_extras_folders(),
+            homepage_sections: default_homepage_sections(),
+            allowed_location_roots: Vec::new(),
+            ignored_patterns: default_ignored_patterns(),
+            missing_content_retention: default_missing_content_retention(),
+            new_users: NewUserDefaults::default(),
+            custom_css: None,
+            media_token_secret: generate_secret(),
+            public_url: None,
+            cors_allowed_origins: Vec::new(),
+            content_types: BTreeMap::new(),
         }
     }
 }
@@ -50,7 +200,29 @@ impl Default for AdminCredentials {
 pub struct ServerSettings {
     port: (Arc<Sender<u16>>, Receiver<u16>),
     index_wait: (Arc<Sender<f64>>, Receiver<f64>),
-    admin: (Arc<Sender<AdminCredentials>>, Receiver<AdminCredentials>),
+    admin: (
+        Arc<Sender<Option<AdminCredentials>>>,
+        Receiver<Option<AdminCredentials>>,
+    ),
+    skip_episode_gaps: (Arc<Sender<bool>>, Receiver<bool>),
+    tmdb_api_key: (Arc<Sender<Option<String>>>, Receiver<Option<String>>),
+    session_idle_timeout: (Arc<Sender<u64>>, Receiver<u64>),
+    session_reconnect_grace: (Arc<Sender<u64>>, Receiver<u64>),
+    watched_threshold: (Arc<Sender<f64>>, Receiver<f64>),
+    extras_folders: (Arc<Sender<Vec<String>>>, Receiver<Vec<String>>),
+    homepage_sections: (Arc<Sender<Vec<String>>>, Receiver<Vec<String>>),
+    allowed_location_roots: (Arc<Sender<Vec<String>>>, Receiver<Vec<String>>),
+    ignored_patterns: (Arc<Sender<Vec<String>>>, Receiver<Vec<String>>),
+    missing_content_retention: (Arc<Sender<u64>>, Receiver<u64>),
+    new_users: (Arc<Sender<NewUserDefaults>>, Receiver<NewUserDefaults>),
+    custom_css: (Arc<Sender<Option<String>>>, Receiver<Option<String>>),
+    media_token_secret: (Arc<Sender<String>>, Receiver<String>),
+    public_url: (Arc<Sender<Option<String>>>, Receiver<Option<String>>),
+    cors_allowed_origins: (Arc<Sender<Vec<String>>>, Receiver<Vec<String>>),
+    content_types: (
+        Arc<Sender<BTreeMap<String, String>>>,
+        Receiver<BTreeMap<String, String>>,
+    ),
 }
 
 impl ServerSettings {
@@ -65,8 +237,29 @@ impl ServerSettings {
                 .log_err_with_msg("Failed to parse config file, using the default config instead")
                 .unwrap_or_default();
 
+            let mut changed = false;
+
             if let Some(port) = port {
                 config.port = port;
+                changed = true;
+            }
+
+            if !is_valid_watched_threshold(config.watched_threshold) {
+                warn!(
+                    "The watched_threshold {} has to be above 0.5 and at most 1, using the default instead",
+                    config.watched_threshold
+                );
+                config.watched_threshold = default_watched_threshold();
+                changed = true;
+            }
+
+            // Config files from before the secret existed get one, it has to stay the same across restarts
+            if config.media_token_secret.is_empty() {
+                config.media_token_secret = generate_secret();
+                changed = true;
+            }
+
+            if changed {
                 Self::write_config_file(&config).await;
             }
 
@@ -82,15 +275,7 @@ impl ServerSettings {
             default
         };
 
-        let (port, port_recv) = watch::channel(config.port);
-        let (index_wait, index_wait_recv) = watch::channel(config.index_wait);
-        let (admin, admin_recv) = watch::channel(config.admin.clone());
-
-        let data = Self {
-            port: (Arc::new(port), port_recv),
-            index_wait: (Arc::new(index_wait), index_wait_recv),
-            admin: (Arc::new(admin), admin_recv),
-        };
+        let data = Self::from_config(&config);
 
         {
             let mut last_admin = data.admin();
@@ -107,14 +292,141 @@ impl ServerSettings {
         data
     }
 
+    /// Only holds the values, reading and writing the config file is up to the caller
+    fn from_config(config: &ConfigFile) -> Self {
+        let (port, port_recv) = watch::channel(config.port);
+        let (index_wait, index_wait_recv) = watch::channel(config.index_wait);
+        let (admin, admin_recv) = watch::channel(config.admin.clone());
+        let (skip_episode_gaps, skip_episode_gaps_recv) = watch::channel(config.skip_episode_gaps);
+        let (tmdb_api_key, tmdb_api_key_recv) = watch::channel(config.tmdb_api_key.clone());
+        let (session_idle_timeout, session_idle_timeout_recv) =
+            watch::channel(config.session_idle_timeout);
+        let (session_reconnect_grace, session_reconnect_grace_recv) =
+            watch::channel(config.session_reconnect_grace);
+        let (watched_threshold, watched_threshold_recv) = watch::channel(config.watched_threshold);
+        let (extras_folders, extras_folders_recv) = watch::channel(config.extras_folders.clone());
+        let (homepage_sections, homepage_sections_recv) =
+            watch::channel(config.homepage_sections.clone());
+        let (allowed_location_roots, allowed_location_roots_recv) =
+            watch::channel(config.allowed_location_roots.clone());
+        let (ignored_patterns, ignored_patterns_recv) =
+            watch::channel(config.ignored_patterns.clone());
+        let (missing_content_retention, missing_content_retention_recv) =
+            watch::channel(config.missing_content_retention);
+        let (new_users, new_users_recv) = watch::channel(config.new_users.clone());
+        let (custom_css, custom_css_recv) = watch::channel(config.custom_css.clone());
+        let (media_token_secret, media_token_secret_recv) =
+            watch::channel(config.media_token_secret.clone());
+        let (public_url, public_url_recv) =
+            watch::channel(normalized_url(config.public_url.clone()));
+        let (cors_allowed_origins, cors_allowed_origins_recv) =
+            watch::channel(config.cors_allowed_origins.clone());
+        let (content_types, content_types_recv) = watch::channel(config.content_types.clone());
+
+        Self {
+            port: (Arc::new(port), port_recv),
+            index_wait: (Arc::new(index_wait), index_wait_recv),
+            admin: (Arc::new(admin), admin_recv),
+            skip_episode_gaps: (Arc::new(skip_episode_gaps), skip_episode_gaps_recv),
+            tmdb_api_key: (Arc::new(tmdb_api_key), tmdb_api_key_recv),
+            session_idle_timeout: (Arc::new(session_idle_timeout), session_idle_timeout_recv),
+            session_reconnect_grace: (
+                Arc::new(session_reconnect_grace),
+                session_reconnect_grace_recv,
+            ),
+            watched_threshold: (Arc::new(watched_threshold), watched_threshold_recv),
+            extras_folders: (Arc::new(extras_folders), extras_folders_recv),
+            homepage_sections: (Arc::new(homepage_sections), homepage_sections_recv),
+            allowed_location_roots: (
+                Arc::new(allowed_location_roots),
+                allowed_location_roots_recv,
+            ),
+            ignored_patterns: (Arc::new(ignored_patterns), ignored_patterns_recv),
+            missing_content_retention: (
+                Arc::new(missing_content_retention),
+                missing_content_retention_recv,
+            ),
+            new_users: (Arc::new(new_users), new_users_recv),
+            custom_css: (Arc::new(custom_css), custom_css_recv),
+            media_token_secret: (Arc::new(media_token_secret), media_token_secret_recv),
+            public_url: (Arc::new(public_url), public_url_recv),
+            cors_allowed_origins: (Arc::new(cors_allowed_origins), cors_allowed_origins_recv),
+            content_types: (Arc::new(content_types), content_types_recv),
+        }
+    }
+
+    /// The default settings without a config file, nothing is ever written to disk
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self::from_config(&ConfigFile::default())
+    }
+
+    /// The config file for commands that run without the server
+    /// Falls back to the defaults when there is no usable config file, but never writes one
+    async fn read_config_file() -> ConfigFile {
+        tokio::fs::read_to_string(Self::PATH)
+            .await
+            .ok()
+            .and_then(|config_file| {
+                toml::from_str::<ConfigFile>(&config_file)
+                    .log_warn_with_msg("Failed to parse config file, using the defaults instead")
+            })
+            .unwrap_or_default()
+    }
+
+    /// The extras folders and ignored patterns from the config file, for commands that run without the server
+    pub async fn indexing_rules() -> (Vec<String>, Vec<String>) {
+        let config = Self::read_config_file().await;
+        (config.extras_folders, config.ignored_patterns)
+    }
+
+    /// The name of the admin and the defaults for new users from the config file, for commands that run without the server
+    pub async fn user_rules() -> (Option<String>, NewUserDefaults) {
+        let config = Self::read_config_file().await;
+        let admin = config.admin.map(|admin| admin.username.trim().to_owned());
+        (admin, config.new_users)
+    }
+
     fn create_config(&self) -> ConfigFile {
         let port = self.port();
         let index_wait = self.index_wait();
         let admin = self.admin();
+        let skip_episode_gaps = self.skip_episode_gaps();
+        let tmdb_api_key = self.tmdb_api_key();
+        let session_idle_timeout = self.session_idle_timeout();
+        let session_reconnect_grace = self.session_reconnect_grace();
+        let watched_threshold = self.watched_threshold();
+        let extras_folders = self.extras_folders();
+        let homepage_sections = self.homepage_sections();
+        let allowed_location_roots = self.allowed_location_roots();
+        let ignored_patterns = self.ignored_patterns();
+        let missing_content_retention = self.missing_content_retention();
+        let new_users = self.new_users();
+        let custom_css = self.custom_css();
+        let media_token_secret = self.media_token_secret();
+        let public_url = self.public_url();
+        let cors_allowed_origins = self.cors_allowed_origins();
+        let content_types = self.content_types();
         ConfigFile {
             port,
             index_wait,
             admin,
+            skip_episode_gaps,
+            tmdb_api_key,
+            session_idle_timeout,
+            session_reconnect_grace,
+            watched_threshold,
+            extras_folders,
+            homepage_sections,
+            allowed_location_roots,
+            ignored_patterns,
+            missing_content_retention,
+            new_users,
+            custom_css,
+            media_token_secret,
+            public_url,
+            cors_allowed_origins,
+            content_types,
         }
     }
 
@@ -205,6 +517,22 @@ impl ServerSettings {
             _ = self.port.1.changed() => {},
             _ = self.index_wait.1.changed() => {},
             _ = self.admin.1.changed() => {},
+            _ = self.skip_episode_gaps.1.changed() => {},
+            _ = self.tmdb_api_key.1.changed() => {},
+            _ = self.session_idle_timeout.1.changed() => {},
+            _ = self.session_reconnect_grace.1.changed() => {},
+            _ = self.watched_threshold.1.changed() => {},
+            _ = self.extras_folders.1.changed() => {},
+            _ = self.homepage_sections.1.changed() => {},
+            _ = self.allowed_location_roots.1.changed() => {},
+            _ = self.ignored_patterns.1.changed() => {},
+            _ = self.missing_content_retention.1.changed() => {},
+            _ = self.new_users.1.changed() => {},
+            _ = self.custom_css.1.changed() => {},
+            _ = self.media_token_secret.1.changed() => {},
+            _ = self.public_url.1.changed() => {},
+            _ = self.cors_allowed_origins.1.changed() => {},
+            _ = self.content_types.1.changed() => {},
         }
     }
 
@@ -246,69 +574,56 @@ impl ServerSettings {
         .expect("failed to resolve once modified, this should never happen")
     }
 
+    /// Waits until the configured time has passed since this was called
+    /// Changing the time while waiting counts the time already waited towards the new one, so it takes effect in both directions
     pub async fn wait_configured_time(&self) {
+        let start = tokio::time::Instant::now();
         let mut recv = self.index_wait.0.subscribe();
-        tokio::select! {
-            _ = recv.changed() => {
-                info!("changed indexing waiting time to {} seconds and started indexing again", *recv.borrow());
-            },
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs_f64(self.index_wait())) => {},
+
+        loop {
+            let wait = *recv.borrow_and_update();
+            let deadline = start + tokio::time::Duration::from_secs_f64(wait);
+
+            tokio::select! {
+                changed = recv.changed() => {
+                    if changed.is_err() {
+                        tokio::time::sleep_until(deadline).await;
+                        return;
+                    }
+                    info!("changed indexing waiting time to {} seconds", *recv.borrow());
+                },
+                _ = tokio::time::sleep_until(deadline) => return,
+            }
         }
     }
 
     async fn update_db_to_file_content(
         &self,
         db: &Database,
-        last_admin: &mut AdminCredentials,
+        last_admin: &mut Option<AdminCredentials>,
     ) -> AppResult<()> {
-        let conn = db.get()?;
-
-        let users_is_empty = conn
-            .query_row_get("SELECT COUNT(*) FROM users", [])
-            .map(|count: i64| count == 0)
-            .unwrap_or(false);
-
         let admin = self.admin();
-        let (username, pw) = (admin.username.clone(), admin.password.clone());
-        let password = tokio::task::spawn_blocking(|| password_auth::generate_hash(pw))
-            .await
-            .expect("generating the password shouldn't fail");
-
-        let (last_username, pw) = (last_admin.username.clone(), last_admin.password.clone());
-        let last_password = tokio::task::spawn_blocking(|| password_auth::generate_hash(pw))
-            .await
-            .expect("generating the password shouldn't fail");
-
-        if (&username, &password) == (&last_username, &last_password) && !users_is_empty {
-            return Ok(());
-        }
-        *last_admin = admin;
-
-        let owner_permission_id =
-            conn.query_row_get::<u32>("SELECT id FROM permissions WHERE name = 'owner'", [])?;
-
-        let mut stmt =
-            conn.prepare("SELECT userid FROM user_permissions WHERE permissionid = ?1")?;
-        let user_ids_with_perm = stmt
-            .query_map_get::<u32>([owner_permission_id])?
-            .map(|v| v.unwrap())
-            .collect::<Vec<_>>();
-
-        for user_id in user_ids_with_perm {
-            conn.execute("DELETE FROM user_permissions WHERE userid = ?1", [user_id])?;
-            conn.execute("DELETE FROM users WHERE id = ?1", [user_id])?;
+        if let Some(admin) = &admin {
+            let username = validate_credentials(CredentialKind::Username, &admin.username);
+            let password = validate_credentials(CredentialKind::Password, &admin.password);
+            if let Err(msg) = username.and(password) {
+                bail!(format!(
+                    "The admin credentials in the config file are invalid: {msg}"
+                ));
+            }
         }
 
-        let user_id = conn.query_row_get::<u32>(
-            "INSERT INTO users (username, password) VALUES (?1, ?2) RETURNING id",
-            [username, password],
-        )?;
-
-        conn.execute(
-            "INSERT INTO user_permissions (userid, permissionid) VALUES (?1, ?2)",
-            [user_id, owner_permission_id],
-        )?;
+        let previous = last_admin
+            .as_ref()
+            .map(|previous| previous.username.trim().to_owned());
+        let (conn, new_admin, defaults) = (db.get()?, admin.clone(), self.new_users());
+        tokio::task::spawn_blocking(move || {
+            sync_admin(&conn, previous.as_deref(), new_admin.as_ref(), &defaults)
+        })
+        .await
+        .expect("updating the admin account shouldn't panic")?;
 
+        *last_admin = admin;
         Ok(())
     }
 
@@ -341,11 +656,11 @@ impl ServerSettings {
         });
     }
 
-    pub fn admin(&self) -> AdminCredentials {
+    pub fn admin(&self) -> Option<AdminCredentials> {
         self.admin.1.borrow().clone()
     }
 
-    pub fn set_admin(&self, admin: AdminCredentials) {
+    pub fn set_admin(&self, admin: Option<AdminCredentials>) {
         self.admin.0.send_if_modified(|current| {
             let is_different = *current != admin;
             if is_different {
@@ -355,20 +670,294 @@ impl ServerSettings {
         });
     }
 
+    /// Does nothing when there is no admin in the config
     pub fn update_admin_username(&self, username: &str) {
-        let pw = self.admin().password;
-        self.set_admin(AdminCredentials {
+        let Some(admin) = self.admin() else {
+            return;
+        };
+        self.set_admin(Some(AdminCredentials {
             username: username.to_owned(),
-            password: pw,
-        })
+            password: admin.password,
+        }))
     }
 
+    /// Does nothing when there is no admin in the config
     pub fn update_admin_password(&self, password: &str) {
-        let username = self.admin().username;
-        self.set_admin(AdminCredentials {
-            username,
+        let Some(admin) = self.admin() else {
+            return;
+        };
+        self.set_admin(Some(AdminCredentials {
+            username: admin.username,
             password: password.to_owned(),
-        })
+        }))
+    }
+
+    /// Whether this user's credentials are managed through the config file
+    pub fn is_config_admin(&self, username: &str) -> bool {
+        self.admin().is_some_and(|admin| admin.username == username)
+    }
+
+    pub fn skip_episode_gaps(&self) -> bool {
+        *self.skip_episode_gaps.1.borrow()
+    }
+
+    pub fn set_skip_episode_gaps(&self, skip: bool) {
+        self.skip_episode_gaps.0.send_if_modified(|current| {
+            let is_different = *current != skip;
+            if is_different {
+                *current = skip;
+            }
+            is_different
+        });
+    }
+
+    pub fn tmdb_api_key(&self) -> Option<String> {
+        self.tmdb_api_key.1.borrow().clone()
+    }
+
+    pub fn set_tmdb_api_key(&self, key: Option<String>) {
+        self.tmdb_api_key.0.send_if_modified(|current| {
+            let is_different = *current != key;
+            if is_different {
+                *current = key;
+            }
+            is_different
+        });
+    }
+
+    /// Minutes without activity after which a session is closed, 0 means sessions never time out
+    pub fn session_idle_timeout(&self) -> u64 {
+        *self.session_idle_timeout.1.borrow()
+    }
+
+    pub fn set_session_idle_timeout(&self, minutes: u64) {
+        self.session_idle_timeout.0.send_if_modified(|current| {
+            let is_different = *current != minutes;
+            if is_different {
+                *current = minutes;
+            }
+            is_different
+        });
+    }
+
+    /// Seconds an empty session waits for someone to reconnect before it is removed
+    pub fn session_reconnect_grace(&self) -> u64 {
+        *self.session_reconnect_grace.1.borrow()
+    }
+
+    pub fn set_session_reconnect_grace(&self, seconds: u64) {
+        self.session_reconnect_grace.0.send_if_modified(|current| {
+            let is_different = *current != seconds;
+            if is_different {
+                *current = seconds;
+            }
+            is_different
+        });
+    }
+
+    /// The fraction of a video after which it counts as watched and the next thing is recommended
+    pub fn watched_threshold(&self) -> f64 {
+        *self.watched_threshold.1.borrow()
+    }
+
+    pub fn set_watched_threshold(&self, threshold: f64) {
+        if !is_valid_watched_threshold(threshold) {
+            warn!(
+                "Ignoring the watched_threshold {threshold}, it has to be above 0.5 and at most 1"
+            );
+            return;
+        }
+        self.watched_threshold.0.send_if_modified(|current| {
+            let is_different = (*current - threshold).abs() > f64::EPSILON;
+            if is_different {
+                *current = threshold;
+            }
+            is_different
+        });
+    }
+
+    pub fn extras_folders(&self) -> Vec<String> {
+        self.extras_folders.1.borrow().clone()
+    }
+
+    pub fn set_extras_folders(&self, folders: Vec<String>) {
+        self.extras_folders.0.send_if_modified(|current| {
+            let is_different = *current != folders;
+            if is_different {
+                *current = folders;
+            }
+            is_different
+        });
+    }
+
+    pub fn homepage_sections(&self) -> Vec<String> {
+        self.homepage_sections.1.borrow().clone()
+    }
+
+    pub fn set_homepage_sections(&self, sections: Vec<String>) {
+        self.homepage_sections.0.send_if_modified(|current| {
+            let is_different = *current != sections;
+            if is_different {
+                *current = sections;
+            }
+            is_different
+        });
+    }
+
+    pub fn allowed_location_roots(&self) -> Vec<String> {
+        self.allowed_location_roots.1.borrow().clone()
+    }
+
+    pub fn set_allowed_location_roots(&self, roots: Vec<String>) {
+        self.allowed_location_roots.0.send_if_modified(|current| {
+            let is_different = *current != roots;
+            if is_different {
+                *current = roots;
+            }
+            is_different
+        });
+    }
+
+    pub fn ignored_patterns(&self) -> Vec<String> {
+        self.ignored_patterns.1.borrow().clone()
+    }
+
+    pub fn set_ignored_patterns(&self, patterns: Vec<String>) {
+        self.ignored_patterns.0.send_if_modified(|current| {
+            let is_different = *current != patterns;
+            if is_different {
+                *current = patterns;
+            }
+            is_different
+        });
+    }
+
+    /// Days missing content is kept before indexing removes it, 0 means it is never removed
+    pub fn missing_content_retention(&self) -> u64 {
+        *self.missing_content_retention.1.borrow()
+    }
+
+    pub fn set_missing_content_retention(&self, days: u64) {
+        self.missing_content_retention
+            .0
+            .send_if_modified(|current| {
+                let is_different = *current != days;
+                if is_different {
+                    *current = days;
+                }
+                is_different
+            });
+    }
+
+    pub fn new_users(&self) -> NewUserDefaults {
+        self.new_users.1.borrow().clone()
+    }
+
+    pub fn set_new_users(&self, defaults: NewUserDefaults) {
+        self.new_users.0.send_if_modified(|current| {
+            let is_different = *current != defaults;
+            if is_different {
+                *current = defaults;
+            }
+            is_different
+        });
+    }
+
+    /// The path of the custom stylesheet
+    pub fn custom_css(&self) -> Option<String> {
+        self.custom_css.1.borrow().clone()
+    }
+
+    pub fn set_custom_css(&self, path: Option<String>) {
+        self.custom_css.0.send_if_modified(|current| {
+            let is_different = *current != path;
+            if is_different {
+                *current = path;
+            }
+            is_different
+        });
+    }
+
+    pub fn media_token_secret(&self) -> String {
+        self.media_token_secret.1.borrow().clone()
+    }
+
+    /// An empty secret would make every link trivial to forge, so those are ignored
+    pub fn set_media_token_secret(&self, secret: String) {
+        if secret.is_empty() {
+            warn!("Ignoring the empty media_token_secret, keeping the previous one");
+            return;
+        }
+        self.media_token_secret.0.send_if_modified(|current| {
+            let is_different = *current != secret;
+            if is_different {
+                *current = secret;
+            }
+            is_different
+        });
+    }
+
+    /// Without a trailing slash, so routes can be appended
+    pub fn public_url(&self) -> Option<String> {
+        self.public_url.1.borrow().clone()
+    }
+
+    pub fn set_public_url(&self, url: Option<String>) {
+        let url = normalized_url(url);
+        self.public_url.0.send_if_modified(|current| {
+            let is_different = *current != url;
+            if is_different {
+                *current = url;
+            }
+            is_different
+        });
+    }
+
+    pub fn cors_allowed_origins(&self) -> Vec<String> {
+        self.cors_allowed_origins.1.borrow().clone()
+    }
+
+    /// Whether a page from this origin may use the JSON API, checked on every request so changes apply immediately
+    pub fn cors_allows(&self, origin: &str) -> bool {
+        self.cors_allowed_origins
+            .1
+            .borrow()
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+
+    pub fn set_cors_allowed_origins(&self, origins: Vec<String>) {
+        self.cors_allowed_origins.0.send_if_modified(|current| {
+            let is_different = *current != origins;
+            if is_different {
+                *current = origins;
+            }
+            is_different
+        });
+    }
+
+    pub fn content_types(&self) -> BTreeMap<String, String> {
+        self.content_types.1.borrow().clone()
+    }
+
+    /// The configured content type for files with this extension, compared case insensitively
+    pub fn content_type(&self, extension: &str) -> Option<String> {
+        self.content_types
+            .1
+            .borrow()
+            .iter()
+            .find(|(configured, _)| configured.eq_ignore_ascii_case(extension))
+            .map(|(_, content_type)| content_type.clone())
+    }
+
+    pub fn set_content_types(&self, content_types: BTreeMap<String, String>) {
+        self.content_types.0.send_if_modified(|current| {
+            let is_different = *current != content_types;
+            if is_different {
+                *current = content_types;
+            }
+            is_different
+        });
     }
 
     pub fn set_all(&self, config: ConfigFile) {
@@ -376,5 +965,286 @@ impl ServerSettings {
         self.set_port(port);
         self.set_index_wait(wait);
         self.set_admin(admin);
+        self.set_skip_episode_gaps(config.skip_episode_gaps);
+        self.set_tmdb_api_key(config.tmdb_api_key);
+        self.set_session_idle_timeout(config.session_idle_timeout);
+        self.set_session_reconnect_grace(config.session_reconnect_grace);
+        self.set_watched_threshold(config.watched_threshold);
+        self.set_extras_folders(config.extras_folders);
+        self.set_homepage_sections(config.homepage_sections);
+        self.set_allowed_location_roots(config.allowed_location_roots);
+        self.set_ignored_patterns(config.ignored_patterns);
+        self.set_missing_content_retention(config.missing_content_retention);
+        self.set_new_users(config.new_users);
+        self.set_custom_css(config.custom_css);
+        self.set_media_token_secret(config.media_token_secret);
+        self.set_public_url(config.public_url);
+        self.set_cors_allowed_origins(config.cors_allowed_origins);
+        self.set_content_types(config.content_types);
+    }
+}
+
+/// Makes sure the admin from the config file can log in as an owner
+/// Only the accounts with the previous and the current name of the admin are changed, other owners stay as they are
+fn sync_admin(
+    conn: &rusqlite::Connection,
+    previous: Option<&str>,
+    admin: Option<&AdminCredentials>,
+    defaults: &NewUserDefaults,
+) -> AppResult<()> {
+    let username = admin.map(|admin| admin.username.trim());
+
+    if let Some(previous) = previous.filter(|&previous| Some(previous) != username) {
+        // Renaming keeps everything that belongs to the account, unless the new name is already taken
+        let renamed = match username {
+            Some(username) => {
+                conn.execute(
+                    "UPDATE users SET username = ?2 WHERE username = ?1
+                        AND NOT exists(SELECT 1 FROM users WHERE username = ?2)",
+                    [previous, username],
+                )? > 0
+            }
+            None => false,
+        };
+
+        let previous_id = conn
+            .query_row_get::<u64>("SELECT id FROM users WHERE username = ?1", [previous])
+            .optional()?;
+        if let Some(id) = previous_id.filter(|_| !renamed) {
+            conn.execute("DELETE FROM user_preferences WHERE user_id = ?1", [id])?;
+            conn.execute("DELETE FROM user_permissions WHERE userid = ?1", [id])?;
+            conn.execute("DELETE FROM user_groups WHERE userid = ?1", [id])?;
+            conn.execute("DELETE FROM users WHERE id = ?1", [id])?;
+            info!("Removed \"{previous}\", it is no longer the admin in the config file");
+        }
+    }
+
+    let (Some(admin), Some(username)) = (admin, username) else {
+        return Ok(());
+    };
+
+    let existing = conn
+        .query_row_into::<(u64, String)>(
+            "SELECT id, password FROM users WHERE username = ?1",
+            [username],
+        )
+        .optional()?;
+
+    let user_id = match existing {
+        // Hashes are salted, so only verifying tells whether the password is still the same
+        Some((id, hash)) => {
+            if password_auth::verify_password(&admin.password, &hash).is_err() {
+                conn.execute(
+                    "UPDATE users SET password = ?2 WHERE id = ?1",
+                    params![id, password_auth::generate_hash(&admin.password)],
+                )?;
+            }
+            id
+        }
+        None => create_user(
+            conn,
+            username,
+            &password_auth::generate_hash(&admin.password),
+            defaults,
+        )?,
+    };
+
+    conn.execute(
+        "INSERT OR IGNORE INTO user_permissions (userid, permissionid)
+            SELECT ?1, id FROM permissions WHERE name = 'owner'",
+        [user_id],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::*;
+    use crate::{database::in_memory, indexing::scan_summary, utils::TempDir};
+
+    fn admin(username: &str, password: &str) -> AdminCredentials {
+        AdminCredentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    fn password_of(conn: &rusqlite::Connection, username: &str) -> Option<String> {
+        conn.query_row_get("SELECT password FROM users WHERE username = ?1", [username])
+            .optional()
+            .unwrap()
+    }
+
+    fn is_owner(conn: &rusqlite::Connection, username: &str) -> bool {
+        conn.query_row_get(
+            "SELECT exists(SELECT 1 FROM users, user_permissions, permissions
+                WHERE users.username = ?1 AND user_permissions.userid = users.id
+                AND user_permissions.permissionid = permissions.id AND permissions.name = 'owner')",
+            [username],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn other_owners_are_kept() {
+        let conn = in_memory();
+        let defaults = NewUserDefaults::default();
+        let id = create_user(&conn, "provisioned", "hash", &defaults).unwrap();
+        conn.execute(
+            "INSERT INTO user_permissions (userid, permissionid)
+                SELECT ?1, id FROM permissions WHERE name = 'owner'",
+            [id],
+        )
+        .unwrap();
+
+        let admin = admin("admin", "admin");
+        sync_admin(&conn, None, Some(&admin), &defaults).unwrap();
+        sync_admin(&conn, Some("admin"), Some(&admin), &defaults).unwrap();
+
+        assert!(is_owner(&conn, "provisioned"));
+        assert!(is_owner(&conn, "admin"));
+    }
+
+    #[test]
+    fn the_password_is_only_replaced_when_it_changed() {
+        let conn = in_memory();
+        let defaults = NewUserDefaults::default();
+
+        sync_admin(&conn, None, Some(&admin("admin", "admin")), &defaults).unwrap();
+        let hash = password_of(&conn, "admin").unwrap();
+        sync_admin(
+            &conn,
+            Some("admin"),
+            Some(&admin("admin", "admin")),
+            &defaults,
+        )
+        .unwrap();
+        assert_eq!(password_of(&conn, "admin").unwrap(), hash);
+
+        sync_admin(
+            &conn,
+            Some("admin"),
+            Some(&admin("admin", "secret")),
+            &defaults,
+        )
+        .unwrap();
+        let changed = password_of(&conn, "admin").unwrap();
+        assert!(password_auth::verify_password("secret", &changed).is_ok());
+    }
+
+    #[test]
+    fn a_renamed_admin_keeps_the_account() {
+        let conn = in_memory();
+        let defaults = NewUserDefaults::default();
+        let id_of = |username: &str| {
+            conn.query_row_get::<u64>("SELECT id FROM users WHERE username = ?1", [username])
+                .optional()
+                .unwrap()
+        };
+
+        sync_admin(&conn, None, Some(&admin("admin", "admin")), &defaults).unwrap();
+        let id = id_of("admin");
+        sync_admin(
+            &conn,
+            Some("admin"),
+            Some(&admin(" root ", "admin")),
+            &defaults,
+        )
+        .unwrap();
+
+        assert_eq!(id_of("admin"), None);
+        assert_eq!(id_of("root"), id);
+        assert!(is_owner(&conn, "root"));
+    }
+
+    #[test]
+    fn the_previous_admin_is_removed_when_the_new_name_is_taken() {
+        let conn = in_memory();
+        let defaults = NewUserDefaults::default();
+        create_user(&conn, "root", "hash", &defaults).unwrap();
+
+        sync_admin(&conn, None, Some(&admin("admin", "admin")), &defaults).unwrap();
+        sync_admin(
+            &conn,
+            Some("admin"),
+            Some(&admin("root", "admin")),
+            &defaults,
+        )
+        .unwrap();
+        assert_eq!(password_of(&conn, "admin"), None);
+        assert!(is_owner(&conn, "root"));
+
+        // Without an admin in the config file the last one is removed as well
+        sync_admin(&conn, Some("root"), None, &defaults).unwrap();
+        assert_eq!(password_of(&conn, "root"), None);
+    }
+
+    #[test]
+    fn partial_downloads_and_samples_are_ignored_by_default() {
+        let dir = TempDir::new("default-ignored");
+        for ignored in [
+            "Heat/Heat.mkv.part",
+            "Heat/Heat.mkv.!ut",
+            "Heat/Heat.mkv.crdownload",
+            "Heat/Sample/Heat.mkv",
+            "Heat/heat-sample.mkv",
+        ] {
+            dir.file(ignored);
+        }
+        dir.file("Heat/Heat.mkv");
+        dir.file("Samples of Life/Samples of Life.mkv");
+
+        let summary = scan_summary(dir.path(), true, &default_ignored_patterns()).unwrap();
+        assert_eq!((summary.files, summary.ignored), (2, 5));
+    }
+
+    /// Waits with the given time, changes it to the new one after a while and returns how long the wait took in the end
+    async fn wait_changed(wait: f64, changed_after: u64, new_wait: f64) -> Duration {
+        let settings = ServerSettings::in_memory();
+        settings.set_index_wait(wait);
+
+        let start = tokio::time::Instant::now();
+        let waiting = tokio::spawn({
+            let settings = settings.clone();
+            async move { settings.wait_configured_time().await }
+        });
+
+        tokio::time::sleep(Duration::from_secs(changed_after)).await;
+        settings.set_index_wait(new_wait);
+        waiting.await.unwrap();
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shorter_waits_take_effect_while_waiting() {
+        assert_eq!(wait_changed(300., 10, 30.).await, Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn longer_waits_take_effect_while_waiting() {
+        assert_eq!(wait_changed(30., 10, 300.).await, Duration::from_secs(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_shorter_than_the_time_waited_end_right_away() {
+        assert_eq!(wait_changed(300., 100, 30.).await, Duration::from_secs(100));
+    }
+
+    #[test]
+    fn watched_thresholds_have_to_be_past_the_middle() {
+        let settings = ServerSettings::in_memory();
+        assert_eq!(settings.watched_threshold(), 0.95);
+
+        for invalid in [0.5, 1.01, -1., f64::NAN] {
+            settings.set_watched_threshold(invalid);
+            assert_eq!(settings.watched_threshold(), 0.95);
+        }
+        for valid in [0.51, 1.] {
+            settings.set_watched_threshold(valid);
+            assert_eq!(settings.watched_threshold(), valid);
+        }
     }
 }
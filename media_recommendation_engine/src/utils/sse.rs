@@ -0,0 +1,56 @@
+use axum::{
+    http::{header::CONTENT_TYPE, HeaderValue},
+    response::{sse::Event, IntoResponse, Response, Sse},
+    BoxError,
+};
+use futures_util::Stream;
+
+/// Creates an event out of rendered html
+/// Carriage returns can't be transmitted over SSE, so all line breaks are normalized to '\n',
+/// which axum splits into multiple data fields that the client joins back together
+pub fn html_event(content: &str) -> Event {
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    Event::default().data(content)
+}
+
+/// Turns the stream into a response with the charset explicitly set, as the rendered html is always UTF-8
+pub fn utf8_sse<S, E>(sse: Sse<S>) -> Response
+where
+    S: Stream<Item = Result<Event, E>> + Send + 'static,
+    E: Into<BoxError>,
+{
+    let mut response = sse.into_response();
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/event-stream; charset=UTF-8"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::body::to_bytes;
+    use futures_util::stream;
+
+    use super::*;
+
+    /// The response body of a stream with only this event
+    async fn sent(event: Event) -> (Option<HeaderValue>, String) {
+        let response = utf8_sse(Sse::new(stream::once(async { Ok::<_, Infallible>(event) })));
+        let content_type = response.headers().get(CONTENT_TYPE).cloned();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn every_kind_of_line_break_becomes_its_own_data_line() {
+        let (content_type, body) = sent(html_event("<div>\r\n<p>Windows</p>\rMac\n</div>")).await;
+        assert_eq!(content_type.unwrap(), "text/event-stream; charset=UTF-8");
+        assert_eq!(
+            body,
+            "data: <div>\ndata: <p>Windows</p>\ndata: Mac\ndata: </div>\n\n"
+        );
+    }
+}
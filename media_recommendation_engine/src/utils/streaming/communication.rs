@@ -11,10 +11,11 @@ use futures_util::{
     SinkExt, StreamExt,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 use tracing::debug;
 
 use crate::{
+    recommendation::Step,
     state::{AppResult, Shutdown},
     utils::{auth::User, bail, templates::Notification as NotificationTemplate, HandleErr},
 };
@@ -36,8 +37,33 @@ pub enum WSSend {
         video_time: f32,
         state: SessionState,
     },
+    /// Html that is only sent to one specific user
+    Direct {
+        msg: String,
+        target: UserSessionID,
+    },
+    /// The popup near the end, not sent to users that turned it off in their preferences
+    Recommendation {
+        msg: String,
+    },
+    /// Someone turned the popup on or off for the whole session
+    Recommendations {
+        enabled: bool,
+    },
+    /// The last message before the session goes away, the connection ends once it was delivered
+    Closed {
+        msg: String,
+    },
     Reload,
     Join,
+    /// The current state of the session, only sent to the user that asked for it
+    Stats {
+        #[serde(skip)]
+        target: UserSessionID,
+        participants: Vec<String>,
+        state: SessionState,
+        video_time: f32,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +79,15 @@ pub enum WSReceive {
         id: u64,
     },
     Join,
+    Stats,
+    Recommendations {
+        enabled: bool,
+    },
+    /// Shows what comes next to only this user, even with recommendations turned off
+    WhatsNext,
+    /// Switches to the next episode, or to what is queued next
+    Next,
+    Previous,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,9 +117,27 @@ pub struct SessionChannel {
     pub to_websocket: broadcast::Sender<WSSend>,
     to_notification_limiter: mpsc::Sender<Notification>,
     pub has_switched: Arc<Notify>,
+    /// How many clients are currently receiving messages
+    connected: Arc<watch::Sender<usize>>,
     shutdown: Shutdown,
 }
 
+/// Counts a connection for as long as it is alive, even when its task gets aborted
+struct Connected(Arc<watch::Sender<usize>>);
+
+impl Connected {
+    fn new(counter: Arc<watch::Sender<usize>>) -> Self {
+        counter.send_modify(|connected| *connected += 1);
+        Self(counter)
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.0.send_modify(|connected| *connected -= 1);
+    }
+}
+
 impl SessionChannel {
     pub fn new(shutdown: Shutdown) -> Self {
         let (websocket_sender, _) = broadcast::channel(32);
@@ -94,6 +147,7 @@ impl SessionChannel {
             to_websocket: websocket_sender,
             to_notification_limiter: notification_sender,
             has_switched: Notify::new().into(),
+            connected: Arc::new(watch::channel(0).0),
             shutdown,
         };
 
@@ -109,6 +163,15 @@ impl SessionChannel {
             .log_err_with_msg("Failed to send message to websocket broadcast");
     }
 
+    /// Resolves once no client is connected anymore
+    pub async fn disconnected(&self) {
+        let _ = self
+            .connected
+            .subscribe()
+            .wait_for(|&connected| connected == 0)
+            .await;
+    }
+
     fn send_notification(&self, notification: &Notification) {
         let origin = notification.origin;
         let msg = notification
@@ -119,6 +182,14 @@ impl SessionChannel {
         self.send(WSSend::Notification { msg, origin });
     }
 
+    fn send_direct_notification(&self, msg: String, target: UserSessionID) {
+        let msg = NotificationTemplate { msg, script: "" }
+            .render()
+            .log_err_with_msg("Failed to render notification template, this should not happen")
+            .unwrap_or_default();
+        self.send(WSSend::Direct { msg, target });
+    }
+
     async fn send_text_notification(&self, msg: String, origin: UserSessionID) {
         self.to_notification_limiter
             .send(Notification {
@@ -199,6 +270,7 @@ impl SessionChannel {
         socket: WebSocket,
         user: &User,
         user_id: UserSessionID,
+        show_recommendations: bool,
     ) {
         let (mut sender, receiver) = socket.split();
 
@@ -230,7 +302,9 @@ impl SessionChannel {
 
         let channel = self.clone();
         let mut send_task = tokio::spawn(async move {
-            channel.send_session_to_clients(sender, user_id).await;
+            channel
+                .send_session_to_clients(sender, user_id, show_recommendations)
+                .await;
         });
 
         tokio::select! {
@@ -249,9 +323,25 @@ impl SessionChannel {
         self,
         mut client_sender: SplitSink<WebSocket, Message>,
         user_id: UserSessionID,
+        show_recommendations: bool,
     ) {
+        let _connected = Connected::new(self.connected.clone());
         let mut receiver = self.to_websocket.subscribe();
         while let Ok(msg) = receiver.recv().await {
+            if let WSSend::Closed { msg } = msg {
+                client_sender
+                    .send(Message::Text(msg))
+                    .await
+                    .log_err_with_msg("an error occured while sending a message to the client");
+                break;
+            }
+
+            if let WSSend::Stats { target, .. } = msg {
+                if target != user_id {
+                    continue;
+                }
+            }
+
             let msg = match msg {
                 WSSend::Notification { msg, origin } => {
                     if origin == user_id {
@@ -259,6 +349,18 @@ impl SessionChannel {
                     }
                     msg
                 }
+                WSSend::Direct { msg, target } => {
+                    if target != user_id {
+                        continue;
+                    }
+                    msg
+                }
+                WSSend::Recommendation { msg } => {
+                    if !show_recommendations {
+                        continue;
+                    }
+                    msg
+                }
                 _ => serde_json::to_string(&msg).unwrap(),
             };
 
@@ -368,15 +470,80 @@ impl SessionChannel {
                     .await;
                 self.send(WSSend::Join);
             }
-            WSReceive::SwitchTo { id } => {
-                session.reuse(id).await.log_err();
-
-                self.has_switched.notify_one();
+            WSReceive::SwitchTo { id } => self.switch_to(id, user_id, session, false).await?,
+            WSReceive::Stats => {
+                self.send(WSSend::Stats {
+                    target: user_id,
+                    participants: session.participant_names().await,
+                    state: session.get_state().await,
+                    video_time: session.get_current_video_time().await as f32,
+                });
+            }
+            WSReceive::Recommendations { enabled } => {
+                if session.recommendations_enabled() == enabled {
+                    return Ok(());
+                }
+                session.set_recommendations(enabled);
 
-                self.send(WSSend::Reload);
+                let username = &user.username;
+                let msg = if enabled {
+                    format!("{username} turned recommendations on")
+                } else {
+                    format!("{username} turned recommendations off")
+                };
+                self.send_text_notification(msg, user_id).await;
+                self.send(WSSend::Recommendations { enabled });
             }
+            WSReceive::WhatsNext => {
+                let msg = session.next_popup().await?;
+                self.send(WSSend::Direct {
+                    msg,
+                    target: user_id,
+                });
+            }
+            WSReceive::Next => match session.step(Step::Next).await? {
+                Some(id) => self.switch_to(id, user_id, session, false).await?,
+                None => {
+                    self.send_direct_notification("There is no next episode".to_owned(), user_id)
+                }
+            },
+            WSReceive::Previous => match session.step(Step::Previous).await? {
+                Some(id) => self.switch_to(id, user_id, session, true).await?,
+                None => self
+                    .send_direct_notification("There is no previous episode".to_owned(), user_id),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Everyone in the session reloads the player once the new content is ready
+    /// Stepping back keeps the queue going from the content that was playing, anything else moves it forward
+    async fn switch_to(
+        &self,
+        id: u64,
+        user_id: UserSessionID,
+        session: &Session,
+        back: bool,
+    ) -> AppResult<()> {
+        if !session.is_playable(id)? {
+            self.send_direct_notification(
+                "The requested content can't be played".to_owned(),
+                user_id,
+            );
+            return Ok(());
         }
 
+        if back {
+            session.requeue_current().await;
+        } else {
+            session.advance_queue(id).await;
+        }
+        session.reuse(id).await.log_err();
+
+        self.has_switched.notify_one();
+
+        self.send(WSSend::Reload);
         Ok(())
     }
 
@@ -1,3 +1,3 @@
 mod communication;
 mod session;
-pub use session::{Session, StreamingSessions};
+pub use session::{Session, SessionState, StreamingSessions};
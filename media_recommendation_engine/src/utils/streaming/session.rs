@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -14,21 +14,28 @@ use axum::{
     extract::{ws::WebSocket, Request},
     response::IntoResponse,
 };
-use futures_util::Future;
+use futures_util::{future::join_all, Future};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{watch, Mutex, Notify};
+use tokio::{
+    sync::{watch, Mutex, Notify},
+    time::Instant,
+};
 use tower::Service;
 use tower_http::services::ServeFile;
-use tracing::error;
+use tracing::{error, info};
 
 use crate::{
     database::{Database, QueryRowGetConnExt},
+    indexing::content_title,
+    recommendation::{
+        adjacent_episode, record_series_activity, record_series_watched, Recommender, Step,
+    },
     state::{AppResult, Shutdown},
     utils::{
         auth::User,
-        frontend_redirect, pseudo_random,
-        templates::{GridElement, RecommendationPopup},
-        ConvertErr, HXTarget, HandleErr,
+        frontend_redirect, pseudo_random, shows_recommendations,
+        templates::{Notification, RecommendationPopup, SessionElement},
+        HXTarget, HandleErr, ServerSettings,
     },
 };
 
@@ -40,18 +47,33 @@ pub enum SessionState {
     Paused,
 }
 
-pub type Sessions = Arc<Mutex<HashMap<u32, Arc<Session>>>>;
+/// Ordered by id, so the rendered session list doesn't reshuffle between rerenders
+pub type Sessions = Arc<Mutex<BTreeMap<u32, Arc<Session>>>>;
+
+/// How often sessions are checked for inactivity
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How long closing a session waits for its clients to receive the notification
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many invite codes a user can try within INVITE_ATTEMPT_WINDOW, so codes can't be guessed
+const INVITE_ATTEMPTS: usize = 10;
+const INVITE_ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+/// How many ffmpeg errors a session keeps, older ones are dropped
+const LOGGED_ERRORS: usize = 50;
 
 #[derive(Clone)]
 pub struct StreamingSessions {
     sessions: Sessions,
     rendered_sessions: (Arc<watch::Sender<String>>, watch::Receiver<String>),
     should_rerender: Arc<Notify>,
+    settings: ServerSettings,
+    recommender: Recommender,
+    /// When each user last tried invite codes, only the ones within INVITE_ATTEMPT_WINDOW are kept
+    invite_attempts: Arc<Mutex<HashMap<i64, VecDeque<Instant>>>>,
 }
 
 impl StreamingSessions {
-    pub fn new(shutdown: Shutdown) -> Self {
-        let sessions = Arc::new(Mutex::new(HashMap::new()));
+    pub fn new(shutdown: Shutdown, settings: ServerSettings, recommender: Recommender) -> Self {
+        let sessions = Arc::new(Mutex::new(BTreeMap::new()));
 
         let (sender, receiver) = watch::channel(String::new());
         let sender = Arc::new(sender);
@@ -62,6 +84,13 @@ impl StreamingSessions {
             notify.clone(),
             sender.clone(),
             sessions.clone(),
+            shutdown.clone(),
+        ));
+
+        tokio::task::spawn(Self::idle_task(
+            notify.clone(),
+            sessions.clone(),
+            settings.clone(),
             shutdown,
         ));
 
@@ -69,6 +98,9 @@ impl StreamingSessions {
             sessions,
             rendered_sessions: (sender, receiver),
             should_rerender: notify,
+            settings,
+            recommender,
+            invite_attempts: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -82,10 +114,50 @@ impl StreamingSessions {
         iter.into_iter()
     }
 
+    /// Every session that currently exists, ordered by id
+    pub async fn all(&self) -> impl Iterator<Item = (u32, Arc<Session>)> {
+        Self::get_sessions(&self.sessions).await
+    }
+
     pub async fn get(&self, id: &u32) -> Option<Arc<Session>> {
         self.sessions.lock().await.get(id).cloned()
     }
 
+    /// Codes stop working once their session is gone, the user is allowed into the session the code belongs to
+    pub async fn join_with_code(&self, user_id: i64, code: &str) -> Option<u32> {
+        let (id, session) = self
+            .sessions
+            .lock()
+            .await
+            .iter()
+            .find(|(_, session)| session.invite_code.eq_ignore_ascii_case(code.trim()))
+            .map(|(id, session)| (*id, session.clone()))?;
+        session.invite(user_id).await;
+        Some(id)
+    }
+
+    /// Counts an attempt at using an invite code, false once the user tried too many recently
+    pub async fn try_invite_attempt(&self, user_id: i64) -> bool {
+        let now = Instant::now();
+        let mut attempts = self.invite_attempts.lock().await;
+        attempts.retain(|_, recent| {
+            while recent
+                .front()
+                .is_some_and(|&attempt| now.duration_since(attempt) >= INVITE_ATTEMPT_WINDOW)
+            {
+                recent.pop_front();
+            }
+            !recent.is_empty()
+        });
+
+        let recent = attempts.entry(user_id).or_default();
+        if recent.len() >= INVITE_ATTEMPTS {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+
     pub async fn insert(&mut self, id: u32, session: Session) {
         if self
             .sessions
@@ -99,8 +171,29 @@ impl StreamingSessions {
         self.should_rerender.notify_one();
     }
 
-    pub async fn remove(&mut self, id: &u32) {
-        self.sessions.lock().await.remove(id);
+    /// Removes the session unless someone reconnects to it within the configured grace period
+    pub async fn remove_abandoned(&mut self, id: u32, session: &Arc<Session>, shutdown: &Shutdown) {
+        let grace = Duration::from_secs(self.settings.session_reconnect_grace());
+        tokio::select! {
+            _ = tokio::time::sleep(grace) => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        let mut sessions = self.sessions.lock().await;
+        // The id could belong to a different session by now if this one was closed in the meantime
+        if !sessions
+            .get(&id)
+            .is_some_and(|current| Arc::ptr_eq(current, session))
+        {
+            return;
+        }
+        if session.receiver_count().await > 0 {
+            return;
+        }
+
+        sessions.remove(&id);
+        // Nobody is left to tell about it, so the tasks of the session are just stopped
+        session.closed.close();
         self.should_rerender.notify_one();
     }
 
@@ -124,28 +217,93 @@ impl StreamingSessions {
         }
     }
 
+    /// Closes sessions that haven't seen any playback activity for the configured time
+    async fn idle_task(
+        rerender: Arc<Notify>,
+        sessions: Sessions,
+        settings: ServerSettings,
+        shutdown: Shutdown,
+    ) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(IDLE_CHECK_INTERVAL) => {}
+                _ = shutdown.cancelled() => {return;}
+            }
+
+            let minutes = settings.session_idle_timeout();
+            if minutes == 0 {
+                continue;
+            }
+            let timeout = Duration::from_secs(minutes * 60);
+
+            let mut idle = Vec::new();
+            for (id, session) in Self::get_sessions(&sessions).await {
+                if session.idle_for().await >= timeout {
+                    idle.push(id);
+                }
+            }
+
+            if idle.is_empty() {
+                continue;
+            }
+
+            let closed = {
+                let mut sessions = sessions.lock().await;
+                idle.into_iter()
+                    .filter_map(|id| sessions.remove(&id).map(|session| (id, session)))
+                    .collect::<Vec<_>>()
+            };
+            rerender.notify_one();
+
+            join_all(closed.into_iter().map(|(id, session)| async move {
+                info!("Closing session {id} after {minutes} minutes without activity");
+                session.close().await;
+            }))
+            .await;
+        }
+    }
+
     async fn render_sessions(sessions: &Sessions) -> AppResult<String> {
-        Self::get_sessions(sessions)
+        // Invite only sessions can only be found through their invite code
+        let details = Self::get_sessions(sessions)
             .await
-            .map(|(id, _session)| GridElement {
-                title: format!("Session {id}"),
+            .filter(|(_, session)| !session.invite_only)
+            .map(|(id, session)| async move {
+                let title = session
+                    .title()
+                    .await
+                    .log_warn_with_msg("Failed to resolve the title of a session")
+                    .unwrap_or_else(|| format!("Session {id}"));
+                let viewers = session.receiver_count().await;
+                let state = session.get_state().await;
+                (id, title, viewers, state)
+            });
+
+        let mut rendered = String::new();
+        for (id, title, viewers, state) in join_all(details).await {
+            let element = SessionElement {
+                title,
                 redirect_entire: frontend_redirect(&format!("/video/session/{id}"), HXTarget::All),
-                redirect_img: String::new(),
-                redirect_title: String::new(),
-            })
-            .map(|el| el.render().convert_err())
-            .collect()
+                viewers,
+                paused: matches!(state, SessionState::Paused),
+            };
+            rendered.push_str(&element.render()?);
+        }
+        Ok(rendered)
     }
 
     pub fn render_receiver(&self) -> watch::Receiver<String> {
         self.rendered_sessions.0.subscribe()
     }
 
+    /// The creator can always join, in invite only sessions everyone else needs the invite code
     pub async fn new_session(
         &mut self,
         content_id: u64,
         db: &Database,
         shutdown: Shutdown,
+        creator: &User,
+        invite_only: bool,
     ) -> AppResult<u32> {
         let random = loop {
             let random = pseudo_random();
@@ -154,16 +312,42 @@ impl StreamingSessions {
             }
         };
 
-        let session = Session::new(db, shutdown, content_id)?;
+        let session = Session::new(
+            db,
+            shutdown,
+            content_id,
+            self.should_rerender.clone(),
+            self.recommender.clone(),
+            self.settings.clone(),
+            invite_only,
+        )?;
+        session.invite(creator.id).await;
         self.insert(random, session).await;
 
         Ok(random)
     }
 }
 
+#[cfg(test)]
+impl StreamingSessions {
+    /// Sessions with the default settings and a recommender of their own, like the server has them
+    pub fn with_defaults(db: &Database, shutdown: &Shutdown) -> Self {
+        let settings = ServerSettings::in_memory();
+        let recommender = Recommender::spawn(
+            db.clone(),
+            settings.clone(),
+            &crate::state::ContentUpdates::new(shutdown.clone()),
+            shutdown.clone(),
+        );
+        Self::new(shutdown.clone(), settings, recommender)
+    }
+}
+
 pub struct Session {
     video_id: Mutex<u64>,
     file_path: Mutex<String>,
+    /// Used to notice when the file behind the same path was replaced
+    file_modified: Mutex<Option<SystemTime>>,
     stream: Mutex<ServeFile>,
     receivers: Mutex<Vec<(User, UserSessionID)>>,
     channel: SessionChannel,
@@ -171,10 +355,37 @@ pub struct Session {
     time_estimate: Arc<TimeKeeper>,
     next_recommended: Arc<Mutex<RecommendationPopupState>>,
     db: Database,
+    /// Used to update the session list when the content changes
+    rerender: Arc<Notify>,
+    recommender: Recommender,
+    /// The last time a client reported progress or requested part of the stream
+    last_activity: Mutex<Instant>,
+    /// Stops the tasks and connections belonging to only this session
+    closed: Shutdown,
+    /// Short code that lets others join, shown to everyone in the session
+    invite_code: String,
+    invite_only: bool,
+    /// Users that are allowed into an invite only session
+    invited: Mutex<HashSet<i64>>,
+    /// Content that is offered next in this order instead of recommendations, like the rest of a season
+    queue: Mutex<VecDeque<u64>>,
+    /// Whether the popup near the end is shown, queued content is offered either way
+    recommendations: watch::Sender<bool>,
+    settings: ServerSettings,
+    /// What ffmpeg complained about in this session, oldest first, so admins can see why a file won't play
+    errors: Mutex<VecDeque<String>>,
 }
 
 impl Session {
-    pub fn new(db: &Database, shutdown: Shutdown, content_id: u64) -> AppResult<Self> {
+    pub fn new(
+        db: &Database,
+        shutdown: Shutdown,
+        content_id: u64,
+        rerender: Arc<Notify>,
+        recommender: Recommender,
+        settings: ServerSettings,
+        invite_only: bool,
+    ) -> AppResult<Self> {
         let file_path: String = db.get()?.query_row_get(
             "SELECT data_file.path FROM content, data_file
                 WHERE content.data_id = data_file.id
@@ -184,26 +395,35 @@ impl Session {
         )?;
 
         let stream = ServeFile::new(&file_path);
+        let file_modified = modified_time(&file_path);
 
         let media_context = ffmpeg::format::input(&file_path)?;
         let total_time = media_context.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
 
+        let shutdown = shutdown.child();
         let channel = SessionChannel::new(shutdown.clone());
 
-        let time_estimate = Arc::new(TimeKeeper::new(total_time));
+        let time_estimate = Arc::new(TimeKeeper::new(total_time, settings.clone()));
 
-        let next_recommended = Arc::new(Mutex::new(RecommendationPopupState::new(db, content_id)));
+        let next_recommended = Arc::new(Mutex::new(RecommendationPopupState::new(
+            &recommender,
+            content_id,
+        )));
+
+        let (recommendations, enabled) = watch::channel(true);
 
         Self::send_recommendations(
             time_estimate.clone(),
             channel.clone(),
             next_recommended.clone(),
-            shutdown,
+            enabled,
+            shutdown.clone(),
         );
 
         let session = Self {
             video_id: Mutex::new(content_id),
             file_path: Mutex::new(file_path),
+            file_modified: Mutex::new(file_modified),
             stream: Mutex::new(stream),
             receivers: Mutex::new(Vec::new()),
             channel,
@@ -211,6 +431,17 @@ impl Session {
             time_estimate,
             next_recommended,
             db: db.clone(),
+            rerender,
+            recommender,
+            last_activity: Mutex::new(Instant::now()),
+            closed: shutdown,
+            invite_code: invite_code(),
+            invite_only,
+            invited: Mutex::new(HashSet::new()),
+            queue: Mutex::new(VecDeque::new()),
+            recommendations,
+            settings,
+            errors: Mutex::new(VecDeque::new()),
         };
 
         Ok(session)
@@ -224,57 +455,287 @@ impl Session {
             [content_id],
         )?;
 
-        if *self.file_path.lock().await == file_path {
+        let file_modified = modified_time(&file_path);
+        if *self.file_path.lock().await == file_path
+            && *self.file_modified.lock().await == file_modified
+        {
             return Ok(());
         }
 
-        *self.video_id.lock().await = content_id;
-        self.file_path.lock().await.clone_from(&file_path);
-
-        let media_context = ffmpeg::format::input(&file_path)?;
+        // A file that can't be opened leaves the session playing what it played before
+        let media_context = match ffmpeg::format::input(&file_path) {
+            Ok(media_context) => media_context,
+            Err(err) => {
+                self.log_error(format!("Failed to open {file_path}: {err}"))
+                    .await;
+                return Err(err.into());
+            }
+        };
         let total_time = media_context.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
 
+        let watching = self
+            .receivers
+            .lock()
+            .await
+            .iter()
+            .map(|(user, _)| user.id)
+            .collect::<Vec<_>>();
+        self.record_watched(&watching).await;
+
+        *self.video_id.lock().await = content_id;
+        *self.file_modified.lock().await = file_modified;
+
         self.time_estimate.reset(total_time).await;
-        *self.next_recommended.lock().await = RecommendationPopupState::new(&self.db, content_id);
+        *self.next_recommended.lock().await = self.popup_state(content_id).await;
+        self.record_activity(&watching).await;
 
         let serve_file = ServeFile::new(&file_path);
         self.replace_stream(serve_file, &file_path).await;
 
+        self.rerender.notify_one();
+
         Ok(())
     }
+
+    /// Plays these in order after the current content, recommendations only come back once it is empty
+    pub async fn set_queue(&self, queue: Vec<u64>) {
+        *self.queue.lock().await = queue.into();
+        let content_id = self.video_id().await;
+        *self.next_recommended.lock().await = self.popup_state(content_id).await;
+    }
+
+    /// Switching to something in the queue skips everything before it, switching to anything else ends the queue
+    pub async fn advance_queue(&self, content_id: u64) {
+        if content_id == self.video_id().await {
+            return;
+        }
+
+        let mut queue = self.queue.lock().await;
+        match queue.iter().position(|&queued| queued == content_id) {
+            Some(position) => {
+                queue.drain(..=position);
+            }
+            None => queue.clear(),
+        }
+    }
+
+    /// Stepping back inside the queue keeps it going, what is playing now comes again after the content before it
+    pub async fn requeue_current(&self) {
+        let current = self.video_id().await;
+        let mut queue = self.queue.lock().await;
+        if !queue.is_empty() {
+            queue.push_front(current);
+        }
+    }
+
+    async fn popup_state(&self, content_id: u64) -> RecommendationPopupState {
+        match self.queue.lock().await.front() {
+            Some(&next) => RecommendationPopupState::queued(&self.db, next),
+            None => RecommendationPopupState::new(&self.recommender, content_id),
+        }
+    }
+
+    /// The next thing in the queue or the episode before or after the current one
+    pub async fn step(&self, step: Step) -> AppResult<Option<u64>> {
+        if let (Step::Next, Some(&next)) = (step, self.queue.lock().await.front()) {
+            return Ok(Some(next));
+        }
+
+        let content_id = self.video_id().await;
+        adjacent_episode(
+            &self.db.get()?,
+            content_id,
+            step,
+            self.settings.skip_episode_gaps(),
+        )
+    }
+
+    pub fn recommendations_enabled(&self) -> bool {
+        *self.recommendations.borrow()
+    }
+
+    pub fn set_recommendations(&self, enabled: bool) {
+        self.recommendations.send_replace(enabled);
+    }
+
+    /// What would be offered next, for when someone asks instead of waiting for the popup
+    pub async fn next_popup(&self) -> AppResult<String> {
+        self.next_recommended.lock().await.get_popup().await
+    }
+
+    /// Users that turned recommendations off in their preferences don't get the popup at all
+    fn wants_recommendations(&self, user_id: i64) -> bool {
+        let Some(conn) = self.db.get().log_warn() else {
+            return true;
+        };
+        shows_recommendations(&conn, user_id)
+            .log_warn_with_msg("Failed to read the preferences of a user")
+            .unwrap_or(true)
+    }
+
+    /// Whether the content exists and currently has a file that can be streamed
+    pub fn is_playable(&self, content_id: u64) -> AppResult<bool> {
+        let playable = self.db.get()?.query_row_get(
+            "SELECT exists(SELECT 1 FROM content, data_file
+                WHERE content.data_id = data_file.id
+                AND content.id = ?1)",
+            [content_id],
+        )?;
+        Ok(playable)
+    }
+
+    /// The display title of the content that is currently playing
+    pub async fn title(&self) -> AppResult<String> {
+        let content_id = *self.video_id.lock().await;
+        content_title(&self.db.get()?, content_id)
+    }
+
+    /// The id of the content that is currently playing
+    pub async fn video_id(&self) -> u64 {
+        *self.video_id.lock().await
+    }
+
     pub async fn stream(&self, req: Request<Body>) -> impl IntoResponse {
+        self.mark_active().await;
         self.stream.lock().await.call(req).await
     }
 
+    async fn mark_active(&self) {
+        *self.last_activity.lock().await = Instant::now();
+    }
+
+    /// How long it has been since anyone interacted with this session
+    pub async fn idle_for(&self) -> Duration {
+        self.last_activity.lock().await.elapsed()
+    }
+
+    /// Tells the remaining clients that the session is gone, then stops everything belonging to it
+    pub async fn close(&self) {
+        let msg = Notification {
+            msg: "This session was closed because nobody was watching".to_owned(),
+            script: "/scripts/back.js",
+        }
+        .render()
+        .log_err_with_msg("Failed to render notification template, this should not happen")
+        .unwrap_or_default();
+
+        self.channel.send(WSSend::Closed { msg });
+
+        // Cancelling right away would abort the connections before they delivered the notification
+        tokio::time::timeout(CLOSE_TIMEOUT, self.channel.disconnected())
+            .await
+            .log_warn_with_msg("Not every client received that the session was closed");
+        self.closed.close();
+    }
+
+    /// The stream and the path it serves always change together
     async fn replace_stream(&self, stream: ServeFile, path: &str) {
         *self.stream.lock().await = stream;
-        path.clone_into(&mut (self.file_path.lock().await.to_string()));
+        path.clone_into(&mut *self.file_path.lock().await);
     }
 
     pub async fn add_receiver(&self, user: &User, id: UserSessionID) {
         self.receivers.lock().await.push((user.clone(), id));
+        self.record_activity(&[user.id]).await;
+        self.rerender.notify_one();
+    }
+
+    /// Keeps the "Continue Series" row of these users up to date with what is playing now
+    async fn record_activity(&self, user_ids: &[i64]) {
+        let content_id = self.video_id().await;
+        let Some(conn) = self.db.get().log_warn() else {
+            return;
+        };
+        for &user_id in user_ids {
+            record_series_activity(&conn, user_id, content_id)
+                .log_warn_with_msg("Failed to record what a user played");
+        }
+    }
+
+    /// Moves the "Continue Series" row of these users past what is playing, if enough of it was watched
+    async fn record_watched(&self, user_ids: &[i64]) {
+        if !self.time_estimate.reached_threshold().await {
+            return;
+        }
+        let content_id = self.video_id().await;
+        let Some(conn) = self.db.get().log_warn() else {
+            return;
+        };
+        for &user_id in user_ids {
+            record_series_watched(
+                &conn,
+                user_id,
+                content_id,
+                self.settings.skip_episode_gaps(),
+            )
+            .log_warn_with_msg("Failed to record what a user watched");
+        }
     }
 
     pub async fn remove_receiver(&self, id: UserSessionID) {
-        self.receivers
-            .lock()
-            .await
-            .retain(|(_, entry)| *entry != id);
+        let left = {
+            let mut receivers = self.receivers.lock().await;
+            let left = receivers
+                .iter()
+                .filter(|(_, entry)| *entry == id)
+                .map(|(user, _)| user.id)
+                .collect::<Vec<_>>();
+            receivers.retain(|(_, entry)| *entry != id);
+            left
+        };
+        self.record_watched(&left).await;
+        self.rerender.notify_one();
+    }
+
+    async fn log_error(&self, error: String) {
+        let mut errors = self.errors.lock().await;
+        if errors.len() == LOGGED_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    pub async fn errors(&self) -> Vec<String> {
+        self.errors.lock().await.iter().cloned().collect()
+    }
+
+    pub fn invite_code(&self) -> &str {
+        &self.invite_code
+    }
+
+    pub async fn invite(&self, user_id: i64) {
+        self.invited.lock().await.insert(user_id);
+    }
+
+    pub async fn may_join(&self, user_id: i64) -> bool {
+        !self.invite_only || self.invited.lock().await.contains(&user_id)
     }
 
     pub async fn receiver_count(&self) -> usize {
         self.receivers.lock().await.len()
     }
 
+    /// The names of everyone that is currently connected, once per connection
+    pub async fn participant_names(&self) -> Vec<String> {
+        self.receivers
+            .lock()
+            .await
+            .iter()
+            .map(|(user, _)| user.username.clone())
+            .collect()
+    }
+
     pub async fn get_state(&self) -> SessionState {
         *self.state.lock().await
     }
 
     pub async fn set_state(&self, state: SessionState) {
         *self.state.lock().await = state;
+        self.rerender.notify_one();
     }
 
     pub async fn update_timekeeper(&self, time: f64, state: SessionState) {
+        self.mark_active().await;
         self.time_estimate.update(time, state).await;
     }
 
@@ -300,10 +761,17 @@ impl Session {
         };
 
         session.add_receiver(&user, user_id).await;
+        let show_recommendations = session.wants_recommendations(user.id);
 
         session
             .channel
-            .handle_communications(session.clone(), socket, &user, user_id)
+            .handle_communications(
+                session.clone(),
+                socket,
+                &user,
+                user_id,
+                show_recommendations,
+            )
             .await;
 
         session.remove_receiver(user_id).await;
@@ -319,6 +787,7 @@ impl Session {
         timekeeper: Arc<TimeKeeper>,
         channel: SessionChannel,
         popup: Arc<Mutex<RecommendationPopupState>>,
+        mut enabled: watch::Receiver<bool>,
         shutdown: Shutdown,
     ) {
         tokio::spawn(async move {
@@ -328,6 +797,16 @@ impl Session {
                     _ = shutdown.cancelled() => break,
                 }
 
+                // The queue was chosen by the session, so it is offered even with recommendations turned off
+                let queued = popup.lock().await.queued;
+                if !queued && !*enabled.borrow() {
+                    tokio::select! {
+                        _ = channel.has_switched.notified() => continue,
+                        _ = enabled.wait_for(|enabled| *enabled) => {},
+                        _ = shutdown.cancelled() => break,
+                    }
+                }
+
                 let Some(popup) = popup
                     .lock()
                     .await
@@ -338,9 +817,13 @@ impl Session {
                     continue;
                 };
 
-                let msg = WSSend::Notification {
-                    msg: popup,
-                    origin: u32::MAX, // Probably unlikely, doesn't matter for now
+                let msg = if queued {
+                    WSSend::Notification {
+                        msg: popup,
+                        origin: u32::MAX, // Probably unlikely, doesn't matter for now
+                    }
+                } else {
+                    WSSend::Recommendation { msg: popup }
                 };
 
                 let Ok(_) = channel.to_websocket.send(msg) else {
@@ -353,22 +836,31 @@ impl Session {
     }
 }
 
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
 struct TimeKeeper {
     last_known_time: Mutex<f64>,
     total_time: Mutex<f64>,
     currently_playing: AtomicBool,
     last_update: Mutex<SystemTime>,
     was_updated: Notify,
+    /// Where the configured watched threshold comes from
+    settings: ServerSettings,
 }
 
 impl TimeKeeper {
-    fn new(total_time: f64) -> Self {
+    fn new(total_time: f64, settings: ServerSettings) -> Self {
         Self {
             last_known_time: 0.0.into(),
             total_time: total_time.into(),
             currently_playing: true.into(),
             last_update: SystemTime::now().into(),
             was_updated: Notify::new(),
+            settings,
         }
     }
 
@@ -394,7 +886,12 @@ impl TimeKeeper {
     }
 
     pub async fn when_to_recommend(&self) -> f64 {
-        *self.total_time.lock().await * 0.95
+        *self.total_time.lock().await * self.settings.watched_threshold()
+    }
+
+    /// Whether enough of the video was watched for it to count as watched
+    async fn reached_threshold(&self) -> bool {
+        self.current_estimate().await >= self.when_to_recommend().await
     }
 
     async fn current_estimate(&self) -> f64 {
@@ -432,6 +929,20 @@ impl TimeKeeper {
     }
 }
 
+/// Twelve characters without ones that are easily confused, like 0 and O
+fn invite_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+    // The code is all that keeps invite only sessions private, so it comes from the operating system
+    let mut bytes = [0; 12];
+    getrandom::getrandom(&mut bytes).expect("the operating system should provide randomness");
+    // 32 characters divide 256 evenly, so every character is equally likely
+    bytes
+        .iter()
+        .map(|byte| char::from(ALPHABET[usize::from(*byte) % ALPHABET.len()]))
+        .collect()
+}
+
 type PopupFuture = Pin<Box<dyn Future<Output = AppResult<RecommendationPopup>> + Send + Sync>>;
 
 enum Store<A, B> {
@@ -440,13 +951,33 @@ enum Store<A, B> {
 }
 struct RecommendationPopupState {
     inner: Store<PopupFuture, String>,
+    /// Comes from the queue of the session instead of the recommender
+    queued: bool,
 }
 
 impl RecommendationPopupState {
-    fn new(db: &Database, content_id: u64) -> Self {
+    fn new(recommender: &Recommender, content_id: u64) -> Self {
+        let recommender = recommender.clone();
+        Self {
+            inner: Store::Future(Box::pin(
+                async move { recommender.recommend(content_id).await },
+            )),
+            queued: false,
+        }
+    }
+
+    fn queued(db: &Database, content_id: u64) -> Self {
         let db = db.clone();
         Self {
-            inner: Store::Future(Box::pin(RecommendationPopup::new(db, content_id))),
+            inner: Store::Future(Box::pin(async move {
+                Ok(RecommendationPopup {
+                    id: content_id,
+                    image: String::new(),
+                    title: content_title(&db.get()?, content_id)?,
+                    queued: true,
+                })
+            })),
+            queued: true,
         }
     }
 
@@ -466,3 +997,458 @@ impl RecommendationPopupState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use rusqlite::params;
+    use tokio::sync::broadcast::error::RecvError;
+
+    use super::*;
+    use crate::{
+        database::{in_file, QueryRowIntoConnExt},
+        indexing::{fixture_content, index_fixture},
+        utils::{silence, TempDir},
+    };
+
+    const PILOT: &str = "Lost/Season 1/Pilot - s1e1.mp4";
+    const TABULA_RASA: &str = "Lost/Season 1/Tabula Rasa - s1e2.mp4";
+
+    /// Sessions over content that can actually be played, the directory has to outlive them
+    struct Library {
+        dir: TempDir,
+        db: Database,
+        sessions: StreamingSessions,
+        shutdown: Shutdown,
+        /// In the order the files were given
+        content: Vec<u64>,
+    }
+
+    fn library(name: &str, files: &[&str]) -> Library {
+        ffmpeg::init().unwrap();
+
+        let dir = TempDir::new(name);
+        let db = in_file(&dir.path().join("database.sqlite"));
+        let mut conn = db.get().unwrap();
+        index_fixture(&mut conn, files);
+
+        // The indexed paths are made up, so they are pointed at files that exist
+        let content = files
+            .iter()
+            .map(|file| {
+                let id = fixture_content(&conn, file);
+                let path = dir.path().join(format!("{id}.wav"));
+                silence(&path);
+                conn.execute(
+                    "UPDATE data_file SET path = ?1
+                        WHERE id = (SELECT data_id FROM content WHERE id = ?2)",
+                    params![path.to_str().unwrap(), id],
+                )
+                .unwrap();
+                id
+            })
+            .collect();
+        drop(conn);
+
+        let (shutdown, _) = Shutdown::new();
+        let sessions = StreamingSessions::with_defaults(&db, &shutdown);
+        Library {
+            dir,
+            db,
+            sessions,
+            shutdown,
+            content,
+        }
+    }
+
+    fn user(db: &Database, name: &str) -> User {
+        let conn = db.get().unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password) VALUES (?1, '')",
+            [name],
+        )
+        .unwrap();
+        conn.query_row_into(
+            "SELECT id, username, password FROM users WHERE id = ?1",
+            [conn.last_insert_rowid()],
+        )
+        .unwrap()
+    }
+
+    /// An invite only session of a new user, returned with its id
+    async fn private_session(library: &mut Library) -> (u32, Arc<Session>) {
+        let owner = user(&library.db, "owner");
+        let id = library
+            .sessions
+            .new_session(
+                library.content[0],
+                &library.db,
+                library.shutdown.clone(),
+                &owner,
+                true,
+            )
+            .await
+            .unwrap();
+        (id, library.sessions.get(&id).await.unwrap())
+    }
+
+    /// A session everyone can find in the session list, returned with its id
+    async fn public_session(library: &mut Library) -> (u32, Arc<Session>) {
+        let owner = user(&library.db, "host");
+        let id = library
+            .sessions
+            .new_session(
+                library.content[0],
+                &library.db,
+                library.shutdown.clone(),
+                &owner,
+                false,
+            )
+            .await
+            .unwrap();
+        (id, library.sessions.get(&id).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_valid_invite_code_lets_users_join() {
+        let mut library = library("invite-valid", &[PILOT]);
+        let (id, session) = private_session(&mut library).await;
+        let guest = user(&library.db, "guest");
+        assert!(!session.may_join(guest.id).await);
+
+        let code = session.invite_code().to_lowercase();
+        assert_eq!(
+            library.sessions.join_with_code(guest.id, &code).await,
+            Some(id)
+        );
+        assert!(session.may_join(guest.id).await);
+    }
+
+    #[tokio::test]
+    async fn unknown_and_expired_invite_codes_are_rejected() {
+        let mut library = library("invite-invalid", &[PILOT]);
+        let (id, session) = private_session(&mut library).await;
+        let guest = user(&library.db, "guest");
+
+        let wrong = if session.invite_code() == "AAAAAAAAAAAA" {
+            "BBBBBBBBBBBB"
+        } else {
+            "AAAAAAAAAAAA"
+        };
+        assert_eq!(library.sessions.join_with_code(guest.id, wrong).await, None);
+
+        let code = session.invite_code().to_owned();
+        library.sessions.sessions.lock().await.remove(&id);
+        assert_eq!(library.sessions.join_with_code(guest.id, &code).await, None);
+        assert!(!session.may_join(guest.id).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_attempts_are_limited_per_user() {
+        let library = library("invite-attempts", &[]);
+        let sessions = &library.sessions;
+
+        for _ in 0..INVITE_ATTEMPTS {
+            assert!(sessions.try_invite_attempt(1).await);
+        }
+        assert!(!sessions.try_invite_attempt(1).await);
+        assert!(sessions.try_invite_attempt(2).await);
+
+        tokio::time::advance(INVITE_ATTEMPT_WINDOW).await;
+        assert!(sessions.try_invite_attempt(1).await);
+    }
+
+    #[test]
+    fn invite_codes_are_long_and_unambiguous() {
+        let code = invite_code();
+        assert_eq!(code.len(), 12);
+        assert!(!code.contains(['0', 'O', '1', 'I']));
+        assert_ne!(code, invite_code());
+    }
+
+    #[tokio::test]
+    async fn reusing_a_session_serves_the_new_file() {
+        let mut library = library("reuse-path", &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+        let next = library.content[1];
+
+        session.reuse(next).await.unwrap();
+        assert_eq!(session.video_id().await, next);
+        let path = &*session.file_path.lock().await;
+        assert!(path.ends_with(&format!("{next}.wav")));
+    }
+
+    #[tokio::test]
+    async fn reusing_a_session_with_a_broken_file_keeps_the_old_one() {
+        let mut library = library("reuse-broken", &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+        let (first, next) = (library.content[0], library.content[1]);
+        let path = session.file_path.lock().await.clone();
+
+        let broken = library.dir.file("broken.wav");
+        library
+            .db
+            .get()
+            .unwrap()
+            .execute(
+                "UPDATE data_file SET path = ?1
+                    WHERE id = (SELECT data_id FROM content WHERE id = ?2)",
+                params![broken.to_str().unwrap(), next],
+            )
+            .unwrap();
+
+        assert!(session.reuse(next).await.is_err());
+        assert_eq!(session.video_id().await, first);
+        assert_eq!(*session.file_path.lock().await, path);
+    }
+
+    #[tokio::test]
+    async fn failed_opens_are_logged_in_the_session() {
+        let mut library = library("reuse-log", &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+        let next = library.content[1];
+        assert!(session.errors().await.is_empty());
+
+        let broken = library.dir.file("broken.wav");
+        library
+            .db
+            .get()
+            .unwrap()
+            .execute(
+                "UPDATE data_file SET path = ?1
+                    WHERE id = (SELECT data_id FROM content WHERE id = ?2)",
+                params![broken.to_str().unwrap(), next],
+            )
+            .unwrap();
+
+        assert!(session.reuse(next).await.is_err());
+        let errors = session.errors().await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&format!("Failed to open {}: ", broken.display())));
+    }
+
+    #[tokio::test]
+    async fn only_existing_content_with_a_file_is_playable() {
+        let mut library = library("playable", &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+        let (first, next) = (library.content[0], library.content[1]);
+
+        assert!(session.is_playable(next).unwrap());
+        assert!(!session.is_playable(9999).unwrap());
+
+        library
+            .db
+            .get()
+            .unwrap()
+            .execute("UPDATE content SET data_id = NULL WHERE id = ?1", [next])
+            .unwrap();
+        assert!(!session.is_playable(next).unwrap());
+        assert_eq!(session.video_id().await, first);
+    }
+
+    #[tokio::test]
+    async fn the_session_list_shows_what_is_playing() {
+        let mut library = library("session-title", &[PILOT, TABULA_RASA]);
+        let (id, session) = public_session(&mut library).await;
+        private_session(&mut library).await;
+        assert_eq!(session.title().await.unwrap(), "Pilot - Episode 1");
+
+        let rendered = StreamingSessions::render_sessions(&library.sessions.sessions)
+            .await
+            .unwrap();
+        assert_eq!(rendered.matches("class=\"gridcell\"").count(), 1);
+        assert!(rendered.contains("Pilot - Episode 1"));
+        assert!(rendered.contains(&format!("/video/session/{id}")));
+
+        session.reuse(library.content[1]).await.unwrap();
+        assert_eq!(session.title().await.unwrap(), "Tabula Rasa - Episode 2");
+        let rendered = StreamingSessions::render_sessions(&library.sessions.sessions)
+            .await
+            .unwrap();
+        assert!(rendered.contains("Tabula Rasa - Episode 2"));
+        assert!(!rendered.contains("Pilot"));
+    }
+
+    #[tokio::test]
+    async fn the_session_list_shows_viewers_and_playback_state() {
+        let mut library = library("session-state", &[PILOT]);
+        let (_, session) = public_session(&mut library).await;
+        let render = || StreamingSessions::render_sessions(&library.sessions.sessions);
+        assert!(render().await.unwrap().contains("0 watching - Playing"));
+
+        let (first, second) = (user(&library.db, "first"), user(&library.db, "second"));
+        session.add_receiver(&first, 1).await;
+        session.add_receiver(&second, 2).await;
+        session.set_state(SessionState::Paused).await;
+        assert!(render().await.unwrap().contains("2 watching - Paused"));
+    }
+
+    #[tokio::test]
+    async fn the_session_list_keeps_its_order_between_rerenders() {
+        let mut library = library("session-order", &[PILOT]);
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            ids.push(public_session(&mut library).await.0);
+        }
+        let render = || StreamingSessions::render_sessions(&library.sessions.sessions);
+
+        let first = render().await.unwrap();
+        assert_eq!(render().await.unwrap(), first);
+
+        // Ordered by id
+        ids.sort();
+        let positions = ids
+            .iter()
+            .map(|id| first.find(&format!("/video/session/{id}\"")).unwrap())
+            .collect::<Vec<_>>();
+        assert!(positions.is_sorted());
+    }
+
+    /// Whether the tasks of the session were stopped
+    fn is_closed(session: &Session) -> bool {
+        session.closed.cancelled().now_or_never().is_some()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnecting_within_the_grace_period_keeps_the_session() {
+        let mut library = library("reconnect", &[PILOT]);
+        let (id, session) = private_session(&mut library).await;
+        let viewer = user(&library.db, "viewer");
+
+        session.add_receiver(&viewer, 1).await;
+        session.remove_receiver(1).await;
+        let abandoned = tokio::spawn({
+            let (mut sessions, session) = (library.sessions.clone(), session.clone());
+            let shutdown = library.shutdown.clone();
+            async move { sessions.remove_abandoned(id, &session, &shutdown).await }
+        });
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        session.add_receiver(&viewer, 2).await;
+        abandoned.await.unwrap();
+
+        let current = library.sessions.get(&id).await.unwrap();
+        assert!(Arc::ptr_eq(&current, &session));
+        assert!(!is_closed(&session));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sessions_nobody_returns_to_are_removed_and_stopped() {
+        let mut library = library("abandoned", &[PILOT]);
+        let (id, session) = private_session(&mut library).await;
+        let viewer = user(&library.db, "viewer");
+
+        session.add_receiver(&viewer, 1).await;
+        session.remove_receiver(1).await;
+        let mut sessions = library.sessions.clone();
+        sessions
+            .remove_abandoned(id, &session, &library.shutdown)
+            .await;
+
+        assert!(library.sessions.get(&id).await.is_none());
+        assert!(is_closed(&session));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_sessions_are_closed_and_their_clients_told() {
+        let mut library = library("idle", &[PILOT]);
+        let (id, session) = public_session(&mut library).await;
+        let mut received = session.channel.to_websocket.subscribe();
+
+        let minutes = library.sessions.settings.session_idle_timeout();
+        tokio::time::advance(Duration::from_secs(minutes * 60) + IDLE_CHECK_INTERVAL).await;
+
+        let closed = async {
+            loop {
+                match received.recv().await {
+                    Ok(WSSend::Closed { msg }) => return Some(msg),
+                    Err(RecvError::Closed) => return None,
+                    _ => {}
+                }
+            }
+        };
+        let msg = tokio::time::timeout(IDLE_CHECK_INTERVAL, closed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(msg.contains("nobody was watching"));
+
+        tokio::time::timeout(CLOSE_TIMEOUT, session.closed.cancelled())
+            .await
+            .unwrap();
+        assert!(library.sessions.get(&id).await.is_none());
+        assert!(is_closed(&session));
+    }
+
+    #[tokio::test]
+    async fn the_watched_threshold_moves_the_recommendation() {
+        let settings = ServerSettings::in_memory();
+        let timekeeper = TimeKeeper::new(100., settings.clone());
+        assert_eq!(timekeeper.when_to_recommend().await, 95.);
+
+        settings.set_watched_threshold(0.8);
+        assert_eq!(timekeeper.when_to_recommend().await, 80.);
+    }
+
+    /// Whether the session broadcasts a recommendation popup, the files are a second long so it is due right away
+    async fn broadcasts_a_popup(name: &str, enabled: bool) -> bool {
+        let mut library = library(name, &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+        let mut received = session.channel.to_websocket.subscribe();
+        session.set_recommendations(enabled);
+
+        let popup = async {
+            loop {
+                match received.recv().await {
+                    Ok(WSSend::Recommendation { .. }) => return true,
+                    Err(RecvError::Closed) => return false,
+                    _ => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(3), popup)
+            .await
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn the_popup_is_broadcast_near_the_end() {
+        assert!(broadcasts_a_popup("popup-enabled", true).await);
+    }
+
+    #[tokio::test]
+    async fn turned_off_recommendations_are_not_broadcast() {
+        assert!(!broadcasts_a_popup("popup-disabled", false).await);
+    }
+
+    #[tokio::test]
+    async fn stepping_back_inside_a_queue_keeps_it_going() {
+        const WALKABOUT: &str = "Lost/Season 1/Walkabout - s1e3.mp4";
+
+        let mut library = library("queue-back", &[PILOT, TABULA_RASA, WALKABOUT]);
+        let (_, session) = private_session(&mut library).await;
+        let (pilot, tabula_rasa, walkabout) =
+            (library.content[0], library.content[1], library.content[2]);
+
+        // Playing everything from the pilot, then going to the next one
+        session.set_queue(vec![tabula_rasa, walkabout]).await;
+        session.advance_queue(tabula_rasa).await;
+        session.reuse(tabula_rasa).await.unwrap();
+        assert_eq!(*session.queue.lock().await, [walkabout]);
+
+        assert_eq!(session.step(Step::Previous).await.unwrap(), Some(pilot));
+        session.requeue_current().await;
+        session.reuse(pilot).await.unwrap();
+        assert_eq!(*session.queue.lock().await, [tabula_rasa, walkabout]);
+        assert_eq!(session.step(Step::Next).await.unwrap(), Some(tabula_rasa));
+    }
+
+    #[tokio::test]
+    async fn stepping_back_without_a_queue_starts_none() {
+        let mut library = library("no-queue-back", &[PILOT, TABULA_RASA]);
+        let (_, session) = private_session(&mut library).await;
+
+        session.requeue_current().await;
+        assert!(session.queue.lock().await.is_empty());
+    }
+}
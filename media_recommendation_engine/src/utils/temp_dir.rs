@@ -0,0 +1,44 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A directory of its own for a test, removed again once it is dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// The name has to be unique among all tests, they run at the same time
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "media-recommendation-engine-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Creates the file and the directories above it, its content is the relative path
+    pub fn file(&self, relative: &str) -> PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, relative).unwrap();
+        path
+    }
+
+    pub fn dir(&self, relative: &str) -> PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
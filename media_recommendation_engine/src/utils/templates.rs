@@ -2,7 +2,16 @@ use std::fmt::Display;
 
 use askama::Template;
 
-use crate::routes::Section;
+use crate::{indexing::ScanSummary, routes::Section};
+
+mod filters {
+    use std::fmt::Display;
+
+    /// Use as {{ "/route"|base }} for every url in a template
+    pub fn base(route: impl Display) -> askama::Result<String> {
+        Ok(crate::utils::with_base(&route.to_string()))
+    }
+}
 
 pub trait AsDisplay: Display {
     fn to_box(self) -> Box<dyn Display>;
@@ -13,6 +22,8 @@ pub trait AsDisplay: Display {
 pub struct Index {
     pub body: String,
     pub all: String,
+    /// Sent along with every htmx request, pages that can't get one at the moment leave it empty
+    pub csrf_token: String,
 }
 
 #[derive(Template)]
@@ -36,7 +47,10 @@ pub struct Homepage<'a> {
 #[derive(Template)]
 #[template(path = "../frontend/content/error.html")]
 pub struct Error<'a> {
-    pub err: &'a str,
+    pub code: u16,
+    pub title: &'a str,
+    pub description: &'a str,
+    pub message: Option<&'a str>,
     pub redirect: &'a str,
 }
 
@@ -60,6 +74,8 @@ pub struct Settings {
 #[derive(Template)]
 #[template(path = "../frontend/content/settings/admin_section.html")]
 pub struct AdminSettings {
+    /// Like "Library: 4.2 TB across 1,203 files"
+    pub library_size: String,
     pub admin_settings: Vec<Setting>,
 }
 
@@ -73,12 +89,21 @@ pub struct AccountSettings {
 #[template(path = "../frontend/content/settings/profile_section.html")]
 pub struct ProfileSettings {
     pub name: String,
+    pub show_recommendations: bool,
 }
 
 #[derive(Template)]
 #[template(path = "../frontend/content/settings/setting.html")]
 pub enum Setting {
-    CreationMenu { creation: Creation },
+    CreationMenu {
+        creation: Creation,
+    },
+    /// Entries that can only be looked at or removed
+    List {
+        title: &'static str,
+        list_id: &'static str,
+        entries: Vec<Box<dyn Display>>,
+    },
 }
 
 #[derive(Template)]
@@ -100,6 +125,11 @@ pub enum CreationInput {
         name: &'static str,
         placeholder: &'static str,
     },
+    /// Sends the rest of the form somewhere else without submitting it
+    Button {
+        label: &'static str,
+        post_addr: &'static str,
+    },
     Checkbox {
         label: &'static str,
         name: &'static str,
@@ -121,6 +151,59 @@ impl AsDisplay for UserEntry {
     }
 }
 
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/report_entry.html")]
+pub struct ReportEntry {
+    pub report_id: u64,
+    pub title: String,
+    pub username: String,
+    pub video_time: String,
+    pub description: String,
+}
+
+impl AsDisplay for ReportEntry {
+    fn to_box(self) -> Box<dyn Display> {
+        Box::new(self)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/logs.html")]
+pub struct LogView {
+    /// The name of the current log file, None when logs aren't written to a file
+    pub file: Option<String>,
+    pub lines: String,
+    pub level: &'static str,
+    pub levels: &'static [&'static str],
+    pub count: usize,
+    pub follow: bool,
+    /// Where the shown lines end in the file
+    pub offset: u64,
+}
+
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/log_lines.html")]
+pub struct LogLines {
+    pub lines: String,
+}
+
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/review_entry.html")]
+pub struct ReviewEntry {
+    /// None for files that failed to classify, the next indexing run tries them again
+    pub content_id: Option<u64>,
+    pub title: String,
+    pub path: String,
+    pub confidence: &'static str,
+    pub error: Option<String>,
+}
+
+impl AsDisplay for ReviewEntry {
+    fn to_box(self) -> Box<dyn Display> {
+        Box::new(self)
+    }
+}
+
 #[derive(Template)]
 #[template(path = "../frontend/content/settings/location_entry.html")]
 pub struct LocationEntry {
@@ -135,10 +218,34 @@ impl AsDisplay for LocationEntry {
     }
 }
 
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/location_preview.html")]
+pub struct LocationPreview {
+    pub path: String,
+    pub summary: ScanSummary,
+}
+
+#[derive(Template)]
+#[template(path = "../frontend/content/settings/collection_entry.html")]
+pub struct CollectionEntry {
+    pub collection_id: u64,
+    pub title: String,
+}
+
+impl AsDisplay for CollectionEntry {
+    fn to_box(self) -> Box<dyn Display> {
+        Box::new(self)
+    }
+}
+
 #[derive(Template)]
 #[template(path = "../frontend/content/library/library.html")]
 pub struct Library {
-    pub load_next: LoadNext,
+    /// The heading and content of every row
+    pub sections: Vec<(&'static str, LoadNext)>,
+    /// Nothing was indexed yet, so there are only instructions on how to change that
+    pub empty: bool,
+    pub redirect_settings: String,
 }
 
 #[derive(Template)]
@@ -147,6 +254,8 @@ pub struct LoadNext {
     pub route: String,
     pub page: u64,
     pub per_page: u64,
+    /// Pages below this are loaded immediately instead of once they are scrolled into view
+    pub restore: u64,
     random: u32,
 }
 
@@ -156,9 +265,15 @@ impl LoadNext {
             route,
             page,
             per_page,
+            restore: 0,
             random: super::pseudo_random(),
         }
     }
+
+    pub fn restore(mut self, pages: u64) -> Self {
+        self.restore = pages;
+        self
+    }
 }
 
 #[derive(Template)]
@@ -166,6 +281,8 @@ impl LoadNext {
 pub struct PaginationResponse<T: Template> {
     pub elements: Vec<T>,
     pub load_next: Option<LoadNext>,
+    /// Shown instead of the elements when the first page has none
+    pub empty: Option<&'static str>,
 }
 
 #[derive(Template)]
@@ -184,6 +301,16 @@ pub struct PreviewTemplate<'a> {
 pub struct LargeImage {
     pub title: String,
     pub image_interaction: String,
+    /// Resolution and source of the video, if known
+    pub badge: Option<String>,
+    /// Like "1080p • h264 • eac3 5.1" for anything playable, "unknown" if the file couldn't be probed
+    pub stream_summary: Option<String>,
+    /// Label and value pairs, the section is left out when this is empty
+    pub technical_details: Vec<(&'static str, String)>,
+    /// Where to download a playlist for external players, if there is anything to play
+    pub export: Option<String>,
+    /// Plays everything in the collection in order
+    pub play_all: Option<String>,
 }
 
 #[derive(Template)]
@@ -195,10 +322,23 @@ pub struct GridElement {
     pub redirect_title: String,
 }
 
+#[derive(Template)]
+#[template(path = "../frontend/content/library/session_element.html")]
+pub struct SessionElement {
+    pub title: String,
+    pub redirect_entire: String,
+    pub viewers: usize,
+    pub paused: bool,
+}
+
 #[derive(Template)]
 #[template(path = "../frontend/content/video.html")]
 pub struct Video {
     pub id: u64,
+    /// Empty when the session doesn't exist
+    pub invite_code: String,
+    /// Whether the session currently shows the popup near the end
+    pub recommendations: bool,
 }
 
 #[derive(Template)]
@@ -214,6 +354,8 @@ pub struct RecommendationPopup {
     pub id: u64,
     pub image: String,
     pub title: String,
+    /// Comes from the queue of the session instead of the recommender and starts once the current video ends
+    pub queued: bool,
 }
 
 #[derive(Template, Clone)]
@@ -226,3 +368,52 @@ where
     pub swap_method: Option<&'a str>,
     pub content: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_only_load_a_script_when_they_have_one() {
+        let plain = Notification {
+            msg: "Hi".to_owned(),
+            script: "",
+        };
+        assert!(!plain.render().unwrap().contains("<script"));
+
+        let with_script = Notification {
+            msg: "Hi".to_owned(),
+            script: "/scripts/back.js",
+        };
+        assert!(with_script
+            .render()
+            .unwrap()
+            .contains(r#"<script src="/scripts/back.js">"#));
+    }
+
+    #[test]
+    fn usernames_in_notifications_are_escaped() {
+        let username = "<script>alert(1)</script>";
+        let rendered = Notification {
+            msg: format!("{username} paused the video"),
+            script: "",
+        }
+        .render()
+        .unwrap();
+        assert!(!rendered.contains("<script"));
+        assert!(rendered.contains("&lt;script&gt;alert(1)&lt;/script&gt; paused the video"));
+    }
+
+    #[test]
+    fn invite_links_point_at_the_join_route() {
+        let video = Video {
+            id: 1,
+            invite_code: "ABCDEFGHJKLM".to_owned(),
+            recommendations: true,
+        };
+        assert!(video
+            .render()
+            .unwrap()
+            .contains("location.origin + '/video/join/ABCDEFGHJKLM'"));
+    }
+}
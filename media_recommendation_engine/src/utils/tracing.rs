@@ -1,19 +1,42 @@
-use std::time::Duration;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
 
 use axum::{http::Request, response::Response, Router};
 use tower_http::trace::TraceLayer;
 use tracing::{debug, debug_span, field, Level, Span};
+use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     filter::LevelFilter,
-    fmt::{self, time::OffsetTime},
+    fmt::{
+        self,
+        time::{FormatTime, OffsetTime},
+        MakeWriter,
+    },
     layer::SubscriberExt,
     util::SubscriberInitExt,
-    Layer,
+    Layer, Registry,
 };
 
-use crate::{state::AppState, Logging};
+use crate::{state::AppState, LogFormat, Logging};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+const LOG_FILE_PREFIX: &str = "mre.log";
+
+static LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
 
-pub fn init_tracing(logging: Logging) {
+/// The returned guard flushes the log file when dropped, so it has to be held until the program exits
+pub fn init_tracing(
+    logging: Logging,
+    format: LogFormat,
+    log_dir: Option<&Path>,
+) -> Option<WorkerGuard> {
     let (levelfilter, level) = match logging {
         Logging::None => (LevelFilter::OFF, Level::ERROR),
         Logging::Info => (LevelFilter::INFO, Level::INFO),
@@ -25,24 +48,112 @@ pub fn init_tracing(logging: Logging) {
     let filter = tracing_subscriber::filter::Targets::new()
         .with_target("media_recommendation_engine", level);
 
-    let format = time::format_description::parse(
+    let time_format = time::format_description::parse(
         "[year]-[month padding:zero]-[day padding:zero] [hour]:[minute]:[second]",
     )
     .unwrap();
     let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    let timer = OffsetTime::new(offset, time_format);
 
-    let custom_layer = fmt::layer()
-        .with_target(false)
-        .with_timer(OffsetTime::new(offset, format))
-        .with_filter(levelfilter)
-        .with_filter(filter);
+    // TODO: Look into own formatter -> I want pretty colors and noone can stop me
+    let mut layers = vec![formatted_layer(
+        std::io::stdout,
+        format,
+        timer.clone(),
+        true,
+    )];
+
+    let guard = log_dir.map(|log_dir| {
+        LOG_DIR.get_or_init(|| log_dir.to_path_buf());
+        let appender = tracing_appender::rolling::daily(log_dir, LOG_FILE_PREFIX);
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        layers.push(formatted_layer(writer, format, timer, false));
+        guard
+    });
 
     tracing_subscriber::registry()
-        .with(
-            // TODO: Look into own formatter -> I want pretty colors and noone can stop me
-            custom_layer,
-        )
+        .with(layers)
+        .with(levelfilter)
+        .with(filter)
         .init();
+
+    guard
+}
+
+fn formatted_layer<W, T>(writer: W, format: LogFormat, timer: T, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    T: FormatTime + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_target(false)
+        .with_ansi(ansi)
+        .with_timer(timer)
+        .with_writer(writer);
+
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// The file currently being logged into, None when logs are only written to stdout
+/// Only files the logger created are ever returned, nothing else in the directory
+pub fn current_log_file() -> Option<PathBuf> {
+    let log_dir = LOG_DIR.get()?;
+    std::fs::read_dir(log_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(LOG_FILE_PREFIX))
+        })
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        // The date the daily files end with sorts chronologically
+        .max()
+}
+
+/// Whether the line was logged at the level or a more severe one, lines without a level are kept
+pub fn log_line_matches(line: &str, level: Level) -> bool {
+    line.split(|c: char| !c.is_ascii_alphabetic())
+        .filter(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_uppercase()))
+        .find_map(|word| Level::from_str(word).ok())
+        .is_none_or(|line_level| line_level <= level)
+}
+
+/// The complete lines written after the offset and the offset after them
+/// Starts over from the beginning if the file got shorter, only the last `max_bytes` are read
+pub fn read_log_lines(path: &Path, offset: u64, max_bytes: u64) -> std::io::Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let offset = if offset > len { 0 } else { offset };
+    let start = offset.max(len.saturating_sub(max_bytes));
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut buffer = Vec::new();
+    file.take(len - start).read_to_end(&mut buffer)?;
+
+    // A line that is still being written is picked up next time
+    let complete = buffer
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    buffer.truncate(complete);
+
+    let mut lines = String::from_utf8_lossy(&buffer).into_owned();
+    // Reading started in the middle of a line, which isn't shown
+    if start > offset {
+        lines = lines
+            .split_once('\n')
+            .map(|(_, rest)| rest.to_owned())
+            .unwrap_or_default();
+    }
+
+    Ok((lines, start + complete as u64))
 }
 
 pub trait TraceLayerExt {
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+/// Samples per second, the files are one second long
+const RATE: u32 = 8000;
+
+/// A black 1x1 PNG
+const PIXEL: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x7e, 0x9b,
+    0x55, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x01, 0x48, 0xaf, 0xa4, 0x71, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+    0x42, 0x60, 0x82,
+];
+
+/// A second of silence, ffmpeg opens it like any video
+pub fn silence(path: &Path) {
+    write(path, &[]);
+}
+
+/// Silence with cover art, which ffmpeg lists as a video stream
+pub fn silence_with_cover(path: &Path) {
+    // An ID3v2.3 tag with a single APIC frame
+    let mut frame = Vec::new();
+    // Latin-1 text, the MIME type, a front cover and an empty description
+    frame.push(0);
+    frame.extend_from_slice(b"image/png\0");
+    frame.push(3);
+    frame.push(0);
+    frame.extend_from_slice(PIXEL);
+
+    let mut tag = Vec::new();
+    tag.extend_from_slice(b"ID3\x03\x00\x00");
+    // The size of the tag is stored 7 bits per byte, it is small enough for the last byte
+    let size = 10 + frame.len() as u32;
+    assert!(size < 0x80);
+    tag.extend_from_slice(&[0, 0, 0, size as u8]);
+    tag.extend_from_slice(b"APIC");
+    tag.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend_from_slice(&frame);
+
+    write(path, &[(b"id3 ", &tag)]);
+}
+
+fn write(path: &Path, chunks: &[(&[u8; 4], &[u8])]) {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"fmt ");
+    body.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    // One byte per sample, so the byte rate is the sample rate
+    body.extend_from_slice(&RATE.to_le_bytes());
+    body.extend_from_slice(&RATE.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&8u16.to_le_bytes());
+
+    for (id, data) in chunks {
+        body.extend_from_slice(*id);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        // Chunks start at even offsets
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+
+    body.extend_from_slice(b"data");
+    body.extend_from_slice(&RATE.to_le_bytes());
+    // Unsigned 8 bit samples are silent in the middle
+    body.extend(std::iter::repeat_n(128u8, RATE as usize));
+
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(4 + body.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(&body);
+    std::fs::write(path, wav).unwrap();
+}